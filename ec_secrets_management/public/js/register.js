@@ -1,4 +1,4 @@
-const { createApp, reactive, ref } = Vue;
+const { createApp, reactive, ref, onMounted } = Vue;
 
 createApp({
     setup() {
@@ -8,6 +8,20 @@ createApp({
         });
 
         const isLoading = ref(false); // Track request state
+        // Invitation links carry the token: register.html?invitation=<token>
+        const invitationToken = new URLSearchParams(window.location.search).get("invitation");
+        const registrationOpen = ref(true);
+
+        onMounted(async () => {
+            if (invitationToken) return;
+            try {
+                const response = await fetch(`${API_BASE_URL}/registration`);
+                const data = await response.json();
+                registrationOpen.value = data.open;
+            } catch (error) {
+                console.error("[Error]::[Auth] -> ", error);
+            }
+        });
 
         const displayToaster = (message, backgroundColor = "#ffa07a") => {
             Toastify({
@@ -29,7 +43,7 @@ createApp({
         };
 
         const registerUser = async () => {
-            if (!formData.email || !formData.password) {
+            if ((!invitationToken && !formData.email) || !formData.password) {
                 displayToaster("Email and password are required!");
                 return;
             }
@@ -38,10 +52,13 @@ createApp({
             isLoading.value = true; // Disable form interaction
 
             try {
-                const response = await fetch(`${API_BASE_URL}/setup`, {
+                const [path, body] = invitationToken
+                    ? ["/invitations/accept", { token: invitationToken, password: formData.password }]
+                    : ["/setup", { email: formData.email, password: formData.password }];
+                const response = await fetch(`${API_BASE_URL}${path}`, {
                     method: "POST",
                     headers: { "Content-Type": "application/json" },
-                    body: JSON.stringify({ email: formData.email, password: formData.password })
+                    body: JSON.stringify(body)
                 });
 
                 const data = await response.json();
@@ -63,6 +80,6 @@ createApp({
             }
         };
 
-        return { formData, registerUser, isLoading };
+        return { formData, registerUser, isLoading, invitationToken, registrationOpen };
     }
 }).mount("#app");
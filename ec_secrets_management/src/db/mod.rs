@@ -7,7 +7,8 @@ use std::sync::Arc;
 Custom modules
 ---------------*/
 use ec_secrets_shared_library::repositories::{
-    keys::KeyRepository, users::UserRepository, vault::VaultRepository,
+    app_roles::AppRoleRepository, keys::KeyRepository, service_accounts::ServiceAccountRepository,
+    users::UserRepository, vault::VaultRepository,
 };
 
 pub fn init() -> AdHoc {
@@ -15,10 +16,20 @@ pub fn init() -> AdHoc {
         "Establish connection with Database cluster",
         |rocket| async {
             match connect().await {
-                Ok((user_repository, vault_repository, key_repository)) => rocket
-                    .manage(Arc::new(user_repository))
-                    .manage(Arc::new(vault_repository))
-                    .manage(Arc::new(key_repository)),
+                Ok(repositories) => rocket
+                    .manage(Arc::new(repositories.users))
+                    .manage(Arc::new(repositories.vault))
+                    .manage(Arc::new(repositories.keys))
+                    .manage(Arc::new(repositories.service_accounts))
+                    .manage(Arc::new(repositories.app_roles))
+                    .manage(Arc::new(repositories.settings))
+                    .manage(Arc::new(repositories.login_attempts))
+                    .manage(Arc::new(repositories.user_tokens))
+                    .manage(Arc::new(repositories.invitations))
+                    .manage(Arc::new(repositories.groups))
+                    .manage(Arc::new(repositories.policies))
+                    .manage(Arc::new(repositories.organizations))
+                    .manage(Arc::new(repositories.seal)),
                 Err(error) => {
                     panic!("Cannot connect to instance:: {:?}", error)
                 }
@@ -5,6 +5,7 @@ use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Header;
 use rocket::{Request, Response};
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CORS;
 
 #[rocket::async_trait]
@@ -30,3 +31,146 @@ impl Fairing for CORS {
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
     }
 }
+
+/*--------------------
+Rate limiting
+---------------------*/
+use rocket::serde::Deserialize;
+use rocket::{Build, Rocket};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// `[default.rate_limit]` in Rocket.toml, applies to the login endpoints.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RateLimitConfig {
+    pub requests: u32,
+    pub window_seconds: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests: 10,
+            window_seconds: 60,
+        }
+    }
+}
+
+/// Fixed window request counter per client IP.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    windows: Mutex<HashMap<Option<IpAddr>, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    /// Past this many tracked clients, expired windows are dropped.
+    const PRUNE_THRESHOLD: usize = 10_000;
+
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request, returning false once the client is over its limit.
+    pub fn check(&self, client: Option<IpAddr>, now: Instant) -> bool {
+        let window = Duration::from_secs(self.config.window_seconds);
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        if windows.len() > Self::PRUNE_THRESHOLD {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+
+        let (start, count) = windows.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= window {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.config.requests
+    }
+}
+
+pub struct RateLimit;
+
+#[rocket::async_trait]
+impl Fairing for RateLimit {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limit login endpoints per client IP",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let config = rocket
+            .figment()
+            .extract_inner::<RateLimitConfig>("rate_limit")
+            .unwrap_or_default();
+        Ok(rocket.manage(Arc::new(RateLimiter::new(config))))
+    }
+}
+
+/*--------------------
+Master keys
+---------------------*/
+use ec_secrets_shared_library::utils::{key_provider, seal};
+
+/// Loads the master keys before the server takes requests, off the async
+/// executor since providers block. Startup fails when they can't be loaded.
+pub struct MasterKeys;
+
+#[rocket::async_trait]
+impl Fairing for MasterKeys {
+    fn info(&self) -> Info {
+        Info {
+            name: "Load master keys from the key provider",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let loaded = tokio::task::spawn_blocking(|| {
+            key_provider::load_keys().map_err(|e| e.to_string())?;
+            // Sealed instances get their master key from Shamir shares instead
+            if !seal::sealed_mode() {
+                seal::master_key().map_err(|e| e.to_string())?;
+            }
+            Ok::<_, String>(())
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+        match loaded {
+            Ok(()) => Ok(rocket),
+            Err(error) => {
+                log::error!("Failed to load the master keys: {error}");
+                Err(rocket)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_per_client_and_resets_after_window() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests: 2,
+            window_seconds: 60,
+        });
+        let client = Some(IpAddr::from([10, 0, 0, 1]));
+        let other = Some(IpAddr::from([10, 0, 0, 2]));
+        let now = Instant::now();
+
+        assert!(limiter.check(client, now));
+        assert!(limiter.check(client, now));
+        assert!(!limiter.check(client, now));
+        assert!(limiter.check(other, now));
+        assert!(limiter.check(client, now + Duration::from_secs(61)));
+    }
+}
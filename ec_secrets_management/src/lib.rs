@@ -4,4 +4,3 @@ pub mod fairings;
 pub mod models;
 pub mod request_guards;
 pub mod routes;
-
@@ -17,6 +17,18 @@ mod request_guards;
 mod routes;
 
 use custom_catchers::*;
+use ec_secrets_shared_library::utils::mailer::mailer_from_env;
+use routes::account::account_routes;
+use routes::app_roles::app_role_routes;
+use routes::e2e::e2e_routes;
+use routes::groups::group_routes;
+use routes::invitations::invitation_routes;
+use routes::mfa::mfa_routes;
+use routes::organizations::organization_routes;
+use routes::policies::policy_routes;
+use routes::seal::seal_routes;
+use routes::service_accounts::service_account_routes;
+use routes::settings::settings_routes;
 use routes::users::user_routes;
 use routes::vault::vault_routes;
 
@@ -38,11 +50,25 @@ fn rocket() -> _ {
     let public_path: PathBuf = current_dir.join("./public");
 
     rocket::build()
+        .attach(fairings::MasterKeys)
         .attach(db::init())
         .attach(fairings::CORS)
+        .attach(fairings::RateLimit)
+        .manage(mailer_from_env().expect("[ECS_MAILER] is invalid"))
         .mount("/", routes![health_check, _options])
         .mount("/", user_routes())
+        .mount("/", account_routes())
+        .mount("/", invitation_routes())
+        .mount("/", group_routes())
+        .mount("/", seal_routes())
+        .mount("/", organization_routes())
+        .mount("/", policy_routes())
         .mount("/", vault_routes())
+        .mount("/", e2e_routes())
+        .mount("/", service_account_routes())
+        .mount("/", app_role_routes())
+        .mount("/", mfa_routes())
+        .mount("/", settings_routes())
         .mount("/", FileServer::from(public_path))
         .register(
             "/",
@@ -1,3 +1,4 @@
+use ec_secrets_shared_library::utils::secret::SecretString;
 use rocket::response::Responder;
 use serde::{Deserialize, Serialize};
 
@@ -10,6 +11,13 @@ pub struct CreateSecretResponse {
     pub message: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateFileResponse {
+    pub status: u16,
+    pub id: String,
+    pub size: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeleteSecretResponse {
     pub status: u16,
@@ -37,6 +45,109 @@ pub struct SetupResponse {
 pub struct LoginResponse {
     pub status: u16,
     pub token: String,
+    /// Set when the token only allows the next MFA step: "challenge" or "enroll"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mfa: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccountResponse {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RegistrationResponse {
+    pub status: u16,
+    /// False when accounts can only be created through invitations
+    pub open: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InvitationResponse {
+    pub id: String,
+    pub email: String,
+    pub role: String,
+    pub invited_by: String,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateInvitationResponse {
+    pub status: u16,
+    pub id: String,
+    pub email: String,
+    pub role: String,
+    /// Also mailed to the invited address, only returned once
+    pub token: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GroupResponse {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Vec<String>,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GroupMemberResponse {
+    pub id: String,
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SealInitResponse {
+    pub status: u16,
+    pub threshold: u8,
+    /// Base64 Shamir shares of the master key, only returned this once
+    pub shares: Vec<SecretString>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OrganizationResponse {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AdoptLegacyResponse {
+    pub status: u16,
+    pub users: u64,
+    pub secrets: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PolicyResponse {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// Rules in their text form, e.g. `read,list payments/*/prod/*`
+    pub rules: Vec<String>,
+    pub users: Vec<String>,
+    pub groups: Vec<String>,
+    pub service_accounts: Vec<String>,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+/// A user as the API shows it, without the password hash or MFA secrets.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserResponse {
+    pub id: String,
+    pub email: String,
+    pub admin: bool,
+    pub mfa_enabled: bool,
+    pub email_verified: bool,
+    pub groups: Vec<String>,
+    pub created_at: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -44,3 +155,105 @@ pub struct DeleteUserResponse {
     pub status: u16,
     pub message: String,
 }
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ServiceAccountResponse {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub created_at: String,
+}
+
+/// Only returned once, the key itself is never stored.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateApiKeyResponse {
+    pub status: u16,
+    pub id: String,
+    pub api_key: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeleteServiceAccountResponse {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AppRoleResponse {
+    pub name: String,
+    pub role_id: String,
+    pub scopes: Vec<String>,
+    pub secret_id_ttl_minutes: i64,
+    pub secret_id_num_uses: Option<i64>,
+    pub token_ttl_minutes: i64,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RoleIdResponse {
+    pub status: u16,
+    pub role_id: String,
+}
+
+/// Only returned once, the secret ID itself is never stored.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SecretIdResponse {
+    pub status: u16,
+    pub secret_id: String,
+    pub uses_remaining: Option<i64>,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeleteAppRoleResponse {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct E2eKeyResponse {
+    pub status: u16,
+    /// Hex key id of the registered public key
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MfaEnrollmentResponse {
+    pub status: u16,
+    pub secret: SecretString,
+    pub otpauth_uri: SecretString,
+}
+
+/// Recovery codes are only returned once. Confirming an enrollment also
+/// returns a session token, which replaces an enrollment-only token.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub status: u16,
+    pub recovery_codes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MfaResponse {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SettingsResponse {
+    pub require_mfa: bool,
+    pub updated_by: Option<String>,
+    pub updated_at: Option<String>,
+}
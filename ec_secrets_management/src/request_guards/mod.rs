@@ -1,4 +1,7 @@
-use ec_secrets_shared_library::utils::auth::decode_keys;
+use ec_secrets_shared_library::utils::auth::{
+    authenticate_api_key, ensure_token_current, has_scope, is_admin, is_api_key,
+    is_machine_identity, pending_mfa, MFA_CHALLENGE,
+};
 use pasetors::{
     claims::{Claims, ClaimsValidationRules},
     public,
@@ -7,48 +10,117 @@ use pasetors::{
     Public,
 };
 use rocket::async_trait;
+use rocket::serde::json::Json;
 use rocket::{
     http::Status,
     request::{FromRequest, Outcome},
     Request, State,
 };
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
-use ec_secrets_shared_library::repositories::keys::KeyRepository;
+use crate::fairings::RateLimiter;
+use crate::models::ErrorResponse;
+use ec_secrets_shared_library::repositories::{
+    keys::KeyRepository, service_accounts::ServiceAccountRepository, users::UserRepository,
+};
 
+/*---------------------------------------------------------------------------
+    Accepts either a PASETO issued by /login (or an exchanged API key), or
+    a service account API key used directly as the bearer credential.
+---------------------------------------------------------------------------*/
 pub struct TokenGuard(pub Claims);
 
-#[async_trait]
-impl<'r> FromRequest<'r> for TokenGuard {
-    type Error = Status;
+impl TokenGuard {
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get_claim("sub").and_then(|subject| subject.as_str())
+    }
 
-    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let key_repo = match request.guard::<&State<Arc<KeyRepository>>>().await {
-            Outcome::Success(state) => state,
-            _ => return Outcome::Forward(Status::InternalServerError),
-        };
+    pub fn service_account(&self) -> Option<&str> {
+        self.0
+            .get_claim("service_account")
+            .and_then(|service_account| service_account.as_str())
+    }
+
+    /// Rejects service account credentials that weren't granted `scope`.
+    pub fn require_scope(&self, scope: &str) -> Result<(), Json<ErrorResponse>> {
+        if has_scope(&self.0, scope) {
+            Ok(())
+        } else {
+            Err(Json(ErrorResponse {
+                status: Status::Forbidden.code,
+                message: format!("Missing required scope '{scope}'"),
+            }))
+        }
+    }
 
-        let auth_header = request.headers().get_one("Authorization");
-
-        match auth_header {
-            Some(token) if token.starts_with("Bearer ") => {
-                let token = token.trim_start_matches("Bearer ").trim();
-                let validation_rules = ClaimsValidationRules::new();
-                if let Ok(untrusted_token) = UntrustedToken::<Public, V4>::try_from(token) {
-                    if let Ok(kp) = decode_keys(key_repo).await {
-                        if let Ok(trusted_token) =
-                            public::verify(&kp.1, &untrusted_token, &validation_rules, None, None)
-                        {
-                            if let Some(claims) = trusted_token.payload_claims() {
-                                Outcome::Success(TokenGuard(claims.clone()))
-                            } else {
-                                Outcome::Error((Status::Unauthorized, Status::Unauthorized))
-                            }
+    /// Rejects everyone but admins.
+    pub fn require_admin(&self) -> Result<(), Json<ErrorResponse>> {
+        if is_admin(&self.0) {
+            Ok(())
+        } else {
+            Err(Json(ErrorResponse {
+                status: Status::Forbidden.code,
+                message: "This operation requires an admin account".to_string(),
+            }))
+        }
+    }
+
+    /// Rejects service account and AppRole credentials for user-only operations.
+    pub fn require_user(&self) -> Result<(), Json<ErrorResponse>> {
+        if is_machine_identity(&self.0) {
+            Err(Json(ErrorResponse {
+                status: Status::Forbidden.code,
+                message: "This operation is not available to machine identities".to_string(),
+            }))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Verified claims of the request's bearer credential, shared by guards.
+---------------------------------------------------------------------------*/
+async fn bearer_claims(request: &Request<'_>) -> Outcome<Claims, Status> {
+    let key_repo = match request.guard::<&State<Arc<KeyRepository>>>().await {
+        Outcome::Success(state) => state,
+        _ => return Outcome::Forward(Status::InternalServerError),
+    };
+
+    let auth_header = request.headers().get_one("Authorization");
+
+    match auth_header {
+        Some(token) if token.starts_with("Bearer ") => {
+            let token = token.trim_start_matches("Bearer ").trim();
+            if is_api_key(token) {
+                let service_account_repo = match request
+                    .guard::<&State<Arc<ServiceAccountRepository>>>()
+                    .await
+                {
+                    Outcome::Success(state) => state,
+                    _ => return Outcome::Forward(Status::InternalServerError),
+                };
+                let user_repo = match request.guard::<&State<Arc<UserRepository>>>().await {
+                    Outcome::Success(state) => state,
+                    _ => return Outcome::Forward(Status::InternalServerError),
+                };
+                return match authenticate_api_key(token, service_account_repo, user_repo).await {
+                    Ok(claims) => Outcome::Success(claims),
+                    Err(_) => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+                };
+            }
+            let validation_rules = ClaimsValidationRules::new();
+            if let Ok(untrusted_token) = UntrustedToken::<Public, V4>::try_from(token) {
+                if let Ok(public_key) = key_repo.public_key().await {
+                    if let Ok(trusted_token) =
+                        public::verify(&public_key, &untrusted_token, &validation_rules, None, None)
+                    {
+                        if let Some(claims) = trusted_token.payload_claims() {
+                            current_claims(request, claims.clone()).await
                         } else {
-                            Outcome::Error((
-                                Status::InternalServerError,
-                                Status::InternalServerError,
-                            ))
+                            Outcome::Error((Status::Unauthorized, Status::Unauthorized))
                         }
                     } else {
                         Outcome::Error((Status::InternalServerError, Status::InternalServerError))
@@ -56,8 +128,91 @@ impl<'r> FromRequest<'r> for TokenGuard {
                 } else {
                     Outcome::Error((Status::InternalServerError, Status::InternalServerError))
                 }
+            } else {
+                Outcome::Error((Status::InternalServerError, Status::InternalServerError))
             }
-            _ => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+        }
+        _ => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+    }
+}
+
+/// Rejects user tokens revoked by a password or email change.
+async fn current_claims(request: &Request<'_>, claims: Claims) -> Outcome<Claims, Status> {
+    let user_repo = match request.guard::<&State<Arc<UserRepository>>>().await {
+        Outcome::Success(state) => state,
+        _ => return Outcome::Forward(Status::InternalServerError),
+    };
+    match ensure_token_current(&claims, user_repo).await {
+        Ok(()) => Outcome::Success(claims),
+        Err(_) => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for TokenGuard {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match bearer_claims(request).await {
+            // Pending MFA tokens are only accepted by the MFA endpoints
+            Outcome::Success(claims) if pending_mfa(&claims).is_some() => {
+                Outcome::Error((Status::Unauthorized, Status::Unauthorized))
+            }
+            Outcome::Success(claims) => Outcome::Success(TokenGuard(claims)),
+            Outcome::Error(error) => Outcome::Error(error),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Accepts user session tokens and the enrollment tokens /login issues to
+    accounts that have to enroll in MFA before they can get a session.
+---------------------------------------------------------------------------*/
+pub struct EnrollmentGuard(pub Claims);
+
+#[async_trait]
+impl<'r> FromRequest<'r> for EnrollmentGuard {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match bearer_claims(request).await {
+            Outcome::Success(claims)
+                if is_machine_identity(&claims) || pending_mfa(&claims) == Some(MFA_CHALLENGE) =>
+            {
+                Outcome::Error((Status::Unauthorized, Status::Unauthorized))
+            }
+            Outcome::Success(claims) => Outcome::Success(EnrollmentGuard(claims)),
+            Outcome::Error(error) => Outcome::Error(error),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Throttles unauthenticated login endpoints per client IP. Rocket
+    resolves the client IP from the configured `ip_header` when present.
+    Over the limit the request fails with 429.
+---------------------------------------------------------------------------*/
+pub struct RateLimitGuard {
+    pub client_ip: Option<IpAddr>,
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for RateLimitGuard {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let limiter = match request.guard::<&State<Arc<RateLimiter>>>().await {
+            Outcome::Success(state) => state,
+            _ => return Outcome::Forward(Status::InternalServerError),
+        };
+
+        let client_ip = request.client_ip();
+        if limiter.check(client_ip, Instant::now()) {
+            Outcome::Success(RateLimitGuard { client_ip })
+        } else {
+            Outcome::Error((Status::TooManyRequests, Status::TooManyRequests))
         }
     }
 }
@@ -0,0 +1,230 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::{RateLimitGuard, TokenGuard};
+use crate::routes::responses::{error_response, internal_error};
+use ec_secrets_shared_library::{
+    models::{EmailChange, EmailVerification, PasswordChange, PasswordReset, PasswordResetRequest},
+    repositories::{
+        keys::KeyRepository, login_attempts::LoginAttemptRepository, policies::PolicyRepository,
+        user_tokens::UserTokenRepository, users::UserRepository,
+    },
+    utils::{
+        account::{self, AccountError},
+        auth::{issue_user_token, token_user},
+        mailer::Mailer,
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::error;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{post, put, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+pub fn account_error(error: AccountError) -> Json<ErrorResponse> {
+    let status = match &error {
+        AccountError::InvalidPassword | AccountError::InvalidToken => Status::Unauthorized,
+        AccountError::Policy(_) => Status::BadRequest,
+        AccountError::EmailTaken | AccountError::AdminExists => Status::Conflict,
+        AccountError::RegistrationClosed => Status::Forbidden,
+        AccountError::Internal(message) => {
+            return internal_error("Account operation", message);
+        }
+    };
+    error_response(status, &error.to_string())
+}
+
+fn account_response(message: &str) -> Json<AccountResponse> {
+    Json(AccountResponse {
+        status: Status::Ok.code,
+        message: message.to_string(),
+    })
+}
+
+/*----------------------------------------------------------
+ Change the password, revoking every token issued so far.
+ The response carries a fresh token for the caller.
+----------------------------------------------------------*/
+#[post("/password/change", data = "<change>")]
+pub async fn change_password(
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    token_repo: &State<Arc<UserTokenRepository>>,
+    token: TokenGuard,
+    change: Json<PasswordChange>,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    let user = token_user(&token.0, repo)
+        .await
+        .map_err(|_| error_response(Status::Unauthorized, "Insufficient Permissions"))?;
+
+    account::change_password(
+        &user,
+        &change.current_password,
+        &change.new_password,
+        repo,
+        token_repo,
+    )
+    .await
+    .map_err(account_error)?;
+
+    let user = token_user(&token.0, repo)
+        .await
+        .map_err(|e| account_error(AccountError::Internal(e)))?;
+    let token = issue_user_token(&user, key_repo)
+        .await
+        .map_err(|e| account_error(AccountError::Internal(e)))?;
+    Ok(Json(LoginResponse {
+        status: Status::Ok.code,
+        token,
+        mfa: None,
+    }))
+}
+
+/*----------------------------------------------------------
+ Change the email address, which has to be verified again
+----------------------------------------------------------*/
+#[put("/update/<id>", data = "<change>")]
+pub async fn update_user(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<UserTokenRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    mailer: &State<Arc<dyn Mailer>>,
+    token: TokenGuard,
+    id: String,
+    change: Json<EmailChange>,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    let user = token_user(&token.0, repo)
+        .await
+        .map_err(|_| error_response(Status::Unauthorized, "Insufficient Permissions"))?;
+    if user.id.to_hex() != id {
+        return Err(error_response(
+            Status::Forbidden,
+            "Users can only update their own account",
+        ));
+    }
+
+    account::change_email(
+        &user,
+        &change.email,
+        &change.current_password,
+        repo,
+        token_repo,
+        policy_repo,
+        mailer.as_ref(),
+    )
+    .await
+    .map_err(account_error)?;
+
+    Ok(account_response(
+        "Email updated, check your inbox to verify the new address and login again",
+    ))
+}
+
+/*----------------------------------------------------------
+ Mail a password reset token. The response is the same
+ whether or not the account exists.
+----------------------------------------------------------*/
+#[post("/password/reset/request", data = "<request>")]
+pub async fn request_password_reset(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<UserTokenRepository>>,
+    mailer: &State<Arc<dyn Mailer>>,
+    request: Json<PasswordResetRequest>,
+    _rate_limit: RateLimitGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    account::request_password_reset(&request.email, repo, token_repo, mailer.as_ref())
+        .await
+        .map_err(account_error)?;
+
+    Ok(account_response(
+        "If the account exists, a password reset token has been sent",
+    ))
+}
+
+/*----------------------------------------------------------
+ Redeem a reset token, also lifting any account lockout
+----------------------------------------------------------*/
+#[post("/password/reset", data = "<reset>")]
+pub async fn reset_password(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<UserTokenRepository>>,
+    attempts_repo: &State<Arc<LoginAttemptRepository>>,
+    reset: Json<PasswordReset>,
+    _rate_limit: RateLimitGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    let user = account::reset_password(&reset.token, &reset.new_password, repo, token_repo)
+        .await
+        .map_err(account_error)?;
+    if let Err(e) = attempts_repo.record_success(&user.email).await {
+        error!("Failed to clear login failures: {:?}", e);
+    }
+
+    Ok(account_response("Password reset successfully"))
+}
+
+/*-------------------------------
+ Redeem an email verification token
+-------------------------------*/
+#[post("/email/verify", data = "<verification>")]
+pub async fn verify_email(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<UserTokenRepository>>,
+    verification: Json<EmailVerification>,
+    _rate_limit: RateLimitGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    account::verify_email(&verification.token, repo, token_repo)
+        .await
+        .map_err(account_error)?;
+
+    Ok(account_response("Email verified successfully"))
+}
+
+/*-------------------------------
+ Mail a new verification token
+-------------------------------*/
+#[post("/email/verify/resend")]
+pub async fn resend_verification(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<UserTokenRepository>>,
+    mailer: &State<Arc<dyn Mailer>>,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    let user = token_user(&token.0, repo)
+        .await
+        .map_err(|_| error_response(Status::Unauthorized, "Insufficient Permissions"))?;
+    if user.email_verified {
+        return Err(error_response(
+            Status::Conflict,
+            "The email address is already verified",
+        ));
+    }
+
+    account::send_email_verification(&user, token_repo, mailer.as_ref())
+        .await
+        .map_err(account_error)?;
+
+    Ok(account_response("Verification email sent"))
+}
+
+pub fn account_routes() -> Vec<rocket::Route> {
+    routes![
+        change_password,
+        update_user,
+        request_password_reset,
+        reset_password,
+        verify_email,
+        resend_verification
+    ]
+}
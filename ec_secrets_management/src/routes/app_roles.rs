@@ -0,0 +1,214 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::{RateLimitGuard, TokenGuard};
+use crate::routes::responses::{error_response, internal_error, owner};
+use ec_secrets_shared_library::{
+    models::{AppRole, AppRoleCredentials, AppRoleDocument},
+    repositories::{app_roles::AppRoleRepository, keys::KeyRepository, users::UserRepository},
+    utils::auth::{authenticate_app_role, sign_claims, token_org, SCOPES},
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::{error, info};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+async fn owned_role(
+    repo: &AppRoleRepository,
+    name: &str,
+    owner: &str,
+) -> Result<AppRoleDocument, Json<ErrorResponse>> {
+    match repo.get_role(name, owner).await {
+        Ok(Some(role)) => Ok(role),
+        Ok(None) => Err(error_response(Status::NotFound, "AppRole not found")),
+        Err(e) => Err(internal_error("AppRole operation", e)),
+    }
+}
+
+impl From<&AppRoleDocument> for AppRoleResponse {
+    fn from(role: &AppRoleDocument) -> Self {
+        Self {
+            name: role.name.clone(),
+            role_id: role.role_id.clone(),
+            scopes: role.scopes.clone(),
+            secret_id_ttl_minutes: role.secret_id_ttl_minutes,
+            secret_id_num_uses: role.secret_id_num_uses,
+            token_ttl_minutes: role.token_ttl_minutes,
+            created_at: role.created_at.to_rfc3339(),
+        }
+    }
+}
+
+/*-----------------
+ Create an AppRole
+-----------------*/
+#[post("/approles", data = "<role>")]
+pub async fn create_role(
+    repo: &State<Arc<AppRoleRepository>>,
+    role: Json<AppRole>,
+    token: TokenGuard,
+) -> Result<Json<AppRoleResponse>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+
+    if let Some(scope) = role
+        .scopes
+        .iter()
+        .find(|scope| !SCOPES.contains(&scope.as_str()))
+    {
+        return Err(error_response(
+            Status::BadRequest,
+            &format!("Unknown scope '{scope}'"),
+        ));
+    }
+    let positive = |value: Option<i64>| value.is_none_or(|value| value > 0);
+    if !positive(role.secret_id_ttl_minutes)
+        || !positive(role.token_ttl_minutes)
+        || role.secret_id_num_uses.is_some_and(|uses| uses < 0)
+    {
+        return Err(error_response(
+            Status::BadRequest,
+            "TTLs must be positive and secret_id_num_uses can't be negative",
+        ));
+    }
+
+    match repo.create_role(&role, owner, token_org(&token.0)).await {
+        Ok(role) => {
+            info!("AppRole created successfully.");
+            Ok(Json(AppRoleResponse::from(&role)))
+        }
+        Err(e) if matches!(e.kind.as_ref(), mongodb::error::ErrorKind::Io(_)) => Err(
+            error_response(Status::Conflict, "An AppRole with this name already exists"),
+        ),
+        Err(e) => Err(internal_error("AppRole operation", e)),
+    }
+}
+
+/*------------------
+ List owned AppRoles
+-------------------*/
+#[get("/approles")]
+pub async fn list_roles(
+    repo: &State<Arc<AppRoleRepository>>,
+    token: TokenGuard,
+) -> Result<Json<Vec<AppRoleResponse>>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    match repo.list_roles(owner).await {
+        Ok(roles) => Ok(Json(roles.iter().map(AppRoleResponse::from).collect())),
+        Err(e) => Err(internal_error("AppRole operation", e)),
+    }
+}
+
+/*-------------------------------------------
+ Delete an AppRole and all its secret IDs
+-------------------------------------------*/
+#[delete("/approles/<name>")]
+pub async fn delete_role(
+    repo: &State<Arc<AppRoleRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<DeleteAppRoleResponse>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    match repo.delete_role(name, owner).await {
+        Ok(Some(_)) => Ok(Json(DeleteAppRoleResponse {
+            status: Status::Ok.code,
+            message: "AppRole deleted successfully".to_string(),
+        })),
+        Ok(None) => Err(error_response(Status::NotFound, "AppRole not found")),
+        Err(e) => Err(internal_error("AppRole operation", e)),
+    }
+}
+
+/*--------------------------
+ Read an AppRole's role ID
+--------------------------*/
+#[get("/approles/<name>/role-id")]
+pub async fn read_role_id(
+    repo: &State<Arc<AppRoleRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<RoleIdResponse>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    let role = owned_role(repo, name, owner).await?;
+    Ok(Json(RoleIdResponse {
+        status: Status::Ok.code,
+        role_id: role.role_id,
+    }))
+}
+
+/*-----------------------------
+ Issue a secret ID for a role
+-----------------------------*/
+#[post("/approles/<name>/secret-id")]
+pub async fn create_secret_id(
+    repo: &State<Arc<AppRoleRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<SecretIdResponse>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    let role = owned_role(repo, name, owner).await?;
+    match repo.create_secret_id(&role).await {
+        Ok((document, secret_id)) => Ok(Json(SecretIdResponse {
+            status: Status::Ok.code,
+            secret_id,
+            uses_remaining: document.uses_remaining,
+            expires_at: document.expires_at.to_rfc3339(),
+        })),
+        Err(e) => Err(internal_error("AppRole operation", e)),
+    }
+}
+
+/*------------------------------------------
+ Exchange a role ID / secret ID for a token
+------------------------------------------*/
+#[post("/approles/login", data = "<credentials>")]
+pub async fn login(
+    repo: &State<Arc<AppRoleRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    credentials: Json<AppRoleCredentials>,
+    _rate_limit: RateLimitGuard,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    let claims = authenticate_app_role(
+        &credentials.role_id,
+        &credentials.secret_id,
+        repo,
+        user_repo,
+    )
+    .await
+    .map_err(|_| error_response(Status::Unauthorized, "Invalid role ID or secret ID"))?;
+    match sign_claims(&claims, key_repo).await {
+        Ok(token) => Ok(Json(LoginResponse {
+            status: Status::Ok.code,
+            token,
+            mfa: None,
+        })),
+        Err(e) => {
+            error!("Failed to sign token: {e}");
+            Err(error_response(
+                Status::InternalServerError,
+                "Internal server error",
+            ))
+        }
+    }
+}
+
+pub fn app_role_routes() -> Vec<rocket::Route> {
+    routes![
+        create_role,
+        list_roles,
+        delete_role,
+        read_role_id,
+        create_secret_id,
+        login
+    ]
+}
@@ -0,0 +1,128 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::TokenGuard;
+use crate::routes::policies::{authorized_vault, policy_error};
+use crate::routes::responses::{error_response, internal_error};
+use ec_secrets_shared_library::{
+    models::{E2ePublicKey, E2eRecipient, Secret},
+    repositories::{
+        groups::GroupRepository, organizations::OrganizationRepository, policies::PolicyRepository,
+        users::UserRepository, vault::VaultRepository,
+    },
+    utils::{
+        auth::{token_org, token_user, SCOPE_SECRETS_WRITE},
+        recipients::{self, RecipientError},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::info;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, post, put, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+pub fn recipient_error(error: RecipientError) -> Json<ErrorResponse> {
+    let status = match &error {
+        RecipientError::InvalidKey | RecipientError::Empty => Status::BadRequest,
+        RecipientError::NotFound(_) | RecipientError::GroupNotFound(_) => Status::NotFound,
+        RecipientError::MissingKeys(_) => Status::Conflict,
+        RecipientError::Internal(message) => {
+            return internal_error("Recipient lookup", message);
+        }
+    };
+    error_response(status, &error.to_string())
+}
+
+/*---------------------------------------------
+ Register the caller's public key for end-to-end
+ encrypted secrets. The private key stays with
+ the caller's clients.
+----------------------------------------------*/
+#[post("/e2e/key", data = "<key>")]
+pub async fn register_key(
+    repo: &State<Arc<UserRepository>>,
+    token: TokenGuard,
+    key: Json<E2ePublicKey>,
+) -> Result<Json<E2eKeyResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    let user = token_user(&token.0, repo)
+        .await
+        .map_err(|_| error_response(Status::Unauthorized, "Insufficient Permissions"))?;
+    let public_key = recipients::register_public_key(&user, &key.public_key, repo)
+        .await
+        .map_err(recipient_error)?;
+    info!("End-to-end encryption key registered");
+    Ok(Json(E2eKeyResponse {
+        status: Status::Ok.code,
+        fingerprint: public_key.fingerprint(),
+    }))
+}
+
+/*---------------------------------------------
+ Public keys to seal a value for: members of the
+ caller's organization, by email or group
+----------------------------------------------*/
+#[get("/e2e/recipients?<email>&<group>")]
+pub async fn list_recipients(
+    repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
+    token: TokenGuard,
+    email: Vec<String>,
+    group: Vec<String>,
+) -> Result<Json<Vec<E2eRecipient>>, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_WRITE)?;
+    let Some(org) = token_org(&token.0) else {
+        return Err(error_response(
+            Status::Forbidden,
+            "Access denied: the token has no active organization",
+        ));
+    };
+    let recipients = recipients::find_recipients(&org, &email, &group, repo, group_repo)
+        .await
+        .map_err(recipient_error)?;
+    Ok(Json(recipients))
+}
+
+/*---------------------------------------------
+ Replace the sealed value of one of the caller's
+ end-to-end encrypted entries, e.g. to add
+ recipients
+----------------------------------------------*/
+#[put("/e2e/vault/entry", data = "<secret>")]
+pub async fn reseal_entry(
+    repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
+    secret: Json<Secret>,
+    token: TokenGuard,
+) -> Result<Json<CreateSecretResponse>, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_WRITE)?;
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    match vault.reseal_secret(&secret.key, &secret.value).await {
+        Ok(true) => Ok(Json(CreateSecretResponse {
+            status: Status::Ok.code,
+            message: "Vault entry updated successfully".to_string(),
+        })),
+        Ok(false) => Err(error_response(
+            Status::NotFound,
+            &format!("You have no secret '{}'", secret.key),
+        )),
+        Err(e) => Err(policy_error(e)),
+    }
+}
+
+pub fn e2e_routes() -> Vec<rocket::Route> {
+    routes![register_key, list_recipients, reseal_entry]
+}
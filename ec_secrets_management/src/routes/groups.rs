@@ -0,0 +1,277 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::TokenGuard;
+use crate::routes::policies::{authorized_vault, policy_error};
+use crate::routes::responses::{error_response, internal_error};
+use ec_secrets_shared_library::{
+    models::{Group, GroupDocument, GroupMember, GroupPermissions, SharedSecret, UserDocument},
+    repositories::{
+        groups::GroupRepository, organizations::OrganizationRepository, policies::PolicyRepository,
+        users::UserRepository, vault::VaultRepository,
+    },
+    utils::{
+        auth::SCOPE_SECRETS_WRITE,
+        groups::{self, GroupError},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::info;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, put, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+impl From<&GroupDocument> for GroupResponse {
+    fn from(group: &GroupDocument) -> Self {
+        Self {
+            id: group.id.to_hex(),
+            name: group.name.clone(),
+            description: group.description.clone(),
+            permissions: group.permissions.clone(),
+            created_by: group.created_by.clone(),
+            created_at: group.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl From<&UserDocument> for GroupMemberResponse {
+    fn from(user: &UserDocument) -> Self {
+        Self {
+            id: user.id.to_hex(),
+            email: user.email.clone(),
+        }
+    }
+}
+
+pub fn group_error(error: GroupError) -> Json<ErrorResponse> {
+    let status = match &error {
+        GroupError::NotFound(_) | GroupError::UserNotFound(_) | GroupError::SecretNotFound(_) => {
+            Status::NotFound
+        }
+        GroupError::Exists | GroupError::NotMember(_) => Status::Conflict,
+        GroupError::Invalid(_) => Status::BadRequest,
+        GroupError::Internal(message) => {
+            return internal_error("Group operation", message);
+        }
+    };
+    error_response(status, &error.to_string())
+}
+
+fn group_response(message: &str) -> Json<AccountResponse> {
+    Json(AccountResponse {
+        status: Status::Ok.code,
+        message: message.to_string(),
+    })
+}
+
+/*---------------------------------------------
+ Create a group, admins only
+----------------------------------------------*/
+#[post("/groups", data = "<group>")]
+pub async fn create_group(
+    repo: &State<Arc<GroupRepository>>,
+    group: Json<Group>,
+    token: TokenGuard,
+) -> Result<Json<GroupResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let created_by = token.subject().unwrap_or_default();
+    let group = groups::create_group(&group, created_by, repo)
+        .await
+        .map_err(group_error)?;
+
+    info!("Group created successfully.");
+    Ok(Json(GroupResponse::from(&group)))
+}
+
+/*---------------------------------------------
+ List groups, admins only
+----------------------------------------------*/
+#[get("/groups")]
+pub async fn list_groups(
+    repo: &State<Arc<GroupRepository>>,
+    token: TokenGuard,
+) -> Result<Json<Vec<GroupResponse>>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    match repo.list_groups().await {
+        Ok(groups) => Ok(Json(groups.iter().map(GroupResponse::from).collect())),
+        Err(e) => Err(group_error(e.into())),
+    }
+}
+
+/*---------------------------------------------
+ Replace a group's permissions, admins only
+----------------------------------------------*/
+#[put("/groups/<name>/permissions", data = "<permissions>")]
+pub async fn set_permissions(
+    repo: &State<Arc<GroupRepository>>,
+    name: &str,
+    permissions: Json<GroupPermissions>,
+    token: TokenGuard,
+) -> Result<Json<GroupResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let group = groups::set_permissions(name, &permissions.permissions, repo)
+        .await
+        .map_err(group_error)?;
+    Ok(Json(GroupResponse::from(&group)))
+}
+
+/*---------------------------------------------
+ Delete a group, its memberships and policy
+ attachments, admins only
+----------------------------------------------*/
+#[delete("/groups/<name>")]
+pub async fn delete_group(
+    repo: &State<Arc<GroupRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    groups::delete_group(name, repo, user_repo, vault_repo, policy_repo)
+        .await
+        .map_err(group_error)?;
+    Ok(group_response("Group deleted successfully"))
+}
+
+/*---------------------------------------------
+ List the members of a group, admins only
+----------------------------------------------*/
+#[get("/groups/<name>/members")]
+pub async fn list_members(
+    repo: &State<Arc<GroupRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<Vec<GroupMemberResponse>>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let members = groups::list_members(name, repo, user_repo)
+        .await
+        .map_err(group_error)?;
+    Ok(Json(
+        members.iter().map(GroupMemberResponse::from).collect(),
+    ))
+}
+
+/*---------------------------------------------
+ Add a member to a group, admins only
+----------------------------------------------*/
+#[post("/groups/<name>/members", data = "<member>")]
+pub async fn add_member(
+    repo: &State<Arc<GroupRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    name: &str,
+    member: Json<GroupMember>,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    groups::add_member(name, &member.email, repo, user_repo)
+        .await
+        .map_err(group_error)?;
+    Ok(group_response(
+        "Member added, the group applies from the member's next login",
+    ))
+}
+
+/*---------------------------------------------
+ Remove a member from a group, revoking the
+ member's tokens. Admins only.
+----------------------------------------------*/
+#[delete("/groups/<name>/members/<email>")]
+pub async fn remove_member(
+    repo: &State<Arc<GroupRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    name: &str,
+    email: &str,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    groups::remove_member(name, email, repo, user_repo)
+        .await
+        .map_err(group_error)?;
+    Ok(group_response("Member removed successfully"))
+}
+
+/*---------------------------------------------
+ Share one of the caller's vault entries with
+ a group
+----------------------------------------------*/
+#[post("/groups/<name>/secrets", data = "<secret>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn share_secret(
+    repo: &State<Arc<GroupRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    name: &str,
+    secret: Json<SharedSecret>,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    token.require_scope(SCOPE_SECRETS_WRITE)?;
+    groups::get_group(name, repo).await.map_err(group_error)?;
+    let vault =
+        authorized_vault(&token, vault_repo, org_repo, policy_repo, repo, user_repo).await?;
+    if !vault
+        .share_secret(&secret.key, name)
+        .await
+        .map_err(policy_error)?
+    {
+        return Err(group_error(GroupError::SecretNotFound(secret.key.clone())));
+    }
+    Ok(group_response("Vault entry shared successfully"))
+}
+
+/*---------------------------------------------
+ Stop sharing a vault entry with a group
+----------------------------------------------*/
+#[delete("/groups/<name>/secrets?<key>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn unshare_secret(
+    repo: &State<Arc<GroupRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    name: &str,
+    key: &str,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    token.require_scope(SCOPE_SECRETS_WRITE)?;
+    let vault =
+        authorized_vault(&token, vault_repo, org_repo, policy_repo, repo, user_repo).await?;
+    if !vault
+        .unshare_secret(key, name)
+        .await
+        .map_err(policy_error)?
+    {
+        return Err(group_error(GroupError::SecretNotFound(key.to_string())));
+    }
+    Ok(group_response("Vault entry unshared successfully"))
+}
+
+pub fn group_routes() -> Vec<rocket::Route> {
+    routes![
+        create_group,
+        list_groups,
+        set_permissions,
+        delete_group,
+        list_members,
+        add_member,
+        remove_member,
+        share_secret,
+        unshare_secret
+    ]
+}
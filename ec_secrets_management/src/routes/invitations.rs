@@ -0,0 +1,149 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::{RateLimitGuard, TokenGuard};
+use crate::routes::account::account_error;
+use crate::routes::responses::{error_response, internal_error, parse_id};
+use ec_secrets_shared_library::{
+    models::{Invitation, InvitationAcceptance, InvitationDocument},
+    repositories::{invitations::InvitationRepository, users::UserRepository},
+    utils::{
+        account::{self, registration_open},
+        mailer::Mailer,
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::info;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+impl From<&InvitationDocument> for InvitationResponse {
+    fn from(invitation: &InvitationDocument) -> Self {
+        Self {
+            id: invitation.id.to_hex(),
+            email: invitation.email.clone(),
+            role: invitation.role.as_str().to_string(),
+            invited_by: invitation.invited_by.clone(),
+            expires_at: invitation.expires_at.to_rfc3339(),
+            created_at: invitation.created_at.to_rfc3339(),
+        }
+    }
+}
+
+/*---------------------------------------------
+ Whether /setup accepts self-registration
+----------------------------------------------*/
+#[get("/registration")]
+pub async fn registration() -> Json<RegistrationResponse> {
+    Json(RegistrationResponse {
+        status: Status::Ok.code,
+        open: registration_open(),
+    })
+}
+
+/*---------------------------------------------
+ Invite an address, admins only
+----------------------------------------------*/
+#[post("/invitations", data = "<invitation>")]
+pub async fn create_invitation(
+    repo: &State<Arc<InvitationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    mailer: &State<Arc<dyn Mailer>>,
+    invitation: Json<Invitation>,
+    token: TokenGuard,
+) -> Result<Json<CreateInvitationResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let invited_by = token.subject().unwrap_or_default();
+    let (invitation, invitation_token) =
+        account::invite_user(&invitation, invited_by, user_repo, repo, mailer.as_ref())
+            .await
+            .map_err(account_error)?;
+
+    info!("Invitation created successfully.");
+    Ok(Json(CreateInvitationResponse {
+        status: Status::Ok.code,
+        id: invitation.id.to_hex(),
+        email: invitation.email,
+        role: invitation.role.as_str().to_string(),
+        token: invitation_token,
+        expires_at: invitation.expires_at.to_rfc3339(),
+    }))
+}
+
+/*---------------------------------------------
+ List invitations, admins only
+----------------------------------------------*/
+#[get("/invitations")]
+pub async fn list_invitations(
+    repo: &State<Arc<InvitationRepository>>,
+    token: TokenGuard,
+) -> Result<Json<Vec<InvitationResponse>>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    match repo.list_invitations().await {
+        Ok(invitations) => Ok(Json(
+            invitations.iter().map(InvitationResponse::from).collect(),
+        )),
+        Err(e) => Err(internal_error("Invitation operation", e)),
+    }
+}
+
+/*---------------------------------------------
+ Revoke an invitation, admins only
+----------------------------------------------*/
+#[delete("/invitations/<id>")]
+pub async fn revoke_invitation(
+    repo: &State<Arc<InvitationRepository>>,
+    id: &str,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let id = parse_id(id)?;
+    match repo.delete_invitation(&id).await {
+        Ok(Some(_)) => Ok(Json(AccountResponse {
+            status: Status::Ok.code,
+            message: "Invitation revoked successfully".to_string(),
+        })),
+        Ok(None) => Err(error_response(Status::NotFound, "Invitation not found")),
+        Err(e) => Err(internal_error("Invitation operation", e)),
+    }
+}
+
+/*---------------------------------------------
+ Accept an invitation, creating the account
+----------------------------------------------*/
+#[post("/invitations/accept", data = "<acceptance>")]
+pub async fn accept_invitation(
+    repo: &State<Arc<InvitationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    acceptance: Json<InvitationAcceptance>,
+    _rate_limit: RateLimitGuard,
+) -> Result<Json<SetupResponse>, Json<ErrorResponse>> {
+    account::accept_invitation(&acceptance.token, &acceptance.password, user_repo, repo)
+        .await
+        .map_err(account_error)?;
+
+    Ok(Json(SetupResponse {
+        status: Status::Ok.code,
+        message: "User registered successfully".to_string(),
+    }))
+}
+
+pub fn invitation_routes() -> Vec<rocket::Route> {
+    routes![
+        registration,
+        create_invitation,
+        list_invitations,
+        revoke_invitation,
+        accept_invitation
+    ]
+}
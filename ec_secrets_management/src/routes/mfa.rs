@@ -0,0 +1,213 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::{EnrollmentGuard, RateLimitGuard, TokenGuard};
+use crate::routes::responses::{error_response, internal_error};
+use crate::routes::users::{account_locked, record_login_failure};
+use ec_secrets_shared_library::{
+    models::{MfaCode, MfaLogin, UserDocument},
+    repositories::{
+        keys::KeyRepository, login_attempts::LoginAttemptRepository, settings::SettingsRepository,
+        users::UserRepository,
+    },
+    utils::{
+        auth::{complete_mfa_login, issue_user_token, token_user, verify_token},
+        mfa,
+    },
+};
+use pasetors::claims::Claims;
+
+/*-------------
+3rd party modules
+--------------*/
+use log::{error, info};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+async fn current_user(
+    repo: &UserRepository,
+    claims: &Claims,
+) -> Result<UserDocument, Json<ErrorResponse>> {
+    token_user(claims, repo)
+        .await
+        .map_err(|_| error_response(Status::Unauthorized, "Insufficient Permissions"))
+}
+
+/*------------------------------------------------
+ Second login step, exchange a challenge token
+------------------------------------------------*/
+#[post("/login/mfa", data = "<login>")]
+pub async fn login_mfa(
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    attempts_repo: &State<Arc<LoginAttemptRepository>>,
+    login: Json<MfaLogin>,
+    rate_limit: RateLimitGuard,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    let invalid = || error_response(Status::Unauthorized, "Invalid MFA token or code");
+
+    // Wrong codes count towards the account's lockout like wrong passwords
+    let claims = verify_token(&login.mfa_token, key_repo)
+        .await
+        .map_err(|_| invalid())?;
+    let account = claims
+        .get_claim("sub")
+        .and_then(|subject| subject.as_str())
+        .ok_or_else(invalid)?;
+    match attempts_repo.locked_until(account).await {
+        Ok(Some(locked_until)) => return Err(account_locked(locked_until)),
+        Ok(None) => {}
+        Err(e) => return Err(internal_error("MFA operation", e)),
+    }
+
+    match complete_mfa_login(&login.mfa_token, &login.code, repo, key_repo).await {
+        Ok(token) => {
+            if let Err(e) = attempts_repo.record_success(account).await {
+                error!("Failed to clear login failures: {:?}", e);
+            }
+            Ok(Json(LoginResponse {
+                status: Status::Ok.code,
+                token,
+                mfa: None,
+            }))
+        }
+        Err(_) => {
+            record_login_failure(attempts_repo, account, &rate_limit).await;
+            Err(invalid())
+        }
+    }
+}
+
+/*-------------------------
+ Start TOTP enrollment
+-------------------------*/
+#[post("/mfa/enroll")]
+pub async fn enroll(
+    repo: &State<Arc<UserRepository>>,
+    token: EnrollmentGuard,
+) -> Result<Json<MfaEnrollmentResponse>, Json<ErrorResponse>> {
+    let user = current_user(repo, &token.0).await?;
+    if user.mfa_enabled() {
+        return Err(error_response(
+            Status::Conflict,
+            "MFA is already enabled for this account",
+        ));
+    }
+    match mfa::begin_enrollment(repo, &user).await {
+        Ok(enrollment) => Ok(Json(MfaEnrollmentResponse {
+            status: Status::Ok.code,
+            secret: enrollment.secret,
+            otpauth_uri: enrollment.otpauth_uri,
+        })),
+        Err(e) => Err(internal_error("MFA operation", e)),
+    }
+}
+
+/*----------------------------------------------------------
+ Verify the first code, enabling MFA and returning recovery
+ codes along with a full session token
+----------------------------------------------------------*/
+#[post("/mfa/enroll/verify", data = "<code>")]
+pub async fn verify_enrollment(
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    code: Json<MfaCode>,
+    token: EnrollmentGuard,
+) -> Result<Json<RecoveryCodesResponse>, Json<ErrorResponse>> {
+    let user = current_user(repo, &token.0).await?;
+    let recovery_codes = mfa::confirm_enrollment(repo, &user, &code.code)
+        .await
+        .map_err(|e| error_response(Status::BadRequest, &e))?;
+    info!("MFA enabled.");
+
+    let token = issue_user_token(&user, key_repo)
+        .await
+        .map_err(|e| internal_error("MFA operation", e))?;
+    Ok(Json(RecoveryCodesResponse {
+        status: Status::Ok.code,
+        recovery_codes,
+        token: Some(token),
+    }))
+}
+
+/*--------------------------------
+ Replace the recovery codes
+--------------------------------*/
+#[post("/mfa/recovery-codes", data = "<code>")]
+pub async fn regenerate_recovery_codes(
+    repo: &State<Arc<UserRepository>>,
+    code: Json<MfaCode>,
+    token: TokenGuard,
+) -> Result<Json<RecoveryCodesResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    let user = current_user(repo, &token.0).await?;
+    if !user.mfa_enabled() {
+        return Err(error_response(
+            Status::BadRequest,
+            "MFA is not enabled for this account",
+        ));
+    }
+    match mfa::regenerate_recovery_codes(repo, &user, &code.code).await {
+        Ok(recovery_codes) => Ok(Json(RecoveryCodesResponse {
+            status: Status::Ok.code,
+            recovery_codes,
+            token: None,
+        })),
+        Err(e) => Err(error_response(Status::Unauthorized, &e)),
+    }
+}
+
+/*------------------------------------------------------
+ Disable MFA, unless an admin requires it for everyone
+------------------------------------------------------*/
+#[delete("/mfa", data = "<code>")]
+pub async fn disable(
+    repo: &State<Arc<UserRepository>>,
+    settings_repo: &State<Arc<SettingsRepository>>,
+    code: Json<MfaCode>,
+    token: TokenGuard,
+) -> Result<Json<MfaResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    let settings = settings_repo
+        .get_settings()
+        .await
+        .map_err(|e| internal_error("MFA operation", e))?;
+    if settings.require_mfa {
+        return Err(error_response(
+            Status::Forbidden,
+            "MFA is required for all accounts",
+        ));
+    }
+
+    let user = current_user(repo, &token.0).await?;
+    if !user.mfa_enabled() {
+        return Err(error_response(
+            Status::BadRequest,
+            "MFA is not enabled for this account",
+        ));
+    }
+    match mfa::disable(repo, &user, &code.code).await {
+        Ok(()) => Ok(Json(MfaResponse {
+            status: Status::Ok.code,
+            message: "MFA disabled successfully".to_string(),
+        })),
+        Err(e) => Err(error_response(Status::Unauthorized, &e)),
+    }
+}
+
+pub fn mfa_routes() -> Vec<rocket::Route> {
+    routes![
+        login_mfa,
+        enroll,
+        verify_enrollment,
+        regenerate_recovery_codes,
+        disable
+    ]
+}
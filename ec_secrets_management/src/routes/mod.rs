@@ -1,2 +1,14 @@
+pub mod account;
+pub mod app_roles;
+pub mod e2e;
+pub mod groups;
+pub mod invitations;
+pub mod mfa;
+pub mod organizations;
+pub mod policies;
+pub mod responses;
+pub mod seal;
+pub mod service_accounts;
+pub mod settings;
 pub mod users;
 pub mod vault;
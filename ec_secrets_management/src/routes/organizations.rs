@@ -0,0 +1,253 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::TokenGuard;
+use crate::routes::responses::{error_response, internal_error};
+use ec_secrets_shared_library::{
+    models::{Organization, OrganizationDocument, OrganizationMember},
+    repositories::{
+        keys::KeyRepository, organizations::OrganizationRepository, users::UserRepository,
+        vault::VaultRepository,
+    },
+    utils::{
+        auth::{is_admin, token_user},
+        organizations::{self, OrganizationError},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::info;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+impl From<&OrganizationDocument> for OrganizationResponse {
+    fn from(org: &OrganizationDocument) -> Self {
+        Self {
+            id: org.id.to_hex(),
+            name: org.name.clone(),
+            description: org.description.clone(),
+            created_by: org.created_by.clone(),
+            created_at: org.created_at.to_rfc3339(),
+        }
+    }
+}
+
+pub fn organization_error(error: OrganizationError) -> Json<ErrorResponse> {
+    let status = match &error {
+        OrganizationError::NotFound(_) | OrganizationError::UserNotFound(_) => Status::NotFound,
+        OrganizationError::Exists | OrganizationError::NotMember(_) => Status::Conflict,
+        OrganizationError::Invalid(_) => Status::BadRequest,
+        OrganizationError::Sealed => Status::ServiceUnavailable,
+        OrganizationError::Internal(message) => {
+            return internal_error("Organization operation", message);
+        }
+    };
+    error_response(status, &error.to_string())
+}
+
+fn organization_response(message: &str) -> Json<AccountResponse> {
+    Json(AccountResponse {
+        status: Status::Ok.code,
+        message: message.to_string(),
+    })
+}
+
+/*---------------------------------------------
+ Create an organization, admins only. The
+ creator becomes its first member.
+----------------------------------------------*/
+#[post("/orgs", data = "<org>")]
+pub async fn create_organization(
+    repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    org: Json<Organization>,
+    token: TokenGuard,
+) -> Result<Json<OrganizationResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let created_by = token.subject().unwrap_or_default();
+    let org = organizations::create_organization(&org, created_by, repo, user_repo, vault_repo)
+        .await
+        .map_err(organization_error)?;
+
+    info!("Organization created successfully.");
+    Ok(Json(OrganizationResponse::from(&org)))
+}
+
+/*---------------------------------------------
+ List organizations: every one for admins, the
+ caller's own for everyone else
+----------------------------------------------*/
+#[get("/orgs")]
+pub async fn list_organizations(
+    repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    token: TokenGuard,
+) -> Result<Json<Vec<OrganizationResponse>>, Json<ErrorResponse>> {
+    token.require_user()?;
+    let orgs = if is_admin(&token.0) {
+        repo.list_organizations()
+            .await
+            .map_err(|e| organization_error(e.into()))?
+    } else {
+        let user = token_user(&token.0, user_repo)
+            .await
+            .map_err(|_| error_response(Status::Unauthorized, "Insufficient Permissions"))?;
+        organizations::user_organizations(&user, repo)
+            .await
+            .map_err(organization_error)?
+    };
+    Ok(Json(orgs.iter().map(OrganizationResponse::from).collect()))
+}
+
+/*---------------------------------------------
+ Delete an organization and every entry in its
+ vault, admins only
+----------------------------------------------*/
+#[delete("/orgs/<name>")]
+pub async fn delete_organization(
+    repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    organizations::delete_organization(name, repo, user_repo, vault_repo)
+        .await
+        .map_err(organization_error)?;
+    Ok(organization_response("Organization deleted successfully"))
+}
+
+/*---------------------------------------------
+ List the members of an organization, admins
+ only
+----------------------------------------------*/
+#[get("/orgs/<name>/members")]
+pub async fn list_members(
+    repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<Vec<GroupMemberResponse>>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let members = organizations::list_members(name, repo, user_repo)
+        .await
+        .map_err(organization_error)?;
+    Ok(Json(
+        members.iter().map(GroupMemberResponse::from).collect(),
+    ))
+}
+
+/*---------------------------------------------
+ Add a member to an organization, admins only
+----------------------------------------------*/
+#[post("/orgs/<name>/members", data = "<member>")]
+pub async fn add_member(
+    repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    name: &str,
+    member: Json<OrganizationMember>,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    organizations::add_member(name, &member.email, repo, user_repo)
+        .await
+        .map_err(organization_error)?;
+    Ok(organization_response("Member added successfully"))
+}
+
+/*---------------------------------------------
+ Remove a member from an organization,
+ revoking the member's tokens. Admins only.
+----------------------------------------------*/
+#[delete("/orgs/<name>/members/<email>")]
+pub async fn remove_member(
+    repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    name: &str,
+    email: &str,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    organizations::remove_member(name, email, repo, user_repo)
+        .await
+        .map_err(organization_error)?;
+    Ok(organization_response("Member removed successfully"))
+}
+
+/*---------------------------------------------
+ Switch the caller's active organization,
+ returns a token carrying the new one
+----------------------------------------------*/
+#[post("/orgs/<name>/switch")]
+pub async fn switch_organization(
+    repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    token.require_user()?;
+    let user = token_user(&token.0, user_repo)
+        .await
+        .map_err(|_| error_response(Status::Unauthorized, "Insufficient Permissions"))?;
+    let token = organizations::switch_organization(name, &user, repo, key_repo)
+        .await
+        .map_err(organization_error)?;
+    Ok(Json(LoginResponse {
+        status: Status::Ok.code,
+        token,
+        mfa: None,
+    }))
+}
+
+/*---------------------------------------------
+ Move users and vault entries from before
+ organizations existed into one, admins only
+----------------------------------------------*/
+#[post("/orgs/<name>/adopt-legacy")]
+pub async fn adopt_legacy(
+    repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<AdoptLegacyResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let adopted = organizations::adopt_legacy(name, repo, user_repo, vault_repo)
+        .await
+        .map_err(organization_error)?;
+
+    info!(
+        "Moved {} users and {} vault entries into organization {name}.",
+        adopted.users, adopted.secrets
+    );
+    Ok(Json(AdoptLegacyResponse {
+        status: Status::Ok.code,
+        users: adopted.users,
+        secrets: adopted.secrets,
+    }))
+}
+
+pub fn organization_routes() -> Vec<rocket::Route> {
+    routes![
+        create_organization,
+        list_organizations,
+        delete_organization,
+        list_members,
+        add_member,
+        remove_member,
+        switch_organization,
+        adopt_legacy
+    ]
+}
@@ -0,0 +1,279 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::TokenGuard;
+use crate::routes::responses::{error_response, internal_error};
+use ec_secrets_shared_library::{
+    models::{Policy, PolicyAttachment, PolicyCheck, PolicyDocument, PolicyRules, PrincipalKind},
+    repositories::{
+        groups::GroupRepository, organizations::OrganizationRepository, policies::PolicyRepository,
+        service_accounts::ServiceAccountRepository, users::UserRepository, vault::VaultRepository,
+    },
+    utils::{
+        auth::is_admin,
+        policy::{self, AuthorizedVault, Decision, PolicyError},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::{error, info};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, put, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+impl From<&PolicyDocument> for PolicyResponse {
+    fn from(policy: &PolicyDocument) -> Self {
+        Self {
+            id: policy.id.to_hex(),
+            name: policy.name.clone(),
+            description: policy.description.clone(),
+            rules: policy.rules.iter().map(ToString::to_string).collect(),
+            users: policy.users.clone(),
+            groups: policy.groups.clone(),
+            service_accounts: policy.service_accounts.clone(),
+            created_by: policy.created_by.clone(),
+            created_at: policy.created_at.to_rfc3339(),
+        }
+    }
+}
+
+pub fn policy_error(error: PolicyError) -> Json<ErrorResponse> {
+    let status = match &error {
+        PolicyError::Denied(_) => Status::Forbidden,
+        PolicyError::NotFound(_) | PolicyError::PrincipalNotFound(_) => Status::NotFound,
+        PolicyError::Exists => Status::Conflict,
+        PolicyError::Invalid(_) => Status::BadRequest,
+        PolicyError::Sealed => Status::ServiceUnavailable,
+        PolicyError::TooLarge { .. } => Status::PayloadTooLarge,
+        PolicyError::Integrity(message) => {
+            error!("{message}");
+            return error_response(
+                Status::InternalServerError,
+                "The stored value failed its integrity check",
+            );
+        }
+        PolicyError::Internal(message) => {
+            return internal_error("Policy operation", message);
+        }
+    };
+    error_response(status, &error.to_string())
+}
+
+/// The vault of the token's active organization as seen by its bearer,
+/// every call checked against the policies that apply to it.
+pub async fn authorized_vault<'a>(
+    token: &TokenGuard,
+    vault_repo: &'a VaultRepository,
+    org_repo: &OrganizationRepository,
+    policy_repo: &PolicyRepository,
+    group_repo: &GroupRepository,
+    user_repo: &UserRepository,
+) -> Result<AuthorizedVault<'a>, Json<ErrorResponse>> {
+    AuthorizedVault::for_claims(
+        &token.0,
+        vault_repo,
+        org_repo,
+        policy_repo,
+        group_repo,
+        user_repo,
+    )
+    .await
+    .map_err(policy_error)
+}
+
+/*---------------------------------------------
+ Create a policy, admins only
+----------------------------------------------*/
+#[post("/policies", data = "<policy>")]
+pub async fn create_policy(
+    repo: &State<Arc<PolicyRepository>>,
+    policy: Json<Policy>,
+    token: TokenGuard,
+) -> Result<Json<PolicyResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let created_by = token.subject().unwrap_or_default();
+    let policy = policy::create_policy(&policy, created_by, repo)
+        .await
+        .map_err(policy_error)?;
+
+    info!("Policy created successfully.");
+    Ok(Json(PolicyResponse::from(&policy)))
+}
+
+/*---------------------------------------------
+ List policies, admins only
+----------------------------------------------*/
+#[get("/policies")]
+pub async fn list_policies(
+    repo: &State<Arc<PolicyRepository>>,
+    token: TokenGuard,
+) -> Result<Json<Vec<PolicyResponse>>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    match repo.list_policies().await {
+        Ok(policies) => Ok(Json(policies.iter().map(PolicyResponse::from).collect())),
+        Err(e) => Err(policy_error(e.into())),
+    }
+}
+
+/*---------------------------------------------
+ Get a policy, admins only
+----------------------------------------------*/
+#[get("/policies/<name>")]
+pub async fn get_policy(
+    repo: &State<Arc<PolicyRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<PolicyResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let policy = policy::get_policy(name, repo).await.map_err(policy_error)?;
+    Ok(Json(PolicyResponse::from(&policy)))
+}
+
+/*---------------------------------------------
+ Replace a policy's rules, admins only
+----------------------------------------------*/
+#[put("/policies/<name>", data = "<rules>")]
+pub async fn update_policy(
+    repo: &State<Arc<PolicyRepository>>,
+    name: &str,
+    rules: Json<PolicyRules>,
+    token: TokenGuard,
+) -> Result<Json<PolicyResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let policy = policy::update_policy(name, &rules, repo)
+        .await
+        .map_err(policy_error)?;
+    Ok(Json(PolicyResponse::from(&policy)))
+}
+
+/*---------------------------------------------
+ Delete a policy, admins only
+----------------------------------------------*/
+#[delete("/policies/<name>")]
+pub async fn delete_policy(
+    repo: &State<Arc<PolicyRepository>>,
+    name: &str,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    policy::delete_policy(name, repo)
+        .await
+        .map_err(policy_error)?;
+    Ok(Json(AccountResponse {
+        status: Status::Ok.code,
+        message: "Policy deleted successfully".to_string(),
+    }))
+}
+
+/*---------------------------------------------
+ Attach a policy to a user, group or service
+ account, admins only
+----------------------------------------------*/
+#[post("/policies/<name>/attachments", data = "<attachment>")]
+pub async fn attach_policy(
+    repo: &State<Arc<PolicyRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
+    service_account_repo: &State<Arc<ServiceAccountRepository>>,
+    name: &str,
+    attachment: Json<PolicyAttachment>,
+    token: TokenGuard,
+) -> Result<Json<PolicyResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let policy = policy::attach_policy(
+        name,
+        &attachment,
+        repo,
+        user_repo,
+        group_repo,
+        service_account_repo,
+    )
+    .await
+    .map_err(policy_error)?;
+    Ok(Json(PolicyResponse::from(&policy)))
+}
+
+/*---------------------------------------------
+ Detach a policy, admins only
+----------------------------------------------*/
+#[delete("/policies/<name>/attachments?<kind>&<id>")]
+pub async fn detach_policy(
+    repo: &State<Arc<PolicyRepository>>,
+    name: &str,
+    kind: &str,
+    id: &str,
+    token: TokenGuard,
+) -> Result<Json<PolicyResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let kind: PrincipalKind = kind
+        .parse()
+        .map_err(|message: String| error_response(Status::BadRequest, &message))?;
+    let attachment = PolicyAttachment {
+        kind,
+        id: id.to_string(),
+    };
+    let policy = policy::detach_policy(name, &attachment, repo)
+        .await
+        .map_err(policy_error)?;
+    Ok(Json(PolicyResponse::from(&policy)))
+}
+
+/*---------------------------------------------
+ Explain whether a capability on a path is
+ allowed. Admins can check any user or service
+ account, everyone else only themselves.
+----------------------------------------------*/
+#[post("/policies/check", data = "<check>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn check_policy(
+    repo: &State<Arc<PolicyRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
+    service_account_repo: &State<Arc<ServiceAccountRepository>>,
+    check: Json<PolicyCheck>,
+    token: TokenGuard,
+) -> Result<Json<Decision>, Json<ErrorResponse>> {
+    if (check.user.is_some() || check.service_account.is_some()) && !is_admin(&token.0) {
+        return Err(error_response(
+            Status::Forbidden,
+            "Only admins can check the policies of others",
+        ));
+    }
+    let principal = policy::check_principal(&check, &token.0, user_repo, service_account_repo)
+        .await
+        .map_err(policy_error)?;
+    let org_vault = policy::org_vault(&token.0, vault_repo, org_repo, user_repo)
+        .await
+        .map_err(policy_error)?;
+    let vault = AuthorizedVault::new(principal, org_vault, repo, group_repo)
+        .await
+        .map_err(policy_error)?;
+    let decision = vault
+        .explain(check.capability, &check.path)
+        .await
+        .map_err(policy_error)?;
+    Ok(Json(decision))
+}
+
+pub fn policy_routes() -> Vec<rocket::Route> {
+    routes![
+        create_policy,
+        list_policies,
+        get_policy,
+        update_policy,
+        delete_policy,
+        attach_policy,
+        detach_policy,
+        check_policy
+    ]
+}
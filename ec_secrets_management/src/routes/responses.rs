@@ -0,0 +1,48 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::ErrorResponse;
+use crate::request_guards::TokenGuard;
+
+/*-------------
+3rd party modules
+--------------*/
+use log::error;
+use mongodb::bson::oid::ObjectId;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+
+/*-------------
+stdlib modules
+--------------*/
+use std::fmt::Display;
+
+/*---------------------------------------------------------------------------
+    Helpers shared by the route modules. Each module maps the errors of its
+    own utils on top of these (`policy_error`, `group_error`, ...).
+---------------------------------------------------------------------------*/
+pub fn error_response(status: Status, message: &str) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: status.code,
+        message: message.to_string(),
+    })
+}
+
+/// Logs `error` and answers a generic 500, keeping internals out of the
+/// response.
+pub fn internal_error(operation: &str, error: impl Display) -> Json<ErrorResponse> {
+    error!("{operation} failed: {error}");
+    error_response(Status::InternalServerError, "Internal server error")
+}
+
+/// Subject of the caller, for resources managed by their (human) owner only.
+pub fn owner(token: &TokenGuard) -> Result<&str, Json<ErrorResponse>> {
+    token.require_user()?;
+    token
+        .subject()
+        .ok_or_else(|| error_response(Status::Unauthorized, "Insufficient Permissions"))
+}
+
+pub fn parse_id(id: &str) -> Result<ObjectId, Json<ErrorResponse>> {
+    ObjectId::parse_str(id).map_err(|_| error_response(Status::BadRequest, "Invalid id"))
+}
@@ -0,0 +1,249 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::TokenGuard;
+use crate::routes::responses::{error_response, internal_error};
+use ec_secrets_shared_library::{
+    models::{SealInit, UnsealShare},
+    repositories::{
+        keys::KeyRepository, organizations::OrganizationRepository, seal::SealRepository,
+        users::UserRepository, vault::VaultRepository,
+    },
+    utils::{
+        key_provider::{self, KeyName},
+        seal::{self, SealError, SealStatus},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::{info, warn};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+pub fn seal_error(error: SealError) -> Json<ErrorResponse> {
+    let status = match &error {
+        SealError::Sealed => Status::ServiceUnavailable,
+        SealError::Disabled
+        | SealError::NotInitialized
+        | SealError::AlreadyInitialized
+        | SealError::KeyUnavailable => Status::Conflict,
+        SealError::InvalidShare(_) | SealError::Invalid(_) => Status::BadRequest,
+        SealError::Internal(message) => {
+            return internal_error("Seal operation", message);
+        }
+    };
+    error_response(status, &error.to_string())
+}
+
+/*---------------------------------------------
+ Whether the vault is sealed and how many
+ shares have been submitted towards unsealing
+----------------------------------------------*/
+#[get("/sys/seal-status")]
+pub async fn seal_status(
+    repo: &State<Arc<SealRepository>>,
+) -> Result<Json<SealStatus>, Json<ErrorResponse>> {
+    let status = seal::status(repo).await.map_err(seal_error)?;
+    Ok(Json(status))
+}
+
+/// Who initializes: an admin, or anyone on a fresh deployment. Until the
+/// vault is initialized no token can be issued, so a database holding
+/// nothing encrypted yet is the only one that can be set up without one.
+fn init_caller(
+    token: Option<&TokenGuard>,
+    holds_encrypted_data: bool,
+) -> Result<&str, Json<ErrorResponse>> {
+    match token {
+        Some(token) => {
+            token.require_admin()?;
+            Ok(token.subject().unwrap_or_default())
+        }
+        None if holds_encrypted_data => Err(error_response(
+            Status::Unauthorized,
+            "The database already holds data, sign in as an admin to initialize",
+        )),
+        None => Ok("bootstrap"),
+    }
+}
+
+/*---------------------------------------------
+ Split the master key into Shamir shares. The
+ shares are only returned by this call. Admins
+ only, unless nothing is stored yet.
+----------------------------------------------*/
+#[post("/sys/init", data = "<init>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn initialize(
+    repo: &State<Arc<SealRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    init: Json<SealInit>,
+    token: Option<TokenGuard>,
+) -> Result<Json<SealInitResponse>, Json<ErrorResponse>> {
+    let holds_data = seal::holds_encrypted_data(key_repo, org_repo, vault_repo, user_repo)
+        .await
+        .map_err(seal_error)?;
+    let initialized_by = init_caller(token.as_ref(), holds_data)?;
+    let existing_key = key_provider::key(KeyName::Encryption)
+        .map_err(|e| seal_error(SealError::Internal(e.to_string())))?;
+    let shares = seal::initialize(&init, existing_key, holds_data, initialized_by, repo)
+        .await
+        .map_err(seal_error)?;
+
+    info!(
+        "Master key split into {} shares with a threshold of {}.",
+        init.shares, init.threshold
+    );
+    Ok(Json(SealInitResponse {
+        status: Status::Ok.code,
+        threshold: init.threshold,
+        shares,
+    }))
+}
+
+/*---------------------------------------------
+ Submit one share, the vault is unsealed once
+ the threshold is reached. The shares are the
+ credential, no token is needed.
+----------------------------------------------*/
+#[post("/sys/unseal", data = "<share>")]
+pub async fn unseal(
+    repo: &State<Arc<SealRepository>>,
+    share: Json<UnsealShare>,
+) -> Result<Json<SealStatus>, Json<ErrorResponse>> {
+    match seal::unseal(&share.share, repo).await {
+        Ok(status) => {
+            if !status.sealed {
+                info!("Vault unsealed.");
+            }
+            Ok(Json(status))
+        }
+        Err(e) => {
+            warn!("Unseal attempt failed: {e}");
+            Err(seal_error(e))
+        }
+    }
+}
+
+/*---------------------------------------------
+ Wipe the master key from memory, admins only
+----------------------------------------------*/
+#[post("/sys/seal")]
+pub async fn seal_vault(
+    key_repo: &State<Arc<KeyRepository>>,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    seal::seal().map_err(seal_error)?;
+    // Drops the decrypted signing key along with the master key
+    key_repo.invalidate().await;
+
+    info!("Vault sealed.");
+    Ok(Json(AccountResponse {
+        status: Status::Ok.code,
+        message: "Vault sealed successfully".to_string(),
+    }))
+}
+
+pub fn seal_routes() -> Vec<rocket::Route> {
+    routes![seal_status, initialize, unseal, seal_vault]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ec_secrets_shared_library::utils::auth::issue_user_token;
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn only_fresh_deployments_initialize_without_a_token() {
+        assert_eq!(init_caller(None, false).unwrap(), "bootstrap");
+        let refused = init_caller(None, true).unwrap_err();
+        assert_eq!(refused.status, Status::Unauthorized.code);
+    }
+
+    /*-------------------------------------------------------------------
+        Needs MongoDB and changes the process' seal state, so it's
+        ignored by default. Run it on its own against a throwaway
+        server, e.g.
+
+            ECS_TEST_MONGODB_URI=mongodb://127.0.0.1:27017 \
+            cargo test fresh_install -- --include-ignored
+    --------------------------------------------------------------------*/
+    #[rocket::async_test]
+    #[ignore = "needs MongoDB, see above"]
+    async fn fresh_install_initializes_without_a_token() {
+        // SAFETY: set before any key is loaded, no other test runs alongside
+        unsafe {
+            std::env::set_var("ECS_SEAL_MODE", "shamir");
+            std::env::remove_var("ECS_ENCRYPTION_KEY");
+        }
+        let uri = std::env::var("ECS_TEST_MONGODB_URI").expect("ECS_TEST_MONGODB_URI");
+        let db = mongodb::Client::with_uri_str(uri).await.unwrap();
+        let name = format!("ecs_test_fresh_install_{}", std::process::id());
+        let users = Arc::new(UserRepository::new(&db, &name, "users"));
+        let keys = Arc::new(KeyRepository::new(&db, &name, "keys"));
+        let rocket = rocket::build()
+            .manage(Arc::new(SealRepository::new(&db, &name, "seal")))
+            .manage(keys.clone())
+            .manage(Arc::new(OrganizationRepository::new(&db, &name, "orgs")))
+            .manage(Arc::new(VaultRepository::new(&db, &name, "vault")))
+            .manage(users.clone())
+            .mount("/", seal_routes());
+        let client = Client::untracked(rocket).await.unwrap();
+        let init = json!({ "shares": 3, "threshold": 2 });
+
+        let response = client.post("/sys/init").json(&init).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = response.into_json().await.unwrap();
+        let shares = body["shares"].as_array().unwrap().clone();
+        assert_eq!(shares.len(), 3);
+
+        let response = client.post("/sys/init").json(&init).dispatch().await;
+        assert_eq!(response.status(), Status::Conflict);
+
+        for share in &shares[..2] {
+            let response = client
+                .post("/sys/unseal")
+                .json(&json!({ "share": share }))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+        assert!(!seal::is_sealed());
+
+        // Unsealed, the first admin can sign in and the database now
+        // holds the signing key, so tokenless init is refused
+        let admin = users
+            .create_user("admin@example.com", "hash", true)
+            .await
+            .unwrap();
+        let token = issue_user_token(&admin, &keys).await.unwrap();
+        let response = client.post("/sys/init").json(&init).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .post("/sys/seal")
+            .header(Header::new("Authorization", format!("Bearer {token}")))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(seal::is_sealed());
+
+        db.database(&name).drop().await.unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::{RateLimitGuard, TokenGuard};
+use crate::routes::responses::{error_response, internal_error, owner, parse_id};
+use ec_secrets_shared_library::{
+    models::{
+        ApiKeyCredentials, ApiKeyDocument, ApiKeyRequest, PrincipalKind, ServiceAccount,
+        ServiceAccountDocument,
+    },
+    repositories::{
+        keys::KeyRepository, policies::PolicyRepository,
+        service_accounts::ServiceAccountRepository, users::UserRepository,
+    },
+    utils::auth::{authenticate_api_key, sign_claims, token_org, SCOPES},
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::{error, info};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+async fn owned_account(
+    repo: &ServiceAccountRepository,
+    id: &str,
+    owner: &str,
+) -> Result<ServiceAccountDocument, Json<ErrorResponse>> {
+    match repo.get_service_account(&parse_id(id)?, owner).await {
+        Ok(Some(account)) => Ok(account),
+        Ok(None) => Err(error_response(
+            Status::NotFound,
+            "Service account not found",
+        )),
+        Err(e) => Err(internal_error("Service account operation", e)),
+    }
+}
+
+impl From<&ServiceAccountDocument> for ServiceAccountResponse {
+    fn from(account: &ServiceAccountDocument) -> Self {
+        Self {
+            id: account.id.to_hex(),
+            name: account.name.clone(),
+            description: account.description.clone(),
+            created_at: account.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl From<&ApiKeyDocument> for ApiKeyResponse {
+    fn from(key: &ApiKeyDocument) -> Self {
+        Self {
+            id: key.id.to_hex(),
+            scopes: key.scopes.clone(),
+            expires_at: key.expires_at.map(|date| date.to_rfc3339()),
+            revoked_at: key.revoked_at.map(|date| date.to_rfc3339()),
+            created_at: key.created_at.to_rfc3339(),
+        }
+    }
+}
+
+/*-------------------------
+ Create a service account
+-------------------------*/
+#[post("/service-accounts", data = "<account>")]
+pub async fn create_service_account(
+    repo: &State<Arc<ServiceAccountRepository>>,
+    account: Json<ServiceAccount>,
+    token: TokenGuard,
+) -> Result<Json<ServiceAccountResponse>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    match repo
+        .create_service_account(
+            &account.name,
+            account.description.as_deref(),
+            owner,
+            token_org(&token.0),
+        )
+        .await
+    {
+        Ok(account) => {
+            info!("Service account created successfully.");
+            Ok(Json(ServiceAccountResponse::from(&account)))
+        }
+        Err(e) if matches!(e.kind.as_ref(), mongodb::error::ErrorKind::Io(_)) => {
+            Err(error_response(
+                Status::Conflict,
+                "A service account with this name already exists",
+            ))
+        }
+        Err(e) => Err(internal_error("Service account operation", e)),
+    }
+}
+
+/*--------------------------
+ List owned service accounts
+---------------------------*/
+#[get("/service-accounts")]
+pub async fn list_service_accounts(
+    repo: &State<Arc<ServiceAccountRepository>>,
+    token: TokenGuard,
+) -> Result<Json<Vec<ServiceAccountResponse>>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    match repo.list_service_accounts(owner).await {
+        Ok(accounts) => Ok(Json(
+            accounts.iter().map(ServiceAccountResponse::from).collect(),
+        )),
+        Err(e) => Err(internal_error("Service account operation", e)),
+    }
+}
+
+/*------------------------------------------
+ Delete a service account and all its keys
+------------------------------------------*/
+#[delete("/service-accounts/<id>")]
+pub async fn delete_service_account(
+    repo: &State<Arc<ServiceAccountRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    id: &str,
+    token: TokenGuard,
+) -> Result<Json<DeleteServiceAccountResponse>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    match repo.delete_service_account(&parse_id(id)?, owner).await {
+        Ok(Some(account)) => {
+            policy_repo
+                .detach_all(PrincipalKind::ServiceAccount, &account.id.to_hex())
+                .await
+                .map_err(|e| internal_error("Service account operation", e))?;
+            Ok(Json(DeleteServiceAccountResponse {
+                status: Status::Ok.code,
+                message: "Service account deleted successfully".to_string(),
+            }))
+        }
+        Ok(None) => Err(error_response(
+            Status::NotFound,
+            "Service account not found",
+        )),
+        Err(e) => Err(internal_error("Service account operation", e)),
+    }
+}
+
+/*----------------------------------------
+ Issue an API key for a service account
+----------------------------------------*/
+#[post("/service-accounts/<id>/keys", data = "<request>")]
+pub async fn create_api_key(
+    repo: &State<Arc<ServiceAccountRepository>>,
+    id: &str,
+    request: Json<ApiKeyRequest>,
+    token: TokenGuard,
+) -> Result<Json<CreateApiKeyResponse>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    let account = owned_account(repo, id, owner).await?;
+
+    if request.scopes.is_empty() {
+        return Err(error_response(
+            Status::BadRequest,
+            "At least one scope is required",
+        ));
+    }
+    if let Some(scope) = request
+        .scopes
+        .iter()
+        .find(|scope| !SCOPES.contains(&scope.as_str()))
+    {
+        return Err(error_response(
+            Status::BadRequest,
+            &format!("Unknown scope '{scope}'"),
+        ));
+    }
+    if request.expires_in_days.is_some_and(|days| days <= 0) {
+        return Err(error_response(
+            Status::BadRequest,
+            "expires_in_days must be positive",
+        ));
+    }
+
+    match repo
+        .create_api_key(&account.id, &request.scopes, request.expires_in_days)
+        .await
+    {
+        Ok((key, api_key)) => Ok(Json(CreateApiKeyResponse {
+            status: Status::Ok.code,
+            id: key.id.to_hex(),
+            api_key,
+            scopes: key.scopes,
+            expires_at: key.expires_at.map(|date| date.to_rfc3339()),
+        })),
+        Err(e) => Err(internal_error("Service account operation", e)),
+    }
+}
+
+/*-----------------------------------
+ List the keys of a service account
+-----------------------------------*/
+#[get("/service-accounts/<id>/keys")]
+pub async fn list_api_keys(
+    repo: &State<Arc<ServiceAccountRepository>>,
+    id: &str,
+    token: TokenGuard,
+) -> Result<Json<Vec<ApiKeyResponse>>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    let account = owned_account(repo, id, owner).await?;
+    match repo.list_api_keys(&account.id).await {
+        Ok(keys) => Ok(Json(keys.iter().map(ApiKeyResponse::from).collect())),
+        Err(e) => Err(internal_error("Service account operation", e)),
+    }
+}
+
+/*------------------
+ Revoke an API key
+------------------*/
+#[delete("/service-accounts/<id>/keys/<key_id>")]
+pub async fn revoke_api_key(
+    repo: &State<Arc<ServiceAccountRepository>>,
+    id: &str,
+    key_id: &str,
+    token: TokenGuard,
+) -> Result<Json<DeleteServiceAccountResponse>, Json<ErrorResponse>> {
+    let owner = owner(&token)?;
+    let account = owned_account(repo, id, owner).await?;
+    match repo.revoke_api_key(&account.id, &parse_id(key_id)?).await {
+        Ok(Some(_)) => Ok(Json(DeleteServiceAccountResponse {
+            status: Status::Ok.code,
+            message: "API key revoked successfully".to_string(),
+        })),
+        Ok(None) => Err(error_response(
+            Status::NotFound,
+            "API key not found or already revoked",
+        )),
+        Err(e) => Err(internal_error("Service account operation", e)),
+    }
+}
+
+/*--------------------------------------------
+ Exchange an API key for a short lived token
+--------------------------------------------*/
+#[post("/service-accounts/token", data = "<credentials>")]
+pub async fn exchange_api_key(
+    repo: &State<Arc<ServiceAccountRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    credentials: Json<ApiKeyCredentials>,
+    _rate_limit: RateLimitGuard,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    let claims = authenticate_api_key(&credentials.api_key, repo, user_repo)
+        .await
+        .map_err(|_| error_response(Status::Unauthorized, "Invalid API key"))?;
+    match sign_claims(&claims, key_repo).await {
+        Ok(token) => Ok(Json(LoginResponse {
+            status: Status::Ok.code,
+            token,
+            mfa: None,
+        })),
+        Err(e) => {
+            error!("Failed to sign token: {e}");
+            Err(error_response(
+                Status::InternalServerError,
+                "Internal server error",
+            ))
+        }
+    }
+}
+
+pub fn service_account_routes() -> Vec<rocket::Route> {
+    routes![
+        create_service_account,
+        list_service_accounts,
+        delete_service_account,
+        create_api_key,
+        list_api_keys,
+        revoke_api_key,
+        exchange_api_key
+    ]
+}
@@ -0,0 +1,144 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::*;
+use crate::request_guards::TokenGuard;
+use crate::routes::responses::internal_error;
+use ec_secrets_shared_library::{
+    models::{Settings, SettingsDocument},
+    repositories::{
+        keys::KeyRepository, organizations::OrganizationRepository, settings::SettingsRepository,
+        users::UserRepository, vault::VaultRepository,
+    },
+    utils::reencryption::{reencrypt_all, Reencrypted, ReencryptionError},
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::{error, info};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, post, put, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+impl From<SettingsDocument> for SettingsResponse {
+    fn from(settings: SettingsDocument) -> Self {
+        Self {
+            require_mfa: settings.require_mfa,
+            updated_by: settings.updated_by,
+            updated_at: settings.updated_at.map(|date| date.to_rfc3339()),
+        }
+    }
+}
+
+/*---------------------
+ Read instance settings
+----------------------*/
+#[get("/admin/settings")]
+pub async fn get_settings(
+    repo: &State<Arc<SettingsRepository>>,
+    token: TokenGuard,
+) -> Result<Json<SettingsResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    match repo.get_settings().await {
+        Ok(settings) => Ok(Json(SettingsResponse::from(settings))),
+        Err(e) => Err(internal_error("Settings operation", e)),
+    }
+}
+
+/*-----------------------
+ Update instance settings
+------------------------*/
+#[put("/admin/settings", data = "<settings>")]
+pub async fn update_settings(
+    repo: &State<Arc<SettingsRepository>>,
+    settings: Json<Settings>,
+    token: TokenGuard,
+) -> Result<Json<SettingsResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let updated_by = token.subject().unwrap_or_default();
+    match repo.set_require_mfa(settings.require_mfa, updated_by).await {
+        Ok(settings) => {
+            info!("Instance settings updated.");
+            Ok(Json(SettingsResponse::from(settings)))
+        }
+        Err(e) => Err(internal_error("Settings operation", e)),
+    }
+}
+
+/*---------------------------------------------
+ Replace the token signing key pair, signing
+ everyone out. Other instances pick it up
+ within five minutes.
+----------------------------------------------*/
+#[post("/admin/signing-key/rotate")]
+pub async fn rotate_signing_key(
+    key_repo: &State<Arc<KeyRepository>>,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    match key_repo.rotate_key_pair().await {
+        Ok(_) => {
+            info!("Token signing key rotated.");
+            Ok(Json(AccountResponse {
+                status: Status::Ok.code,
+                message: "Signing key rotated, every session has to log in again".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Signing key rotation failed: {e}");
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    }
+}
+
+/*-----------------------------------------------
+ Re-encrypt everything stored encrypted with the
+ cipher and key derivation configured for new
+ data, see ECS_VAULT_CIPHER
+------------------------------------------------*/
+#[post("/admin/reencrypt")]
+pub async fn reencrypt(
+    org_repo: &State<Arc<OrganizationRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    token: TokenGuard,
+) -> Result<Json<Reencrypted>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    match reencrypt_all(org_repo, vault_repo, key_repo, user_repo).await {
+        Ok(reencrypted) => {
+            info!("Data at rest re-encrypted: {reencrypted:?}");
+            Ok(Json(reencrypted))
+        }
+        Err(e) => {
+            let status = match &e {
+                ReencryptionError::Config(_) => Status::BadRequest,
+                ReencryptionError::Sealed => Status::ServiceUnavailable,
+                ReencryptionError::Internal(message) => {
+                    error!("Re-encryption failed: {message}");
+                    return Err(Json(ErrorResponse {
+                        status: Status::InternalServerError.code,
+                        message: "Internal server error".to_string(),
+                    }));
+                }
+            };
+            Err(Json(ErrorResponse {
+                status: status.code,
+                message: e.to_string(),
+            }))
+        }
+    }
+}
+
+pub fn settings_routes() -> Vec<rocket::Route> {
+    routes![get_settings, update_settings, rotate_signing_key, reencrypt]
+}
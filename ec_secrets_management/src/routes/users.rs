@@ -1,30 +1,88 @@
 /*-------------
 Custom modules
 --------------*/
-use crate::models::{DeleteUserResponse, ErrorResponse, LoginResponse, SetupResponse};
+use crate::models::{
+    DeleteUserResponse, ErrorResponse, LoginResponse, SetupResponse, UserResponse,
+};
+use crate::request_guards::{RateLimitGuard, TokenGuard};
 use ec_secrets_shared_library::{
-    models::{User, UserCredentials, UserDocument},
-    repositories::{keys::KeyRepository, users::UserRepository},
-    utils::auth::{authorize_user, hash_password}
+    models::{PrincipalKind, UserCredentials, UserDocument},
+    repositories::{
+        app_roles::AppRoleRepository, keys::KeyRepository, login_attempts::LoginAttemptRepository,
+        policies::PolicyRepository, service_accounts::ServiceAccountRepository,
+        settings::SettingsRepository, user_tokens::UserTokenRepository, users::UserRepository,
+    },
+    utils::{
+        account::{registration_open, send_email_verification, AccountError},
+        auth::{authorize_user, LoginOutcome, MFA_CHALLENGE, MFA_ENROLL},
+        mailer::Mailer,
+        password::{hash_password_async, validate_password},
+    },
 };
 
 /*-------------
 3rd party modules
 --------------*/
+use chrono::{DateTime, Utc};
+use log::{error, warn};
 use rocket::http::Status;
 use rocket::serde::json::Json;
-use rocket::{delete, get, post, put, routes, State};
+use rocket::{delete, get, post, routes, State};
 
 /*-------------
 stdlib modules
 --------------*/
 use std::sync::Arc;
 
+/// Response for logins against a locked account.
+pub fn account_locked(locked_until: DateTime<Utc>) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: Status::TooManyRequests.code,
+        message: format!(
+            "Too many failed login attempts, try again after {}",
+            locked_until.to_rfc3339()
+        ),
+    })
+}
+
+/// Records a failed login for `account`, logging when it triggers a lockout.
+pub async fn record_login_failure(
+    attempts_repo: &LoginAttemptRepository,
+    account: &str,
+    rate_limit: &RateLimitGuard,
+) {
+    let client_ip = rate_limit.client_ip.map(|ip| ip.to_string());
+    match attempts_repo
+        .record_failure(account, client_ip.as_deref())
+        .await
+    {
+        Ok(Some(locked_until)) => warn!("Account locked until {locked_until} after failed logins"),
+        Ok(None) => {}
+        Err(e) => error!("Failed to record login failure: {:?}", e),
+    }
+}
+
 #[post("/setup", data = "<credentials>")]
 pub async fn setup(
     repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<UserTokenRepository>>,
+    mailer: &State<Arc<dyn Mailer>>,
     credentials: Json<UserCredentials>,
+    _rate_limit: RateLimitGuard,
 ) -> Result<Json<SetupResponse>, Json<ErrorResponse>> {
+    if !registration_open() {
+        let message = match repo.count_admins().await {
+            Ok(0) => {
+                "Self-registration is disabled, bootstrap the first admin with `ec_lock_smith admin bootstrap`".to_string()
+            }
+            _ => AccountError::RegistrationClosed.to_string(),
+        };
+        return Err(Json(ErrorResponse {
+            status: Status::Forbidden.code,
+            message,
+        }));
+    }
+
     // Check if the user already exists
     if let Ok(Some(_)) = repo.get_user_by_email(&credentials.email).await {
         return Err(Json(ErrorResponse {
@@ -33,9 +91,16 @@ pub async fn setup(
         }));
     }
 
-    let hashed_password = match hash_password(credentials.password.clone()) {
+    if let Err(e) = validate_password(&credentials.password) {
+        return Err(Json(ErrorResponse {
+            status: Status::BadRequest.code,
+            message: e,
+        }));
+    }
+
+    let hashed_password = match hash_password_async(&credentials.password).await {
         Ok(hash) => hash,
-        Err(_e) => {
+        Err(_) => {
             return Err(Json(ErrorResponse {
                 status: Status::InternalServerError.code,
                 message: "Internal server error".to_string(),
@@ -43,7 +108,10 @@ pub async fn setup(
         }
     };
 
-    let _ = match repo.create_user(&credentials.email, &hashed_password).await {
+    let user = match repo
+        .create_user(&credentials.email, &hashed_password, false)
+        .await
+    {
         Ok(user) => user,
         Err(_) => {
             return Err(Json(ErrorResponse {
@@ -53,6 +121,11 @@ pub async fn setup(
         }
     };
 
+    // The account is usable right away, verification can be resent later
+    if let Err(e) = send_email_verification(&user, token_repo, mailer.as_ref()).await {
+        error!("Failed to send verification email: {e}");
+    }
+
     Ok(Json(SetupResponse {
         status: Status::Ok.code,
         message: "User registered successfully".to_string(),
@@ -63,15 +136,31 @@ pub async fn setup(
 pub async fn login(
     repo: &State<Arc<UserRepository>>,
     key_repo: &State<Arc<KeyRepository>>,
+    settings_repo: &State<Arc<SettingsRepository>>,
+    attempts_repo: &State<Arc<LoginAttemptRepository>>,
     credentials: Json<UserCredentials>,
+    rate_limit: RateLimitGuard,
 ) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    match attempts_repo.locked_until(&credentials.email).await {
+        Ok(Some(locked_until)) => return Err(account_locked(locked_until)),
+        Ok(None) => {}
+        Err(_) => {
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    }
+
     let user_document = match repo.get_user_by_email(&credentials.email).await {
         Ok(Some(user_document)) => user_document,
         Ok(None) => {
+            // Unknown accounts count too, so responses don't reveal which exist
+            record_login_failure(attempts_repo, &credentials.email, &rate_limit).await;
             return Err(Json(ErrorResponse {
                 status: Status::Unauthorized.code,
                 message: "Invalid email or password".to_string(),
-            }))
+            }));
         }
         Err(_) => {
             return Err(Json(ErrorResponse {
@@ -81,33 +170,76 @@ pub async fn login(
         }
     };
 
-    let user = User {
-        id: user_document.id.to_string(),
-        email: user_document.email.clone(),
-        password: user_document.password.clone(),
-        created_at: user_document.created_at.to_rfc3339(),
+    let settings = match settings_repo.get_settings().await {
+        Ok(settings) => settings,
+        Err(_) => {
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
     };
 
-    let token = match authorize_user(&user, &credentials, key_repo).await {
-        Ok(token) => token,
+    let outcome = match authorize_user(
+        &user_document,
+        &credentials,
+        settings.require_mfa,
+        repo,
+        key_repo,
+    )
+    .await
+    {
+        Ok(outcome) => outcome,
         Err(_) => {
+            record_login_failure(attempts_repo, &credentials.email, &rate_limit).await;
             return Err(Json(ErrorResponse {
                 status: Status::Unauthorized.code,
                 message: "Invalid email or password".to_string(),
-            }))
+            }));
+        }
+    };
+
+    // Failures are only cleared once no second factor is pending
+    if !matches!(outcome, LoginOutcome::MfaRequired(_)) {
+        if let Err(e) = attempts_repo.record_success(&credentials.email).await {
+            error!("Failed to clear login failures: {:?}", e);
         }
+    }
+
+    // With MFA the token only allows the next step, see routes::mfa
+    let (token, mfa) = match outcome {
+        LoginOutcome::Authenticated(token) => (token, None),
+        LoginOutcome::MfaRequired(token) => (token, Some(MFA_CHALLENGE.to_string())),
+        LoginOutcome::EnrollmentRequired(token) => (token, Some(MFA_ENROLL.to_string())),
     };
 
     Ok(Json(LoginResponse {
         status: Status::Ok.code,
         token,
+        mfa,
     }))
 }
 
+impl From<&UserDocument> for UserResponse {
+    fn from(user: &UserDocument) -> Self {
+        Self {
+            id: user.id.to_hex(),
+            email: user.email.clone(),
+            admin: user.admin,
+            mfa_enabled: user.mfa_enabled(),
+            email_verified: user.email_verified,
+            groups: user.groups.clone(),
+            created_at: user.created_at.to_rfc3339(),
+        }
+    }
+}
+
 #[get("/users")]
 pub async fn list_users(
     repo: &State<Arc<UserRepository>>,
-) -> Result<Json<Vec<UserDocument>>, Json<ErrorResponse>> {
+    token: TokenGuard,
+) -> Result<Json<Vec<UserResponse>>, Json<ErrorResponse>> {
+    token.require_admin()?;
     let users = match repo.list_users().await {
         Ok(users) => users,
         Err(_) => {
@@ -118,14 +250,16 @@ pub async fn list_users(
         }
     };
 
-    Ok(Json(users))
+    Ok(Json(users.iter().map(UserResponse::from).collect()))
 }
 
 #[get("/users/<id>")]
 pub async fn get_user(
     repo: &State<Arc<UserRepository>>,
+    token: TokenGuard,
     id: String,
-) -> Result<Json<UserDocument>, Json<ErrorResponse>> {
+) -> Result<Json<UserResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
     let user = match repo.get_user_by_id(&id).await {
         Ok(Some(user)) => user,
         Ok(None) => {
@@ -142,40 +276,26 @@ pub async fn get_user(
         }
     };
 
-    Ok(Json(user))
+    Ok(Json(UserResponse::from(&user)))
 }
 
-#[put("/update/<id>", data = "<credentials>")]
-pub async fn update_user(
+#[delete("/delete/user/<id>")]
+pub async fn delete_user(
     repo: &State<Arc<UserRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    service_account_repo: &State<Arc<ServiceAccountRepository>>,
+    app_role_repo: &State<Arc<AppRoleRepository>>,
+    token: TokenGuard,
     id: String,
-    credentials: Json<UserCredentials>,
-) -> Result<Json<UserDocument>, Json<ErrorResponse>> {
-    // Check if the email is already in use by another user
-    if let Ok(Some(existing_user)) = repo.get_user_by_email(&credentials.email).await {
-        // If the email exists and it's not the user being updated
-        if existing_user.id.to_string() != id {
-            return Err(Json(ErrorResponse {
-                status: Status::Conflict.code,
-                message: "A user with this email already exists".to_string(),
-            }));
-        }
-    }
-
-    let hashed_password = match hash_password(credentials.password.clone()) {
-        Ok(hash) => hash,
-        Err(_) => {
-            return Err(Json(ErrorResponse {
-                status: Status::InternalServerError.code,
-                message: "Internal server error".to_string(),
-            }))
-        }
+) -> Result<Json<DeleteUserResponse>, Json<ErrorResponse>> {
+    token.require_admin()?;
+    let internal_error = || {
+        Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        })
     };
-
-    let user = match repo
-        .update_user(&id, Some(&credentials.email), Some(&hashed_password))
-        .await
-    {
+    let user = match repo.get_user_by_id(&id).await {
         Ok(Some(user)) => user,
         Ok(None) => {
             return Err(Json(ErrorResponse {
@@ -183,39 +303,38 @@ pub async fn update_user(
                 message: "User not found".to_string(),
             }))
         }
-        Err(_) => {
-            return Err(Json(ErrorResponse {
-                status: Status::InternalServerError.code,
-                message: "Internal server error".to_string(),
-            }))
-        }
+        Err(_) => return Err(internal_error()),
     };
 
-    Ok(Json(user))
-}
+    // Machine credentials go first: if this fails the user is still there
+    // to retry the deletion with
+    if let Err(e) = service_account_repo.delete_owned_by(&user.email).await {
+        error!("Failed to delete the service accounts of a user: {:?}", e);
+        return Err(internal_error());
+    }
+    if let Err(e) = app_role_repo.delete_owned_by(&user.email).await {
+        error!("Failed to delete the AppRoles of a user: {:?}", e);
+        return Err(internal_error());
+    }
 
-#[delete("/delete/user/<id>")]
-pub async fn delete_user(
-    repo: &State<Arc<UserRepository>>,
-    id: String,
-) -> Result<Json<DeleteUserResponse>, Json<ErrorResponse>> {
     match repo.delete_user(&id).await {
-        Ok(Some(_)) => Ok(Json(DeleteUserResponse {
-            status: Status::Ok.code,
-            message: "User deleted successfully".to_string(),
-        })),
-        Ok(None) => {
-            return Err(Json(ErrorResponse {
-                status: Status::NotFound.code,
-                message: "User not found".to_string(),
-            }))
-        }
-        Err(_) => {
-            return Err(Json(ErrorResponse {
-                status: Status::InternalServerError.code,
-                message: "Internal server error".to_string(),
+        Ok(Some(user)) => {
+            if let Err(e) = policy_repo
+                .detach_all(PrincipalKind::User, &user.email)
+                .await
+            {
+                error!("Failed to detach the policies of a deleted user: {:?}", e);
+            }
+            Ok(Json(DeleteUserResponse {
+                status: Status::Ok.code,
+                message: "User deleted successfully".to_string(),
             }))
         }
+        Ok(None) => Err(Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "User not found".to_string(),
+        })),
+        Err(_) => Err(internal_error()),
     }
 }
 
@@ -225,7 +344,162 @@ pub fn user_routes() -> Vec<rocket::Route> {
         login,
         // list_users, - This endpoint will be used for administrative processes
         get_user,
-        update_user,
         delete_user
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ec_secrets_shared_library::{
+        models::AppRole,
+        utils::auth::{
+            authenticate_api_key, authenticate_app_role, issue_user_token, SCOPE_SECRETS_READ,
+        },
+    };
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+
+    /// The repositories never reach the database: the guard rejects the
+    /// request first. The MongoDB client only connects on first use.
+    async fn client() -> Client {
+        let db = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9")
+            .await
+            .expect("Failed to parse the MongoDB URI");
+        let rocket = rocket::build()
+            .manage(Arc::new(UserRepository::new(&db, "test", "users")))
+            .manage(Arc::new(KeyRepository::new(&db, "test", "keys")))
+            .manage(Arc::new(PolicyRepository::new(&db, "test", "policies")))
+            .manage(Arc::new(ServiceAccountRepository::new(
+                &db,
+                "test",
+                "service_accounts",
+                "api_keys",
+            )))
+            .manage(Arc::new(AppRoleRepository::new(
+                &db,
+                "test",
+                "app_roles",
+                "secret_ids",
+            )))
+            .mount("/", routes![get_user, delete_user]);
+        Client::untracked(rocket)
+            .await
+            .expect("Failed to build the test client")
+    }
+
+    #[rocket::async_test]
+    async fn user_routes_require_a_token() {
+        let client = client().await;
+        let id = "507f1f77bcf86cd799439011";
+
+        let response = client.get(format!("/users/{id}")).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.delete(format!("/delete/user/{id}")).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+    /*-------------------------------------------------------------------
+        Needs MongoDB, so it's ignored by default. Point
+        [ECS_TEST_MONGODB_URI] at a throwaway server, e.g.
+
+            ECS_TEST_MONGODB_URI=mongodb://127.0.0.1:27017 \
+            cargo test deleting_a_user -- --include-ignored
+    --------------------------------------------------------------------*/
+    #[rocket::async_test]
+    #[ignore = "needs MongoDB, see above"]
+    async fn deleting_a_user_revokes_their_machine_credentials() {
+        // SAFETY: set before any key is loaded, no other test reads it
+        unsafe { std::env::set_var("ECS_ENCRYPTION_KEY", "test encryption key") };
+        let uri = std::env::var("ECS_TEST_MONGODB_URI").expect("ECS_TEST_MONGODB_URI");
+        let db = mongodb::Client::with_uri_str(uri).await.unwrap();
+        let name = format!("ecs_test_delete_user_{}", std::process::id());
+        let users = Arc::new(UserRepository::new(&db, &name, "users"));
+        let keys = Arc::new(KeyRepository::new(&db, &name, "keys"));
+        let service_accounts = Arc::new(ServiceAccountRepository::new(
+            &db,
+            &name,
+            "service_accounts",
+            "api_keys",
+        ));
+        let app_roles = Arc::new(AppRoleRepository::new(
+            &db,
+            &name,
+            "app_roles",
+            "secret_ids",
+        ));
+
+        let admin = users
+            .create_user("admin@example.com", "hash", true)
+            .await
+            .unwrap();
+        let owner = users
+            .create_user("owner@example.com", "hash", false)
+            .await
+            .unwrap();
+        let account = service_accounts
+            .create_service_account("ci", None, &owner.email, None)
+            .await
+            .unwrap();
+        let (_, api_key) = service_accounts
+            .create_api_key(&account.id, &[SCOPE_SECRETS_READ.to_string()], None)
+            .await
+            .unwrap();
+        let role = app_roles
+            .create_role(
+                &AppRole {
+                    name: "deploy".to_string(),
+                    scopes: vec![SCOPE_SECRETS_READ.to_string()],
+                    secret_id_ttl_minutes: None,
+                    secret_id_num_uses: Some(0),
+                    token_ttl_minutes: None,
+                },
+                &owner.email,
+                None,
+            )
+            .await
+            .unwrap();
+        let (_, secret_id) = app_roles.create_secret_id(&role).await.unwrap();
+        assert!(authenticate_api_key(&api_key, &service_accounts, &users)
+            .await
+            .is_ok());
+        assert!(
+            authenticate_app_role(&role.role_id, &secret_id, &app_roles, &users)
+                .await
+                .is_ok()
+        );
+
+        let token = issue_user_token(&admin, &keys).await.unwrap();
+        let rocket = rocket::build()
+            .manage(users.clone())
+            .manage(keys.clone())
+            .manage(Arc::new(PolicyRepository::new(&db, &name, "policies")))
+            .manage(service_accounts.clone())
+            .manage(app_roles.clone())
+            .mount("/", routes![delete_user]);
+        let client = Client::untracked(rocket).await.unwrap();
+        let response = client
+            .delete(format!("/delete/user/{}", owner.id.to_hex()))
+            .header(Header::new("Authorization", format!("Bearer {token}")))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        assert!(authenticate_api_key(&api_key, &service_accounts, &users)
+            .await
+            .is_err());
+        assert!(
+            authenticate_app_role(&role.role_id, &secret_id, &app_roles, &users)
+                .await
+                .is_err()
+        );
+        assert!(service_accounts
+            .list_service_accounts(&owner.email)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(app_roles.list_roles(&owner.email).await.unwrap().is_empty());
+
+        db.database(&name).drop().await.unwrap();
+    }
+}
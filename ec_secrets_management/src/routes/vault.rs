@@ -3,63 +3,113 @@ Custom modules
 --------------*/
 use crate::models::*;
 use crate::request_guards::TokenGuard;
-use ec_secrets_shared_library::models::{Secret, VaultDocument};
-use ec_secrets_shared_library::repositories::vault::VaultRepository;
+use crate::routes::policies::{authorized_vault, policy_error};
+use crate::routes::responses::{error_response, internal_error};
+use ec_secrets_shared_library::models::{Secret, SecretFile, VaultDocument};
+use ec_secrets_shared_library::repositories::{
+    groups::GroupRepository, organizations::OrganizationRepository, policies::PolicyRepository,
+    users::UserRepository, vault::VaultRepository,
+};
+use ec_secrets_shared_library::utils::auth::{SCOPE_SECRETS_READ, SCOPE_SECRETS_WRITE};
+use ec_secrets_shared_library::utils::secret::{SecretBytes, SecretString};
+use ec_secrets_shared_library::utils::vault::{
+    stream::{self, StreamError},
+    KdfParams,
+};
 
 /*-------------
 3rd party modules
 --------------*/
+use futures::io::AsyncRead;
 use log::{error, info};
-use rocket::http::Status;
+use rocket::data::{ByteUnit, Data, Limits, ToByteUnit};
+use rocket::form::{Form, FromForm};
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
 use rocket::serde::json::Json;
 use rocket::{delete, get, post, routes, State};
+use tokio::task::spawn_blocking;
+use tokio_util::compat::TokioAsyncReadCompatExt;
 
 /*-------------
 stdlib modules
 --------------*/
+use std::io::Cursor;
 use std::sync::Arc;
 
+/// Upload limit, the `file` limit of Rocket.toml.
+fn file_limit(limits: &Limits) -> ByteUnit {
+    limits.get("file").unwrap_or_else(|| 1.mebibytes())
+}
+
+/// A binary value sent back as is, with its content type and file name.
+pub struct FileDownload(SecretFile);
+
+impl<'r> Responder<'r, 'static> for FileDownload {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let SecretFile {
+            file_name,
+            content_type,
+            data,
+        } = self.0;
+        let mut response = Response::build();
+        response
+            .header(ContentType::parse_flexible(&content_type).unwrap_or(ContentType::Binary))
+            .sized_body(data.len(), Cursor::new(data));
+        if let Some(file_name) = file_name {
+            response.raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", file_name.replace('"', "")),
+            );
+        }
+        response.ok()
+    }
+}
+
+/// The last component of an uploaded file name, never a path.
+fn upload_name(name: &str) -> Option<String> {
+    name.rsplit(['/', '\\'])
+        .next()
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+        .map(str::to_string)
+}
+
 /*---------------------
  Create a vault entry
 ---------------------*/
 #[post("/create/vault/entry", data = "<secret>")]
 pub async fn create_secret(
     repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
     secret: Json<Secret>,
     claims: TokenGuard,
 ) -> Result<Json<CreateSecretResponse>, Json<ErrorResponse>> {
-    if let Some(created_by) = claims.0.get_claim("sub") {
-        if let Some(created_by) = created_by.as_str() {
-            match repo
-                .create_secret(&secret.key, &secret.value, created_by)
-                .await
-            {
-                Ok(_) => {
-                    info!("Vault entry created successfully.");
-                    Ok(Json(CreateSecretResponse {
-                        status: Status::Ok.code,
-                        message: "Vault entry created successfully".to_string(),
-                    }))
-                }
-                Err(e) => {
-                    error!("Failed to create vault entry: {:?}", e);
-                    Err(Json(ErrorResponse {
-                        status: Status::InternalServerError.code,
-                        message: "Failed to create vault entry".to_string(),
-                    }))
-                }
-            }
-        } else {
-            Err(Json(ErrorResponse {
-                status: Status::Unauthorized.code,
-                message: "Insufficient Permissions".to_string(),
+    claims.require_scope(SCOPE_SECRETS_WRITE)?;
+    let vault =
+        authorized_vault(&claims, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    let created = if secret.end_to_end {
+        vault.create_sealed_secret(&secret.key, &secret.value).await
+    } else {
+        vault.create_secret(&secret.key, &secret.value).await
+    };
+    match created {
+        Ok(_) => {
+            info!("Vault entry created successfully.");
+            Ok(Json(CreateSecretResponse {
+                status: Status::Ok.code,
+                message: "Vault entry created successfully".to_string(),
             }))
         }
-    } else {
-        Err(Json(ErrorResponse {
-            status: Status::Unauthorized.code,
-            message: "Insufficient Permissions".to_string(),
-        }))
+        Err(e) => {
+            error!("Failed to create vault entry: {:?}", e);
+            Err(policy_error(e))
+        }
     }
 }
 
@@ -69,34 +119,24 @@ pub async fn create_secret(
 #[get("/retrieve/vault/entries")]
 pub async fn list_entries(
     repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
     token: TokenGuard,
 ) -> Result<Json<Vec<VaultDocument>>, Json<ErrorResponse>> {
-    if let Some(subject) = token.0.get_claim("sub") {
-        if let Some(subject) = subject.as_str() {
-            match repo.list_secrets(subject).await {
-                Ok(entries) => {
-                    info!("Successfully retrieved {} vault entries.", entries.len());
-                    Ok(Json(entries)) // Always return an array, even if empty
-                }
-                Err(_) => {
-                    error!("Failed to retrieve vault entries.");
-                    Err(Json(ErrorResponse {
-                        status: Status::InternalServerError.code,
-                        message: "Failed to retrieve vault entries.".to_string(),
-                    }))
-                }
-            }
-        } else {
-            Err(Json(ErrorResponse {
-                status: Status::Unauthorized.code,
-                message: "Insufficient Permissions".to_string(),
-            }))
+    token.require_scope(SCOPE_SECRETS_READ)?;
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    match vault.list_secrets().await {
+        Ok(entries) => {
+            info!("Successfully retrieved {} vault entries.", entries.len());
+            Ok(Json(entries)) // Always return an array, even if empty
+        }
+        Err(e) => {
+            error!("Failed to retrieve vault entries.");
+            Err(policy_error(e))
         }
-    } else {
-        Err(Json(ErrorResponse {
-            status: Status::Unauthorized.code,
-            message: "Insufficient Permissions".to_string(),
-        }))
     }
 }
 
@@ -106,9 +146,14 @@ pub async fn list_entries(
 #[get("/retrieve/vault/entries/<id>")]
 pub async fn get_entry(
     repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
     id: &str,
     token: TokenGuard,
-) -> Result<Json<String>, Json<ErrorResponse>> {
+) -> Result<Json<SecretString>, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_READ)?;
     if id.trim().is_empty() {
         error!("Invalid request: Provided ID is empty.");
         return Err(Json(ErrorResponse {
@@ -116,53 +161,45 @@ pub async fn get_entry(
             message: "Invalid ID provided.".to_string(),
         }));
     }
-    if let Some(subject) = token.0.get_claim("sub") {
-        if let Some(subject) = subject.as_str() {
-            match repo.get_secret_by_id(&id, subject).await {
-                Ok(Some(entry)) => {
-                    info!("Successfully retrieved vault entry with ID: {}", id);
-                    Ok(Json(entry))
-                }
-                Ok(None) => {
-                    error!("Vault entry not found with ID: {}", id);
-                    Err(Json(ErrorResponse {
-                        status: Status::NotFound.code,
-                        message: "Vault entry not found.".to_string(),
-                    }))
-                }
-                Err(e) => {
-                    error!(
-                        "Failed to retrieve vault entry by ID: {}. Error: {:?}",
-                        id, e
-                    );
-                    Err(Json(ErrorResponse {
-                        status: Status::InternalServerError.code,
-                        message: "Failed to retrieve vault entry.".to_string(),
-                    }))
-                }
-            }
-        } else {
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    match vault.get_secret_by_id(id).await {
+        Ok(Some(entry)) => {
+            info!("Successfully retrieved vault entry with ID: {}", id);
+            Ok(Json(entry))
+        }
+        Ok(None) => {
+            error!("Vault entry not found with ID: {}", id);
             Err(Json(ErrorResponse {
-                status: Status::Unauthorized.code,
-                message: "Insufficient Permissions".to_string(),
+                status: Status::NotFound.code,
+                message: "Vault entry not found.".to_string(),
             }))
         }
-    } else {
-        Err(Json(ErrorResponse {
-            status: Status::Unauthorized.code,
-            message: "Insufficient Permissions".to_string(),
-        }))
+        Err(e) => {
+            error!(
+                "Failed to retrieve vault entry by ID: {}. Error: {:?}",
+                id, e
+            );
+            Err(policy_error(e))
+        }
     }
 }
 
 /*---------------------------------
- Retrieve a vault entry by author
+ Retrieve the entries of an author
+ that the caller may read
 ----------------------------------*/
 #[get("/retrieve/vault/entry/<created_by>")]
 pub async fn get_entry_by_author(
     repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
     created_by: &str,
+    token: TokenGuard,
 ) -> Result<Json<Vec<VaultDocument>>, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_READ)?;
     if created_by.trim().is_empty() {
         error!("Invalid request: Provided author name is empty.");
         return Err(Json(ErrorResponse {
@@ -171,7 +208,9 @@ pub async fn get_entry_by_author(
         }));
     }
 
-    match repo.get_secret_by_author(created_by).await {
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    match vault.secrets_by_author(created_by).await {
         Ok(secrets) if !secrets.is_empty() => {
             info!(
                 "Successfully retrieved {} vault entries for author: {}",
@@ -192,10 +231,7 @@ pub async fn get_entry_by_author(
                 "Failed to retrieve vault entries for author: {}. Error: {:?}",
                 created_by, e
             );
-            Err(Json(ErrorResponse {
-                status: Status::InternalServerError.code,
-                message: "Failed to retrieve vault entries.".to_string(),
-            }))
+            Err(policy_error(e))
         }
     }
 }
@@ -206,9 +242,14 @@ pub async fn get_entry_by_author(
 #[delete("/delete/<id>")]
 pub async fn delete_entry(
     repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
     id: &str,
     token: TokenGuard,
 ) -> Result<Json<DeleteSecretResponse>, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_WRITE)?;
     if id.trim().is_empty() || id.contains(char::is_whitespace) {
         error!("Invalid request: Provided ID '{}' is invalid.", id);
         return Err(Json(ErrorResponse {
@@ -217,54 +258,317 @@ pub async fn delete_entry(
         }));
     }
 
-    if let Some(subject) = token.0.get_claim("sub") {
-        if let Some(subject) = subject.as_str() {
-            match repo.delete_secret(&id, subject).await {
-                Ok(Some(_)) => {
-                    info!("Successfully deleted vault entry with ID: {}", id);
-                    Ok(Json(DeleteSecretResponse {
-                        status: Status::Ok.code,
-                        message: "Vault entry deleted successfully.".to_string(),
-                    }))
-                }
-                Ok(None) => {
-                    error!("Vault entry not found for deletion with ID: {}", id);
-                    Err(Json(ErrorResponse {
-                        status: Status::NotFound.code,
-                        message: "Vault entry not found.".to_string(),
-                    }))
-                }
-                Err(e) => {
-                    error!(
-                        "Failed to delete vault entry with ID: {}. Error: {:?}",
-                        id, e
-                    );
-                    Err(Json(ErrorResponse {
-                        status: Status::InternalServerError.code,
-                        message: "Failed to delete vault entry.".to_string(),
-                    }))
-                }
-            }
-        } else {
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    match vault.delete_secret(id).await {
+        Ok(Some(_)) => {
+            info!("Successfully deleted vault entry with ID: {}", id);
+            Ok(Json(DeleteSecretResponse {
+                status: Status::Ok.code,
+                message: "Vault entry deleted successfully.".to_string(),
+            }))
+        }
+        Ok(None) => {
+            error!("Vault entry not found for deletion with ID: {}", id);
             Err(Json(ErrorResponse {
-                status: Status::Unauthorized.code,
-                message: "Insufficient Permissions".to_string(),
+                status: Status::NotFound.code,
+                message: "Vault entry not found.".to_string(),
             }))
         }
-    } else {
-        Err(Json(ErrorResponse {
-            status: Status::Unauthorized.code,
-            message: "Insufficient Permissions".to_string(),
-        }))
+        Err(e) => {
+            error!(
+                "Failed to delete vault entry with ID: {}. Error: {:?}",
+                id, e
+            );
+            Err(policy_error(e))
+        }
     }
 }
 
+async fn store_file<R: AsyncRead + Unpin>(
+    vault: &ec_secrets_shared_library::utils::policy::AuthorizedVault<'_>,
+    key: &str,
+    file_name: Option<String>,
+    content_type: String,
+    reader: &mut R,
+    limit: ByteUnit,
+) -> Result<Json<CreateFileResponse>, Json<ErrorResponse>> {
+    if key.trim().is_empty() {
+        return Err(error_response(Status::BadRequest, "A key is required."));
+    }
+    match vault
+        .create_file_from(key, file_name, content_type, reader, limit.as_u64())
+        .await
+    {
+        Ok(entry) => {
+            info!("Binary vault entry created successfully.");
+            Ok(Json(CreateFileResponse {
+                status: Status::Ok.code,
+                id: entry.id.to_hex(),
+                size: entry.file.map_or(0, |file| file.size),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to create binary vault entry: {:?}", e);
+            Err(policy_error(e))
+        }
+    }
+}
+
+/*---------------------------------------------
+ Store the raw request body as a binary vault
+ entry, typed by its Content-Type. The body is
+ encrypted as it arrives, never buffered whole.
+----------------------------------------------*/
+#[allow(clippy::too_many_arguments)]
+#[post("/vault/files?<key>&<name>", data = "<data>", rank = 2)]
+pub async fn upload_file(
+    repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
+    limits: &Limits,
+    key: &str,
+    name: Option<&str>,
+    content_type: Option<&ContentType>,
+    data: Data<'_>,
+    token: TokenGuard,
+) -> Result<Json<CreateFileResponse>, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_WRITE)?;
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    let limit = file_limit(limits);
+    // A byte past the limit, so oversized bodies are refused rather than cut
+    let mut body = data.open(limit + 1).compat();
+    let content_type = content_type
+        .map(ToString::to_string)
+        .unwrap_or_else(|| ContentType::Binary.to_string());
+    let file_name = name.and_then(upload_name);
+    store_file(&vault, key, file_name, content_type, &mut body, limit).await
+}
+
+#[derive(FromForm)]
+pub struct FileUpload<'r> {
+    file: TempFile<'r>,
+}
+
+/*---------------------------------------------
+ Store the `file` part of a multipart form as a
+ binary vault entry
+----------------------------------------------*/
+#[allow(clippy::too_many_arguments)]
+#[post(
+    "/vault/files?<key>",
+    data = "<upload>",
+    format = "multipart/form-data"
+)]
+pub async fn upload_multipart_file(
+    repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
+    limits: &Limits,
+    key: &str,
+    upload: Form<FileUpload<'_>>,
+    token: TokenGuard,
+) -> Result<Json<CreateFileResponse>, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_WRITE)?;
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    let upload = upload.into_inner().file;
+    let file_name = upload
+        .raw_name()
+        .and_then(|name| upload_name(name.dangerous_unsafe_unsanitized_raw().as_str()));
+    let content_type = upload
+        .content_type()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| ContentType::Binary.to_string());
+    let reader = upload.open().await.map_err(|e| {
+        error!("Failed to read multipart upload: {e}");
+        error_response(Status::BadRequest, "Failed to read the uploaded file.")
+    })?;
+    let mut reader = Box::pin(reader).compat();
+    store_file(
+        &vault,
+        key,
+        file_name,
+        content_type,
+        &mut reader,
+        file_limit(limits),
+    )
+    .await
+}
+
+/*---------------------------------------------
+ Download the raw bytes of a vault entry, by id
+ or by key
+----------------------------------------------*/
+#[get("/vault/files/<id>")]
+pub async fn download_file(
+    repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
+    id: &str,
+    token: TokenGuard,
+) -> Result<FileDownload, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_READ)?;
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    match vault.get_file_by_id(id).await.map_err(policy_error)? {
+        Some(file) => Ok(FileDownload(file)),
+        None => Err(error_response(Status::NotFound, "Vault entry not found.")),
+    }
+}
+
+#[get("/vault/files?<key>")]
+pub async fn download_file_by_key(
+    repo: &State<Arc<VaultRepository>>,
+    policy_repo: &State<Arc<PolicyRepository>>,
+    org_repo: &State<Arc<OrganizationRepository>>,
+    user_repo: &State<Arc<UserRepository>>,
+    group_repo: &State<Arc<GroupRepository>>,
+    key: &str,
+    token: TokenGuard,
+) -> Result<FileDownload, Json<ErrorResponse>> {
+    token.require_scope(SCOPE_SECRETS_READ)?;
+    let vault =
+        authorized_vault(&token, repo, org_repo, policy_repo, group_repo, user_repo).await?;
+    match vault.get_file_by_key(key).await.map_err(policy_error)? {
+        Some(file) => Ok(FileDownload(file)),
+        None => Err(error_response(Status::NotFound, "Vault entry not found.")),
+    }
+}
+
+/// The caller's passphrase for the encryption tools, never stored and
+/// zeroized once the request is done with it.
+pub struct EncryptionKey(SecretString);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for EncryptionKey {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.headers().get_one("X-Encryption-Key") {
+            Some(key) if !key.is_empty() => {
+                request::Outcome::Success(Self(SecretString::from(key)))
+            }
+            _ => request::Outcome::Error((Status::BadRequest, ())),
+        }
+    }
+}
+
+async fn read_body(data: Data<'_>, limits: &Limits) -> Result<Vec<u8>, Json<ErrorResponse>> {
+    let limit = file_limit(limits);
+    let body = data
+        .open(limit)
+        .into_bytes()
+        .await
+        .map_err(|_| error_response(Status::BadRequest, "Failed to read the request body."))?;
+    if !body.is_complete() {
+        return Err(error_response(
+            Status::PayloadTooLarge,
+            &format!("Uploads are limited to {limit}."),
+        ));
+    }
+    Ok(body.into_inner())
+}
+
+/*---------------------------------------------
+ Encrypt the request body with the passphrase
+ in X-Encryption-Key, in the format written by
+ `encrypt_file`. A tar archive encrypted this
+ way is what `encrypt_directory` writes.
+----------------------------------------------*/
+#[post("/tools/encrypt", data = "<data>")]
+pub async fn encrypt_upload(
+    limits: &Limits,
+    key: EncryptionKey,
+    data: Data<'_>,
+    token: TokenGuard,
+) -> Result<FileDownload, Json<ErrorResponse>> {
+    token.require_user()?;
+    let body = read_body(data, limits).await?;
+    let encrypted = spawn_blocking(move || {
+        stream::encrypt_stream(&mut body.as_slice(), Vec::new(), key.0.as_bytes())
+    })
+    .await
+    .map_err(|e| internal_error("Upload encryption", e))?
+    .map_err(|e| internal_error("Upload encryption", e))?;
+    Ok(FileDownload(SecretFile {
+        file_name: None,
+        content_type: ContentType::Binary.to_string(),
+        data: encrypted.into(),
+    }))
+}
+
+/// Argon2 costs accepted in the header of data to decrypt. The costs come
+/// from the uploader and the server derives the key, so the bound is far
+/// below what local decryption allows.
+const MAX_UPLOAD_KDF: KdfParams = KdfParams {
+    memory_kib: 64 * 1024,
+    iterations: 8,
+    lanes: 4,
+};
+
+/*---------------------------------------------
+ Decrypt data written by `encrypt_file` or
+ `encrypt_directory` (a tar archive)
+----------------------------------------------*/
+#[post("/tools/decrypt", data = "<data>")]
+pub async fn decrypt_upload(
+    limits: &Limits,
+    key: EncryptionKey,
+    data: Data<'_>,
+    token: TokenGuard,
+) -> Result<FileDownload, Json<ErrorResponse>> {
+    token.require_user()?;
+    let body = read_body(data, limits).await?;
+    let decrypted = spawn_blocking(move || {
+        let mut decrypted = SecretBytes::default();
+        stream::decrypt_stream_bounded(
+            body.as_slice(),
+            &mut *decrypted,
+            key.0.as_bytes(),
+            &MAX_UPLOAD_KDF,
+        )
+        .map(|_| decrypted)
+    })
+    .await
+    .map_err(|e| internal_error("Upload decryption", e))?
+    .map_err(|e| match e {
+        StreamError::Io(_) | StreamError::Hashing(_) => internal_error("Upload decryption", e),
+        StreamError::InvalidHeader(reason) => error_response(
+            Status::BadRequest,
+            &format!("The data can't be decrypted here ({reason}), decrypt it with the CLI."),
+        ),
+        _ => error_response(
+            Status::BadRequest,
+            "The data could not be decrypted with this key.",
+        ),
+    })?;
+    Ok(FileDownload(SecretFile {
+        file_name: None,
+        content_type: ContentType::Binary.to_string(),
+        data: decrypted,
+    }))
+}
+
 pub fn vault_routes() -> Vec<rocket::Route> {
     routes![
         create_secret,
         list_entries,
         get_entry,
         get_entry_by_author,
-        delete_entry
+        delete_entry,
+        upload_file,
+        upload_multipart_file,
+        download_file,
+        download_file_by_key,
+        encrypt_upload,
+        decrypt_upload
     ]
 }
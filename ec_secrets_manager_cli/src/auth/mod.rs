@@ -1,190 +1,1512 @@
 #![allow(dead_code)]
 
-use home;
-use pasetors::{
-    Public,
-    claims::{Claims, ClaimsValidationRules},
-    public,
-    token::UntrustedToken,
-    version4::V4,
-};
-use prettytable::{Cell, Row, Table};
-use std::fs;
+use mongodb::bson::oid::ObjectId;
+use pasetors::claims::Claims;
 
+use crate::{
+    config::{self, Profile},
+    error::{CliError, ErrorKind},
+    models::{
+        ApiKeySummary, AppRoleSummary, E2eKey, GroupMemberSummary, GroupSummary, Identity,
+        InstanceSettings, InvitationSummary, IssuedApiKey, IssuedInvitation, IssuedSecretId,
+        MfaEnrollment, OrganizationSummary, PolicyDecision, PolicySummary, RecoveryCode,
+        ReencryptSummary, SealedSecret, SecretSummary, SecretValue, ServiceAccountSummary,
+        StoredFile, UserSummary,
+    },
+};
 use ec_secrets_shared_library::{
-    db::connect,
-    models::{User, UserCredentials},
-    repositories::{keys::KeyRepository, users::UserRepository, vault::VaultRepository},
-    utils::auth::{authorize_user, decode_keys, hash_password},
+    db::connect_with,
+    models::{
+        AppRole, E2eRecipient, Group, Invitation, Organization, Policy, PolicyAttachment,
+        PolicyCheck, PolicyRules, PrincipalKind, SecretFile, UserCredentials, UserDocument,
+    },
+    repositories::{
+        app_roles::AppRoleRepository, groups::GroupRepository, invitations::InvitationRepository,
+        keys::KeyRepository, login_attempts::LoginAttemptRepository,
+        organizations::OrganizationRepository, policies::PolicyRepository,
+        service_accounts::ServiceAccountRepository, settings::SettingsRepository,
+        user_tokens::UserTokenRepository, users::UserRepository, vault::VaultRepository,
+    },
+    utils::{
+        account::{self, registration_open},
+        auth::{
+            LoginOutcome, MFA_CHALLENGE, SCOPE_SECRETS_READ, SCOPE_SECRETS_WRITE, SCOPES,
+            authenticate_api_key, authenticate_app_role, authorize_user, complete_mfa_login,
+            ensure_token_current, has_scope, is_admin, is_machine_identity, issue_user_token,
+            pending_mfa, sign_claims, token_groups, token_org, token_user, verify_token,
+        },
+        groups::{self, GroupError},
+        mailer::mailer_from_env,
+        mfa,
+        organizations::{self, Adopted},
+        password::{hash_password, validate_password},
+        policy::{self, AuthorizedVault},
+        recipients::{self, RecipientError},
+        reencryption::reencrypt_all,
+        secret::SecretString,
+        template::{self, TemplateError},
+        vault::e2e::{self, RecipientKey, RecipientKeyPair},
+    },
 };
 
 pub struct AuthenticatedUser {
+    profile_name: String,
+    profile: Profile,
     claims: Option<Claims>,
     user_repo: Option<UserRepository>,
     key_repo: Option<KeyRepository>,
     vault_repo: Option<VaultRepository>,
+    service_account_repo: Option<ServiceAccountRepository>,
+    app_role_repo: Option<AppRoleRepository>,
+    settings_repo: Option<SettingsRepository>,
+    login_attempt_repo: Option<LoginAttemptRepository>,
+    user_token_repo: Option<UserTokenRepository>,
+    invitation_repo: Option<InvitationRepository>,
+    group_repo: Option<GroupRepository>,
+    policy_repo: Option<PolicyRepository>,
+    organization_repo: Option<OrganizationRepository>,
+}
+
+/// Where a password login left the stored token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginResult {
+    LoggedIn,
+    EnrollmentRequired,
 }
 
 impl AuthenticatedUser {
-    pub async fn new() -> Self {
+    pub async fn new(profile_name: String, profile: Profile) -> Self {
         Self {
+            profile_name,
+            profile,
             claims: None,
             key_repo: None,
             user_repo: None,
             vault_repo: None,
+            service_account_repo: None,
+            app_role_repo: None,
+            settings_repo: None,
+            login_attempt_repo: None,
+            user_token_repo: None,
+            invitation_repo: None,
+            group_repo: None,
+            policy_repo: None,
+            organization_repo: None,
         }
     }
 
-    pub async fn get_repos(&mut self) -> Result<(), String> {
-        let repos = connect().await.map_err(|error| error.to_string())?;
-        self.key_repo = Some(repos.2);
-        self.user_repo = Some(repos.0);
-        self.vault_repo = Some(repos.1);
+    pub async fn get_repos(&mut self) -> Result<(), CliError> {
+        let repos = connect_with(
+            self.profile.server_url.as_deref(),
+            self.profile.database_name.as_deref(),
+        )
+        .await?;
+        self.key_repo = Some(repos.keys);
+        self.user_repo = Some(repos.users);
+        self.vault_repo = Some(repos.vault);
+        self.service_account_repo = Some(repos.service_accounts);
+        self.app_role_repo = Some(repos.app_roles);
+        self.settings_repo = Some(repos.settings);
+        self.login_attempt_repo = Some(repos.login_attempts);
+        self.user_token_repo = Some(repos.user_tokens);
+        self.invitation_repo = Some(repos.invitations);
+        self.group_repo = Some(repos.groups);
+        self.policy_repo = Some(repos.policies);
+        self.organization_repo = Some(repos.organizations);
         Ok(())
     }
-    pub async fn login(&mut self, creds: UserCredentials) -> Result<(), String> {
+
+    fn user_repo(&self) -> Result<&UserRepository, CliError> {
+        self.user_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn vault_repo(&self) -> Result<&VaultRepository, CliError> {
+        self.vault_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn service_account_repo(&self) -> Result<&ServiceAccountRepository, CliError> {
+        self.service_account_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn app_role_repo(&self) -> Result<&AppRoleRepository, CliError> {
+        self.app_role_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn settings_repo(&self) -> Result<&SettingsRepository, CliError> {
+        self.settings_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn login_attempt_repo(&self) -> Result<&LoginAttemptRepository, CliError> {
+        self.login_attempt_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn user_token_repo(&self) -> Result<&UserTokenRepository, CliError> {
+        self.user_token_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn invitation_repo(&self) -> Result<&InvitationRepository, CliError> {
+        self.invitation_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn group_repo(&self) -> Result<&GroupRepository, CliError> {
+        self.group_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn policy_repo(&self) -> Result<&PolicyRepository, CliError> {
+        self.policy_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn organization_repo(&self) -> Result<&OrganizationRepository, CliError> {
+        self.organization_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    fn subject(&self) -> Result<String, CliError> {
+        self.claims
+            .as_ref()
+            .and_then(|claims| claims.get_claim("sub"))
+            .and_then(|subject| subject.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| CliError::auth("Insufficient Permissions"))
+    }
+
+    fn key_repo(&self) -> Result<&KeyRepository, CliError> {
+        self.key_repo
+            .as_ref()
+            .ok_or_else(|| CliError::internal("Failed to connect to database"))
+    }
+
+    /*---------------------------------------------
+    Password login. Accounts with MFA are asked for
+    a code through `mfa_code`; accounts that still
+    have to enroll get an enrollment-only token.
+    ----------------------------------------------*/
+    pub async fn login(
+        &mut self,
+        creds: UserCredentials,
+        mfa_code: impl FnOnce() -> Result<String, String>,
+    ) -> Result<LoginResult, CliError> {
         self.get_repos().await?;
-        let Some(user_repo) = &self.user_repo else {
-            return Err("Failed to connect to database".to_owned());
+
+        let attempts = self.login_attempt_repo()?;
+        if let Some(locked_until) = attempts.locked_until(&creds.email).await? {
+            return Err(account_locked(&locked_until.to_rfc3339()));
+        }
+
+        let result = self.authorize(&creds, mfa_code).await;
+        let attempts = self.login_attempt_repo()?;
+        let (token, result) = match result {
+            Ok(authorized) => authorized,
+            Err(e) if e.kind == ErrorKind::Auth => {
+                if let Some(locked_until) = attempts.record_failure(&creds.email, None).await? {
+                    return Err(account_locked(&locked_until.to_rfc3339()));
+                }
+                return Err(e);
+            }
+            Err(e) => return Err(e),
         };
+        attempts.record_success(&creds.email).await?;
+        config::save_token(&self.profile_name, &self.profile, &token)?;
+
+        Ok(result)
+    }
+
+    async fn authorize(
+        &self,
+        creds: &UserCredentials,
+        mfa_code: impl FnOnce() -> Result<String, String>,
+    ) -> Result<(String, LoginResult), CliError> {
+        let Some(user) = self.user_repo()?.get_user_by_email(&creds.email).await? else {
+            return Err(CliError::auth("Invalid login credentials"));
+        };
+        let settings = self.settings_repo()?.get_settings().await?;
+
+        let outcome = authorize_user(
+            &user,
+            creds,
+            settings.require_mfa,
+            self.user_repo()?,
+            self.key_repo()?,
+        )
+        .await
+        .map_err(|_| CliError::auth("Invalid login credentials"))?;
+        Ok(match outcome {
+            LoginOutcome::Authenticated(token) => (token, LoginResult::LoggedIn),
+            LoginOutcome::MfaRequired(mfa_token) => {
+                let code = mfa_code().map_err(CliError::invalid_input)?;
+                let token =
+                    complete_mfa_login(&mfa_token, &code, self.user_repo()?, self.key_repo()?)
+                        .await
+                        .map_err(|_| CliError::auth("Invalid MFA code"))?;
+                (token, LoginResult::LoggedIn)
+            }
+            LoginOutcome::EnrollmentRequired(token) => (token, LoginResult::EnrollmentRequired),
+        })
+    }
+
+    /*---------------------------------------------
+    Exchange a service account API key for a token.
+    ----------------------------------------------*/
+    pub async fn login_with_api_key(&mut self, api_key: &str) -> Result<(), CliError> {
+        self.get_repos().await?;
 
-        let user_doc = user_repo
-            .get_user_by_email(&creds.email)
+        let claims = authenticate_api_key(api_key, self.service_account_repo()?, self.user_repo()?)
             .await
-            .map_err(|error| error.to_string())?;
-
-        if let Some(user_doc) = user_doc {
-            let user = User {
-                id: user_doc.id.to_string(),
-                email: user_doc.email.clone(),
-                password: user_doc.password.clone(),
-                created_at: user_doc.created_at.to_rfc3339(),
-            };
-            let Some(key_repo) = &self.key_repo else {
-                return Err("Failed to connect to database".to_owned());
-            };
-            let token = authorize_user(&user, &creds, key_repo).await?;
-            let Some(home_dir) = home::home_dir() else {
-                return Err("Error acccessing the home directory".to_owned());
-            };
-            let token_file = home_dir.join(".lock_smith.config");
-            fs::write(token_file, token).map_err(|error| error.to_string())?;
-        } else {
-            return Err("Invalid login credentials".to_owned());
+            .map_err(|_| CliError::auth("Invalid API key"))?;
+        let token = sign_claims(&claims, self.key_repo()?).await?;
+        config::save_token(&self.profile_name, &self.profile, &token)?;
+
+        Ok(())
+    }
+
+    /*---------------------------------------------
+    Exchange an AppRole role ID / secret ID pair.
+    ----------------------------------------------*/
+    pub async fn login_with_app_role(
+        &mut self,
+        role_id: &str,
+        secret_id: &str,
+    ) -> Result<(), CliError> {
+        self.get_repos().await?;
+
+        let claims =
+            authenticate_app_role(role_id, secret_id, self.app_role_repo()?, self.user_repo()?)
+                .await
+                .map_err(|_| CliError::auth("Invalid role ID or secret ID"))?;
+        let token = sign_claims(&claims, self.key_repo()?).await?;
+        config::save_token(&self.profile_name, &self.profile, &token)?;
+
+        Ok(())
+    }
+
+    pub async fn validate_token(&mut self) -> Result<(), CliError> {
+        self.load_claims().await?;
+        if self.claims.as_ref().and_then(pending_mfa).is_some() {
+            return Err(CliError::auth(
+                "MFA enrollment is required, run `mfa enroll` to continue",
+            ));
         }
+        Ok(())
+    }
 
+    /// Like [validate_token] but also accepts the enrollment-only token
+    /// issued when MFA is required and the account isn't enrolled yet.
+    async fn validate_enrollment_token(&mut self) -> Result<(), CliError> {
+        self.load_claims().await?;
+        let claims = self.claims.as_ref();
+        if claims.is_some_and(is_machine_identity)
+            || claims.and_then(pending_mfa) == Some(MFA_CHALLENGE)
+        {
+            return Err(CliError::auth("Please login as a user"));
+        }
         Ok(())
     }
 
-    pub async fn validate_token(&mut self) -> Result<(), String> {
+    async fn load_claims(&mut self) -> Result<(), CliError> {
+        let token = config::load_token(&self.profile_name).map_err(CliError::auth)?;
         self.get_repos().await?;
-        let Some(home_dir) = home::home_dir() else {
-            return Err("Error acccessing the home directory".to_owned());
+
+        let claims = verify_token(&token, self.key_repo()?).await.map_err(|_| {
+            CliError::auth("The stored token is invalid or expired, please login again")
+        })?;
+        ensure_token_current(&claims, self.user_repo()?)
+            .await
+            .map_err(|_| CliError::auth("The stored token has been revoked, please login again"))?;
+        self.claims = Some(claims);
+        Ok(())
+    }
+
+    /*---------------------------------------------
+    Discard the stored token for the active profile.
+    ----------------------------------------------*/
+    pub fn logout(&mut self) -> Result<bool, CliError> {
+        self.claims = None;
+        Ok(config::remove_token(&self.profile_name)?)
+    }
+
+    /*---------------------------------------------
+    Describe the identity behind the stored token.
+    ----------------------------------------------*/
+    pub async fn whoami(&mut self) -> Result<Identity, CliError> {
+        self.validate_token().await?;
+        let Some(claims) = &self.claims else {
+            return Err(CliError::auth("Token has no playload"));
+        };
+        let claim = |name: &str| {
+            claims
+                .get_claim(name)
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
         };
-        let token_file = home_dir.join(".lock_smith.config");
 
-        let token = fs::read_to_string(token_file).map_err(|error| error.to_string())?;
+        let org = match token_org(claims) {
+            Some(org_id) => Some(
+                self.organization_repo()?
+                    .get_organization_by_id(&org_id)
+                    .await?
+                    .map_or_else(|| org_id.to_hex(), |org| org.name),
+            ),
+            None => None,
+        };
+
+        Ok(Identity {
+            profile: self.profile_name.clone(),
+            subject: claim("sub"),
+            service_account: claim("service_account"),
+            app_role: claim("app_role"),
+            groups: token_groups(claims),
+            org,
+            expires: claim("exp"),
+            namespace: self.profile.namespace.clone(),
+        })
+    }
+
+    pub async fn get_users(&mut self, id: Option<&str>) -> Result<Vec<UserSummary>, CliError> {
+        self.validate_token().await?;
+        let user_repo = self.user_repo()?;
 
-        let untrusted_token = UntrustedToken::<Public, V4>::try_from(token.as_str())
-            .map_err(|error| error.to_string())?;
+        if let Some(id) = id {
+            parse_id(id)?;
+            match user_repo.get_user_by_id(id).await? {
+                Some(user) => Ok(vec![UserSummary::from(&user)]),
+                None => Err(CliError::not_found(format!("User '{id}' not found"))),
+            }
+        } else {
+            let users = user_repo.list_users().await?;
+            Ok(users.iter().map(UserSummary::from).collect())
+        }
+    }
 
-        let Some(key_repo) = &self.key_repo else {
-            return Err("Failed to connect to database".to_owned());
+    pub async fn delete_user(&mut self, id: Option<&str>) -> Result<(), CliError> {
+        self.validate_token().await?;
+        let Some(id) = id else {
+            return Err(CliError::invalid_input(
+                "Please provide an id for the account to delete",
+            ));
         };
+        parse_id(id)?;
+
+        match self.user_repo()?.delete_user(id).await? {
+            Some(user) => {
+                self.policy_repo()?
+                    .detach_all(PrincipalKind::User, &user.email)
+                    .await?;
+                Ok(())
+            }
+            None => Err(CliError::not_found(format!("User '{id}' not found"))),
+        }
+    }
+
+    pub async fn create_user(
+        &mut self,
+        creds: UserCredentials,
+        admin: bool,
+    ) -> Result<(), CliError> {
+        self.validate_token().await?;
+        let caller_is_admin = self.claims.as_ref().is_some_and(is_admin);
+        if admin && !caller_is_admin {
+            return Err(CliError::auth(
+                "Only admins can create admin accounts, use `admin bootstrap` for the first one",
+            ));
+        }
+        if !registration_open() && !caller_is_admin {
+            return Err(CliError::auth(
+                "Registration is closed, only admins can create accounts",
+            ));
+        }
+        validate_password(&creds.password).map_err(CliError::invalid_input)?;
+        let hashed_pwd = hash_password(&creds.password)?;
+        self.user_repo()?
+            .create_user(&creds.email, &hashed_pwd, admin)
+            .await?;
+        Ok(())
+    }
+
+    /*---------------------------------------------
+    Create the first admin account. Needs database
+    access only, refused once any admin exists.
+    ----------------------------------------------*/
+    pub async fn bootstrap_admin(&mut self, creds: UserCredentials) -> Result<(), CliError> {
+        self.get_repos().await?;
+        account::bootstrap_admin(&creds.email, &creds.password, self.user_repo()?).await?;
+        Ok(())
+    }
 
-        let keys = decode_keys(key_repo).await?;
+    /*---------------------------------------------
+    Invitations, issued and managed by admins.
+    ----------------------------------------------*/
+    pub async fn create_invitation(
+        &mut self,
+        invitation: &Invitation,
+    ) -> Result<IssuedInvitation, CliError> {
+        self.require_admin().await?;
+        let mailer = mailer_from_env().map_err(CliError::internal)?;
+        let (invitation, token) = account::invite_user(
+            invitation,
+            &self.subject()?,
+            self.user_repo()?,
+            self.invitation_repo()?,
+            mailer.as_ref(),
+        )
+        .await?;
+        Ok(IssuedInvitation {
+            id: invitation.id.to_hex(),
+            email: invitation.email,
+            role: invitation.role.as_str().to_string(),
+            token,
+            expires_at: invitation.expires_at.to_rfc3339(),
+        })
+    }
 
-        let validation_rules = ClaimsValidationRules::new();
+    pub async fn list_invitations(&mut self) -> Result<Vec<InvitationSummary>, CliError> {
+        self.require_admin().await?;
+        let invitations = self.invitation_repo()?.list_invitations().await?;
+        Ok(invitations.iter().map(InvitationSummary::from).collect())
+    }
 
-        let trusted_token =
-            public::verify(&keys.1, &untrusted_token, &validation_rules, None, None)
-                .map_err(|error| error.to_string())?;
+    pub async fn revoke_invitation(&mut self, id: &str) -> Result<(), CliError> {
+        self.require_admin().await?;
+        match self
+            .invitation_repo()?
+            .delete_invitation(&parse_id(id)?)
+            .await?
+        {
+            Some(_) => Ok(()),
+            None => Err(CliError::not_found(format!("Invitation '{id}' not found"))),
+        }
+    }
 
-        if let Some(claims) = trusted_token.payload_claims() {
-            self.claims = Some(claims.clone());
+    pub async fn accept_invitation(
+        &mut self,
+        token: &str,
+        password: &str,
+    ) -> Result<String, CliError> {
+        self.get_repos().await?;
+        let user =
+            account::accept_invitation(token, password, self.user_repo()?, self.invitation_repo()?)
+                .await?;
+        Ok(user.email)
+    }
+
+    /*---------------------------------------------
+    Password and email flows of the user. Changes
+    revoke existing tokens, so a fresh one is saved.
+    ----------------------------------------------*/
+    pub async fn change_password(
+        &mut self,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<(), CliError> {
+        self.validate_token().await?;
+        if self.claims.as_ref().is_some_and(is_machine_identity) {
+            return Err(CliError::auth("Please login as a user"));
+        }
+        let user = self.current_user().await?;
+        account::change_password(
+            &user,
+            current_password,
+            new_password,
+            self.user_repo()?,
+            self.user_token_repo()?,
+        )
+        .await?;
+
+        let user = self.current_user().await?;
+        let token = issue_user_token(&user, self.key_repo()?).await?;
+        config::save_token(&self.profile_name, &self.profile, &token)?;
+        Ok(())
+    }
+
+    pub async fn request_password_reset(&mut self, email: &str) -> Result<(), CliError> {
+        self.get_repos().await?;
+        let mailer = mailer_from_env().map_err(CliError::internal)?;
+        account::request_password_reset(
+            email,
+            self.user_repo()?,
+            self.user_token_repo()?,
+            mailer.as_ref(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn reset_password(
+        &mut self,
+        token: &str,
+        new_password: &str,
+    ) -> Result<(), CliError> {
+        self.get_repos().await?;
+        let user = account::reset_password(
+            token,
+            new_password,
+            self.user_repo()?,
+            self.user_token_repo()?,
+        )
+        .await?;
+        self.login_attempt_repo()?
+            .record_success(&user.email)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn verify_email(&mut self, token: &str) -> Result<(), CliError> {
+        self.get_repos().await?;
+        account::verify_email(token, self.user_repo()?, self.user_token_repo()?).await?;
+        Ok(())
+    }
+
+    pub async fn resend_verification(&mut self) -> Result<(), CliError> {
+        self.validate_token().await?;
+        if self.claims.as_ref().is_some_and(is_machine_identity) {
+            return Err(CliError::auth("Please login as a user"));
+        }
+        let user = self.current_user().await?;
+        if user.email_verified {
+            return Err(CliError::conflict("The email address is already verified"));
+        }
+        let mailer = mailer_from_env().map_err(CliError::internal)?;
+        account::send_email_verification(&user, self.user_token_repo()?, mailer.as_ref()).await?;
+        Ok(())
+    }
+
+    /*---------------------------------------------
+    MFA enrollment and management for the user.
+    ----------------------------------------------*/
+    pub async fn enroll_mfa(&mut self) -> Result<MfaEnrollment, CliError> {
+        self.validate_enrollment_token().await?;
+        let user = self.current_user().await?;
+        if user.mfa_enabled() {
+            return Err(CliError::conflict(
+                "MFA is already enabled for this account",
+            ));
+        }
+        let enrollment = mfa::begin_enrollment(self.user_repo()?, &user).await?;
+        Ok(MfaEnrollment {
+            secret: enrollment.secret,
+            otpauth_uri: enrollment.otpauth_uri,
+        })
+    }
+
+    /// Confirms the pending enrollment and replaces the stored token with a
+    /// full session.
+    pub async fn verify_mfa(&mut self, code: &str) -> Result<Vec<RecoveryCode>, CliError> {
+        self.validate_enrollment_token().await?;
+        let user = self.current_user().await?;
+        let recovery_codes = mfa::confirm_enrollment(self.user_repo()?, &user, code)
+            .await
+            .map_err(CliError::invalid_input)?;
+        let token = issue_user_token(&user, self.key_repo()?).await?;
+        config::save_token(&self.profile_name, &self.profile, &token)?;
+        Ok(recovery_codes.into_iter().map(RecoveryCode::from).collect())
+    }
+
+    pub async fn regenerate_recovery_codes(
+        &mut self,
+        code: &str,
+    ) -> Result<Vec<RecoveryCode>, CliError> {
+        self.owner().await?;
+        let user = self.enrolled_user().await?;
+        let recovery_codes = mfa::regenerate_recovery_codes(self.user_repo()?, &user, code)
+            .await
+            .map_err(CliError::auth)?;
+        Ok(recovery_codes.into_iter().map(RecoveryCode::from).collect())
+    }
+
+    pub async fn disable_mfa(&mut self, code: &str) -> Result<(), CliError> {
+        self.owner().await?;
+        if self.settings_repo()?.get_settings().await?.require_mfa {
+            return Err(CliError::auth("MFA is required for all accounts"));
+        }
+        let user = self.enrolled_user().await?;
+        mfa::disable(self.user_repo()?, &user, code)
+            .await
+            .map_err(CliError::auth)
+    }
+
+    async fn current_user(&self) -> Result<UserDocument, CliError> {
+        let claims = self
+            .claims
+            .as_ref()
+            .ok_or_else(|| CliError::auth("Insufficient Permissions"))?;
+        token_user(claims, self.user_repo()?)
+            .await
+            .map_err(CliError::auth)
+    }
+
+    async fn enrolled_user(&self) -> Result<UserDocument, CliError> {
+        let user = self.current_user().await?;
+        if !user.mfa_enabled() {
+            return Err(CliError::invalid_input(
+                "MFA is not enabled for this account",
+            ));
+        }
+        Ok(user)
+    }
+
+    /*---------------------------------------------
+    Instance settings, admins only.
+    ----------------------------------------------*/
+    pub async fn get_settings(&mut self) -> Result<InstanceSettings, CliError> {
+        self.require_admin().await?;
+        let settings = self.settings_repo()?.get_settings().await?;
+        Ok(InstanceSettings::from(settings))
+    }
+
+    pub async fn set_require_mfa(
+        &mut self,
+        require_mfa: bool,
+    ) -> Result<InstanceSettings, CliError> {
+        self.require_admin().await?;
+        let settings = self
+            .settings_repo()?
+            .set_require_mfa(require_mfa, &self.subject()?)
+            .await?;
+        Ok(InstanceSettings::from(settings))
+    }
+
+    /// Replaces the token signing key pair, every session token (this
+    /// one included) stops working.
+    pub async fn rotate_signing_key(&mut self) -> Result<(), CliError> {
+        self.require_admin().await?;
+        self.key_repo()?.rotate_key_pair().await?;
+        Ok(())
+    }
+
+    /// Re-encrypts everything stored encrypted with the parameters
+    /// configured for new data, see ECS_VAULT_CIPHER.
+    pub async fn reencrypt(&mut self) -> Result<ReencryptSummary, CliError> {
+        self.require_admin().await?;
+        let reencrypted = reencrypt_all(
+            self.organization_repo()?,
+            self.vault_repo()?,
+            self.key_repo()?,
+            self.user_repo()?,
+        )
+        .await?;
+        Ok(ReencryptSummary::from(reencrypted))
+    }
+
+    async fn require_admin(&mut self) -> Result<(), CliError> {
+        self.validate_token().await?;
+        if self.claims.as_ref().is_some_and(is_admin) {
             Ok(())
         } else {
-            Err("Token has no playload".into())
+            Err(CliError::auth("This operation requires an admin account"))
         }
     }
 
-    pub async fn get_users(&mut self, id: Option<&str>) -> Result<(), String> {
+    /// The stored token of an admin, for operations that go through the
+    /// server rather than the database.
+    pub async fn admin_token(&mut self) -> Result<String, CliError> {
+        self.require_admin().await?;
+        config::load_token(&self.profile_name).map_err(CliError::auth)
+    }
+
+    pub async fn list_secrets(&mut self) -> Result<Vec<SecretSummary>, CliError> {
         self.validate_token().await?;
+        self.require_scope(SCOPE_SECRETS_READ)?;
+        let secrets = self.authorized_vault().await?.list_secrets().await?;
+        Ok(secrets.iter().map(SecretSummary::from).collect())
+    }
 
-        let Some(user_repo) = &self.user_repo else {
-            return Err("Failed to connect to database".to_owned());
+    pub async fn list_secret_keys(&mut self) -> Result<Vec<String>, CliError> {
+        self.validate_token().await?;
+        self.require_scope(SCOPE_SECRETS_READ)?;
+        Ok(self.authorized_vault().await?.list_secret_keys().await?)
+    }
+
+    pub async fn get_secret(&mut self, key: &str) -> Result<SecretValue, CliError> {
+        self.validate_token().await?;
+        self.require_scope(SCOPE_SECRETS_READ)?;
+        let key = self.profile.qualify_key(key);
+        let Some(entry) = self
+            .authorized_vault()
+            .await?
+            .get_entry_by_key(&key)
+            .await?
+        else {
+            return Err(CliError::not_found(format!("Secret '{key}' not found")));
         };
+        let value = if entry.end_to_end {
+            e2e::open_value(&entry.value, &self.e2e_key_pair()?, &key)?
+        } else {
+            entry.value
+        };
+        Ok(SecretValue { key, value })
+    }
 
-        let mut table = Table::new();
-        table.add_row(Row::new(vec![
-            Cell::new("Id"),
-            Cell::new("Email"),
-            Cell::new("CreatedAt"),
-        ]));
+    /*---------------------------------------------
+    Binary entries, stored and returned as raw
+    bytes.
+    ----------------------------------------------*/
+    pub async fn upload_file(
+        &mut self,
+        key: &str,
+        file: &SecretFile,
+    ) -> Result<StoredFile, CliError> {
+        self.validate_token().await?;
+        self.require_scope(SCOPE_SECRETS_WRITE)?;
+        let key = self.profile.qualify_key(key);
+        let entry = self
+            .authorized_vault()
+            .await?
+            .create_file(&key, file)
+            .await?;
+        Ok(StoredFile {
+            id: entry.id.to_hex(),
+            key,
+            size: file.data.len() as u64,
+        })
+    }
 
-        if let Some(id) = id {
-            user_repo
-                .get_user_by_id(id)
-                .await
-                .map_err(|error| error.to_string())?
-                .map(|user| {
-                    table.add_row(Row::new(vec![
-                        Cell::new(user.id.to_string().as_str()),
-                        Cell::new(user.email.as_str()),
-                        Cell::new(user.created_at.to_string().as_str()),
-                    ]));
-                });
-        } else {
-            let users = user_repo
-                .list_users()
-                .await
-                .map_err(|error| error.to_string())?;
+    pub async fn download_file(&mut self, key: &str) -> Result<SecretFile, CliError> {
+        self.validate_token().await?;
+        self.require_scope(SCOPE_SECRETS_READ)?;
+        let key = self.profile.qualify_key(key);
+        self.authorized_vault()
+            .await?
+            .get_file_by_key(&key)
+            .await?
+            .ok_or_else(|| CliError::not_found(format!("Secret '{key}' not found")))
+    }
 
-            users.iter().for_each(|user| {
-                table.add_row(Row::new(vec![
-                    Cell::new(user.id.to_string().as_str()),
-                    Cell::new(user.email.as_str()),
-                    Cell::new(user.created_at.to_string().as_str()),
-                ]));
-            });
+    /*---------------------------------------------
+    Resolve the secret references of a template.
+    ----------------------------------------------*/
+    pub async fn render_template(&mut self, template: &str) -> Result<SecretString, CliError> {
+        self.validate_token().await?;
+        self.require_scope(SCOPE_SECRETS_READ)?;
+        let vault = self.authorized_vault().await?;
+        let profile = &self.profile;
+        let qualify = |key: &str| profile.qualify_key(key);
+        // Only ask for the key passphrase when an end-to-end encrypted
+        // secret is referenced
+        match template::render_from_vault(template, &vault, qualify, None).await {
+            Err(TemplateError::Sealed(_)) => {
+                let key_pair = self.e2e_key_pair()?;
+                template::render_from_vault(template, &vault, qualify, Some(&key_pair))
+                    .await
+                    .map_err(CliError::from)
+            }
+            rendered => rendered.map_err(CliError::from),
         }
-        table.printstd();
-        Ok(())
     }
 
-    pub async fn delete_user(&mut self, id: Option<&str>) -> Result<(), String> {
+    /*---------------------------------------------
+    End-to-end encrypted secrets, sealed and opened
+    here with the profile's key pair. Neither the
+    server nor the database see their plaintext.
+    ----------------------------------------------*/
+    pub async fn init_e2e(&mut self, force: bool) -> Result<E2eKey, CliError> {
         self.validate_token().await?;
-        let Some(user_repo) = &self.user_repo else {
-            return Err("Failed to connect to database".to_owned());
+        let user = self.current_user().await?;
+        if !force && config::has_e2e_key(&self.profile_name).map_err(CliError::internal)? {
+            return Err(CliError::conflict(format!(
+                "Profile '{}' already has an end-to-end encryption key, pass --force to replace it",
+                self.profile_name
+            )));
+        }
+        let key_pair = RecipientKeyPair::generate();
+        // Stored before registering, so no value is ever sealed for a key
+        // that only the server knows about
+        config::save_e2e_key(&self.profile_name, &key_pair).map_err(CliError::invalid_input)?;
+        let public_key = recipients::register_public_key(
+            &user,
+            &key_pair.public_key().to_base64(),
+            self.user_repo()?,
+        )
+        .await?;
+        Ok(E2eKey::from(&public_key))
+    }
+
+    /// The public key registered for the caller, what others seal for.
+    pub async fn e2e_public_key(&mut self) -> Result<E2eKey, CliError> {
+        self.validate_token().await?;
+        let user = self.current_user().await?;
+        let Some(public_key) = user.e2e_public_key else {
+            return Err(CliError::not_found(
+                "No end-to-end encryption key registered, run `e2e init` first",
+            ));
         };
-        let Some(id) = id else {
-            return Err("Please provide an id for the account to delete".to_owned());
+        Ok(E2eKey::from(&RecipientKey::from_base64(&public_key)?))
+    }
+
+    /// Stores `value` sealed for the caller, the users in `emails` and the
+    /// members of `groups`.
+    pub async fn seal_secret(
+        &mut self,
+        key: &str,
+        value: &str,
+        emails: &[String],
+        groups: &[String],
+    ) -> Result<SealedSecret, CliError> {
+        self.validate_token().await?;
+        self.require_scope(SCOPE_SECRETS_WRITE)?;
+        let key = self.profile.qualify_key(key);
+        let key_pair = self.e2e_key_pair()?;
+        let recipients = self
+            .recipients(key_pair.public_key(), emails, groups)
+            .await?;
+        let sealed = e2e::seal_value(value, &recipient_keys(&recipients)?, &key)?;
+        self.authorized_vault()
+            .await?
+            .create_sealed_secret(&key, &sealed)
+            .await?;
+        Ok(SealedSecret {
+            key,
+            recipients: recipients.into_iter().map(|r| r.email).collect(),
+        })
+    }
+
+    /// Seals an end-to-end encrypted secret the caller owns for more
+    /// recipients, e.g. members who joined a group after it was sealed.
+    pub async fn grant_secret(
+        &mut self,
+        key: &str,
+        emails: &[String],
+        groups: &[String],
+    ) -> Result<SealedSecret, CliError> {
+        self.validate_token().await?;
+        self.require_scope(SCOPE_SECRETS_WRITE)?;
+        if emails.is_empty() && groups.is_empty() {
+            return Err(RecipientError::Empty.into());
+        }
+        let key = self.profile.qualify_key(key);
+        let vault = self.authorized_vault().await?;
+        let Some(entry) = vault.get_entry_by_key(&key).await? else {
+            return Err(CliError::not_found(format!("Secret '{key}' not found")));
         };
+        if !entry.end_to_end {
+            return Err(CliError::invalid_input(format!(
+                "Secret '{key}' is not end-to-end encrypted"
+            )));
+        }
+        let key_pair = self.e2e_key_pair()?;
+        let recipients = self
+            .recipients(key_pair.public_key(), emails, groups)
+            .await?;
+        let sealed =
+            e2e::add_value_recipients(&entry.value, &key_pair, &recipient_keys(&recipients)?)?;
+        if !vault.reseal_secret(&key, &sealed).await? {
+            return Err(CliError::not_found(format!("You have no secret '{key}'")));
+        }
+        Ok(SealedSecret {
+            key,
+            recipients: recipients.into_iter().map(|r| r.email).collect(),
+        })
+    }
 
-        user_repo
-            .delete_user(id)
-            .await
-            .map_err(|error| error.to_string())?;
+    /// The caller, with its local `own_key`, followed by the users in
+    /// `emails` and the members of `groups` within the active organization,
+    /// each once. Their keys must match the fingerprints pinned for them.
+    async fn recipients(
+        &self,
+        own_key: &RecipientKey,
+        emails: &[String],
+        groups: &[String],
+    ) -> Result<Vec<E2eRecipient>, CliError> {
+        let user = self.current_user().await?;
+        let mut recipients = vec![E2eRecipient {
+            email: user.email,
+            public_key: own_key.to_base64(),
+        }];
+        if !emails.is_empty() || !groups.is_empty() {
+            let org = self.active_org().ok_or_else(|| {
+                CliError::invalid_input("No active organization, run `orgs switch` first")
+            })?;
+            for recipient in recipients::find_recipients(
+                &org,
+                emails,
+                groups,
+                self.user_repo()?,
+                self.group_repo()?,
+            )
+            .await?
+            {
+                if !recipients
+                    .iter()
+                    .any(|known| known.email == recipient.email)
+                {
+                    recipients.push(recipient);
+                }
+            }
+        }
 
-        Ok(())
+        let mut fingerprints = Vec::new();
+        for recipient in &recipients[1..] {
+            let fingerprint = RecipientKey::from_base64(&recipient.public_key)?.fingerprint();
+            fingerprints.push((recipient.email.clone(), fingerprint));
+        }
+        let changed =
+            config::pin_e2e_keys(&self.profile_name, &fingerprints).map_err(CliError::internal)?;
+        if !changed.is_empty() {
+            return Err(CliError::conflict(format!(
+                "The public key of {} changed since it was first used. Confirm the new \
+                 fingerprint with them, then run `e2e trust <email> <fingerprint>`",
+                changed.join(", ")
+            )));
+        }
+        Ok(recipients)
+    }
+
+    /// Pins the key fingerprint a recipient confirmed out of band.
+    pub fn trust_e2e_key(&self, email: &str, fingerprint: &str) -> Result<(), CliError> {
+        let valid = fingerprint.len() == 2 * e2e::KEY_ID_LEN
+            && fingerprint.chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Err(CliError::invalid_input(format!(
+                "'{fingerprint}' is not a key fingerprint"
+            )));
+        }
+        config::trust_e2e_key(&self.profile_name, email, fingerprint).map_err(CliError::internal)
+    }
+
+    fn e2e_key_pair(&self) -> Result<RecipientKeyPair, CliError> {
+        config::load_e2e_key(&self.profile_name)
+            .map_err(CliError::invalid_input)?
+            .ok_or_else(|| {
+                CliError::invalid_input(format!(
+                    "Profile '{}' has no end-to-end encryption key, run `e2e init` first",
+                    self.profile_name
+                ))
+            })
+    }
+
+    /// The vault as seen by the token, checked against its policies.
+    async fn authorized_vault(&self) -> Result<AuthorizedVault<'_>, CliError> {
+        let claims = self
+            .claims
+            .as_ref()
+            .ok_or_else(|| CliError::auth("Insufficient Permissions"))?;
+        Ok(AuthorizedVault::for_claims(
+            claims,
+            self.vault_repo()?,
+            self.organization_repo()?,
+            self.policy_repo()?,
+            self.group_repo()?,
+            self.user_repo()?,
+        )
+        .await?)
     }
 
-    pub async fn create_user(&mut self, creds: UserCredentials) -> Result<(), String> {
+    pub async fn create_secret(&mut self) -> Result<(), CliError> {
         self.validate_token().await?;
-        let Some(user_repo) = &self.user_repo else {
-            return Err("Failed to connect to database".to_owned());
+        self.require_scope(SCOPE_SECRETS_WRITE)?;
+
+        Ok(())
+    }
+
+    /*---------------------------------------------
+    Groups, managed by admins. Entries are shared
+    by their owner.
+    ----------------------------------------------*/
+    pub async fn create_group(&mut self, group: &Group) -> Result<GroupSummary, CliError> {
+        self.require_admin().await?;
+        let group = groups::create_group(group, &self.subject()?, self.group_repo()?).await?;
+        Ok(GroupSummary::from(&group))
+    }
+
+    pub async fn list_groups(&mut self) -> Result<Vec<GroupSummary>, CliError> {
+        self.require_admin().await?;
+        let groups = self.group_repo()?.list_groups().await?;
+        Ok(groups.iter().map(GroupSummary::from).collect())
+    }
+
+    pub async fn set_group_permissions(
+        &mut self,
+        name: &str,
+        permissions: &[String],
+    ) -> Result<GroupSummary, CliError> {
+        self.require_admin().await?;
+        let group = groups::set_permissions(name, permissions, self.group_repo()?).await?;
+        Ok(GroupSummary::from(&group))
+    }
+
+    pub async fn delete_group(&mut self, name: &str) -> Result<(), CliError> {
+        self.require_admin().await?;
+        groups::delete_group(
+            name,
+            self.group_repo()?,
+            self.user_repo()?,
+            self.vault_repo()?,
+            self.policy_repo()?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_group_members(
+        &mut self,
+        name: &str,
+    ) -> Result<Vec<GroupMemberSummary>, CliError> {
+        self.require_admin().await?;
+        let members = groups::list_members(name, self.group_repo()?, self.user_repo()?).await?;
+        Ok(members.iter().map(GroupMemberSummary::from).collect())
+    }
+
+    pub async fn add_group_member(&mut self, name: &str, email: &str) -> Result<(), CliError> {
+        self.require_admin().await?;
+        groups::add_member(name, email, self.group_repo()?, self.user_repo()?).await?;
+        Ok(())
+    }
+
+    pub async fn remove_group_member(&mut self, name: &str, email: &str) -> Result<(), CliError> {
+        self.require_admin().await?;
+        groups::remove_member(name, email, self.group_repo()?, self.user_repo()?).await?;
+        Ok(())
+    }
+
+    pub async fn share_secret(&mut self, name: &str, key: &str) -> Result<String, CliError> {
+        self.owner().await?;
+        self.require_scope(SCOPE_SECRETS_WRITE)?;
+        let key = self.profile.qualify_key(key);
+        groups::get_group(name, self.group_repo()?).await?;
+        if !self
+            .authorized_vault()
+            .await?
+            .share_secret(&key, name)
+            .await?
+        {
+            return Err(GroupError::SecretNotFound(key).into());
+        }
+        Ok(key)
+    }
+
+    pub async fn unshare_secret(&mut self, name: &str, key: &str) -> Result<String, CliError> {
+        self.owner().await?;
+        self.require_scope(SCOPE_SECRETS_WRITE)?;
+        let key = self.profile.qualify_key(key);
+        if !self
+            .authorized_vault()
+            .await?
+            .unshare_secret(&key, name)
+            .await?
+        {
+            return Err(GroupError::SecretNotFound(key).into());
+        }
+        Ok(key)
+    }
+
+    /*---------------------------------------------
+    Organizations, managed by admins. Members
+    switch between the ones they belong to.
+    ----------------------------------------------*/
+    pub async fn create_org(
+        &mut self,
+        org: &Organization,
+    ) -> Result<OrganizationSummary, CliError> {
+        self.require_admin().await?;
+        let org = organizations::create_organization(
+            org,
+            &self.subject()?,
+            self.organization_repo()?,
+            self.user_repo()?,
+            self.vault_repo()?,
+        )
+        .await?;
+        Ok(OrganizationSummary::from(&org))
+    }
+
+    /// Every organization for admins, the caller's own for everyone else.
+    pub async fn list_orgs(&mut self) -> Result<Vec<OrganizationSummary>, CliError> {
+        let owner = self.owner().await?;
+        let orgs = if self.claims.as_ref().is_some_and(is_admin) {
+            self.organization_repo()?.list_organizations().await?
+        } else {
+            let Some(user) = self.user_repo()?.get_user_by_email(&owner).await? else {
+                return Err(CliError::auth("Insufficient Permissions"));
+            };
+            organizations::user_organizations(&user, self.organization_repo()?).await?
         };
-        let hashed_pwd = hash_password(creds.password)?;
-        user_repo
-            .create_user(&creds.email, &hashed_pwd)
-            .await
-            .map_err(|error| error.to_string())?;
+        Ok(orgs.iter().map(OrganizationSummary::from).collect())
+    }
+
+    pub async fn delete_org(&mut self, name: &str) -> Result<(), CliError> {
+        self.require_admin().await?;
+        organizations::delete_organization(
+            name,
+            self.organization_repo()?,
+            self.user_repo()?,
+            self.vault_repo()?,
+        )
+        .await?;
         Ok(())
     }
 
-    pub async fn create_secret(&mut self) -> Result<(), String>{
-        self.validate_token().await?;
-        
+    pub async fn list_org_members(
+        &mut self,
+        name: &str,
+    ) -> Result<Vec<GroupMemberSummary>, CliError> {
+        self.require_admin().await?;
+        let members =
+            organizations::list_members(name, self.organization_repo()?, self.user_repo()?).await?;
+        Ok(members.iter().map(GroupMemberSummary::from).collect())
+    }
+
+    pub async fn add_org_member(&mut self, name: &str, email: &str) -> Result<(), CliError> {
+        self.require_admin().await?;
+        organizations::add_member(name, email, self.organization_repo()?, self.user_repo()?)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_org_member(&mut self, name: &str, email: &str) -> Result<(), CliError> {
+        self.require_admin().await?;
+        organizations::remove_member(name, email, self.organization_repo()?, self.user_repo()?)
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the stored token with one active in `name`.
+    pub async fn switch_org(&mut self, name: &str) -> Result<(), CliError> {
+        let owner = self.owner().await?;
+        if self.claims.as_ref().is_some_and(is_machine_identity) {
+            return Err(CliError::auth("Please login as a user"));
+        }
+        let Some(user) = self.user_repo()?.get_user_by_email(&owner).await? else {
+            return Err(CliError::auth("Insufficient Permissions"));
+        };
+        let token = organizations::switch_organization(
+            name,
+            &user,
+            self.organization_repo()?,
+            self.key_repo()?,
+        )
+        .await?;
+        config::save_token(&self.profile_name, &self.profile, &token)?;
         Ok(())
     }
+
+    /// Moves users and entries from before organizations existed into `name`.
+    pub async fn adopt_legacy(&mut self, name: &str) -> Result<Adopted, CliError> {
+        self.require_admin().await?;
+        Ok(organizations::adopt_legacy(
+            name,
+            self.organization_repo()?,
+            self.user_repo()?,
+            self.vault_repo()?,
+        )
+        .await?)
+    }
+
+    fn active_org(&self) -> Option<ObjectId> {
+        self.claims.as_ref().and_then(token_org)
+    }
+
+    /*---------------------------------------------
+    Policies, managed by admins. Anyone can check
+    their own access.
+    ----------------------------------------------*/
+    pub async fn create_policy(&mut self, policy: &Policy) -> Result<PolicySummary, CliError> {
+        self.require_admin().await?;
+        let policy = policy::create_policy(policy, &self.subject()?, self.policy_repo()?).await?;
+        Ok(PolicySummary::from(&policy))
+    }
+
+    pub async fn list_policies(&mut self) -> Result<Vec<PolicySummary>, CliError> {
+        self.require_admin().await?;
+        let policies = self.policy_repo()?.list_policies().await?;
+        Ok(policies.iter().map(PolicySummary::from).collect())
+    }
+
+    pub async fn get_policy(&mut self, name: &str) -> Result<PolicySummary, CliError> {
+        self.require_admin().await?;
+        let policy = policy::get_policy(name, self.policy_repo()?).await?;
+        Ok(PolicySummary::from(&policy))
+    }
+
+    pub async fn update_policy(
+        &mut self,
+        name: &str,
+        rules: &PolicyRules,
+    ) -> Result<PolicySummary, CliError> {
+        self.require_admin().await?;
+        let policy = policy::update_policy(name, rules, self.policy_repo()?).await?;
+        Ok(PolicySummary::from(&policy))
+    }
+
+    pub async fn delete_policy(&mut self, name: &str) -> Result<(), CliError> {
+        self.require_admin().await?;
+        policy::delete_policy(name, self.policy_repo()?).await?;
+        Ok(())
+    }
+
+    pub async fn attach_policy(
+        &mut self,
+        name: &str,
+        attachment: &PolicyAttachment,
+    ) -> Result<PolicySummary, CliError> {
+        self.require_admin().await?;
+        let policy = policy::attach_policy(
+            name,
+            attachment,
+            self.policy_repo()?,
+            self.user_repo()?,
+            self.group_repo()?,
+            self.service_account_repo()?,
+        )
+        .await?;
+        Ok(PolicySummary::from(&policy))
+    }
+
+    pub async fn detach_policy(
+        &mut self,
+        name: &str,
+        attachment: &PolicyAttachment,
+    ) -> Result<PolicySummary, CliError> {
+        self.require_admin().await?;
+        let policy = policy::detach_policy(name, attachment, self.policy_repo()?).await?;
+        Ok(PolicySummary::from(&policy))
+    }
+
+    /// Explains how a capability on a path is decided, for the caller or,
+    /// for admins, another user or a service account.
+    pub async fn check_policy(&mut self, check: &PolicyCheck) -> Result<PolicyDecision, CliError> {
+        self.validate_token().await?;
+        let claims = self
+            .claims
+            .as_ref()
+            .ok_or_else(|| CliError::auth("Insufficient Permissions"))?;
+        if (check.user.is_some() || check.service_account.is_some()) && !is_admin(claims) {
+            return Err(CliError::auth(
+                "Only admins can check the policies of others",
+            ));
+        }
+        let principal = policy::check_principal(
+            check,
+            claims,
+            self.user_repo()?,
+            self.service_account_repo()?,
+        )
+        .await?;
+        let org_vault = policy::org_vault(
+            claims,
+            self.vault_repo()?,
+            self.organization_repo()?,
+            self.user_repo()?,
+        )
+        .await?;
+        let vault = AuthorizedVault::new(
+            principal,
+            org_vault,
+            self.policy_repo()?,
+            self.group_repo()?,
+        )
+        .await?;
+        let decision = vault.explain(check.capability, &check.path).await?;
+        Ok(PolicyDecision::from(&decision))
+    }
+
+    /*---------------------------------------------
+    Service accounts, managed by their owner only.
+    ----------------------------------------------*/
+    pub async fn create_service_account(
+        &mut self,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<ServiceAccountSummary, CliError> {
+        let owner = self.owner().await?;
+        let account = self
+            .service_account_repo()?
+            .create_service_account(name, description, &owner, self.active_org())
+            .await?;
+        Ok(ServiceAccountSummary::from(&account))
+    }
+
+    pub async fn list_service_accounts(&mut self) -> Result<Vec<ServiceAccountSummary>, CliError> {
+        let owner = self.owner().await?;
+        let accounts = self
+            .service_account_repo()?
+            .list_service_accounts(&owner)
+            .await?;
+        Ok(accounts.iter().map(ServiceAccountSummary::from).collect())
+    }
+
+    pub async fn delete_service_account(&mut self, id: &str) -> Result<(), CliError> {
+        let owner = self.owner().await?;
+        match self
+            .service_account_repo()?
+            .delete_service_account(&parse_id(id)?, &owner)
+            .await?
+        {
+            Some(account) => {
+                self.policy_repo()?
+                    .detach_all(PrincipalKind::ServiceAccount, &account.id.to_hex())
+                    .await?;
+                Ok(())
+            }
+            None => Err(CliError::not_found(format!(
+                "Service account '{id}' not found"
+            ))),
+        }
+    }
+
+    pub async fn create_api_key(
+        &mut self,
+        account_id: &str,
+        scopes: &[String],
+        expires_in_days: Option<i64>,
+    ) -> Result<IssuedApiKey, CliError> {
+        if let Some(scope) = scopes
+            .iter()
+            .find(|scope| !SCOPES.contains(&scope.as_str()))
+        {
+            return Err(CliError::invalid_input(format!("Unknown scope '{scope}'")));
+        }
+        let account_id = self.owned_account(account_id).await?;
+        let (key, api_key) = self
+            .service_account_repo()?
+            .create_api_key(&account_id, scopes, expires_in_days)
+            .await?;
+        Ok(IssuedApiKey {
+            id: key.id.to_hex(),
+            api_key,
+            scopes: key.scopes,
+            expires_at: key.expires_at.map(|date| date.to_rfc3339()),
+        })
+    }
+
+    pub async fn list_api_keys(
+        &mut self,
+        account_id: &str,
+    ) -> Result<Vec<ApiKeySummary>, CliError> {
+        let account_id = self.owned_account(account_id).await?;
+        let keys = self
+            .service_account_repo()?
+            .list_api_keys(&account_id)
+            .await?;
+        Ok(keys.iter().map(ApiKeySummary::from).collect())
+    }
+
+    pub async fn revoke_api_key(&mut self, account_id: &str, key_id: &str) -> Result<(), CliError> {
+        let account_id = self.owned_account(account_id).await?;
+        match self
+            .service_account_repo()?
+            .revoke_api_key(&account_id, &parse_id(key_id)?)
+            .await?
+        {
+            Some(_) => Ok(()),
+            None => Err(CliError::not_found(format!(
+                "API key '{key_id}' not found or already revoked"
+            ))),
+        }
+    }
+
+    async fn owner(&mut self) -> Result<String, CliError> {
+        self.validate_token().await?;
+        if self.claims.as_ref().is_some_and(is_machine_identity) {
+            return Err(CliError::auth(
+                "Machine identities can't manage identities, please login as a user",
+            ));
+        }
+        self.subject()
+    }
+
+    /*---------------------------------------------
+    AppRoles, managed by their owner only.
+    ----------------------------------------------*/
+    pub async fn create_app_role(&mut self, role: &AppRole) -> Result<AppRoleSummary, CliError> {
+        if let Some(scope) = role
+            .scopes
+            .iter()
+            .find(|scope| !SCOPES.contains(&scope.as_str()))
+        {
+            return Err(CliError::invalid_input(format!("Unknown scope '{scope}'")));
+        }
+        let owner = self.owner().await?;
+        let role = self
+            .app_role_repo()?
+            .create_role(role, &owner, self.active_org())
+            .await?;
+        Ok(AppRoleSummary::from(&role))
+    }
+
+    pub async fn list_app_roles(&mut self) -> Result<Vec<AppRoleSummary>, CliError> {
+        let owner = self.owner().await?;
+        let roles = self.app_role_repo()?.list_roles(&owner).await?;
+        Ok(roles.iter().map(AppRoleSummary::from).collect())
+    }
+
+    pub async fn delete_app_role(&mut self, name: &str) -> Result<(), CliError> {
+        let owner = self.owner().await?;
+        match self.app_role_repo()?.delete_role(name, &owner).await? {
+            Some(_) => Ok(()),
+            None => Err(CliError::not_found(format!("AppRole '{name}' not found"))),
+        }
+    }
+
+    pub async fn create_secret_id(&mut self, name: &str) -> Result<IssuedSecretId, CliError> {
+        let owner = self.owner().await?;
+        let app_role_repo = self.app_role_repo()?;
+        let Some(role) = app_role_repo.get_role(name, &owner).await? else {
+            return Err(CliError::not_found(format!("AppRole '{name}' not found")));
+        };
+        let (document, secret_id) = app_role_repo.create_secret_id(&role).await?;
+        Ok(IssuedSecretId {
+            role: role.name,
+            secret_id,
+            uses_remaining: document.uses_remaining,
+            expires_at: document.expires_at.to_rfc3339(),
+        })
+    }
+
+    async fn owned_account(&mut self, id: &str) -> Result<ObjectId, CliError> {
+        let owner = self.owner().await?;
+        match self
+            .service_account_repo()?
+            .get_service_account(&parse_id(id)?, &owner)
+            .await?
+        {
+            Some(account) => Ok(account.id),
+            None => Err(CliError::not_found(format!(
+                "Service account '{id}' not found"
+            ))),
+        }
+    }
+
+    fn require_scope(&self, scope: &str) -> Result<(), CliError> {
+        match &self.claims {
+            Some(claims) if has_scope(claims, scope) => Ok(()),
+            _ => Err(CliError::auth(format!("Missing required scope '{scope}'"))),
+        }
+    }
+}
+
+fn account_locked(locked_until: &str) -> CliError {
+    CliError::auth(format!(
+        "Too many failed login attempts, try again after {locked_until}"
+    ))
+}
+
+fn recipient_keys(recipients: &[E2eRecipient]) -> Result<Vec<RecipientKey>, CliError> {
+    recipients
+        .iter()
+        .map(|recipient| RecipientKey::from_base64(&recipient.public_key).map_err(CliError::from))
+        .collect()
+}
+
+fn parse_id(id: &str) -> Result<ObjectId, CliError> {
+    ObjectId::parse_str(id)
+        .map_err(|_| CliError::invalid_input(format!("'{id}' is not a valid id")))
 }
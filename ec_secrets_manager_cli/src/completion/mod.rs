@@ -0,0 +1,92 @@
+use clap::Command;
+use clap_complete::{CompletionCandidate, env::Shells};
+use std::{ffi::OsStr, fs, io, path::Path};
+
+use crate::{
+    auth::AuthenticatedUser,
+    config::{CliConfig, PROFILE_ENV},
+    error::CliError,
+};
+
+/// Environment variable the shell registration scripts use to request completions.
+pub const COMPLETE_ENV: &str = "COMPLETE";
+pub const SHELLS: [&str; 4] = ["bash", "zsh", "fish", "powershell"];
+
+/*---------------------------------------------------------------------------
+    Shell registration. The generated scripts call back into the binary
+    with [COMPLETE] set so values like secret keys are resolved at
+    completion time rather than baked into the script.
+---------------------------------------------------------------------------*/
+pub fn write_registration(
+    shell: &str,
+    name: &str,
+    bin: &str,
+    out: &mut dyn io::Write,
+) -> Result<(), CliError> {
+    let shells = Shells::builtins();
+    let Some(completer) = shells.completer(shell) else {
+        return Err(CliError::invalid_input(format!(
+            "Unsupported shell '{shell}'"
+        )));
+    };
+    completer
+        .write_registration(COMPLETE_ENV, name, bin, bin, out)
+        .map_err(|error| CliError::internal(error.to_string()))
+}
+
+/// Renders the man page for `cmd` to stdout, or one page per subcommand
+/// into `out_dir` when given.
+pub fn write_man_pages(cmd: Command, out_dir: Option<&Path>) -> Result<(), CliError> {
+    let result = match out_dir {
+        Some(out_dir) => {
+            fs::create_dir_all(out_dir).and_then(|_| clap_mangen::generate_to(cmd, out_dir))
+        }
+        None => clap_mangen::Man::new(cmd).render(&mut io::stdout()),
+    };
+    result.map_err(|error| CliError::internal(error.to_string()))
+}
+
+/*---------------------------------------------------------------------------
+    Dynamic value completers. Failures (no config, not logged in, server
+    unreachable) produce no candidates rather than noisy shell output.
+---------------------------------------------------------------------------*/
+pub fn profile_names(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    CliConfig::load()
+        .map(|config| {
+            config
+                .profiles
+                .keys()
+                .filter(|name| name.starts_with(current.as_ref()))
+                .map(CompletionCandidate::new)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn secret_keys(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    let Ok(config) = CliConfig::load() else {
+        return Vec::new();
+    };
+    let profile_name = config.active_profile_name(std::env::var(PROFILE_ENV).ok().as_deref());
+    let Ok(profile) = config.profile(&profile_name) else {
+        return Vec::new();
+    };
+
+    // Completers are synchronous but run inside the CLI's tokio runtime
+    let keys = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(async {
+            AuthenticatedUser::new(profile_name, profile)
+                .await
+                .list_secret_keys()
+                .await
+        })
+    });
+
+    keys.unwrap_or_default()
+        .into_iter()
+        .filter(|key| key.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
+}
@@ -0,0 +1,479 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::prompt::read_secret;
+use ec_secrets_shared_library::utils::{
+    secret::SecretString,
+    vault::{decrypt, e2e::RecipientKeyPair, encrypt},
+};
+
+pub const DEFAULT_PROFILE: &str = "default";
+/// Environment variable used to select a profile when `--profile` isn't given.
+pub const PROFILE_ENV: &str = "ECS_PROFILE";
+/// Environment variable holding the passphrase for encrypted token storage.
+pub const PASSPHRASE_ENV: &str = "ECS_TOKEN_PASSPHRASE";
+/// Environment variable holding the passphrase protecting the end-to-end key.
+pub const E2E_PASSPHRASE_ENV: &str = "ECS_E2E_PASSPHRASE";
+/// Environment variable overriding the configuration directory.
+pub const CONFIG_DIR_ENV: &str = "ECS_CLI_CONFIG_DIR";
+
+const CONFIG_FILE: &str = "config.toml";
+const TOKENS_DIR: &str = "tokens";
+const E2E_KEYS_DIR: &str = "e2e";
+
+/*---------------------------------------------------------------------------
+    A named set of connection settings. Any value left unset falls back to
+    the corresponding ECS_* environment variable.
+---------------------------------------------------------------------------*/
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Profile {
+    /// MongoDB connection string, overrides [ECS_DATABASE_URL]
+    pub server_url: Option<String>,
+    /// Database name, overrides [ECS_DATABASE_NAME]
+    pub database_name: Option<String>,
+    /// Default email used by `login`
+    pub email: Option<String>,
+    /// Default namespace prefixed to secret keys
+    pub namespace: Option<String>,
+    /// Store the session token encrypted with a local passphrase
+    #[serde(default)]
+    pub encrypt_token: bool,
+}
+
+impl Profile {
+    /// Prefixes `key` with the profile's default namespace, if any.
+    pub fn qualify_key(&self, key: &str) -> String {
+        match &self.namespace {
+            Some(namespace) if !key.starts_with(&format!("{namespace}/")) => {
+                format!("{}/{}", namespace.trim_end_matches('/'), key)
+            }
+            _ => key.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CliConfig {
+    pub current_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl CliConfig {
+    pub fn load() -> Result<Self, String> {
+        Self::load_from(&config_dir()?)
+    }
+
+    pub fn load_from(dir: &Path) -> Result<Self, String> {
+        let path = dir.join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        toml::from_str(&contents).map_err(|error| error.to_string())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        self.save_to(&config_dir()?)
+    }
+
+    pub fn save_to(&self, dir: &Path) -> Result<(), String> {
+        let contents = toml::to_string_pretty(self).map_err(|error| error.to_string())?;
+        write_private(&dir.join(CONFIG_FILE), contents.as_bytes())
+    }
+
+    /// Resolves the active profile name: explicit selection (`--profile` or
+    /// [ECS_PROFILE]) first, then the configured current profile.
+    pub fn active_profile_name(&self, selected: Option<&str>) -> String {
+        selected
+            .map(str::to_string)
+            .or_else(|| self.current_profile.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    /// Returns the named profile. The default profile always exists.
+    pub fn profile(&self, name: &str) -> Result<Profile, String> {
+        validate_profile_name(name)?;
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile.clone()),
+            None if name == DEFAULT_PROFILE => Ok(Profile::default()),
+            None => Err(format!("Profile '{name}' does not exist")),
+        }
+    }
+}
+
+/// Profile names end up in file names under the config directory, so only
+/// ASCII letters, digits, `-` and `_` are accepted.
+pub fn validate_profile_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid profile name '{name}', use letters, digits, '-' and '_' only"
+        ))
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Token storage. Tokens live under <config dir>/tokens/<profile>, either
+    as plain text or encrypted with a passphrase using utils::vault.
+    Both the files and their directories are only accessible to the owner.
+---------------------------------------------------------------------------*/
+pub fn save_token(profile_name: &str, profile: &Profile, token: &str) -> Result<(), String> {
+    let dir = config_dir()?;
+    remove_token_in(&dir, profile_name)?;
+    if profile.encrypt_token {
+        let passphrase = token_passphrase()?;
+        let encrypted =
+            encrypt(token.as_bytes(), passphrase.as_bytes()).map_err(|error| error.to_string())?;
+        write_private(&encrypted_token_path(&dir, profile_name)?, &encrypted)
+    } else {
+        write_private(&token_path(&dir, profile_name)?, token.as_bytes())
+    }
+}
+
+pub fn load_token(profile_name: &str) -> Result<String, String> {
+    let dir = config_dir()?;
+    let encrypted_path = encrypted_token_path(&dir, profile_name)?;
+    if encrypted_path.exists() {
+        let passphrase = token_passphrase()?;
+        let encrypted = fs::read(encrypted_path).map_err(|error| error.to_string())?;
+        let token = decrypt(&encrypted, passphrase.as_bytes())
+            .map_err(|_| "Failed to decrypt the stored token (wrong passphrase?)".to_string())?;
+        return token
+            .into_string()
+            .map(|token| token.to_string())
+            .map_err(|error| error.to_string());
+    }
+
+    let path = token_path(&dir, profile_name)?;
+    if !path.exists() {
+        return Err(format!(
+            "Not logged in for profile '{profile_name}', please run `login` first"
+        ));
+    }
+    fs::read_to_string(path).map_err(|error| error.to_string())
+}
+
+/// Removes any stored token for the profile, returning whether one existed.
+pub fn remove_token(profile_name: &str) -> Result<bool, String> {
+    remove_token_in(&config_dir()?, profile_name)
+}
+
+fn remove_token_in(dir: &Path, profile_name: &str) -> Result<bool, String> {
+    let mut removed = false;
+    for path in [
+        token_path(dir, profile_name)?,
+        encrypted_token_path(dir, profile_name)?,
+    ] {
+        if path.exists() {
+            fs::remove_file(path).map_err(|error| error.to_string())?;
+            removed = true;
+        }
+    }
+    Ok(removed)
+}
+
+fn token_passphrase() -> Result<SecretString, String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase.into());
+    }
+    read_secret("Token passphrase: ").map_err(|_| {
+        format!("[{PASSPHRASE_ENV}] must be set to use encrypted token storage without a TTY")
+    })
+}
+
+/*---------------------------------------------------------------------------
+    End-to-end encryption keys. The private key never leaves the machine:
+    it lives under <config dir>/e2e/<profile>.key, always encrypted with a
+    passphrase. Only the public key is registered with the server.
+---------------------------------------------------------------------------*/
+pub fn save_e2e_key(profile_name: &str, key_pair: &RecipientKeyPair) -> Result<(), String> {
+    let passphrase = e2e_passphrase(true)?;
+    let encrypted = encrypt(key_pair.private_key(), passphrase.as_bytes())
+        .map_err(|error| error.to_string())?;
+    write_private(&e2e_key_path(&config_dir()?, profile_name)?, &encrypted)
+}
+
+/// Loads the profile's key pair, `None` when `e2e init` wasn't run for it.
+pub fn load_e2e_key(profile_name: &str) -> Result<Option<RecipientKeyPair>, String> {
+    let path = e2e_key_path(&config_dir()?, profile_name)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let encrypted = fs::read(path).map_err(|error| error.to_string())?;
+    let passphrase = e2e_passphrase(false)?;
+    let private_key = decrypt(&encrypted, passphrase.as_bytes()).map_err(|_| {
+        "Failed to decrypt the end-to-end encryption key (wrong passphrase?)".to_string()
+    })?;
+    RecipientKeyPair::from_private_key(&private_key)
+        .map(Some)
+        .map_err(|error| error.to_string())
+}
+
+pub fn has_e2e_key(profile_name: &str) -> Result<bool, String> {
+    Ok(e2e_key_path(&config_dir()?, profile_name)?.exists())
+}
+
+fn e2e_passphrase(confirm: bool) -> Result<SecretString, String> {
+    if let Ok(passphrase) = std::env::var(E2E_PASSPHRASE_ENV) {
+        return Ok(passphrase.into());
+    }
+    let no_tty = |_| {
+        format!("[{E2E_PASSPHRASE_ENV}] must be set to use end-to-end encryption without a TTY")
+    };
+    let passphrase = read_secret("End-to-end key passphrase: ").map_err(no_tty)?;
+    if confirm && read_secret("Confirm passphrase: ").map_err(no_tty)? != passphrase {
+        return Err("Passphrases do not match".to_owned());
+    }
+    Ok(passphrase)
+}
+
+/*---------------------------------------------------------------------------
+    Recipient keys come from the server, which could hand out a key of its
+    own. The fingerprint of each recipient's key is pinned, by email, the
+    first time a value is sealed for them, in <config dir>/e2e/<profile>.known.
+    A different key for the same email is refused until `e2e trust` pins
+    the fingerprint the recipient confirmed out of band.
+---------------------------------------------------------------------------*/
+type KnownKeys = BTreeMap<String, String>;
+
+/// Pins the fingerprints of `keys` (email, fingerprint) not seen before and
+/// returns the emails whose fingerprint differs from the pinned one. Nothing
+/// is pinned when one does.
+pub fn pin_e2e_keys(profile_name: &str, keys: &[(String, String)]) -> Result<Vec<String>, String> {
+    pin_e2e_keys_in(&config_dir()?, profile_name, keys)
+}
+
+/// Pins `fingerprint` for `email`, replacing any previous one.
+pub fn trust_e2e_key(profile_name: &str, email: &str, fingerprint: &str) -> Result<(), String> {
+    let path = known_e2e_keys_path(&config_dir()?, profile_name)?;
+    let mut known = load_known_e2e_keys(&path)?;
+    known.insert(email.to_owned(), fingerprint.to_ascii_lowercase());
+    save_known_e2e_keys(&path, &known)
+}
+
+fn pin_e2e_keys_in(
+    dir: &Path,
+    profile_name: &str,
+    keys: &[(String, String)],
+) -> Result<Vec<String>, String> {
+    let path = known_e2e_keys_path(dir, profile_name)?;
+    let mut known = load_known_e2e_keys(&path)?;
+    let changed: Vec<String> = keys
+        .iter()
+        .filter(|(email, fingerprint)| known.get(email).is_some_and(|pinned| pinned != fingerprint))
+        .map(|(email, _)| email.clone())
+        .collect();
+    if !changed.is_empty() {
+        return Ok(changed);
+    }
+    let before = known.len();
+    for (email, fingerprint) in keys {
+        known
+            .entry(email.clone())
+            .or_insert_with(|| fingerprint.clone());
+    }
+    if known.len() != before {
+        save_known_e2e_keys(&path, &known)?;
+    }
+    Ok(Vec::new())
+}
+
+fn load_known_e2e_keys(path: &Path) -> Result<KnownKeys, String> {
+    if !path.exists() {
+        return Ok(KnownKeys::new());
+    }
+    let contents = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    toml::from_str(&contents).map_err(|error| error.to_string())
+}
+
+fn save_known_e2e_keys(path: &Path, known: &KnownKeys) -> Result<(), String> {
+    let contents = toml::to_string_pretty(known).map_err(|error| error.to_string())?;
+    write_private(path, contents.as_bytes())
+}
+
+fn known_e2e_keys_path(dir: &Path, profile_name: &str) -> Result<PathBuf, String> {
+    validate_profile_name(profile_name)?;
+    Ok(dir.join(E2E_KEYS_DIR).join(format!("{profile_name}.known")))
+}
+
+fn e2e_key_path(dir: &Path, profile_name: &str) -> Result<PathBuf, String> {
+    validate_profile_name(profile_name)?;
+    Ok(dir.join(E2E_KEYS_DIR).join(format!("{profile_name}.key")))
+}
+
+fn token_path(dir: &Path, profile_name: &str) -> Result<PathBuf, String> {
+    validate_profile_name(profile_name)?;
+    Ok(dir.join(TOKENS_DIR).join(profile_name))
+}
+
+fn encrypted_token_path(dir: &Path, profile_name: &str) -> Result<PathBuf, String> {
+    validate_profile_name(profile_name)?;
+    Ok(dir.join(TOKENS_DIR).join(format!("{profile_name}.enc")))
+}
+
+pub fn config_dir() -> Result<PathBuf, String> {
+    if let Some(dir) = std::env::var_os(CONFIG_DIR_ENV) {
+        return Ok(PathBuf::from(dir));
+    }
+    let Some(home_dir) = home::home_dir() else {
+        return Err("Error acccessing the home directory".to_owned());
+    };
+    Ok(home_dir.join(".lock_smith"))
+}
+
+/// Writes `contents` to `path` with 0600 permissions, creating parent
+/// directories with 0700 permissions as needed.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        create_private_dir(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|error| error.to_string())?;
+    // The mode is only applied on creation, so tighten pre-existing files too
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(|error| error.to_string())?;
+    }
+    file.write_all(contents).map_err(|error| error.to_string())
+}
+
+fn create_private_dir(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|error| error.to_string())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+            .map_err(|error| error.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own per test and process, so parallel runs
+    /// don't share files.
+    fn test_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("lock_smith_{name}_{}", std::process::id()))
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let dir = test_dir("config_round_trip");
+        let mut config = CliConfig {
+            current_profile: Some("staging".to_string()),
+            ..Default::default()
+        };
+        config.profiles.insert(
+            "staging".to_string(),
+            Profile {
+                server_url: Some("mongodb://localhost:27017".to_string()),
+                email: Some("user@example.com".to_string()),
+                namespace: Some("payments".to_string()),
+                ..Default::default()
+            },
+        );
+        config.save_to(&dir).expect("Failed to save config");
+        let loaded = CliConfig::load_from(&dir).expect("Failed to load config");
+        assert_eq!(loaded, config);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join(CONFIG_FILE))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn recipient_keys_are_pinned() {
+        let dir = test_dir("recipient_keys_are_pinned");
+        let key = |email: &str, fingerprint: &str| (email.to_string(), fingerprint.to_string());
+
+        let first = [key("a@domain.com", "aaaa"), key("b@domain.com", "bbbb")];
+        assert!(pin_e2e_keys_in(&dir, "default", &first).unwrap().is_empty());
+        let swapped = [key("a@domain.com", "aaaa"), key("b@domain.com", "ffff")];
+        assert_eq!(
+            pin_e2e_keys_in(&dir, "default", &swapped).unwrap(),
+            ["b@domain.com"]
+        );
+        // Nothing pinned alongside a changed key
+        let with_new = [key("b@domain.com", "ffff"), key("c@domain.com", "cccc")];
+        assert!(
+            !pin_e2e_keys_in(&dir, "default", &with_new)
+                .unwrap()
+                .is_empty()
+        );
+        let path = known_e2e_keys_path(&dir, "default").unwrap();
+        assert!(
+            !load_known_e2e_keys(&path)
+                .unwrap()
+                .contains_key("c@domain.com")
+        );
+        fs::remove_dir_all(dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn profile_selection() {
+        let config = CliConfig {
+            current_profile: Some("staging".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config.active_profile_name(Some("prod")), "prod");
+        assert_eq!(config.active_profile_name(None), "staging");
+        assert_eq!(
+            CliConfig::default().active_profile_name(None),
+            DEFAULT_PROFILE
+        );
+        assert!(config.profile("missing").is_err());
+        assert!(config.profile(DEFAULT_PROFILE).is_ok());
+    }
+
+    #[test]
+    fn profile_names_stay_in_the_config_dir() {
+        assert!(validate_profile_name("prod-eu_1").is_ok());
+        for name in ["", "../../x", "a/b", "a\\b", "..", "prod.enc"] {
+            assert!(validate_profile_name(name).is_err(), "{name}");
+        }
+        assert!(token_path(Path::new("/tmp"), "../x").is_err());
+    }
+
+    #[test]
+    fn namespaced_keys() {
+        let profile = Profile {
+            namespace: Some("payments".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(profile.qualify_key("db/password"), "payments/db/password");
+        assert_eq!(
+            profile.qualify_key("payments/db/password"),
+            "payments/db/password"
+        );
+        assert_eq!(Profile::default().qualify_key("db"), "db");
+    }
+}
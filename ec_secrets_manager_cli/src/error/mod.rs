@@ -0,0 +1,321 @@
+use ec_secrets_shared_library::utils::{
+    account::AccountError, groups::GroupError, key_provider::KeyProviderError,
+    organizations::OrganizationError, policy::PolicyError, recipients::RecipientError,
+    reencryption::ReencryptionError, seal::SealError, template::TemplateError,
+    vault::e2e::E2eError,
+};
+use mongodb::error::ErrorKind as MongoErrorKind;
+use serde::Serialize;
+use std::fmt;
+
+/*---------------------------------------------------------------------------
+    Error categories surfaced to scripts through the process exit code.
+    0 is success and 2 is reserved for argument errors reported by clap,
+    so values rejected after parsing get a code of their own.
+---------------------------------------------------------------------------*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Internal,
+    InvalidInput,
+    Auth,
+    NotFound,
+    Conflict,
+    Connectivity,
+    Sealed,
+    /// A stored value failed its integrity check
+    Integrity,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Internal => 1,
+            ErrorKind::Auth => 3,
+            ErrorKind::NotFound => 4,
+            ErrorKind::Conflict => 5,
+            ErrorKind::Connectivity => 6,
+            ErrorKind::Sealed => 7,
+            ErrorKind::Integrity => 8,
+            ErrorKind::InvalidInput => 9,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CliError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Internal, message)
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidInput, message)
+    }
+
+    pub fn auth(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Auth, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Conflict, message)
+    }
+
+    pub fn connectivity(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Connectivity, message)
+    }
+
+    pub fn sealed(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Sealed, message)
+    }
+
+    pub fn integrity(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Integrity, message)
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        self.kind.exit_code()
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        Self::internal(message)
+    }
+}
+
+impl From<mongodb::error::Error> for CliError {
+    fn from(error: mongodb::error::Error) -> Self {
+        let kind = match error.kind.as_ref() {
+            MongoErrorKind::Io(io_error)
+                if io_error.kind() == std::io::ErrorKind::AlreadyExists =>
+            {
+                ErrorKind::Conflict
+            }
+            MongoErrorKind::Io(io_error) if io_error.kind() == std::io::ErrorKind::InvalidInput => {
+                ErrorKind::InvalidInput
+            }
+            MongoErrorKind::Io(_)
+            | MongoErrorKind::ServerSelection { .. }
+            | MongoErrorKind::DnsResolve { .. }
+            | MongoErrorKind::ConnectionPoolCleared { .. } => ErrorKind::Connectivity,
+            MongoErrorKind::Authentication { .. } => ErrorKind::Auth,
+            _ => ErrorKind::Internal,
+        };
+        let message = match error.kind.as_ref() {
+            MongoErrorKind::Io(io_error) => io_error.to_string(),
+            _ => error.to_string(),
+        };
+        Self::new(kind, message)
+    }
+}
+
+impl From<TemplateError> for CliError {
+    fn from(error: TemplateError) -> Self {
+        match error {
+            TemplateError::MissingSecrets(_) => Self::not_found(error.to_string()),
+            TemplateError::Unterminated(_) | TemplateError::Malformed { .. } => {
+                Self::invalid_input(error.to_string())
+            }
+            TemplateError::Lookup(_, error) => Self::from(error),
+            TemplateError::Fs(_) => Self::internal(error.to_string()),
+            TemplateError::Sealed(_) => Self::invalid_input(error.to_string()),
+            TemplateError::Open(_, ref open_error) => {
+                Self::new(Self::from(*open_error).kind, error.to_string())
+            }
+        }
+    }
+}
+
+impl From<E2eError> for CliError {
+    fn from(error: E2eError) -> Self {
+        match error {
+            E2eError::NotARecipient => Self::auth(error.to_string()),
+            E2eError::Cipher => Self::integrity(error.to_string()),
+            E2eError::PublicKey
+            | E2eError::PrivateKey
+            | E2eError::NoRecipients
+            | E2eError::TooManyRecipients
+            | E2eError::Format(_)
+            | E2eError::UnsupportedVersion(_) => Self::invalid_input(error.to_string()),
+        }
+    }
+}
+
+impl From<RecipientError> for CliError {
+    fn from(error: RecipientError) -> Self {
+        match error {
+            RecipientError::InvalidKey | RecipientError::Empty => {
+                Self::invalid_input(error.to_string())
+            }
+            RecipientError::NotFound(_) | RecipientError::GroupNotFound(_) => {
+                Self::not_found(error.to_string())
+            }
+            RecipientError::MissingKeys(_) => Self::conflict(error.to_string()),
+            RecipientError::Internal(_) => Self::internal(error.to_string()),
+        }
+    }
+}
+
+impl From<AccountError> for CliError {
+    fn from(error: AccountError) -> Self {
+        match error {
+            AccountError::InvalidPassword | AccountError::InvalidToken => {
+                Self::auth(error.to_string())
+            }
+            AccountError::Policy(_) => Self::invalid_input(error.to_string()),
+            AccountError::EmailTaken | AccountError::AdminExists => {
+                Self::conflict(error.to_string())
+            }
+            AccountError::RegistrationClosed => Self::auth(error.to_string()),
+            AccountError::Internal(_) => Self::internal(error.to_string()),
+        }
+    }
+}
+
+impl From<GroupError> for CliError {
+    fn from(error: GroupError) -> Self {
+        match error {
+            GroupError::NotFound(_)
+            | GroupError::UserNotFound(_)
+            | GroupError::SecretNotFound(_) => Self::not_found(error.to_string()),
+            GroupError::Exists | GroupError::NotMember(_) => Self::conflict(error.to_string()),
+            GroupError::Invalid(_) => Self::invalid_input(error.to_string()),
+            GroupError::Internal(_) => Self::internal(error.to_string()),
+        }
+    }
+}
+
+impl From<OrganizationError> for CliError {
+    fn from(error: OrganizationError) -> Self {
+        match error {
+            OrganizationError::NotFound(_) | OrganizationError::UserNotFound(_) => {
+                Self::not_found(error.to_string())
+            }
+            OrganizationError::Exists | OrganizationError::NotMember(_) => {
+                Self::conflict(error.to_string())
+            }
+            OrganizationError::Invalid(_) => Self::invalid_input(error.to_string()),
+            OrganizationError::Sealed => Self::sealed(error.to_string()),
+            OrganizationError::Internal(_) => Self::internal(error.to_string()),
+        }
+    }
+}
+
+impl From<ReencryptionError> for CliError {
+    fn from(error: ReencryptionError) -> Self {
+        match error {
+            ReencryptionError::Config(_) => Self::invalid_input(error.to_string()),
+            ReencryptionError::Sealed => Self::sealed(error.to_string()),
+            ReencryptionError::Internal(_) => Self::internal(error.to_string()),
+        }
+    }
+}
+
+impl From<PolicyError> for CliError {
+    fn from(error: PolicyError) -> Self {
+        match error {
+            PolicyError::Denied(_) => Self::auth(error.to_string()),
+            PolicyError::NotFound(_) | PolicyError::PrincipalNotFound(_) => {
+                Self::not_found(error.to_string())
+            }
+            PolicyError::Exists => Self::conflict(error.to_string()),
+            PolicyError::Invalid(_) => Self::invalid_input(error.to_string()),
+            PolicyError::Sealed => Self::sealed(error.to_string()),
+            PolicyError::TooLarge { .. } => Self::invalid_input(error.to_string()),
+            PolicyError::Integrity(_) => Self::integrity(error.to_string()),
+            PolicyError::Internal(_) => Self::internal(error.to_string()),
+        }
+    }
+}
+
+impl From<KeyProviderError> for CliError {
+    fn from(error: KeyProviderError) -> Self {
+        match error {
+            KeyProviderError::UnknownProvider(_)
+            | KeyProviderError::MissingConfig(_)
+            | KeyProviderError::MissingKey(_)
+            | KeyProviderError::Keyfile(_) => Self::invalid_input(error.to_string()),
+            KeyProviderError::Kms(_) => Self::connectivity(error.to_string()),
+            KeyProviderError::Pkcs11(_) => Self::internal(error.to_string()),
+        }
+    }
+}
+
+impl From<SealError> for CliError {
+    fn from(error: SealError) -> Self {
+        match error {
+            SealError::Sealed => Self::sealed(error.to_string()),
+            SealError::Disabled
+            | SealError::NotInitialized
+            | SealError::AlreadyInitialized
+            | SealError::KeyUnavailable => Self::conflict(error.to_string()),
+            SealError::InvalidShare(_) | SealError::Invalid(_) => {
+                Self::invalid_input(error.to_string())
+            }
+            SealError::Internal(_) => Self::internal(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let kinds = [
+            ErrorKind::Internal,
+            ErrorKind::InvalidInput,
+            ErrorKind::Auth,
+            ErrorKind::NotFound,
+            ErrorKind::Conflict,
+            ErrorKind::Connectivity,
+            ErrorKind::Sealed,
+            ErrorKind::Integrity,
+        ];
+        let mut codes: Vec<u8> = kinds.iter().map(|kind| kind.exit_code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), kinds.len());
+        // 0 is success, 2 belongs to clap's usage errors
+        assert!(!codes.contains(&0));
+        assert!(!codes.contains(&2));
+    }
+
+    #[test]
+    fn classifies_mongo_errors() {
+        let conflict = mongodb::error::Error::from(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "A user with this email already exists.",
+        ));
+        assert_eq!(CliError::from(conflict).kind, ErrorKind::Conflict);
+
+        let refused = mongodb::error::Error::from(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "connection refused",
+        ));
+        assert_eq!(CliError::from(refused).kind, ErrorKind::Connectivity);
+    }
+}
@@ -1 +1,8 @@
 pub mod auth;
+pub mod completion;
+pub mod config;
+pub mod error;
+pub mod models;
+pub mod operator;
+pub mod output;
+pub mod prompt;
@@ -1,123 +1,2299 @@
-use clap::{Arg, Command};
-use ec_secrets_manager_cli::auth::AuthenticatedUser;
-use ec_secrets_shared_library::models::UserCredentials;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command, builder::PossibleValuesParser};
+use clap_complete::{ArgValueCompleter, CompleteEnv};
+use ec_secrets_manager_cli::{
+    auth::{AuthenticatedUser, LoginResult},
+    completion::{self, COMPLETE_ENV, SHELLS},
+    config::{self, CliConfig, PROFILE_ENV},
+    error::CliError,
+    models::{ProfileSummary, SealShareSummary, SealSummary},
+    operator::{self, Operator},
+    output::{self, OutputFormat},
+    prompt::{self, LoginMethod},
+};
+use ec_secrets_shared_library::{
+    models::{
+        AppRole, Capability, Group, Invitation, Organization, Policy, PolicyAttachment,
+        PolicyCheck, PolicyRule, PolicyRules, PrincipalKind, SealInit, SecretFile, UserCredentials,
+        UserRole,
+    },
+    repositories::vault::max_file_size,
+    utils::{
+        auth::SCOPES,
+        key_provider::{self, KeyName, KeyfileProvider, KmsProvider, Pkcs11Provider},
+        policy::{self, PolicyError},
+        template, vault,
+    },
+};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 #[tokio::main]
-async fn main() {
-    let mut authenticated_user = AuthenticatedUser::new().await;
-    let matches = Command::new("ec_lock_smith")
+async fn main() -> ExitCode {
+    CompleteEnv::with_factory(cli).var(COMPLETE_ENV).complete();
+
+    let matches = cli().get_matches();
+    let format = matches
+        .get_one::<String>("output")
+        .and_then(|format| format.parse().ok())
+        .unwrap_or_default();
+
+    match run(&matches, format).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            output::print_error(format, &error);
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+fn cli() -> Command {
+    Command::new("ec_lock_smith")
         .version("1.0")
         .about("Embra Connect Lock Smith CLI")
         .arg_required_else_help(true)
+        .arg(output::output_arg())
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .global(true)
+                .env(PROFILE_ENV)
+                .add(ArgValueCompleter::new(completion::profile_names))
+                .help("The configuration profile to use"),
+        )
         .subcommand(
             Command::new("login")
                 .about("authenticates user to the embra connect secrets manager service")
-                .arg_required_else_help(true)
                 .arg(
                     Arg::new("email")
                         .short('e')
                         .long("email")
+                        .required(false)
+                        .help("The user's email, defaults to the profile's email"),
+                )
+                .args(prompt::password_args())
+                .arg(prompt::api_key_arg())
+                .args(prompt::app_role_args())
+                .arg(prompt::mfa_code_arg().conflicts_with_all(["api-key", "role-id"])),
+        )
+        .subcommand(Command::new("logout").about("discards the stored token for the profile"))
+        .subcommand(Command::new("whoami").about("shows the identity of the stored token"))
+        .subcommand(
+            Command::new("secrets")
+                .about("read secrets stored in lock smith")
+                .arg_required_else_help(true)
+                .subcommand(Command::new("list").about("list the keys of your secrets"))
+                .subcommand(
+                    Command::new("get").about("print a secret's value").arg(
+                        Arg::new("key")
+                            .required(true)
+                            .add(ArgValueCompleter::new(completion::secret_keys))
+                            .help("secret key, prefixed with the profile's namespace if set"),
+                    ),
+                )
+                .subcommand(
+                    Command::new("upload")
+                        .about("store a file, or a directory as a tar archive, as a binary secret")
+                        .arg(
+                            Arg::new("key")
+                                .required(true)
+                                .help("secret key, prefixed with the profile's namespace if set"),
+                        )
+                        .arg(
+                            Arg::new("path")
+                                .required(true)
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("file or directory to upload"),
+                        )
+                        .arg(
+                            Arg::new("content-type")
+                                .long("content-type")
+                                .help("content type, guessed from the file extension when omitted"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("download")
+                        .about("write a secret's raw bytes to a file (0600)")
+                        .arg(
+                            Arg::new("key")
+                                .required(true)
+                                .add(ArgValueCompleter::new(completion::secret_keys))
+                                .help("secret key, prefixed with the profile's namespace if set"),
+                        )
+                        .arg(
+                            Arg::new("out")
+                                .long("out")
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("destination, `-` for stdout; defaults to the uploaded file name"),
+                        )
+                        .arg(
+                            Arg::new("extract")
+                                .long("extract")
+                                .action(ArgAction::SetTrue)
+                                .help("unpack an uploaded directory into --out (default: the current directory)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("seal")
+                        .about("store a secret end-to-end encrypted, readable by its recipients only")
+                        .long_about(
+                            "store a secret end-to-end encrypted, readable by its recipients only\n\n\
+                             The value is read from stdin, or prompted for, and sealed for you and \
+                             the given recipients before it leaves this machine.",
+                        )
+                        .arg(
+                            Arg::new("key")
+                                .required(true)
+                                .help("secret key, prefixed with the profile's namespace if set"),
+                        )
+                        .args(recipient_args()),
+                )
+                .subcommand(
+                    Command::new("grant")
+                        .about("seal one of your end-to-end encrypted secrets for more recipients")
+                        .arg(
+                            Arg::new("key")
+                                .required(true)
+                                .add(ArgValueCompleter::new(completion::secret_keys))
+                                .help("secret key, prefixed with the profile's namespace if set"),
+                        )
+                        .args(recipient_args()),
+                ),
+        )
+        .subcommand(
+            Command::new("files")
+                .about("encrypt local files and directories with a passphrase")
+                .arg_required_else_help(true)
+                .subcommand(file_crypt_command(
+                    "encrypt",
+                    "encrypt a file, or a directory into a single file",
+                ))
+                .subcommand(file_crypt_command(
+                    "decrypt",
+                    "decrypt a file written by `files encrypt`",
+                )),
+        )
+        .subcommand(
+            Command::new("render")
+                .about("render a template, resolving {{ secret \"key\" }} references")
+                .arg(
+                    Arg::new("template")
                         .required(true)
-                        .help("The user's email"),
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("template file, e.g. config.yaml.tmpl"),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("destination file (0600), `-` for stdout; defaults to the template path without `.tmpl`"),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("print the shell completion script")
+                .long_about(
+                    "print the shell completion script, e.g. add \
+                     `source <(ec_lock_smith completions bash)` to your ~/.bashrc",
                 )
                 .arg(
-                    Arg::new("password")
-                        .short('p')
-                        .long("password")
+                    Arg::new("shell")
                         .required(true)
-                        .help("The user's password"),
+                        .value_parser(PossibleValuesParser::new(SHELLS))
+                        .help("target shell"),
                 ),
         )
         .subcommand(
-            Command::new("users")
-                .about("allow users to execute user management capabilities of lock smith")
+            Command::new("man").about("generate man pages").arg(
+                Arg::new("out-dir")
+                    .long("out-dir")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("write one page per command into this directory instead of stdout"),
+            ),
+        )
+        .subcommand(
+            Command::new("profiles")
+                .about("manage named configuration profiles")
                 .arg_required_else_help(true)
+                .subcommand(Command::new("list").about("list configured profiles"))
                 .subcommand(
-                    Command::new("list")
-                        .about("list user account in lock smith")
+                    Command::new("set")
+                        .about("create or update a profile")
                         .arg(
-                            Arg::new("id")
-                                .short('i')
-                                .long("id")
-                                .required(false)
-                                .help("user account id"),
+                            Arg::new("name")
+                                .required(true)
+                                .add(ArgValueCompleter::new(completion::profile_names))
+                                .help("profile name"),
+                        )
+                        .arg(
+                            Arg::new("server-url")
+                                .long("server-url")
+                                .help("database connection string used by this profile"),
+                        )
+                        .arg(
+                            Arg::new("database")
+                                .long("database")
+                                .help("database name used by this profile"),
+                        )
+                        .arg(
+                            Arg::new("email")
+                                .short('e')
+                                .long("email")
+                                .help("default email used by login"),
+                        )
+                        .arg(
+                            Arg::new("namespace")
+                                .short('n')
+                                .long("namespace")
+                                .help("default namespace prefixed to secret keys"),
+                        )
+                        .arg(
+                            Arg::new("encrypt-token")
+                                .long("encrypt-token")
+                                .value_parser(clap::value_parser!(bool))
+                                .help("store the token encrypted with a local passphrase"),
                         ),
                 )
                 .subcommand(
-                    Command::new("delete").about("delete user account").arg(
-                        Arg::new("id")
-                            .short('i')
-                            .long("id")
-                            .required(true)
-                            .help("user account id"),
-                    ),
+                    Command::new("use")
+                        .about("make a profile the current profile")
+                        .arg(
+                            Arg::new("name")
+                                .required(true)
+                                .add(ArgValueCompleter::new(completion::profile_names))
+                                .help("profile name"),
+                        ),
                 )
+                .subcommand(
+                    Command::new("delete")
+                        .about("delete a profile and its stored token")
+                        .arg(
+                            Arg::new("name")
+                                .required(true)
+                                .add(ArgValueCompleter::new(completion::profile_names))
+                                .help("profile name"),
+                        )
+                        .arg(prompt::yes_arg()),
+                ),
+        )
+        .subcommand(
+            Command::new("service-accounts")
+                .about("manage service accounts and their API keys")
+                .arg_required_else_help(true)
                 .subcommand(
                     Command::new("create")
-                        .about("create a new user account in lock smith")
+                        .about("create a service account")
+                        .arg(Arg::new("name").required(true).help("service account name"))
+                        .arg(
+                            Arg::new("description")
+                                .short('d')
+                                .long("description")
+                                .help("what the service account is used for"),
+                        ),
+                )
+                .subcommand(Command::new("list").about("list your service accounts"))
+                .subcommand(
+                    Command::new("delete")
+                        .about("delete a service account and revoke all its keys")
+                        .arg(Arg::new("id").required(true).help("service account id"))
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    Command::new("keys")
+                        .about("manage the API keys of a service account")
+                        .arg_required_else_help(true)
+                        .subcommand(
+                            Command::new("create")
+                                .about("issue an API key, it is only shown once")
+                                .arg(Arg::new("id").required(true).help("service account id"))
+                                .arg(
+                                    Arg::new("scope")
+                                        .short('s')
+                                        .long("scope")
+                                        .required(true)
+                                        .action(ArgAction::Append)
+                                        .value_parser(PossibleValuesParser::new(SCOPES))
+                                        .help("scope granted to the key, may be repeated"),
+                                )
+                                .arg(
+                                    Arg::new("expires-in-days")
+                                        .long("expires-in-days")
+                                        .value_parser(clap::value_parser!(i64).range(1..))
+                                        .help("lifetime of the key, keys never expire by default"),
+                                ),
+                        )
+                        .subcommand(
+                            Command::new("list")
+                                .about("list the API keys of a service account")
+                                .arg(Arg::new("id").required(true).help("service account id")),
+                        )
+                        .subcommand(
+                            Command::new("revoke")
+                                .about("revoke an API key")
+                                .arg(Arg::new("id").required(true).help("service account id"))
+                                .arg(Arg::new("key-id").required(true).help("API key id"))
+                                .arg(prompt::yes_arg()),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("approles")
+                .about("manage AppRoles used by workloads to login")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("create an AppRole")
+                        .arg(Arg::new("name").required(true).help("role name"))
+                        .arg(
+                            Arg::new("scope")
+                                .long("scope")
+                                .required(true)
+                                .action(ArgAction::Append)
+                                .value_parser(PossibleValuesParser::new(SCOPES))
+                                .help("scope granted to the role's tokens, may be repeated"),
+                        )
+                        .arg(
+                            Arg::new("secret-id-ttl")
+                                .long("secret-id-ttl")
+                                .value_parser(clap::value_parser!(i64).range(1..))
+                                .help("minutes a secret ID stays valid [default: 60]"),
+                        )
+                        .arg(
+                            Arg::new("secret-id-num-uses")
+                                .long("secret-id-num-uses")
+                                .value_parser(clap::value_parser!(i64).range(0..))
+                                .help("logins allowed per secret ID, 0 for unlimited [default: 1]"),
+                        )
+                        .arg(
+                            Arg::new("token-ttl")
+                                .long("token-ttl")
+                                .value_parser(clap::value_parser!(i64).range(1..))
+                                .help("minutes issued tokens stay valid, at most 480 [default: 60]"),
+                        ),
+                )
+                .subcommand(Command::new("list").about("list your AppRoles"))
+                .subcommand(
+                    Command::new("delete")
+                        .about("delete an AppRole and all its secret IDs")
+                        .arg(Arg::new("name").required(true).help("role name"))
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    Command::new("secret-id")
+                        .about("issue a secret ID, it is only shown once")
+                        .arg(Arg::new("name").required(true).help("role name")),
+                ),
+        )
+        .subcommand(
+            Command::new("mfa")
+                .about("manage multi-factor authentication for your account")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("enroll")
+                        .about("start TOTP enrollment, add the secret to your authenticator app"),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("verify the first code to enable MFA, prints recovery codes")
+                        .arg(prompt::mfa_code_arg()),
+                )
+                .subcommand(
+                    Command::new("recovery-codes")
+                        .about("replace your recovery codes")
+                        .arg(prompt::mfa_code_arg()),
+                )
+                .subcommand(
+                    Command::new("disable")
+                        .about("disable MFA for your account")
+                        .arg(prompt::mfa_code_arg()),
+                ),
+        )
+        .subcommand(
+            Command::new("e2e")
+                .about("manage your key pair for end-to-end encrypted secrets")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("init")
+                        .about("generate a key pair and register its public key")
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .action(ArgAction::SetTrue)
+                                .help("replace the existing key pair, secrets sealed for it become unreadable"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("public-key").about("show your registered public key"),
+                )
+                .subcommand(
+                    Command::new("trust")
+                        .about("pin the key fingerprint a recipient confirmed, e.g. after they replaced their key")
+                        .arg(Arg::new("email").required(true).help("recipient email"))
+                        .arg(
+                            Arg::new("fingerprint")
+                                .required(true)
+                                .help("fingerprint shown by their `e2e public-key`"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("admin")
+                .about("instance wide settings, admins only")
+                .arg_required_else_help(true)
+                .subcommand(Command::new("settings").about("show instance settings"))
+                .subcommand(
+                    Command::new("bootstrap")
+                        .about("create the first admin account, needs database access only")
                         .arg(
                             Arg::new("email")
                                 .short('e')
                                 .long("email")
                                 .required(true)
-                                .help("user email address"),
+                                .help("admin email address"),
                         )
+                        .args(prompt::password_args()),
+                )
+                .subcommand(
+                    Command::new("rotate-signing-key")
+                        .about("replace the token signing key, signing everyone out")
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    Command::new("re-encrypt")
+                        .about("re-encrypt stored data with the configured cipher and key derivation"),
+                )
+                .subcommand(
+                    Command::new("require-mfa")
+                        .about("require every account to use MFA")
                         .arg(
-                            Arg::new("password")
-                                .short('p')
-                                .long("password")
+                            Arg::new("enabled")
                                 .required(true)
-                                .help("user's password"),
+                                .value_parser(clap::value_parser!(bool))
+                                .help("true or false"),
                         ),
                 ),
         )
-        .get_matches();
-
-    match matches.subcommand() {
-        Some(("login", sub_matches)) => {
-            let creds = UserCredentials {
-                email: sub_matches.get_one::<String>("email").unwrap().to_string(),
-                password: sub_matches
-                    .get_one::<String>("password")
-                    .unwrap()
-                    .to_string(),
-            };
-            authenticated_user.login(creds).await.map_or_else(
-                |error| println!("\x1b[0;31m Login failed: {error} \x1b[0m"),
-                |_| println!("\x1b[0;32m Login successful \x1b[0m"),
-            );
-        }
-        Some(("users", submatches)) => match submatches.subcommand() {
-            Some(("list", submatches)) => {
-                let id: Option<&str> = submatches.get_one::<String>("id").map(|id| id.as_str());
-                authenticated_user.get_users(id).await.map_or_else(
-                    |error| println!("\x1b[0;31m Error fetching users: {error} \x1b[0m"),
-                    |_| println!("\x1b[0;32m Fetch successful \x1b[0m"),
-                );
-            }
-            Some(("delete", submatches)) => {
-                let id: Option<&str> = submatches.get_one::<String>("id").map(|id| id.as_str());
-                authenticated_user.delete_user(id).await.map_or_else(
-                    |error| println!("\x1b[0;31m Error deleting user: {error} \x1b[0m"),
-                    |_| println!("\x1b[0;32m Deleted user successfully '\x1b[0m"),
-                );
-            }
-            Some(("create", submatches)) => {
-                let creds = UserCredentials {
-                    email: submatches.get_one::<String>("email").unwrap().to_string(),
-                    password: submatches
-                        .get_one::<String>("password")
-                        .unwrap()
-                        .to_string(),
-                };
-
-                authenticated_user.create_user(creds).await.map_or_else(
-                    |error| println!("\x1b[0;31m Error creating user: {error} \x1b[0m"),
-                    |_| println!("\x1b[0;32m User created successfully \x1b[0m"),
-                );
-            }
-            _ => {}
-        },
-        _ => {}
+        .subcommand(
+            Command::new("operator")
+                .about("seal and unseal the server's master key")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("address")
+                        .long("address")
+                        .global(true)
+                        .env(operator::ADDRESS_ENV)
+                        .default_value(operator::DEFAULT_ADDRESS)
+                        .help("address of the secrets management server"),
+                )
+                .subcommand(
+                    Command::new("init")
+                        .about("split the master key into Shamir shares, admins only")
+                        .arg(
+                            Arg::new("shares")
+                                .long("shares")
+                                .default_value("5")
+                                .value_parser(clap::value_parser!(u8).range(1..))
+                                .help("number of shares to create"),
+                        )
+                        .arg(
+                            Arg::new("threshold")
+                                .long("threshold")
+                                .default_value("3")
+                                .value_parser(clap::value_parser!(u8).range(1..))
+                                .help("number of shares needed to unseal"),
+                        ),
+                )
+                .subcommand(Command::new("status").about("show whether the server is sealed"))
+                .subcommand(
+                    Command::new("unseal")
+                        .about("submit one share towards unsealing the server")
+                        .arg(
+                            Arg::new("share")
+                                .help("the share, prompted for when omitted"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("seal")
+                        .about("wipe the master key from the server's memory, admins only")
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    Command::new("export-keys")
+                        .about("copy the master keys of the configured key provider to another one")
+                        .arg(
+                            Arg::new("to")
+                                .long("to")
+                                .required(true)
+                                .value_parser(PossibleValuesParser::new(["keyfile", "pkcs11", "kms"]))
+                                .help("the provider to copy the keys to, configured like the server's"),
+                        )
+                        .arg(
+                            Arg::new("keyfile")
+                                .long("keyfile")
+                                .env("ECS_KEYFILE")
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("keyfile to create, must not exist yet"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("invitations")
+                .about("invite users to join, admins only")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("invite an email address, the token is mailed and printed once")
+                        .arg(
+                            Arg::new("email")
+                                .short('e')
+                                .long("email")
+                                .required(true)
+                                .help("address to invite"),
+                        )
+                        .arg(
+                            Arg::new("role")
+                                .long("role")
+                                .default_value("user")
+                                .value_parser(PossibleValuesParser::new(["user", "admin"]))
+                                .help("role the account is created with"),
+                        )
+                        .arg(
+                            Arg::new("expires-in-hours")
+                                .long("expires-in-hours")
+                                .value_parser(clap::value_parser!(i64))
+                                .help("hours until the invitation expires, defaults to 72"),
+                        ),
+                )
+                .subcommand(Command::new("list").about("list invitations"))
+                .subcommand(
+                    Command::new("revoke")
+                        .about("revoke an invitation")
+                        .arg(Arg::new("id").required(true).help("invitation id"))
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    Command::new("accept")
+                        .about("accept an invitation and choose a password")
+                        .arg(
+                            Arg::new("token")
+                                .long("token")
+                                .required(true)
+                                .help("the invitation token"),
+                        )
+                        .args(prompt::password_args()),
+                ),
+        )
+        .subcommand(
+            Command::new("groups")
+                .about("manage groups of users and share secrets with them")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("create a group, admins only")
+                        .arg(Arg::new("name").required(true).help("group name"))
+                        .arg(
+                            Arg::new("description")
+                                .short('d')
+                                .long("description")
+                                .help("what the group is for"),
+                        )
+                        .arg(group_permission_arg()),
+                )
+                .subcommand(Command::new("list").about("list groups, admins only"))
+                .subcommand(
+                    Command::new("delete")
+                        .about("delete a group, removing its members and unsharing its secrets")
+                        .arg(Arg::new("name").required(true).help("group name"))
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    Command::new("permissions")
+                        .about("replace the permissions members get on shared secrets")
+                        .arg(Arg::new("name").required(true).help("group name"))
+                        .arg(group_permission_arg()),
+                )
+                .subcommand(
+                    Command::new("members")
+                        .about("manage the members of a group, admins only")
+                        .arg_required_else_help(true)
+                        .subcommand(
+                            Command::new("list")
+                                .about("list the members of a group")
+                                .arg(Arg::new("name").required(true).help("group name")),
+                        )
+                        .subcommand(
+                            Command::new("add")
+                                .about("add a user, effective from the user's next login")
+                                .arg(Arg::new("name").required(true).help("group name"))
+                                .arg(Arg::new("email").required(true).help("user email address")),
+                        )
+                        .subcommand(
+                            Command::new("remove")
+                                .about("remove a user, signing out all of the user's sessions")
+                                .arg(Arg::new("name").required(true).help("group name"))
+                                .arg(Arg::new("email").required(true).help("user email address"))
+                                .arg(prompt::yes_arg()),
+                        ),
+                )
+                .subcommand(
+                    Command::new("share")
+                        .about("share one of your secrets with a group")
+                        .arg(Arg::new("name").required(true).help("group name"))
+                        .arg(
+                            Arg::new("key")
+                                .required(true)
+                                .add(ArgValueCompleter::new(completion::secret_keys))
+                                .help("secret key, prefixed with the profile's namespace if set"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("unshare")
+                        .about("stop sharing one of your secrets with a group")
+                        .arg(Arg::new("name").required(true).help("group name"))
+                        .arg(
+                            Arg::new("key")
+                                .required(true)
+                                .add(ArgValueCompleter::new(completion::secret_keys))
+                                .help("secret key, prefixed with the profile's namespace if set"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("orgs")
+                .visible_alias("org")
+                .about("manage organizations, each with its own vault")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("create an organization and join it, admins only")
+                        .arg(Arg::new("name").required(true).help("organization name"))
+                        .arg(
+                            Arg::new("description")
+                                .short('d')
+                                .long("description")
+                                .help("what the organization is for"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("list your organizations, or every organization for admins"),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("delete an organization together with all of its secrets")
+                        .arg(Arg::new("name").required(true).help("organization name"))
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    Command::new("members")
+                        .about("manage the members of an organization, admins only")
+                        .arg_required_else_help(true)
+                        .subcommand(
+                            Command::new("list")
+                                .about("list the members of an organization")
+                                .arg(Arg::new("name").required(true).help("organization name")),
+                        )
+                        .subcommand(
+                            Command::new("add")
+                                .about("add a user, who can then switch to the organization")
+                                .arg(Arg::new("name").required(true).help("organization name"))
+                                .arg(Arg::new("email").required(true).help("user email address")),
+                        )
+                        .subcommand(
+                            Command::new("remove")
+                                .about("remove a user, signing out all of the user's sessions")
+                                .arg(Arg::new("name").required(true).help("organization name"))
+                                .arg(Arg::new("email").required(true).help("user email address"))
+                                .arg(prompt::yes_arg()),
+                        ),
+                )
+                .subcommand(
+                    Command::new("switch")
+                        .about("make one of your organizations the active one")
+                        .arg(Arg::new("name").required(true).help("organization name")),
+                )
+                .subcommand(
+                    Command::new("adopt-legacy")
+                        .about(
+                            "move users and secrets stored before organizations existed into an organization, admins only",
+                        )
+                        .arg(Arg::new("name").required(true).help("organization name"))
+                        .arg(prompt::yes_arg()),
+                ),
+        )
+        .subcommand(
+            Command::new("policies")
+                .visible_alias("policy")
+                .about("manage access policies on secret paths")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("create a policy, admins only")
+                        .arg(Arg::new("name").required(true).help("policy name"))
+                        .arg(
+                            Arg::new("description")
+                                .short('d')
+                                .long("description")
+                                .help("what the policy is for"),
+                        )
+                        .args(policy_rule_args()),
+                )
+                .subcommand(Command::new("list").about("list policies, admins only"))
+                .subcommand(
+                    Command::new("show")
+                        .about("show a policy and what it is attached to")
+                        .arg(Arg::new("name").required(true).help("policy name")),
+                )
+                .subcommand(
+                    Command::new("update")
+                        .about("replace the rules of a policy")
+                        .arg(Arg::new("name").required(true).help("policy name"))
+                        .arg(
+                            Arg::new("description")
+                                .short('d')
+                                .long("description")
+                                .help("what the policy is for"),
+                        )
+                        .args(policy_rule_args()),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("delete a policy")
+                        .arg(Arg::new("name").required(true).help("policy name"))
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    policy_principal_args(
+                        Command::new("attach")
+                            .about("attach a policy to a user, group or service account")
+                            .arg(Arg::new("name").required(true).help("policy name")),
+                        true,
+                    ),
+                )
+                .subcommand(
+                    policy_principal_args(
+                        Command::new("detach")
+                            .about("detach a policy from a user, group or service account")
+                            .arg(Arg::new("name").required(true).help("policy name")),
+                        true,
+                    ),
+                )
+                .subcommand(
+                    policy_principal_args(
+                        Command::new("check")
+                            .about("explain whether a capability on a path is allowed, and why")
+                            .arg(
+                                Arg::new("path")
+                                    .long("path")
+                                    .required(true)
+                                    .add(ArgValueCompleter::new(completion::secret_keys))
+                                    .help("secret key to check"),
+                            )
+                            .arg(
+                                Arg::new("capability")
+                                    .long("capability")
+                                    .required(true)
+                                    .value_parser(PossibleValuesParser::new(
+                                        Capability::ALL.map(|capability| capability.as_str()),
+                                    ))
+                                    .help("capability to check"),
+                            ),
+                        false,
+                    ),
+                ),
+        )
+        .subcommand(
+            Command::new("password")
+                .about("change or reset your password")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("change")
+                        .about("change the password, signing out every other session")
+                        .arg(prompt::current_password_arg())
+                        .args(prompt::password_args()),
+                )
+                .subcommand(
+                    Command::new("forgot")
+                        .about("mail a password reset token")
+                        .arg(
+                            Arg::new("email")
+                                .short('e')
+                                .long("email")
+                                .help("The account's email, defaults to the profile's email"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("reset")
+                        .about("choose a new password with a mailed reset token")
+                        .arg(
+                            Arg::new("token")
+                                .long("token")
+                                .required(true)
+                                .help("the password reset token"),
+                        )
+                        .args(prompt::password_args()),
+                ),
+        )
+        .subcommand(
+            Command::new("users")
+                .about("allow users to execute user management capabilities of lock smith")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("list user account in lock smith")
+                        .arg(
+                            Arg::new("id")
+                                .short('i')
+                                .long("id")
+                                .required(false)
+                                .help("user account id"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("delete user account")
+                        .arg(
+                            Arg::new("id")
+                                .short('i')
+                                .long("id")
+                                .required(true)
+                                .help("user account id"),
+                        )
+                        .arg(prompt::yes_arg()),
+                )
+                .subcommand(
+                    Command::new("create")
+                        .about("create a new user account in lock smith")
+                        .arg(
+                            Arg::new("email")
+                                .short('e')
+                                .long("email")
+                                .required(true)
+                                .help("user email address"),
+                        )
+                        .arg(
+                            Arg::new("admin")
+                                .long("admin")
+                                .action(ArgAction::SetTrue)
+                                .help("create an admin account"),
+                        )
+                        .args(prompt::password_args()),
+                )
+                .subcommand(
+                    Command::new("verify-email")
+                        .about("verify your email address with a mailed token")
+                        .arg(
+                            Arg::new("token")
+                                .long("token")
+                                .required(true)
+                                .help("the email verification token"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("resend-verification")
+                        .about("mail a new email verification token"),
+                ),
+        )
+}
+
+fn file_crypt_command(name: &'static str, about: &'static str) -> Command {
+    Command::new(name)
+        .about(about)
+        .arg(
+            Arg::new("path")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("out")
+                .long("out")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("destination file, or directory to extract into when decrypting a directory"),
+        )
+        .arg(
+            Arg::new("directory")
+                .long("directory")
+                .action(ArgAction::SetTrue)
+                .help("the encrypted file holds a directory (decrypt only)"),
+        )
+        .arg(
+            Arg::new("passphrase")
+                .long("passphrase")
+                .env(FILE_PASSPHRASE_ENV)
+                .hide_env_values(true)
+                .help("the passphrase, prompted for when omitted"),
+        )
+}
+
+fn recipient_args() -> [Arg; 2] {
+    [
+        Arg::new("recipient")
+            .short('r')
+            .long("recipient")
+            .action(ArgAction::Append)
+            .help("email of a member of the active organization, may be repeated"),
+        Arg::new("group")
+            .short('g')
+            .long("group")
+            .action(ArgAction::Append)
+            .help("group whose current members are added, may be repeated"),
+    ]
+}
+
+/// The `--recipient` emails and `--group` names of a seal command.
+fn recipients(matches: &ArgMatches) -> (Vec<String>, Vec<String>) {
+    let values = |id| {
+        matches
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    };
+    (values("recipient"), values("group"))
+}
+
+fn group_permission_arg() -> Arg {
+    Arg::new("permission")
+        .long("permission")
+        .action(ArgAction::Append)
+        .value_parser(PossibleValuesParser::new(SCOPES))
+        .help("permission granted on secrets shared with the group, may be repeated")
+}
+
+fn policy_rule_args() -> [Arg; 2] {
+    [
+        Arg::new("rule")
+            .long("rule")
+            .action(ArgAction::Append)
+            .required_unless_present("file")
+            .help("rule as '<capability,...> <path glob>', e.g. 'read,list payments/*/prod/*', may be repeated"),
+        Arg::new("file")
+            .long("file")
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with("rule")
+            .help("file with one rule per line, '#' starts a comment"),
+    ]
+}
+
+/// Adds the principal a policy is attached to or checked for. Checks
+/// default to the caller and can't target groups.
+fn policy_principal_args(command: Command, attaching: bool) -> Command {
+    let principals: &[(&'static str, &'static str)] = if attaching {
+        &[
+            ("user", "user email address"),
+            ("group", "group name"),
+            ("service-account", "service account id"),
+        ]
+    } else {
+        &[
+            ("user", "user email address, admins only"),
+            ("service-account", "service account id, admins only"),
+        ]
+    };
+    principals
+        .iter()
+        .fold(command, |command, (name, help)| {
+            command.arg(Arg::new(*name).long(*name).help(*help))
+        })
+        .group(
+            ArgGroup::new("principal")
+                .args(principals.iter().map(|(name, _)| *name))
+                .required(attaching),
+        )
+}
+
+async fn run(matches: &ArgMatches, format: OutputFormat) -> Result<(), CliError> {
+    let mut cli_config = CliConfig::load()
+        .map_err(|error| CliError::internal(format!("Error reading configuration: {error}")))?;
+    let profile_name =
+        cli_config.active_profile_name(matches.get_one::<String>("profile").map(|p| p.as_str()));
+
+    match matches.subcommand() {
+        Some(("profiles", submatches)) => {
+            return manage_profiles(&mut cli_config, submatches, format);
+        }
+        Some(("completions", submatches)) => {
+            let shell = submatches.get_one::<String>("shell").unwrap();
+            let bin = std::env::args()
+                .next()
+                .unwrap_or_else(|| "ec_lock_smith".into());
+            return completion::write_registration(
+                shell,
+                cli().get_name(),
+                &bin,
+                &mut std::io::stdout(),
+            );
+        }
+        Some(("files", submatches)) => return manage_files(submatches, format),
+        Some(("man", submatches)) => {
+            let out_dir = submatches.get_one::<PathBuf>("out-dir");
+            return completion::write_man_pages(cli(), out_dir.map(PathBuf::as_path));
+        }
+        _ => {}
+    }
+
+    let profile = cli_config
+        .profile(&profile_name)
+        .map_err(CliError::not_found)?;
+    let mut authenticated_user = AuthenticatedUser::new(profile_name, profile.clone()).await;
+
+    match matches.subcommand() {
+        Some(("login", sub_matches)) => {
+            match prompt::login_method(sub_matches).map_err(CliError::invalid_input)? {
+                LoginMethod::ApiKey(api_key) => {
+                    authenticated_user.login_with_api_key(&api_key).await?;
+                    return output::print_success(format, "Login successful");
+                }
+                LoginMethod::AppRole { role_id, secret_id } => {
+                    authenticated_user
+                        .login_with_app_role(&role_id, &secret_id)
+                        .await?;
+                    return output::print_success(format, "Login successful");
+                }
+                LoginMethod::User => {}
+            }
+            let Some(email) = sub_matches
+                .get_one::<String>("email")
+                .cloned()
+                .or(profile.email.clone())
+            else {
+                return Err(CliError::invalid_input(
+                    "Login failed: an email is required",
+                ));
+            };
+            let password =
+                prompt::read_password(sub_matches, false).map_err(CliError::invalid_input)?;
+            let creds = UserCredentials { email, password };
+            match authenticated_user
+                .login(creds, || prompt::read_mfa_code(sub_matches))
+                .await?
+            {
+                LoginResult::LoggedIn => output::print_success(format, "Login successful"),
+                LoginResult::EnrollmentRequired => output::print_notice(
+                    format,
+                    "MFA is required for this account, run `mfa enroll` then `mfa verify` to finish logging in",
+                ),
+            }
+        }
+        Some(("logout", _)) => {
+            if authenticated_user.logout()? {
+                output::print_success(format, "Logged out successfully")
+            } else {
+                output::print_notice(format, "Not logged in")
+            }
+        }
+        Some(("whoami", _)) => {
+            let identity = authenticated_user.whoami().await?;
+            output::print_record(format, &identity)
+        }
+        Some(("secrets", submatches)) => match submatches.subcommand() {
+            Some(("list", _)) => {
+                let secrets = authenticated_user.list_secrets().await?;
+                output::print_records(format, &secrets)
+            }
+            Some(("get", submatches)) => {
+                let key = submatches.get_one::<String>("key").unwrap();
+                let secret = authenticated_user.get_secret(key).await?;
+                match format {
+                    OutputFormat::Plain => {
+                        println!("{}", secret.value.as_str());
+                        Ok(())
+                    }
+                    _ => output::print_record(format, &secret),
+                }
+            }
+            Some(("upload", submatches)) => {
+                let key = submatches.get_one::<String>("key").unwrap();
+                let path = submatches.get_one::<PathBuf>("path").unwrap();
+                let content_type = submatches.get_one::<String>("content-type").cloned();
+                let file = read_upload(path, content_type)?;
+                let stored = authenticated_user.upload_file(key, &file).await?;
+                output::print_record(format, &stored)
+            }
+            Some(("download", submatches)) => {
+                let key = submatches.get_one::<String>("key").unwrap();
+                let file = authenticated_user.download_file(key).await?;
+                write_download(
+                    key,
+                    file,
+                    submatches.get_one::<PathBuf>("out"),
+                    submatches.get_flag("extract"),
+                    format,
+                )
+            }
+            Some(("seal", submatches)) => {
+                let key = submatches.get_one::<String>("key").unwrap();
+                let (emails, groups) = recipients(submatches);
+                let value =
+                    prompt::read_value("Secret value: ").map_err(CliError::invalid_input)?;
+                let sealed = authenticated_user
+                    .seal_secret(key, &value, &emails, &groups)
+                    .await?;
+                output::print_record(format, &sealed)
+            }
+            Some(("grant", submatches)) => {
+                let key = submatches.get_one::<String>("key").unwrap();
+                let (emails, groups) = recipients(submatches);
+                let sealed = authenticated_user
+                    .grant_secret(key, &emails, &groups)
+                    .await?;
+                output::print_record(format, &sealed)
+            }
+            _ => Ok(()),
+        },
+        Some(("render", submatches)) => {
+            let template_path = submatches.get_one::<PathBuf>("template").unwrap();
+            let destination = match submatches.get_one::<PathBuf>("out") {
+                Some(out) => out.clone(),
+                None if template_path.extension().is_some_and(|ext| ext == "tmpl") => {
+                    template_path.with_extension("")
+                }
+                None => {
+                    return Err(CliError::invalid_input(
+                        "The template has no `.tmpl` extension, please provide --out",
+                    ));
+                }
+            };
+            let template = fs::read_to_string(template_path).map_err(|error| {
+                CliError::invalid_input(format!(
+                    "Error reading '{}': {error}",
+                    template_path.display()
+                ))
+            })?;
+
+            let rendered = authenticated_user.render_template(&template).await?;
+            if destination.as_os_str() == "-" {
+                print!("{}", rendered.as_str());
+                return Ok(());
+            }
+            template::write_rendered(&destination, &rendered)?;
+            output::print_success(format, &format!("Rendered '{}'", destination.display()))
+        }
+        Some(("service-accounts", submatches)) => {
+            manage_service_accounts(&mut authenticated_user, submatches, format).await
+        }
+        Some(("mfa", submatches)) => match submatches.subcommand() {
+            Some(("enroll", _)) => {
+                let enrollment = authenticated_user.enroll_mfa().await?;
+                output::print_record(format, &enrollment)?;
+                output::print_notice(
+                    format,
+                    "Add the secret to your authenticator app, then run `mfa verify`",
+                )
+            }
+            Some(("verify", submatches)) => {
+                let code = prompt::read_mfa_code(submatches).map_err(CliError::invalid_input)?;
+                let recovery_codes = authenticated_user.verify_mfa(&code).await?;
+                output::print_records(format, &recovery_codes)?;
+                output::print_notice(
+                    format,
+                    "MFA enabled, store these recovery codes safely, they won't be shown again",
+                )
+            }
+            Some(("recovery-codes", submatches)) => {
+                let code = prompt::read_mfa_code(submatches).map_err(CliError::invalid_input)?;
+                let recovery_codes = authenticated_user.regenerate_recovery_codes(&code).await?;
+                output::print_records(format, &recovery_codes)
+            }
+            Some(("disable", submatches)) => {
+                let code = prompt::read_mfa_code(submatches).map_err(CliError::invalid_input)?;
+                authenticated_user.disable_mfa(&code).await?;
+                output::print_success(format, "MFA disabled successfully")
+            }
+            _ => Ok(()),
+        },
+        Some(("e2e", submatches)) => match submatches.subcommand() {
+            Some(("init", submatches)) => {
+                let key = authenticated_user
+                    .init_e2e(submatches.get_flag("force"))
+                    .await?;
+                output::print_record(format, &key)?;
+                output::print_notice(
+                    format,
+                    "Key pair stored for this profile, keep its passphrase safe: sealed secrets can't be recovered without it",
+                )
+            }
+            Some(("public-key", _)) => {
+                let key = authenticated_user.e2e_public_key().await?;
+                output::print_record(format, &key)
+            }
+            Some(("trust", submatches)) => {
+                let email = submatches.get_one::<String>("email").unwrap();
+                let fingerprint = submatches.get_one::<String>("fingerprint").unwrap();
+                authenticated_user.trust_e2e_key(email, fingerprint)?;
+                output::print_success(format, &format!("Key of {email} pinned"))
+            }
+            _ => Ok(()),
+        },
+        Some(("admin", submatches)) => match submatches.subcommand() {
+            Some(("bootstrap", submatches)) => {
+                let password =
+                    prompt::read_password(submatches, true).map_err(CliError::invalid_input)?;
+                let creds = UserCredentials {
+                    email: submatches.get_one::<String>("email").unwrap().to_string(),
+                    password,
+                };
+                authenticated_user.bootstrap_admin(creds).await?;
+                output::print_success(format, "Admin account created, you can login now")
+            }
+            Some(("settings", _)) => {
+                let settings = authenticated_user.get_settings().await?;
+                output::print_record(format, &settings)
+            }
+            Some(("rotate-signing-key", submatches)) => {
+                let confirmed = prompt::confirm(
+                    "Rotate the signing key? Every session, yours included, has to log in again.",
+                    submatches.get_flag("yes"),
+                )
+                .map_err(CliError::invalid_input)?;
+                if !confirmed {
+                    return output::print_notice(format, "Aborted");
+                }
+                authenticated_user.rotate_signing_key().await?;
+                output::print_success(
+                    format,
+                    "Signing key rotated, running servers pick it up within five minutes",
+                )
+            }
+            Some(("re-encrypt", _)) => {
+                let summary = authenticated_user.reencrypt().await?;
+                output::print_record(format, &summary)
+            }
+            Some(("require-mfa", submatches)) => {
+                let enabled = *submatches.get_one::<bool>("enabled").unwrap();
+                let settings = authenticated_user.set_require_mfa(enabled).await?;
+                output::print_record(format, &settings)
+            }
+            _ => Ok(()),
+        },
+        Some(("operator", submatches)) => {
+            manage_seal(&mut authenticated_user, submatches, format).await
+        }
+        Some(("approles", submatches)) => {
+            manage_app_roles(&mut authenticated_user, submatches, format).await
+        }
+        Some(("invitations", submatches)) => {
+            manage_invitations(&mut authenticated_user, submatches, format).await
+        }
+        Some(("groups", submatches)) => {
+            manage_groups(&mut authenticated_user, submatches, format).await
+        }
+        Some(("orgs", submatches)) => {
+            manage_orgs(&mut authenticated_user, submatches, format).await
+        }
+        Some(("policies", submatches)) => {
+            manage_policies(&mut authenticated_user, submatches, format).await
+        }
+        Some(("password", submatches)) => match submatches.subcommand() {
+            Some(("change", submatches)) => {
+                let current_password =
+                    prompt::read_current_password(submatches).map_err(CliError::invalid_input)?;
+                let new_password =
+                    prompt::read_password(submatches, true).map_err(CliError::invalid_input)?;
+                authenticated_user
+                    .change_password(&current_password, &new_password)
+                    .await?;
+                output::print_success(format, "Password changed, other sessions were signed out")
+            }
+            Some(("forgot", submatches)) => {
+                let Some(email) = submatches
+                    .get_one::<String>("email")
+                    .cloned()
+                    .or(profile.email.clone())
+                else {
+                    return Err(CliError::invalid_input("An email is required"));
+                };
+                authenticated_user.request_password_reset(&email).await?;
+                output::print_notice(
+                    format,
+                    "If the account exists, a password reset token has been sent",
+                )
+            }
+            Some(("reset", submatches)) => {
+                let token = submatches.get_one::<String>("token").unwrap();
+                let password =
+                    prompt::read_password(submatches, true).map_err(CliError::invalid_input)?;
+                authenticated_user.reset_password(token, &password).await?;
+                output::print_success(format, "Password reset successfully, please login again")
+            }
+            _ => Ok(()),
+        },
+        Some(("users", submatches)) => match submatches.subcommand() {
+            Some(("list", submatches)) => {
+                let id: Option<&str> = submatches.get_one::<String>("id").map(|id| id.as_str());
+                let users = authenticated_user.get_users(id).await?;
+                output::print_records(format, &users)
+            }
+            Some(("delete", submatches)) => {
+                let id: Option<&str> = submatches.get_one::<String>("id").map(|id| id.as_str());
+                let confirmed = prompt::confirm(
+                    &format!("Delete user account '{}'?", id.unwrap_or_default()),
+                    submatches.get_flag("yes"),
+                )
+                .map_err(CliError::invalid_input)?;
+                if !confirmed {
+                    return output::print_notice(format, "Aborted");
+                }
+                authenticated_user.delete_user(id).await?;
+                output::print_success(format, "Deleted user successfully")
+            }
+            Some(("create", submatches)) => {
+                let password =
+                    prompt::read_password(submatches, true).map_err(CliError::invalid_input)?;
+                let creds = UserCredentials {
+                    email: submatches.get_one::<String>("email").unwrap().to_string(),
+                    password,
+                };
+                authenticated_user
+                    .create_user(creds, submatches.get_flag("admin"))
+                    .await?;
+                output::print_success(format, "User created successfully")
+            }
+            Some(("verify-email", submatches)) => {
+                let token = submatches.get_one::<String>("token").unwrap();
+                authenticated_user.verify_email(token).await?;
+                output::print_success(format, "Email verified successfully")
+            }
+            Some(("resend-verification", _)) => {
+                authenticated_user.resend_verification().await?;
+                output::print_success(format, "Verification email sent")
+            }
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+async fn manage_service_accounts(
+    authenticated_user: &mut AuthenticatedUser,
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    let id = |matches: &ArgMatches| matches.get_one::<String>("id").unwrap().to_string();
+    match matches.subcommand() {
+        Some(("create", submatches)) => {
+            let name = submatches.get_one::<String>("name").unwrap();
+            let description = submatches.get_one::<String>("description");
+            let account = authenticated_user
+                .create_service_account(name, description.map(String::as_str))
+                .await?;
+            output::print_record(format, &account)
+        }
+        Some(("list", _)) => {
+            let accounts = authenticated_user.list_service_accounts().await?;
+            output::print_records(format, &accounts)
+        }
+        Some(("delete", submatches)) => {
+            let id = id(submatches);
+            let confirmed = prompt::confirm(
+                &format!("Delete service account '{id}' and revoke all its keys?"),
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            authenticated_user.delete_service_account(&id).await?;
+            output::print_success(format, "Service account deleted successfully")
+        }
+        Some(("keys", submatches)) => match submatches.subcommand() {
+            Some(("create", submatches)) => {
+                let scopes: Vec<String> = submatches
+                    .get_many::<String>("scope")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                let expires_in_days = submatches.get_one::<i64>("expires-in-days").copied();
+                let key = authenticated_user
+                    .create_api_key(&id(submatches), &scopes, expires_in_days)
+                    .await?;
+                match format {
+                    OutputFormat::Plain => {
+                        println!("{}", key.api_key);
+                        Ok(())
+                    }
+                    _ => output::print_record(format, &key),
+                }
+            }
+            Some(("list", submatches)) => {
+                let keys = authenticated_user.list_api_keys(&id(submatches)).await?;
+                output::print_records(format, &keys)
+            }
+            Some(("revoke", submatches)) => {
+                let key_id = submatches.get_one::<String>("key-id").unwrap();
+                let confirmed = prompt::confirm(
+                    &format!("Revoke API key '{key_id}'?"),
+                    submatches.get_flag("yes"),
+                )
+                .map_err(CliError::invalid_input)?;
+                if !confirmed {
+                    return output::print_notice(format, "Aborted");
+                }
+                authenticated_user
+                    .revoke_api_key(&id(submatches), key_id)
+                    .await?;
+                output::print_success(format, "API key revoked successfully")
+            }
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+async fn manage_app_roles(
+    authenticated_user: &mut AuthenticatedUser,
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    let name = |matches: &ArgMatches| matches.get_one::<String>("name").unwrap().to_string();
+    match matches.subcommand() {
+        Some(("create", submatches)) => {
+            let minutes = |arg: &str| submatches.get_one::<i64>(arg).copied();
+            let role = AppRole {
+                name: name(submatches),
+                scopes: submatches
+                    .get_many::<String>("scope")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                secret_id_ttl_minutes: minutes("secret-id-ttl"),
+                secret_id_num_uses: minutes("secret-id-num-uses"),
+                token_ttl_minutes: minutes("token-ttl"),
+            };
+            let role = authenticated_user.create_app_role(&role).await?;
+            output::print_record(format, &role)
+        }
+        Some(("list", _)) => {
+            let roles = authenticated_user.list_app_roles().await?;
+            output::print_records(format, &roles)
+        }
+        Some(("delete", submatches)) => {
+            let name = name(submatches);
+            let confirmed = prompt::confirm(
+                &format!("Delete AppRole '{name}' and all its secret IDs?"),
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            authenticated_user.delete_app_role(&name).await?;
+            output::print_success(format, "AppRole deleted successfully")
+        }
+        Some(("secret-id", submatches)) => {
+            let secret_id = authenticated_user
+                .create_secret_id(&name(submatches))
+                .await?;
+            match format {
+                OutputFormat::Plain => {
+                    println!("{}", secret_id.secret_id);
+                    Ok(())
+                }
+                _ => output::print_record(format, &secret_id),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn manage_invitations(
+    authenticated_user: &mut AuthenticatedUser,
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    match matches.subcommand() {
+        Some(("create", submatches)) => {
+            let role = match submatches.get_one::<String>("role").map(String::as_str) {
+                Some("admin") => UserRole::Admin,
+                _ => UserRole::User,
+            };
+            let invitation = Invitation {
+                email: submatches.get_one::<String>("email").unwrap().to_string(),
+                role,
+                expires_in_hours: submatches.get_one::<i64>("expires-in-hours").copied(),
+            };
+            let invitation = authenticated_user.create_invitation(&invitation).await?;
+            output::print_record(format, &invitation)
+        }
+        Some(("list", _)) => {
+            let invitations = authenticated_user.list_invitations().await?;
+            output::print_records(format, &invitations)
+        }
+        Some(("revoke", submatches)) => {
+            let id = submatches.get_one::<String>("id").unwrap();
+            let confirmed = prompt::confirm(
+                &format!("Revoke invitation '{id}'?"),
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            authenticated_user.revoke_invitation(id).await?;
+            output::print_success(format, "Invitation revoked successfully")
+        }
+        Some(("accept", submatches)) => {
+            let token = submatches.get_one::<String>("token").unwrap();
+            let password =
+                prompt::read_password(submatches, true).map_err(CliError::invalid_input)?;
+            let email = authenticated_user
+                .accept_invitation(token, &password)
+                .await?;
+            output::print_success(
+                format,
+                &format!("Account '{email}' created, you can login now"),
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn manage_seal(
+    authenticated_user: &mut AuthenticatedUser,
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    let operator = Operator::new(matches.get_one::<String>("address").unwrap());
+    match matches.subcommand() {
+        Some(("init", submatches)) => {
+            let init = SealInit {
+                shares: *submatches.get_one::<u8>("shares").unwrap(),
+                threshold: *submatches.get_one::<u8>("threshold").unwrap(),
+            };
+            // A fresh deployment has no admin yet, the server decides
+            let token = authenticated_user.admin_token().await.ok();
+            let sealed = operator.initialize(token.as_deref(), &init)?;
+            let shares: Vec<SealShareSummary> = sealed
+                .shares
+                .into_iter()
+                .enumerate()
+                .map(|(index, share)| SealShareSummary {
+                    index: index + 1,
+                    share,
+                })
+                .collect();
+            output::print_records(format, &shares)?;
+            output::print_notice(
+                format,
+                &format!(
+                    "Store these shares apart, {} of them unseal the server. They are not shown again.",
+                    sealed.threshold
+                ),
+            )
+        }
+        Some(("status", _)) => {
+            let status = operator.status()?;
+            output::print_record(format, &SealSummary::from(&status))
+        }
+        Some(("unseal", submatches)) => {
+            let share = match submatches.get_one::<String>("share") {
+                Some(share) => share.as_str().into(),
+                None => prompt::read_secret("Share: ").map_err(CliError::invalid_input)?,
+            };
+            let status = operator.unseal(&share)?;
+            output::print_record(format, &SealSummary::from(&status))
+        }
+        Some(("export-keys", submatches)) => export_keys(submatches, format),
+        Some(("seal", submatches)) => {
+            let confirmed = prompt::confirm(
+                "Seal the server? Secrets are unavailable until it is unsealed again.",
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            let token = authenticated_user.admin_token().await?;
+            operator.seal(&token)?;
+            output::print_success(format, "Server sealed")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Copies the master keys from the configured provider (environment
+/// variables by default) to a keyfile, a PKCS#11 token or a KMS.
+fn export_keys(matches: &ArgMatches, format: OutputFormat) -> Result<(), CliError> {
+    let mut keys = Vec::new();
+    for name in KeyName::ALL {
+        if let Some(key) = key_provider::key(name)? {
+            keys.push((name, key));
+        }
+    }
+    if keys.is_empty() {
+        return Err(CliError::not_found(
+            "The configured key provider has no keys",
+        ));
+    }
+
+    match matches.get_one::<String>("to").map(String::as_str) {
+        Some("keyfile") => {
+            let path = matches
+                .get_one::<PathBuf>("keyfile")
+                .ok_or_else(|| CliError::invalid_input("--keyfile is required"))?;
+            let passphrase = match std::env::var("ECS_KEYFILE_PASSPHRASE") {
+                Ok(passphrase) => passphrase.into(),
+                Err(_) => {
+                    let passphrase = prompt::read_secret("Keyfile passphrase: ")
+                        .map_err(CliError::invalid_input)?;
+                    if prompt::read_secret("Confirm passphrase: ")
+                        .map_err(CliError::invalid_input)?
+                        != passphrase
+                    {
+                        return Err(CliError::invalid_input("Passphrases do not match"));
+                    }
+                    passphrase
+                }
+            };
+            KeyfileProvider::create(path, &passphrase, &keys)?;
+            output::print_success(format, &format!("Keys written to {}", path.display()))
+        }
+        Some("pkcs11") => {
+            let provider = Pkcs11Provider::from_env()?;
+            for (name, key) in &keys {
+                provider.store(*name, key)?;
+            }
+            output::print_success(format, "Keys stored on the PKCS#11 token")
+        }
+        Some("kms") => {
+            let provider = KmsProvider::from_env()?;
+            for (name, key) in &keys {
+                let ciphertext = provider.encrypt(key)?;
+                println!("{}={ciphertext}", KmsProvider::ciphertext_var(*name));
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/*---------------------------------------------
+Binary secrets. Directories travel as tar
+archives, the format `encrypt_directory` uses.
+----------------------------------------------*/
+const TAR_CONTENT_TYPE: &str = "application/x-tar";
+
+fn read_upload(path: &Path, content_type: Option<String>) -> Result<SecretFile, CliError> {
+    let read_error = |error: std::io::Error| {
+        CliError::invalid_input(format!("Error reading '{}': {error}", path.display()))
+    };
+    let metadata = fs::metadata(path).map_err(read_error)?;
+    let limit = max_file_size();
+    if metadata.is_file() && metadata.len() > limit {
+        return Err(PolicyError::TooLarge { limit }.into());
+    }
+
+    let (data, content_type) = if metadata.is_dir() {
+        let archive = vault::archive_directory(path).map_err(read_error)?;
+        (
+            archive,
+            content_type.unwrap_or_else(|| TAR_CONTENT_TYPE.to_string()),
+        )
+    } else {
+        let data = fs::read(path).map_err(read_error)?;
+        (
+            data,
+            content_type.unwrap_or_else(|| guess_content_type(path).to_string()),
+        )
+    };
+    Ok(SecretFile {
+        file_name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+        content_type,
+        data: data.into(),
+    })
+}
+
+fn guess_content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("p12" | "pfx") => "application/x-pkcs12",
+        Some("jks" | "keystore") => "application/x-java-keystore",
+        Some("pem" | "crt" | "key") => "application/x-pem-file",
+        Some("der" | "cer") => "application/pkix-cert",
+        Some("json") => "application/json",
+        Some("txt" | "env") => "text/plain",
+        Some("tar") => TAR_CONTENT_TYPE,
+        Some("gz" | "tgz") => "application/gzip",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+fn write_download(
+    key: &str,
+    file: SecretFile,
+    out: Option<&PathBuf>,
+    extract: bool,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    if extract {
+        if file.content_type != TAR_CONTENT_TYPE {
+            return Err(CliError::invalid_input(format!(
+                "'{key}' is {}, not an uploaded directory",
+                file.content_type
+            )));
+        }
+        let destination = out.cloned().unwrap_or_else(|| PathBuf::from("."));
+        vault::unpack_archive(&file.data, &destination)
+            .map_err(|error| CliError::internal(format!("Error extracting '{key}': {error}")))?;
+        return output::print_success(format, &format!("Extracted '{}'", destination.display()));
+    }
+
+    let destination = match out {
+        Some(out) => out.clone(),
+        None => {
+            // Only the last component of a stored name, never a path
+            let name = file
+                .file_name
+                .as_deref()
+                .and_then(|name| Path::new(name).file_name())
+                .or_else(|| Path::new(key).file_name())
+                .ok_or_else(|| CliError::invalid_input("Please provide --out"))?;
+            PathBuf::from(name)
+        }
+    };
+    if destination.as_os_str() == "-" {
+        return std::io::stdout()
+            .write_all(&file.data)
+            .map_err(|error| CliError::internal(error.to_string()));
+    }
+    template::write_rendered(&destination, &file.data)?;
+    output::print_success(format, &format!("Wrote '{}'", destination.display()))
+}
+
+/*---------------------------------------------
+Local file encryption, no server involved.
+----------------------------------------------*/
+const FILE_PASSPHRASE_ENV: &str = "ECS_FILE_PASSPHRASE";
+
+fn manage_files(matches: &ArgMatches, format: OutputFormat) -> Result<(), CliError> {
+    let Some((action, submatches)) = matches.subcommand() else {
+        return Ok(());
+    };
+    let path = submatches.get_one::<PathBuf>("path").unwrap();
+    let out = submatches.get_one::<PathBuf>("out").unwrap();
+    let passphrase = match submatches.get_one::<String>("passphrase") {
+        Some(passphrase) => passphrase.as_str().into(),
+        None => {
+            let passphrase =
+                prompt::read_secret("Passphrase: ").map_err(CliError::invalid_input)?;
+            if action == "encrypt"
+                && prompt::read_secret("Confirm passphrase: ").map_err(CliError::invalid_input)?
+                    != passphrase
+            {
+                return Err(CliError::invalid_input("Passphrases do not match"));
+            }
+            passphrase
+        }
+    };
+    let key = passphrase.as_bytes();
+
+    let result = match action {
+        "encrypt" if path.is_dir() => {
+            vault::encrypt_directory(path, out, key).map_err(|e| e.to_string())
+        }
+        "encrypt" => vault::encrypt_file(path, out, key).map_err(|e| e.to_string()),
+        "decrypt" if submatches.get_flag("directory") => {
+            vault::decrypt_directory(path, out, key).map_err(|e| e.to_string())
+        }
+        "decrypt" => vault::decrypt_file(path, out, key).map_err(|e| e.to_string()),
+        _ => return Ok(()),
+    };
+    result.map_err(|error| {
+        CliError::invalid_input(format!("Error processing '{}': {error}", path.display()))
+    })?;
+    output::print_success(format, &format!("Wrote '{}'", out.display()))
+}
+
+async fn manage_orgs(
+    authenticated_user: &mut AuthenticatedUser,
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    let arg =
+        |matches: &ArgMatches, name: &str| matches.get_one::<String>(name).unwrap().to_string();
+    match matches.subcommand() {
+        Some(("create", submatches)) => {
+            let org = Organization {
+                name: arg(submatches, "name"),
+                description: submatches.get_one::<String>("description").cloned(),
+            };
+            let org = authenticated_user.create_org(&org).await?;
+            output::print_record(format, &org)
+        }
+        Some(("list", _)) => {
+            let orgs = authenticated_user.list_orgs().await?;
+            output::print_records(format, &orgs)
+        }
+        Some(("delete", submatches)) => {
+            let name = arg(submatches, "name");
+            let confirmed = prompt::confirm(
+                &format!("Delete organization '{name}' and all of its secrets?"),
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            authenticated_user.delete_org(&name).await?;
+            output::print_success(format, "Organization deleted successfully")
+        }
+        Some(("members", submatches)) => match submatches.subcommand() {
+            Some(("list", submatches)) => {
+                let members = authenticated_user
+                    .list_org_members(&arg(submatches, "name"))
+                    .await?;
+                output::print_records(format, &members)
+            }
+            Some(("add", submatches)) => {
+                authenticated_user
+                    .add_org_member(&arg(submatches, "name"), &arg(submatches, "email"))
+                    .await?;
+                output::print_success(format, "Member added successfully")
+            }
+            Some(("remove", submatches)) => {
+                let name = arg(submatches, "name");
+                let email = arg(submatches, "email");
+                let confirmed = prompt::confirm(
+                    &format!("Remove '{email}' from organization '{name}'?"),
+                    submatches.get_flag("yes"),
+                )
+                .map_err(CliError::invalid_input)?;
+                if !confirmed {
+                    return output::print_notice(format, "Aborted");
+                }
+                authenticated_user.remove_org_member(&name, &email).await?;
+                output::print_success(format, "Member removed successfully")
+            }
+            _ => Ok(()),
+        },
+        Some(("switch", submatches)) => {
+            let name = arg(submatches, "name");
+            authenticated_user.switch_org(&name).await?;
+            output::print_success(format, &format!("Switched to organization '{name}'"))
+        }
+        Some(("adopt-legacy", submatches)) => {
+            let name = arg(submatches, "name");
+            let confirmed = prompt::confirm(
+                &format!("Move users and secrets without an organization into '{name}'?"),
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            let adopted = authenticated_user.adopt_legacy(&name).await?;
+            output::print_success(
+                format,
+                &format!(
+                    "Moved {} user(s) and {} secret(s) into '{name}'",
+                    adopted.users, adopted.secrets
+                ),
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn manage_groups(
+    authenticated_user: &mut AuthenticatedUser,
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    let arg =
+        |matches: &ArgMatches, name: &str| matches.get_one::<String>(name).unwrap().to_string();
+    let permissions = |matches: &ArgMatches| -> Vec<String> {
+        matches
+            .get_many::<String>("permission")
+            .unwrap_or_default()
+            .cloned()
+            .collect()
+    };
+    match matches.subcommand() {
+        Some(("create", submatches)) => {
+            let group = Group {
+                name: arg(submatches, "name"),
+                description: submatches.get_one::<String>("description").cloned(),
+                permissions: permissions(submatches),
+            };
+            let group = authenticated_user.create_group(&group).await?;
+            output::print_record(format, &group)
+        }
+        Some(("list", _)) => {
+            let groups = authenticated_user.list_groups().await?;
+            output::print_records(format, &groups)
+        }
+        Some(("delete", submatches)) => {
+            let name = arg(submatches, "name");
+            let confirmed = prompt::confirm(
+                &format!("Delete group '{name}', removing its members and unsharing its secrets?"),
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            authenticated_user.delete_group(&name).await?;
+            output::print_success(format, "Group deleted successfully")
+        }
+        Some(("permissions", submatches)) => {
+            let group = authenticated_user
+                .set_group_permissions(&arg(submatches, "name"), &permissions(submatches))
+                .await?;
+            output::print_record(format, &group)
+        }
+        Some(("members", submatches)) => match submatches.subcommand() {
+            Some(("list", submatches)) => {
+                let members = authenticated_user
+                    .list_group_members(&arg(submatches, "name"))
+                    .await?;
+                output::print_records(format, &members)
+            }
+            Some(("add", submatches)) => {
+                authenticated_user
+                    .add_group_member(&arg(submatches, "name"), &arg(submatches, "email"))
+                    .await?;
+                output::print_success(
+                    format,
+                    "Member added, the group applies from the member's next login",
+                )
+            }
+            Some(("remove", submatches)) => {
+                let name = arg(submatches, "name");
+                let email = arg(submatches, "email");
+                let confirmed = prompt::confirm(
+                    &format!("Remove '{email}' from group '{name}'?"),
+                    submatches.get_flag("yes"),
+                )
+                .map_err(CliError::invalid_input)?;
+                if !confirmed {
+                    return output::print_notice(format, "Aborted");
+                }
+                authenticated_user
+                    .remove_group_member(&name, &email)
+                    .await?;
+                output::print_success(format, "Member removed successfully")
+            }
+            _ => Ok(()),
+        },
+        Some(("share", submatches)) => {
+            let name = arg(submatches, "name");
+            let key = authenticated_user
+                .share_secret(&name, &arg(submatches, "key"))
+                .await?;
+            output::print_success(format, &format!("Shared '{key}' with group '{name}'"))
+        }
+        Some(("unshare", submatches)) => {
+            let name = arg(submatches, "name");
+            let key = authenticated_user
+                .unshare_secret(&name, &arg(submatches, "key"))
+                .await?;
+            output::print_success(
+                format,
+                &format!("Stopped sharing '{key}' with group '{name}'"),
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+fn manage_profiles(
+    cli_config: &mut CliConfig,
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    match matches.subcommand() {
+        Some(("list", _)) => {
+            let current = cli_config.active_profile_name(None);
+            let profiles: Vec<ProfileSummary> = cli_config
+                .profiles
+                .iter()
+                .map(|(name, profile)| ProfileSummary {
+                    name: name.clone(),
+                    current: *name == current,
+                    server_url: profile.server_url.clone(),
+                    email: profile.email.clone(),
+                    namespace: profile.namespace.clone(),
+                })
+                .collect();
+            output::print_records(format, &profiles)
+        }
+        Some(("set", submatches)) => {
+            let name = submatches.get_one::<String>("name").unwrap().to_string();
+            config::validate_profile_name(&name).map_err(CliError::invalid_input)?;
+            let profile = cli_config.profiles.entry(name.clone()).or_default();
+            let value = |arg: &str| submatches.get_one::<String>(arg).cloned();
+            if let Some(server_url) = value("server-url") {
+                profile.server_url = Some(server_url);
+            }
+            if let Some(database) = value("database") {
+                profile.database_name = Some(database);
+            }
+            if let Some(email) = value("email") {
+                profile.email = Some(email);
+            }
+            if let Some(namespace) = value("namespace") {
+                profile.namespace = Some(namespace);
+            }
+            if let Some(encrypt_token) = submatches.get_one::<bool>("encrypt-token") {
+                profile.encrypt_token = *encrypt_token;
+            }
+            cli_config.save()?;
+            output::print_success(format, &format!("Profile '{name}' saved"))
+        }
+        Some(("use", submatches)) => {
+            let name = submatches.get_one::<String>("name").unwrap();
+            cli_config.profile(name).map_err(CliError::not_found)?;
+            cli_config.current_profile = Some(name.to_string());
+            cli_config.save()?;
+            output::print_success(format, &format!("Switched to profile '{name}'"))
+        }
+        Some(("delete", submatches)) => {
+            let name = submatches.get_one::<String>("name").unwrap();
+            if !cli_config.profiles.contains_key(name) {
+                return Err(CliError::not_found(format!(
+                    "Profile '{name}' does not exist"
+                )));
+            }
+            let confirmed = prompt::confirm(
+                &format!("Delete profile '{name}'?"),
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            cli_config.profiles.remove(name);
+            if cli_config.current_profile.as_deref() == Some(name.as_str()) {
+                cli_config.current_profile = None;
+            }
+            // Tokens of names from before names were restricted may live
+            // outside the config directory, leave those alone
+            if config::validate_profile_name(name).is_ok() {
+                config::remove_token(name)?;
+            }
+            cli_config.save()?;
+            output::print_success(format, &format!("Profile '{name}' deleted"))
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn manage_policies(
+    authenticated_user: &mut AuthenticatedUser,
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<(), CliError> {
+    let arg =
+        |matches: &ArgMatches, name: &str| matches.get_one::<String>(name).unwrap().to_string();
+    let rules = |matches: &ArgMatches| -> Result<Vec<PolicyRule>, CliError> {
+        let rules = match matches.get_one::<PathBuf>("file") {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|error| {
+                    CliError::invalid_input(format!("Error reading '{}': {error}", path.display()))
+                })?;
+                policy::parse_rules(&text)
+            }
+            None => matches
+                .get_many::<String>("rule")
+                .unwrap_or_default()
+                .map(|rule| rule.parse())
+                .collect(),
+        };
+        rules.map_err(CliError::invalid_input)
+    };
+    let attachment = |matches: &ArgMatches| -> Option<PolicyAttachment> {
+        [
+            ("user", PrincipalKind::User),
+            ("group", PrincipalKind::Group),
+            ("service-account", PrincipalKind::ServiceAccount),
+        ]
+        .into_iter()
+        .find_map(|(name, kind)| {
+            matches.get_one::<String>(name).map(|id| PolicyAttachment {
+                kind,
+                id: id.clone(),
+            })
+        })
+    };
+    match matches.subcommand() {
+        Some(("create", submatches)) => {
+            let policy = Policy {
+                name: arg(submatches, "name"),
+                description: submatches.get_one::<String>("description").cloned(),
+                rules: rules(submatches)?,
+            };
+            let policy = authenticated_user.create_policy(&policy).await?;
+            output::print_record(format, &policy)
+        }
+        Some(("list", _)) => {
+            let policies = authenticated_user.list_policies().await?;
+            output::print_records(format, &policies)
+        }
+        Some(("show", submatches)) => {
+            let policy = authenticated_user
+                .get_policy(&arg(submatches, "name"))
+                .await?;
+            output::print_record(format, &policy)
+        }
+        Some(("update", submatches)) => {
+            let rules = PolicyRules {
+                description: submatches.get_one::<String>("description").cloned(),
+                rules: rules(submatches)?,
+            };
+            let policy = authenticated_user
+                .update_policy(&arg(submatches, "name"), &rules)
+                .await?;
+            output::print_record(format, &policy)
+        }
+        Some(("delete", submatches)) => {
+            let name = arg(submatches, "name");
+            let confirmed = prompt::confirm(
+                &format!("Delete policy '{name}', detaching it from everyone it applies to?"),
+                submatches.get_flag("yes"),
+            )
+            .map_err(CliError::invalid_input)?;
+            if !confirmed {
+                return output::print_notice(format, "Aborted");
+            }
+            authenticated_user.delete_policy(&name).await?;
+            output::print_success(format, "Policy deleted successfully")
+        }
+        Some(("attach", submatches)) => {
+            let attachment = attachment(submatches).unwrap();
+            let policy = authenticated_user
+                .attach_policy(&arg(submatches, "name"), &attachment)
+                .await?;
+            output::print_record(format, &policy)
+        }
+        Some(("detach", submatches)) => {
+            let attachment = attachment(submatches).unwrap();
+            let policy = authenticated_user
+                .detach_policy(&arg(submatches, "name"), &attachment)
+                .await?;
+            output::print_record(format, &policy)
+        }
+        Some(("check", submatches)) => {
+            let attachment = attachment(submatches);
+            let check = PolicyCheck {
+                path: arg(submatches, "path"),
+                capability: arg(submatches, "capability")
+                    .parse()
+                    .map_err(CliError::invalid_input)?,
+                user: attachment
+                    .as_ref()
+                    .filter(|attachment| attachment.kind == PrincipalKind::User)
+                    .map(|attachment| attachment.id.clone()),
+                service_account: attachment
+                    .as_ref()
+                    .filter(|attachment| attachment.kind == PrincipalKind::ServiceAccount)
+                    .map(|attachment| attachment.id.clone()),
+            };
+            let decision = authenticated_user.check_policy(&check).await?;
+            output::print_record(format, &decision)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pasetors::{
+        claims::Claims,
+        keys::{AsymmetricKeyPair, Generate},
+        public,
+        version4::V4,
+    };
+
+    #[test]
+    fn login_flags_win_over_the_environment() {
+        // SAFETY: no other test reads or writes these variables
+        unsafe {
+            std::env::set_var(prompt::PASSWORD_ENV, "from-env");
+            std::env::set_var(prompt::API_KEY_ENV, "ecs_key_secret");
+        }
+        let login = |args: &[&str]| {
+            let matches = cli()
+                .try_get_matches_from([&["ec_lock_smith", "login"], args].concat())
+                .expect("login arguments failed to parse");
+            let (_, login) = matches.subcommand().unwrap();
+            prompt::login_method(login).unwrap()
+        };
+
+        assert_eq!(
+            login(&["-e", "a@b.c", "--password-stdin"]),
+            LoginMethod::User
+        );
+        assert_eq!(login(&["-p", "secret"]), LoginMethod::User);
+        assert_eq!(login(&[]), LoginMethod::ApiKey("ecs_key_secret".into()));
+        assert!(
+            cli()
+                .try_get_matches_from(["ec_lock_smith", "login", "--api-key", "k", "-p", "x"])
+                .is_err()
+        );
+        // SAFETY: as above
+        unsafe {
+            std::env::remove_var(prompt::PASSWORD_ENV);
+            std::env::remove_var(prompt::API_KEY_ENV);
+        }
+    }
+
+    fn completions(args: &[&str]) -> Vec<String> {
+        let args = [&["ec_lock_smith"], args].concat();
+        let index = args.len() - 1;
+        clap_complete::engine::complete(
+            &mut cli(),
+            args.iter().map(Into::into).collect(),
+            index,
+            None,
+        )
+        .expect("Failed to complete")
+        .iter()
+        .map(|candidate| candidate.get_value().to_string_lossy().into_owned())
+        .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn completions_cover_subcommands_and_survive_an_unreachable_server() {
+        let mut script = Vec::new();
+        completion::write_registration("bash", "ec_lock_smith", "ec_lock_smith", &mut script)
+            .expect("Failed to render bash completions");
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains(COMPLETE_ENV) && script.contains("ec_lock_smith"));
+        assert!(completions(&["sec"]).contains(&"secrets".to_string()));
+        assert!(completions(&["secrets", "g"]).contains(&"get".to_string()));
+
+        // Logged in against a server that isn't there: no candidates, no error
+        let dir =
+            std::env::temp_dir().join(format!("lock_smith_completions_{}", std::process::id()));
+        let profile = config::Profile {
+            server_url: Some("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=200".into()),
+            database_name: Some("completions".into()),
+            ..Default::default()
+        };
+        let mut cli_config = CliConfig::default();
+        cli_config
+            .profiles
+            .insert(config::DEFAULT_PROFILE.into(), profile.clone());
+        cli_config.save_to(&dir).expect("Failed to save config");
+        // SAFETY: no other test reads or writes this variable
+        unsafe { std::env::set_var(config::CONFIG_DIR_ENV, &dir) };
+        // Well formed, so checking it needs the server's public key
+        let key_pair = AsymmetricKeyPair::<V4>::generate().unwrap();
+        let token = public::sign(&key_pair.secret, &Claims::new().unwrap(), None, None).unwrap();
+        config::save_token(config::DEFAULT_PROFILE, &profile, &token)
+            .expect("Failed to save token");
+
+        let found = completions(&["secrets", "get", "pay"]);
+        assert!(found.is_empty(), "{found:?}");
+        std::fs::remove_dir_all(&dir).expect("Failed to remove test directory");
     }
 }
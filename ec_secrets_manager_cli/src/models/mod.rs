@@ -0,0 +1,900 @@
+use mongodb::bson::DateTime;
+use serde::Serialize;
+
+use crate::output::Record;
+use ec_secrets_shared_library::models::{
+    ApiKeyDocument, AppRoleDocument, GroupDocument, InvitationDocument, OrganizationDocument,
+    PolicyDocument, ServiceAccountDocument, SettingsDocument, UserDocument, VaultDocument,
+};
+use ec_secrets_shared_library::utils::{
+    policy::Decision, reencryption::Reencrypted, seal::SealStatus, secret::SecretString,
+    vault::e2e::RecipientKey,
+};
+
+/*------------
+ User models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub id: String,
+    pub email: String,
+    pub admin: bool,
+    pub mfa: bool,
+    pub verified: bool,
+    pub groups: Vec<String>,
+    pub created_at: String,
+}
+
+impl From<&UserDocument> for UserSummary {
+    fn from(user: &UserDocument) -> Self {
+        Self {
+            id: user.id.to_string(),
+            email: user.email.clone(),
+            admin: user.admin,
+            mfa: user.mfa_enabled(),
+            verified: user.email_verified,
+            groups: user.groups.clone(),
+            created_at: user.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl Record for UserSummary {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Id",
+            "Email",
+            "Admin",
+            "Mfa",
+            "Verified",
+            "Groups",
+            "CreatedAt",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.email.clone(),
+            self.admin.to_string(),
+            self.mfa.to_string(),
+            self.verified.to_string(),
+            self.groups.join(","),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+/*------------
+ Group models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct GroupSummary {
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Vec<String>,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+impl From<&GroupDocument> for GroupSummary {
+    fn from(group: &GroupDocument) -> Self {
+        Self {
+            name: group.name.clone(),
+            description: group.description.clone(),
+            permissions: group.permissions.clone(),
+            created_by: group.created_by.clone(),
+            created_at: group.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl Record for GroupSummary {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Name",
+            "Description",
+            "Permissions",
+            "CreatedBy",
+            "CreatedAt",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            display(&self.description),
+            self.permissions.join(","),
+            self.created_by.clone(),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupMemberSummary {
+    pub id: String,
+    pub email: String,
+}
+
+impl From<&UserDocument> for GroupMemberSummary {
+    fn from(user: &UserDocument) -> Self {
+        Self {
+            id: user.id.to_hex(),
+            email: user.email.clone(),
+        }
+    }
+}
+
+impl Record for GroupMemberSummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["Id", "Email"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.id.clone(), self.email.clone()]
+    }
+}
+
+/*-------------------
+ Organization models
+--------------------*/
+#[derive(Debug, Serialize)]
+pub struct OrganizationSummary {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+impl From<&OrganizationDocument> for OrganizationSummary {
+    fn from(org: &OrganizationDocument) -> Self {
+        Self {
+            id: org.id.to_hex(),
+            name: org.name.clone(),
+            description: org.description.clone(),
+            created_by: org.created_by.clone(),
+            created_at: org.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl Record for OrganizationSummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["Id", "Name", "Description", "CreatedBy", "CreatedAt"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.name.clone(),
+            display(&self.description),
+            self.created_by.clone(),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+/*-------------
+ Policy models
+--------------*/
+#[derive(Debug, Serialize)]
+pub struct PolicySummary {
+    pub name: String,
+    pub description: Option<String>,
+    pub rules: Vec<String>,
+    pub users: Vec<String>,
+    pub groups: Vec<String>,
+    pub service_accounts: Vec<String>,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+impl From<&PolicyDocument> for PolicySummary {
+    fn from(policy: &PolicyDocument) -> Self {
+        Self {
+            name: policy.name.clone(),
+            description: policy.description.clone(),
+            rules: policy.rules.iter().map(ToString::to_string).collect(),
+            users: policy.users.clone(),
+            groups: policy.groups.clone(),
+            service_accounts: policy.service_accounts.clone(),
+            created_by: policy.created_by.clone(),
+            created_at: policy.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl Record for PolicySummary {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Name",
+            "Description",
+            "Rules",
+            "Users",
+            "Groups",
+            "ServiceAccounts",
+            "CreatedBy",
+            "CreatedAt",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            display(&self.description),
+            self.rules.join("; "),
+            self.users.join(","),
+            self.groups.join(","),
+            self.service_accounts.join(","),
+            self.created_by.clone(),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PolicyDecision {
+    pub allowed: bool,
+    pub capability: String,
+    pub path: String,
+    pub reasons: Vec<String>,
+}
+
+impl From<&Decision> for PolicyDecision {
+    fn from(decision: &Decision) -> Self {
+        Self {
+            allowed: decision.allowed,
+            capability: decision.capability.as_str().to_string(),
+            path: decision.path.clone(),
+            reasons: decision.reasons.clone(),
+        }
+    }
+}
+
+impl Record for PolicyDecision {
+    fn headers() -> Vec<&'static str> {
+        vec!["Decision", "Capability", "Path", "Reasons"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            if self.allowed { "allowed" } else { "denied" }.to_string(),
+            self.capability.clone(),
+            self.path.clone(),
+            self.reasons.join("; "),
+        ]
+    }
+}
+
+/*------------
+ Vault models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct SecretSummary {
+    pub id: String,
+    pub key: String,
+    pub created_by: String,
+    pub groups: Vec<String>,
+    /// Content type and size of binary entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Sealed client-side, only its recipients can read the value
+    pub end_to_end: bool,
+    pub created_at: String,
+}
+
+impl From<&VaultDocument> for SecretSummary {
+    fn from(secret: &VaultDocument) -> Self {
+        Self {
+            id: secret.id.to_string(),
+            key: secret.key.clone(),
+            created_by: secret.created_by.clone(),
+            groups: secret.groups.clone(),
+            content_type: secret.file.as_ref().map(|file| file.content_type.clone()),
+            size: secret.file.as_ref().map(|file| file.size),
+            end_to_end: secret.end_to_end,
+            created_at: secret.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl Record for SecretSummary {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Id",
+            "Key",
+            "CreatedBy",
+            "Groups",
+            "File",
+            "E2E",
+            "CreatedAt",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let file = match (&self.content_type, self.size) {
+            (Some(content_type), Some(size)) => format!("{content_type} ({size} B)"),
+            _ => String::new(),
+        };
+        vec![
+            self.id.clone(),
+            self.key.clone(),
+            self.created_by.clone(),
+            self.groups.join(","),
+            file,
+            if self.end_to_end { "yes" } else { "" }.to_string(),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredFile {
+    pub id: String,
+    pub key: String,
+    pub size: u64,
+}
+
+impl Record for StoredFile {
+    fn headers() -> Vec<&'static str> {
+        vec!["Id", "Key", "Size"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.id.clone(), self.key.clone(), self.size.to_string()]
+    }
+}
+
+/// An end-to-end encrypted entry and who can open it.
+#[derive(Debug, Serialize)]
+pub struct SealedSecret {
+    pub key: String,
+    pub recipients: Vec<String>,
+}
+
+impl Record for SealedSecret {
+    fn headers() -> Vec<&'static str> {
+        vec!["Key", "Recipients"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.key.clone(), self.recipients.join(",")]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SecretValue {
+    pub key: String,
+    pub value: SecretString,
+}
+
+impl Record for SecretValue {
+    fn headers() -> Vec<&'static str> {
+        vec!["Key", "Value"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.key.clone(), self.value.to_string()]
+    }
+}
+
+/*------------
+ Service account models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct ServiceAccountSummary {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: String,
+}
+
+impl From<&ServiceAccountDocument> for ServiceAccountSummary {
+    fn from(account: &ServiceAccountDocument) -> Self {
+        Self {
+            id: account.id.to_hex(),
+            name: account.name.clone(),
+            description: account.description.clone(),
+            created_at: account.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl Record for ServiceAccountSummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["Id", "Name", "Description", "CreatedAt"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.name.clone(),
+            display(&self.description),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeySummary {
+    pub id: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub created_at: String,
+}
+
+impl From<&ApiKeyDocument> for ApiKeySummary {
+    fn from(key: &ApiKeyDocument) -> Self {
+        Self {
+            id: key.id.to_hex(),
+            scopes: key.scopes.clone(),
+            expires_at: key.expires_at.map(|date| date.to_rfc3339()),
+            revoked_at: key.revoked_at.map(|date| date.to_rfc3339()),
+            created_at: key.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl Record for ApiKeySummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["Id", "Scopes", "ExpiresAt", "RevokedAt", "CreatedAt"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.scopes.join(","),
+            display(&self.expires_at),
+            display(&self.revoked_at),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+/// A freshly issued key, the only time the full key is available.
+#[derive(Debug, Serialize)]
+pub struct IssuedApiKey {
+    pub id: String,
+    pub api_key: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<String>,
+}
+
+impl Record for IssuedApiKey {
+    fn headers() -> Vec<&'static str> {
+        vec!["Id", "ApiKey", "Scopes", "ExpiresAt"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.api_key.clone(),
+            self.scopes.join(","),
+            display(&self.expires_at),
+        ]
+    }
+}
+
+/*------------
+ AppRole models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct AppRoleSummary {
+    pub name: String,
+    pub role_id: String,
+    pub scopes: Vec<String>,
+    pub secret_id_ttl_minutes: i64,
+    pub secret_id_num_uses: Option<i64>,
+    pub token_ttl_minutes: i64,
+    pub created_at: String,
+}
+
+impl From<&AppRoleDocument> for AppRoleSummary {
+    fn from(role: &AppRoleDocument) -> Self {
+        Self {
+            name: role.name.clone(),
+            role_id: role.role_id.clone(),
+            scopes: role.scopes.clone(),
+            secret_id_ttl_minutes: role.secret_id_ttl_minutes,
+            secret_id_num_uses: role.secret_id_num_uses,
+            token_ttl_minutes: role.token_ttl_minutes,
+            created_at: role.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl Record for AppRoleSummary {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Name",
+            "RoleId",
+            "Scopes",
+            "SecretIdTtl",
+            "SecretIdUses",
+            "TokenTtl",
+            "CreatedAt",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.role_id.clone(),
+            self.scopes.join(","),
+            format!("{}m", self.secret_id_ttl_minutes),
+            display(&self.secret_id_num_uses.map(|uses| uses.to_string())),
+            format!("{}m", self.token_ttl_minutes),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+/// A freshly issued secret ID, the only time it is available.
+#[derive(Debug, Serialize)]
+pub struct IssuedSecretId {
+    pub role: String,
+    pub secret_id: String,
+    pub uses_remaining: Option<i64>,
+    pub expires_at: String,
+}
+
+impl Record for IssuedSecretId {
+    fn headers() -> Vec<&'static str> {
+        vec!["Role", "SecretId", "UsesRemaining", "ExpiresAt"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.role.clone(),
+            self.secret_id.clone(),
+            display(&self.uses_remaining.map(|uses| uses.to_string())),
+            self.expires_at.clone(),
+        ]
+    }
+}
+
+/*------------
+ Invitation models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct InvitationSummary {
+    pub id: String,
+    pub email: String,
+    pub role: String,
+    pub invited_by: String,
+    pub expires_at: String,
+    pub expired: bool,
+}
+
+impl From<&InvitationDocument> for InvitationSummary {
+    fn from(invitation: &InvitationDocument) -> Self {
+        Self {
+            id: invitation.id.to_hex(),
+            email: invitation.email.clone(),
+            role: invitation.role.as_str().to_string(),
+            invited_by: invitation.invited_by.clone(),
+            expires_at: invitation.expires_at.to_rfc3339(),
+            expired: invitation.expires_at <= DateTime::now().to_chrono(),
+        }
+    }
+}
+
+impl Record for InvitationSummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["Id", "Email", "Role", "InvitedBy", "ExpiresAt", "Expired"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.email.clone(),
+            self.role.clone(),
+            self.invited_by.clone(),
+            self.expires_at.clone(),
+            self.expired.to_string(),
+        ]
+    }
+}
+
+/// A freshly issued invitation, the only time its token is available.
+#[derive(Debug, Serialize)]
+pub struct IssuedInvitation {
+    pub id: String,
+    pub email: String,
+    pub role: String,
+    pub token: String,
+    pub expires_at: String,
+}
+
+impl Record for IssuedInvitation {
+    fn headers() -> Vec<&'static str> {
+        vec!["Id", "Email", "Role", "Token", "ExpiresAt"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.email.clone(),
+            self.role.clone(),
+            self.token.clone(),
+            self.expires_at.clone(),
+        ]
+    }
+}
+
+/*------------
+ MFA models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct MfaEnrollment {
+    pub secret: SecretString,
+    pub otpauth_uri: SecretString,
+}
+
+impl Record for MfaEnrollment {
+    fn headers() -> Vec<&'static str> {
+        vec!["Secret", "OtpauthUri"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.secret.to_string(), self.otpauth_uri.to_string()]
+    }
+}
+
+/// Public half of the key pair end-to-end encrypted values are sealed for.
+#[derive(Debug, Serialize)]
+pub struct E2eKey {
+    pub public_key: String,
+    pub fingerprint: String,
+}
+
+impl From<&RecipientKey> for E2eKey {
+    fn from(key: &RecipientKey) -> Self {
+        Self {
+            public_key: key.to_base64(),
+            fingerprint: key.fingerprint(),
+        }
+    }
+}
+
+impl Record for E2eKey {
+    fn headers() -> Vec<&'static str> {
+        vec!["PublicKey", "Fingerprint"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.public_key.clone(), self.fingerprint.clone()]
+    }
+}
+
+/// Single use recovery code, only shown when generated.
+#[derive(Debug, Serialize)]
+pub struct RecoveryCode {
+    pub code: String,
+}
+
+impl From<String> for RecoveryCode {
+    fn from(code: String) -> Self {
+        Self { code }
+    }
+}
+
+impl Record for RecoveryCode {
+    fn headers() -> Vec<&'static str> {
+        vec!["RecoveryCode"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.code.clone()]
+    }
+}
+
+/*------------
+ Settings models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct InstanceSettings {
+    pub require_mfa: bool,
+    pub updated_by: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+impl From<SettingsDocument> for InstanceSettings {
+    fn from(settings: SettingsDocument) -> Self {
+        Self {
+            require_mfa: settings.require_mfa,
+            updated_by: settings.updated_by,
+            updated_at: settings.updated_at.map(|date| date.to_rfc3339()),
+        }
+    }
+}
+
+impl Record for InstanceSettings {
+    fn headers() -> Vec<&'static str> {
+        vec!["RequireMfa", "UpdatedBy", "UpdatedAt"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.require_mfa.to_string(),
+            display(&self.updated_by),
+            display(&self.updated_at),
+        ]
+    }
+}
+
+/// Counts of an `admin re-encrypt` run.
+#[derive(Debug, Serialize)]
+pub struct ReencryptSummary {
+    pub org_keys: u64,
+    pub secrets: u64,
+    pub signing_keys: u64,
+    pub mfa_secrets: u64,
+    pub failed: u64,
+}
+
+impl From<Reencrypted> for ReencryptSummary {
+    fn from(reencrypted: Reencrypted) -> Self {
+        Self {
+            org_keys: reencrypted.org_keys,
+            secrets: reencrypted.secrets,
+            signing_keys: reencrypted.signing_keys,
+            mfa_secrets: reencrypted.mfa_secrets,
+            failed: reencrypted.failed,
+        }
+    }
+}
+
+impl Record for ReencryptSummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["OrgKeys", "Secrets", "SigningKeys", "MfaSecrets", "Failed"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.org_keys.to_string(),
+            self.secrets.to_string(),
+            self.signing_keys.to_string(),
+            self.mfa_secrets.to_string(),
+            self.failed.to_string(),
+        ]
+    }
+}
+
+/*------------
+ Seal models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct SealSummary {
+    pub sealed_mode: bool,
+    pub initialized: bool,
+    pub sealed: bool,
+    pub threshold: u8,
+    pub shares: u8,
+    pub progress: u8,
+}
+
+impl From<&SealStatus> for SealSummary {
+    fn from(status: &SealStatus) -> Self {
+        Self {
+            sealed_mode: status.sealed_mode,
+            initialized: status.initialized,
+            sealed: status.sealed,
+            threshold: status.threshold,
+            shares: status.shares,
+            progress: status.progress,
+        }
+    }
+}
+
+impl Record for SealSummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["SealedMode", "Initialized", "Sealed", "Progress"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.sealed_mode.to_string(),
+            self.initialized.to_string(),
+            self.sealed.to_string(),
+            format!(
+                "{}/{} of {} shares",
+                self.progress, self.threshold, self.shares
+            ),
+        ]
+    }
+}
+
+/// A Shamir share of the master key, see `operator init`.
+#[derive(Debug, Serialize)]
+pub struct SealShareSummary {
+    pub index: usize,
+    pub share: String,
+}
+
+impl Record for SealShareSummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["Share", "Key"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.index.to_string(), self.share.clone()]
+    }
+}
+
+/*------------
+ Session models
+-------------*/
+#[derive(Debug, Serialize)]
+pub struct Identity {
+    pub profile: String,
+    pub subject: Option<String>,
+    pub service_account: Option<String>,
+    pub app_role: Option<String>,
+    pub groups: Vec<String>,
+    pub org: Option<String>,
+    pub expires: Option<String>,
+    pub namespace: Option<String>,
+}
+
+impl Record for Identity {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Profile",
+            "Subject",
+            "ServiceAccount",
+            "AppRole",
+            "Groups",
+            "Org",
+            "Expires",
+            "Namespace",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.profile.clone(),
+            display(&self.subject),
+            display(&self.service_account),
+            display(&self.app_role),
+            self.groups.join(","),
+            display(&self.org),
+            display(&self.expires),
+            display(&self.namespace),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileSummary {
+    pub name: String,
+    pub current: bool,
+    pub server_url: Option<String>,
+    pub email: Option<String>,
+    pub namespace: Option<String>,
+}
+
+impl Record for ProfileSummary {
+    fn headers() -> Vec<&'static str> {
+        vec!["", "Name", "ServerUrl", "Email", "Namespace"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            if self.current { "*" } else { "" }.to_string(),
+            self.name.clone(),
+            display(&self.server_url),
+            display(&self.email),
+            display(&self.namespace),
+        ]
+    }
+}
+
+pub(crate) fn display(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "-".to_string())
+}
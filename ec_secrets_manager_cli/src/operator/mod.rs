@@ -0,0 +1,142 @@
+use ec_secrets_shared_library::{
+    models::{SealInit, UnsealShare},
+    utils::seal::SealStatus,
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::error::CliError;
+
+/// Environment variable with the address of the secrets management server.
+pub const ADDRESS_ENV: &str = "ECS_ADDRESS";
+pub const DEFAULT_ADDRESS: &str = "http://127.0.0.1:8089";
+
+/*---------------------------------------------------------------------------
+    Seal operations go to the running server: the master key only exists
+    in the memory of the server process, so unsealing has to happen there
+    rather than through the database like the other commands.
+---------------------------------------------------------------------------*/
+pub struct Operator {
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SealShares {
+    pub threshold: u8,
+    pub shares: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    status: u16,
+    message: String,
+}
+
+impl Operator {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn status(&self) -> Result<SealStatus, CliError> {
+        self.send(ureq::get(&self.url("/sys/seal-status")))
+    }
+
+    /// Without a token the server only initializes a fresh deployment.
+    pub fn initialize(&self, token: Option<&str>, init: &SealInit) -> Result<SealShares, CliError> {
+        let mut request = ureq::post(&self.url("/sys/init"));
+        if let Some(token) = token {
+            request = request.set("Authorization", &format!("Bearer {token}"));
+        }
+        self.send_json(request, init)
+    }
+
+    pub fn unseal(&self, share: &str) -> Result<SealStatus, CliError> {
+        let share = UnsealShare {
+            share: share.into(),
+        };
+        self.send_json(ureq::post(&self.url("/sys/unseal")), &share)
+    }
+
+    pub fn seal(&self, token: &str) -> Result<(), CliError> {
+        let request =
+            ureq::post(&self.url("/sys/seal")).set("Authorization", &format!("Bearer {token}"));
+        self.send::<Value>(request).map(|_| ())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.address)
+    }
+
+    fn send<T: DeserializeOwned>(&self, request: ureq::Request) -> Result<T, CliError> {
+        self.parse(request.call())
+    }
+
+    fn send_json<T: DeserializeOwned>(
+        &self,
+        request: ureq::Request,
+        body: &impl serde::Serialize,
+    ) -> Result<T, CliError> {
+        self.parse(request.send_json(body))
+    }
+
+    /// Errors come back as `{status, message}` bodies, whatever the HTTP
+    /// status of the response.
+    fn parse<T: DeserializeOwned>(
+        &self,
+        response: Result<ureq::Response, ureq::Error>,
+    ) -> Result<T, CliError> {
+        let response = match response {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(error) => {
+                return Err(CliError::connectivity(format!(
+                    "Failed to reach the server at {}: {error}",
+                    self.address
+                )));
+            }
+        };
+        let body: Value = response
+            .into_json()
+            .map_err(|error| CliError::internal(format!("Invalid server response: {error}")))?;
+        if let Ok(error) = serde_json::from_value::<ErrorResponse>(body.clone())
+            && error.status >= 400
+        {
+            return Err(response_error(error));
+        }
+        serde_json::from_value(body)
+            .map_err(|error| CliError::internal(format!("Invalid server response: {error}")))
+    }
+}
+
+fn response_error(error: ErrorResponse) -> CliError {
+    match error.status {
+        400 | 422 => CliError::invalid_input(error.message),
+        401 | 403 => CliError::auth(error.message),
+        404 => CliError::not_found(error.message),
+        409 => CliError::conflict(error.message),
+        503 => CliError::sealed(error.message),
+        _ => CliError::internal(error.message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn error_statuses() {
+        let error = |status| {
+            response_error(ErrorResponse {
+                status,
+                message: String::new(),
+            })
+            .kind
+        };
+        assert_eq!(error(503), ErrorKind::Sealed);
+        assert_eq!(error(409), ErrorKind::Conflict);
+        assert_eq!(error(401), ErrorKind::Auth);
+        assert_eq!(error(500), ErrorKind::Internal);
+    }
+}
@@ -0,0 +1,162 @@
+use clap::{Arg, builder::PossibleValuesParser};
+use prettytable::{Cell, Row, Table};
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::error::CliError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+    Plain,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "plain" => Ok(OutputFormat::Plain),
+            other => Err(format!("Unsupported output format '{other}'")),
+        }
+    }
+}
+
+pub fn output_arg() -> Arg {
+    Arg::new("output")
+        .short('o')
+        .long("output")
+        .global(true)
+        .default_value("table")
+        .value_parser(PossibleValuesParser::new([
+            "table", "json", "yaml", "plain",
+        ]))
+        .help("Output format")
+}
+
+/*---------------------------------------------------------------------------
+    Records printed by the CLI. Structured formats serialize the record
+    as-is, table and plain modes use the headers and row values.
+---------------------------------------------------------------------------*/
+pub trait Record: Serialize {
+    fn headers() -> Vec<&'static str>;
+    fn row(&self) -> Vec<String>;
+}
+
+#[derive(Debug, Serialize)]
+struct Status<'a> {
+    status: &'a str,
+    message: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorEnvelope<'a> {
+    error: &'a CliError,
+    exit_code: u8,
+}
+
+pub fn print_records<T: Record>(format: OutputFormat, records: &[T]) -> Result<(), CliError> {
+    match format {
+        OutputFormat::Table => {
+            let mut table = Table::new();
+            table.add_row(Row::new(T::headers().into_iter().map(Cell::new).collect()));
+            records.iter().for_each(|record| {
+                table.add_row(Row::new(
+                    record.row().iter().map(|value| Cell::new(value)).collect(),
+                ));
+            });
+            table.printstd();
+        }
+        OutputFormat::Plain => records
+            .iter()
+            .for_each(|record| println!("{}", record.row().join("\t"))),
+        OutputFormat::Json | OutputFormat::Yaml => print_structured(format, &records)?,
+    }
+    Ok(())
+}
+
+pub fn print_record<T: Record>(format: OutputFormat, record: &T) -> Result<(), CliError> {
+    match format {
+        OutputFormat::Table => {
+            let mut table = Table::new();
+            T::headers()
+                .into_iter()
+                .zip(record.row())
+                .for_each(|(header, value)| {
+                    table.add_row(Row::new(vec![Cell::new(header), Cell::new(&value)]));
+                });
+            table.printstd();
+        }
+        OutputFormat::Plain => println!("{}", record.row().join("\t")),
+        OutputFormat::Json | OutputFormat::Yaml => print_structured(format, record)?,
+    }
+    Ok(())
+}
+
+pub fn print_success(format: OutputFormat, message: &str) -> Result<(), CliError> {
+    match format {
+        OutputFormat::Table => println!("\x1b[0;32m {message} \x1b[0m"),
+        OutputFormat::Plain => println!("{message}"),
+        OutputFormat::Json | OutputFormat::Yaml => print_structured(
+            format,
+            &Status {
+                status: "ok",
+                message,
+            },
+        )?,
+    }
+    Ok(())
+}
+
+pub fn print_notice(format: OutputFormat, message: &str) -> Result<(), CliError> {
+    match format {
+        OutputFormat::Table => println!("\x1b[0;33m {message} \x1b[0m"),
+        _ => print_success(format, message)?,
+    }
+    Ok(())
+}
+
+/// Errors go to stderr so structured stdout stays parseable.
+pub fn print_error(format: OutputFormat, error: &CliError) {
+    let envelope = ErrorEnvelope {
+        error,
+        exit_code: error.exit_code(),
+    };
+    match format {
+        OutputFormat::Table => eprintln!("\x1b[0;31m {error} \x1b[0m"),
+        OutputFormat::Plain => eprintln!("error: {error}"),
+        OutputFormat::Json => match serde_json::to_string_pretty(&envelope) {
+            Ok(json) => eprintln!("{json}"),
+            Err(_) => eprintln!("error: {error}"),
+        },
+        OutputFormat::Yaml => match serde_yaml::to_string(&envelope) {
+            Ok(yaml) => eprint!("{yaml}"),
+            Err(_) => eprintln!("error: {error}"),
+        },
+    }
+}
+
+fn print_structured<T: Serialize + ?Sized>(
+    format: OutputFormat,
+    value: &T,
+) -> Result<(), CliError> {
+    match format {
+        OutputFormat::Yaml => {
+            let yaml = serde_yaml::to_string(value)
+                .map_err(|error| CliError::internal(error.to_string()))?;
+            print!("{yaml}");
+        }
+        _ => {
+            let json = serde_json::to_string_pretty(value)
+                .map_err(|error| CliError::internal(error.to_string()))?;
+            println!("{json}");
+        }
+    }
+    Ok(())
+}
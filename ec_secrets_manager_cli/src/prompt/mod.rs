@@ -0,0 +1,255 @@
+use clap::{Arg, ArgAction, ArgMatches};
+use ec_secrets_shared_library::utils::secret::SecretString;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+
+/// Environment variable read when neither `--password` nor `--password-stdin` is given.
+pub const PASSWORD_ENV: &str = "ECS_PASSWORD";
+/// Environment variable holding a service account API key for `login --api-key`.
+pub const API_KEY_ENV: &str = "ECS_API_KEY";
+/// Environment variables holding AppRole credentials for `login --role-id`.
+pub const ROLE_ID_ENV: &str = "ECS_ROLE_ID";
+pub const SECRET_ID_ENV: &str = "ECS_SECRET_ID";
+/// Environment variable read for the current password by `password change`.
+pub const CURRENT_PASSWORD_ENV: &str = "ECS_CURRENT_PASSWORD";
+/// Environment variable read for the MFA code when `--code` isn't given.
+pub const MFA_CODE_ENV: &str = "ECS_MFA_CODE";
+
+/*---------------------------------------------------------------------------
+    Password arguments shared by every command that takes a password.
+    `--password` is kept for compatibility but leaks into shell history,
+    so prefer the environment variable, stdin, or the interactive prompt.
+    Arguments with conflicts don't use clap's `env`: clap counts values
+    from the environment as given, so an exported variable would make an
+    explicit flag fail to parse. Those variables are read afterwards, with
+    the flags taking precedence.
+---------------------------------------------------------------------------*/
+pub fn password_args() -> [Arg; 2] {
+    [
+        Arg::new("password")
+            .short('p')
+            .long("password")
+            .conflicts_with("password-stdin")
+            .help(format!(
+                "The password (insecure: visible in shell history, prefer [{PASSWORD_ENV}] or the prompt)"
+            )),
+        Arg::new("password-stdin")
+            .long("password-stdin")
+            .action(ArgAction::SetTrue)
+            .help("Read the password from stdin"),
+    ]
+}
+
+pub fn current_password_arg() -> Arg {
+    Arg::new("current-password")
+        .long("current-password")
+        .env(CURRENT_PASSWORD_ENV)
+        .hide_env_values(true)
+        .help("The current password, prompted for when omitted")
+}
+
+/// Resolves the current password from the arguments or a hidden TTY prompt.
+pub fn read_current_password(matches: &ArgMatches) -> Result<SecretString, String> {
+    if let Some(password) = matches.get_one::<String>("current-password") {
+        return Ok(password.as_str().into());
+    }
+    read_secret("Current password: ").map_err(|_| {
+        format!(
+            "No TTY available to prompt for the current password, use --current-password or [{CURRENT_PASSWORD_ENV}]"
+        )
+    })
+}
+
+pub fn api_key_arg() -> Arg {
+    Arg::new("api-key")
+        .long("api-key")
+        .conflicts_with_all(["email", "password", "password-stdin"])
+        .help(format!(
+            "Login as a service account with an API key instead of a password [{API_KEY_ENV}]"
+        ))
+}
+
+pub fn app_role_args() -> [Arg; 2] {
+    [
+        Arg::new("role-id")
+            .long("role-id")
+            .conflicts_with_all(["email", "password", "password-stdin", "api-key"])
+            .help(format!(
+                "Login as an AppRole, together with --secret-id [{ROLE_ID_ENV}]"
+            )),
+        Arg::new("secret-id")
+            .long("secret-id")
+            .conflicts_with_all(["email", "password", "password-stdin", "api-key"])
+            .help(format!(
+                "The AppRole secret ID delivered at deploy time [{SECRET_ID_ENV}]"
+            )),
+    ]
+}
+
+/// A non-empty environment variable.
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// How `login` authenticates.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoginMethod {
+    ApiKey(SecretString),
+    AppRole {
+        role_id: String,
+        secret_id: SecretString,
+    },
+    User,
+}
+
+/*---------------------------------------------------------------------------
+    Picks the login method. Flags win: `--api-key`, then `--role-id` or
+    `--secret-id`, then any user flag (`--email`, `--password`,
+    `--password-stdin`). Without flags the environment decides in the same
+    order: [ECS_API_KEY], then [ECS_ROLE_ID], then a user login which reads
+    [ECS_PASSWORD] or prompts.
+---------------------------------------------------------------------------*/
+pub fn login_method(matches: &ArgMatches) -> Result<LoginMethod, String> {
+    let flag = |id: &str| matches.get_one::<String>(id).cloned();
+    let app_role = |role_id: Option<String>| {
+        let role_id = role_id
+            .or_else(|| env_value(ROLE_ID_ENV))
+            .ok_or_else(|| format!("An AppRole login needs --role-id or [{ROLE_ID_ENV}]"))?;
+        let secret_id = flag("secret-id")
+            .or_else(|| env_value(SECRET_ID_ENV))
+            .ok_or_else(|| format!("An AppRole login needs --secret-id or [{SECRET_ID_ENV}]"))?;
+        Ok(LoginMethod::AppRole {
+            role_id,
+            secret_id: SecretString::new(secret_id),
+        })
+    };
+
+    if let Some(api_key) = flag("api-key") {
+        return Ok(LoginMethod::ApiKey(SecretString::new(api_key)));
+    }
+    if flag("role-id").is_some() || flag("secret-id").is_some() {
+        return app_role(flag("role-id"));
+    }
+    let user_flags =
+        flag("email").is_some() || flag("password").is_some() || matches.get_flag("password-stdin");
+    if !user_flags {
+        if let Some(api_key) = env_value(API_KEY_ENV) {
+            return Ok(LoginMethod::ApiKey(SecretString::new(api_key)));
+        }
+        if env_value(ROLE_ID_ENV).is_some() {
+            return app_role(None);
+        }
+    }
+    Ok(LoginMethod::User)
+}
+
+pub fn mfa_code_arg() -> Arg {
+    Arg::new("code").long("code").help(format!(
+        "TOTP or recovery code, prompted for when omitted [{MFA_CODE_ENV}]"
+    ))
+}
+
+/// Resolves the MFA code from the arguments, [ECS_MFA_CODE] or a hidden
+/// TTY prompt.
+pub fn read_mfa_code(matches: &ArgMatches) -> Result<String, String> {
+    if let Some(code) = matches
+        .get_one::<String>("code")
+        .cloned()
+        .or_else(|| env_value(MFA_CODE_ENV))
+    {
+        return Ok(code);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(format!(
+            "No TTY available to prompt for the MFA code, use --code or [{MFA_CODE_ENV}]"
+        ));
+    }
+    rpassword::prompt_password("MFA code: ").map_err(|error| error.to_string())
+}
+
+pub fn yes_arg() -> Arg {
+    Arg::new("yes")
+        .short('y')
+        .long("yes")
+        .action(ArgAction::SetTrue)
+        .help("Skip the confirmation prompt")
+}
+
+/// Resolves the password from stdin, the arguments (`--password` or
+/// [ECS_PASSWORD]) or a hidden TTY prompt, in that order: the explicit
+/// `--password-stdin` flag wins over the environment variable.
+/// When `confirm` is set the prompt asks twice and requires both to match.
+pub fn read_password(matches: &ArgMatches, confirm: bool) -> Result<SecretString, String> {
+    if matches.get_flag("password-stdin") {
+        let mut password = SecretString::default();
+        io::stdin()
+            .lock()
+            .read_line(&mut password)
+            .map_err(|error| error.to_string())?;
+        return Ok(password.trim_end_matches(['\r', '\n']).into());
+    }
+    if let Some(password) = matches.get_one::<String>("password") {
+        return Ok(password.as_str().into());
+    }
+    if let Some(password) = env_value(PASSWORD_ENV) {
+        return Ok(SecretString::new(password));
+    }
+
+    let no_tty = |_| {
+        format!(
+            "No TTY available to prompt for the password, use --password-stdin or [{PASSWORD_ENV}]"
+        )
+    };
+    let password = read_secret("Password: ").map_err(no_tty)?;
+    if confirm && read_secret("Confirm password: ").map_err(no_tty)? != password {
+        return Err("Passwords do not match".to_owned());
+    }
+    Ok(password)
+}
+
+/// Prompts for a secret value without echoing it to the terminal.
+pub fn read_secret(prompt: &str) -> Result<SecretString, String> {
+    if !io::stdin().is_terminal() {
+        return Err(format!(
+            "No TTY available to prompt for '{}'",
+            prompt.trim_end_matches([':', ' '])
+        ));
+    }
+    rpassword::prompt_password(prompt)
+        .map(SecretString::new)
+        .map_err(|error| error.to_string())
+}
+
+/// Reads a value piped on stdin, or prompts for it without echoing when
+/// stdin is a terminal. A single trailing newline is dropped.
+pub fn read_value(prompt: &str) -> Result<SecretString, String> {
+    if io::stdin().is_terminal() {
+        return read_secret(prompt);
+    }
+    let mut value = SecretString::default();
+    io::stdin()
+        .lock()
+        .read_to_string(&mut value)
+        .map_err(|error| error.to_string())?;
+    let trimmed = value.strip_suffix('\n').unwrap_or(&value);
+    Ok(trimmed.strip_suffix('\r').unwrap_or(trimmed).into())
+}
+
+/// Asks the user to confirm a destructive operation. `assume_yes` skips
+/// the prompt; without a TTY the operation is refused unless confirmed.
+pub fn confirm(message: &str, assume_yes: bool) -> Result<bool, String> {
+    if assume_yes {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err("Refusing to continue without confirmation, pass --yes to proceed".to_owned());
+    }
+
+    print!("{message} [y/N]: ");
+    io::stdout().flush().map_err(|error| error.to_string())?;
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|error| error.to_string())?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
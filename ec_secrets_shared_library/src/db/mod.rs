@@ -1,19 +1,53 @@
-use crate::repositories::{keys::KeyRepository, users::UserRepository, vault::VaultRepository};
+use crate::repositories::{
+    app_roles::AppRoleRepository, groups::GroupRepository, invitations::InvitationRepository,
+    keys::KeyRepository, login_attempts::LoginAttemptRepository,
+    organizations::OrganizationRepository, policies::PolicyRepository, seal::SealRepository,
+    service_accounts::ServiceAccountRepository, settings::SettingsRepository,
+    user_tokens::UserTokenRepository, users::UserRepository, vault::VaultRepository,
+};
 use dotenvy::dotenv;
-use mongodb::{Client, options::ClientOptions};
+use mongodb::{Client, error::Error, options::ClientOptions};
 
-pub async fn connect() -> mongodb::error::Result<(UserRepository, VaultRepository, KeyRepository)> {
+pub struct Repositories {
+    pub users: UserRepository,
+    pub vault: VaultRepository,
+    pub keys: KeyRepository,
+    pub service_accounts: ServiceAccountRepository,
+    pub app_roles: AppRoleRepository,
+    pub settings: SettingsRepository,
+    pub login_attempts: LoginAttemptRepository,
+    pub user_tokens: UserTokenRepository,
+    pub invitations: InvitationRepository,
+    pub groups: GroupRepository,
+    pub policies: PolicyRepository,
+    pub organizations: OrganizationRepository,
+    pub seal: SealRepository,
+}
+
+pub async fn connect() -> mongodb::error::Result<Repositories> {
+    connect_with(None, None).await
+}
+
+/*---------------------------------------------------------------------------
+    Connect using an explicit database url and/or name, falling back to
+    the [ECS_DATABASE_URL] and [ECS_DATABASE_NAME] environment variables
+    for any value that isn't provided (e.g. from a CLI profile).
+---------------------------------------------------------------------------*/
+pub async fn connect_with(
+    database_url: Option<&str>,
+    database_name: Option<&str>,
+) -> mongodb::error::Result<Repositories> {
     dotenv().ok();
 
-    let database_url = std::env::var_os("ECS_DATABASE_URL")
-        .expect("[ECS_DATABASE_URL] must be set...")
-        .into_string()
-        .unwrap();
+    let database_url = match database_url {
+        Some(database_url) => database_url.to_string(),
+        None => required_env("ECS_DATABASE_URL")?,
+    };
 
-    let database_name = std::env::var_os("ECS_DATABASE_NAME")
-        .expect("[ECS_DATABASE_NAME] must be set...")
-        .into_string()
-        .unwrap();
+    let database_name = match database_name {
+        Some(database_name) => database_name.to_string(),
+        None => required_env("ECS_DATABASE_NAME")?,
+    };
 
     let client_options = ClientOptions::parse(database_url).await?;
     let client = Client::with_options(client_options)?;
@@ -24,5 +58,50 @@ pub async fn connect() -> mongodb::error::Result<(UserRepository, VaultRepositor
 
     let keys_repo = KeyRepository::new(&client, &database_name, "keys");
 
-    Ok((user_repo, vault_repo, keys_repo))
+    let service_account_repo =
+        ServiceAccountRepository::new(&client, &database_name, "service_accounts", "api_keys");
+
+    let app_role_repo = AppRoleRepository::new(&client, &database_name, "app_roles", "secret_ids");
+
+    let settings_repo = SettingsRepository::new(&client, &database_name, "settings");
+
+    let login_attempt_repo =
+        LoginAttemptRepository::new(&client, &database_name, "login_attempts", "lockout_events");
+
+    let user_token_repo = UserTokenRepository::new(&client, &database_name, "user_tokens");
+
+    let invitation_repo = InvitationRepository::new(&client, &database_name, "invitations");
+
+    let group_repo = GroupRepository::new(&client, &database_name, "groups");
+
+    let policy_repo = PolicyRepository::new(&client, &database_name, "policies");
+
+    let organization_repo = OrganizationRepository::new(&client, &database_name, "organizations");
+
+    let seal_repo = SealRepository::new(&client, &database_name, "seal");
+
+    Ok(Repositories {
+        users: user_repo,
+        vault: vault_repo,
+        keys: keys_repo,
+        service_accounts: service_account_repo,
+        app_roles: app_role_repo,
+        settings: settings_repo,
+        login_attempts: login_attempt_repo,
+        user_tokens: user_token_repo,
+        invitations: invitation_repo,
+        groups: group_repo,
+        policies: policy_repo,
+        organizations: organization_repo,
+        seal: seal_repo,
+    })
+}
+
+fn required_env(name: &str) -> mongodb::error::Result<String> {
+    std::env::var(name).map_err(|_| {
+        Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("[{name}] must be set..."),
+        ))
+    })
 }
@@ -3,13 +3,23 @@ use mongodb::bson::oid::ObjectId;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::utils::secret::{SecretBytes, SecretString};
+
 /*------------
  Encryption Keys models
 -------------*/
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct KeyPairDocument {
+    /// `current` for the key pair in use, missing on documents from
+    /// before slots existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<String>,
+    /// Base64 Ed25519 secret key encrypted with the master key, plain
+    /// base64 in documents stored before `encrypted` existed
     pub private_key: String,
     pub public_key: String,
+    #[serde(default)]
+    pub encrypted: bool,
     #[serde(
         with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
         rename = "createdAt"
@@ -31,6 +41,47 @@ pub struct UserDocument {
         rename = "createdAt"
     )]
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mfa: Option<MfaDocument>,
+    #[serde(default, rename = "emailVerified")]
+    pub email_verified: bool,
+    /// Bumped on password and email changes, tokens carrying an older
+    /// version are rejected
+    #[serde(default, rename = "tokenVersion")]
+    pub token_version: i64,
+    /// Names of the groups the user belongs to
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Ids of the organizations the user belongs to, the first one is
+    /// active after login
+    #[serde(default)]
+    pub orgs: Vec<ObjectId>,
+    /// Base64 X25519 public key end-to-end encrypted values are sealed
+    /// for, the private key stays with the user's clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e2e_public_key: Option<String>,
+}
+
+impl UserDocument {
+    pub fn mfa_enabled(&self) -> bool {
+        self.mfa.as_ref().is_some_and(|mfa| mfa.enabled)
+    }
+}
+
+/// TOTP enrollment of a user. Stays disabled until the first code is verified.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MfaDocument {
+    /// Base32 TOTP secret, encrypted with [ECS_ENCRYPTION_KEY]
+    pub secret: String,
+    pub enabled: bool,
+    /// Argon2id hashes of the unused recovery codes (SHA-256 for codes
+    /// issued before they were salted)
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    /// Last accepted time step, codes can't be replayed within their window
+    pub last_used_step: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
@@ -46,7 +97,563 @@ pub struct User {
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct UserCredentials {
     pub email: String,
-    pub password: String,
+    pub password: SecretString,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PasswordChange {
+    pub current_password: SecretString,
+    pub new_password: SecretString,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct EmailChange {
+    pub email: String,
+    pub current_password: SecretString,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PasswordReset {
+    pub token: String,
+    pub new_password: SecretString,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct EmailVerification {
+    pub token: String,
+}
+
+/*------------
+ Invitation models
+-------------*/
+/// Role an invited user is created with.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    #[default]
+    User,
+    Admin,
+}
+
+impl UserRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+/// Pending invitation, only the SHA-256 hash of its token is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InvitationDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub email: String,
+    pub role: UserRole,
+    pub token_hash: String,
+    pub invited_by: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Invitation {
+    pub email: String,
+    #[serde(default)]
+    pub role: UserRole,
+    /// Defaults to 72 hours
+    pub expires_in_hours: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct InvitationAcceptance {
+    pub token: String,
+    pub password: SecretString,
+}
+
+/// What a [UserTokenDocument] can be redeemed for.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserTokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+/// Single use token mailed to a user, only its SHA-256 hash is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserTokenDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user_id: ObjectId,
+    pub purpose: UserTokenPurpose,
+    pub token_hash: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct MfaCode {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct MfaLogin {
+    pub mfa_token: String,
+    pub code: String,
+}
+
+/*------------
+ Login attempt models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoginAttemptDocument {
+    /// The account (email) the failures were recorded for
+    #[serde(rename = "_id")]
+    pub account: String,
+    pub failures: i64,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "lockedUntil",
+        default
+    )]
+    pub locked_until: Option<DateTime<Utc>>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "lastFailureAt"
+    )]
+    pub last_failure_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LockoutEventDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub account: String,
+    pub ip: Option<String>,
+    pub failures: i64,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "lockedUntil"
+    )]
+    pub locked_until: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+/*------------
+ Settings models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SettingsDocument {
+    /// Every account has to enroll in and use MFA to login
+    #[serde(default)]
+    pub require_mfa: bool,
+    pub updated_by: Option<String>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "updatedAt",
+        default
+    )]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Settings {
+    pub require_mfa: bool,
+}
+
+/*------------
+ Seal models
+-------------*/
+/// Shamir configuration of a sealed instance. The master key itself is
+/// never stored, only a value encrypted with it to check recovered keys.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SealDocument {
+    pub shares: u8,
+    pub threshold: u8,
+    pub check: String,
+    /// SHA-256 of each share, so a wrong share is refused on submission.
+    /// Empty for seals initialized before digests were stored.
+    #[serde(default)]
+    pub share_digests: Vec<String>,
+    pub initialized_by: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "initializedAt"
+    )]
+    pub initialized_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SealInit {
+    pub shares: u8,
+    pub threshold: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct UnsealShare {
+    pub share: SecretString,
+}
+
+/*------------
+ Service account models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServiceAccountDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_by: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+    /// Organization whose vault the account works in, the owner's active
+    /// organization when it was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<ObjectId>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKeyDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub service_account_id: ObjectId,
+    /// SHA-256 of the secret part of the key, the key itself is never stored
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "expiresAt"
+    )]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "revokedAt",
+        default
+    )]
+    pub revoked_at: Option<DateTime<Utc>>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKeyDocument {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+            && self
+                .expires_at
+                .is_none_or(|expires_at| expires_at > Utc::now())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ServiceAccount {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ApiKeyRequest {
+    pub scopes: Vec<String>,
+    /// Lifetime of the key in days, keys without one never expire
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ApiKeyCredentials {
+    pub api_key: SecretString,
+}
+
+/*------------
+ AppRole models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppRoleDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    /// Non-secret identifier baked into workload images
+    pub role_id: String,
+    pub scopes: Vec<String>,
+    pub secret_id_ttl_minutes: i64,
+    /// Logins allowed per secret ID, unlimited when unset
+    pub secret_id_num_uses: Option<i64>,
+    pub token_ttl_minutes: i64,
+    pub created_by: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+    /// Organization whose vault the role works in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<ObjectId>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SecretIdDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub app_role_id: ObjectId,
+    /// SHA-256 of the secret ID, the secret ID itself is never stored
+    pub secret_id_hash: String,
+    pub uses_remaining: Option<i64>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AppRole {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub secret_id_ttl_minutes: Option<i64>,
+    pub secret_id_num_uses: Option<i64>,
+    pub token_ttl_minutes: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AppRoleCredentials {
+    pub role_id: String,
+    pub secret_id: SecretString,
+}
+
+/*------------
+ Group models
+-------------*/
+/// A team of users. Members are granted the group's permissions on vault
+/// entries shared with the group.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GroupDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub description: Option<String>,
+    /// Scopes granted on shared entries, e.g. `secrets:read`
+    pub permissions: Vec<String>,
+    pub created_by: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Group {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct GroupPermissions {
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct GroupMember {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SharedSecret {
+    pub key: String,
+}
+
+/*------------
+ Organization models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrganizationDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub description: Option<String>,
+    /// Vault encryption key of the organization, encrypted with
+    /// [ECS_ENCRYPTION_KEY]
+    pub encryption_key: String,
+    pub created_by: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Organization {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct OrganizationMember {
+    pub email: String,
+}
+
+/*------------
+ Policy models
+-------------*/
+/// What a policy rule allows on matching paths. `Deny` overrides every
+/// grant, including ownership.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Read,
+    Create,
+    Update,
+    Delete,
+    List,
+    Deny,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Self::Read,
+        Self::Create,
+        Self::Update,
+        Self::Delete,
+        Self::List,
+        Self::Deny,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+            Self::List => "list",
+            Self::Deny => "deny",
+        }
+    }
+}
+
+/// Capabilities granted on the secret keys matching a path glob.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct PolicyRule {
+    pub path: String,
+    pub capabilities: Vec<Capability>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PolicyDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub description: Option<String>,
+    pub rules: Vec<PolicyRule>,
+    /// Emails of the users the policy is attached to
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Ids of the service accounts the policy is attached to
+    #[serde(default)]
+    pub service_accounts: Vec<String>,
+    pub created_by: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Policy {
+    pub name: String,
+    pub description: Option<String>,
+    pub rules: Vec<PolicyRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PolicyRules {
+    pub description: Option<String>,
+    pub rules: Vec<PolicyRule>,
+}
+
+/// Kinds of principals a policy can be attached to.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrincipalKind {
+    User,
+    Group,
+    ServiceAccount,
+}
+
+impl PrincipalKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Group => "group",
+            Self::ServiceAccount => "service_account",
+        }
+    }
+
+    /// Field of [PolicyDocument] listing the attached principals.
+    pub fn field(&self) -> &'static str {
+        match self {
+            Self::User => "users",
+            Self::Group => "groups",
+            Self::ServiceAccount => "service_accounts",
+        }
+    }
+}
+
+/// A user email, group name or service account id.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PolicyAttachment {
+    pub kind: PrincipalKind,
+    pub id: String,
+}
+
+/// Asks how a capability on a path is decided, for the caller or, for
+/// admins, for another user or a service account.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PolicyCheck {
+    pub path: String,
+    pub capability: Capability,
+    pub user: Option<String>,
+    pub service_account: Option<String>,
 }
 
 /*------------
@@ -57,13 +664,41 @@ pub struct VaultDocument {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     pub key: String,
-    pub value: String,
+    /// Encrypted, or the plaintext once the entry is decrypted
+    pub value: SecretString,
     pub created_by: String,
     #[serde(
         with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
         rename = "createdAt"
     )]
     pub created_at: DateTime<Utc>,
+    /// Groups the entry is shared with, on top of its author
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Organization owning the entry, None for entries stored before
+    /// organizations existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<ObjectId>,
+    /// Set for binary values, whose bytes are revealed as base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileMetadata>,
+    /// The value was sealed by a client (`utils::vault::e2e`), revealing
+    /// it yields the base64 sealed value, which only recipients can open
+    #[serde(default)]
+    pub end_to_end: bool,
+}
+
+/// A binary vault entry: uploaded bytes, optionally with a file name.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub file_name: Option<String>,
+    pub content_type: String,
+    /// Size of the plaintext in bytes
+    pub size: u64,
+    /// GridFS file holding the encrypted bytes of large entries, whose
+    /// `value` is then empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob_id: Option<ObjectId>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
@@ -71,7 +706,7 @@ pub struct Vault {
     #[serde(rename = "_id")]
     pub id: String,
     pub key: String,
-    pub value: String,
+    pub value: SecretString,
     pub created_by: String,
     #[serde(rename = "createdAt")]
     pub created_at: String,
@@ -80,5 +715,29 @@ pub struct Vault {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Secret {
     pub key: String,
-    pub value: String,
+    pub value: SecretString,
+    /// `value` is a base64 value sealed by the client, stored as is
+    #[serde(default)]
+    pub end_to_end: bool,
+}
+
+/// Public key of the caller for end-to-end encrypted values.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct E2ePublicKey {
+    pub public_key: String,
+}
+
+/// Someone end-to-end encrypted values can be sealed for.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct E2eRecipient {
+    pub email: String,
+    pub public_key: String,
+}
+
+/// Bytes to store as a binary vault entry.
+#[derive(Debug, Clone)]
+pub struct SecretFile {
+    pub file_name: Option<String>,
+    pub content_type: String,
+    pub data: SecretBytes,
 }
@@ -0,0 +1,382 @@
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection,
+    bson::{Document, doc, oid::ObjectId},
+    error::{Error, Result},
+};
+
+use crate::models::{AppRole, AppRoleDocument, SecretIdDocument};
+use crate::utils::auth::{generate_role_id, generate_secret_id, hash_token};
+
+pub const DEFAULT_SECRET_ID_TTL_MINUTES: i64 = 60;
+pub const DEFAULT_SECRET_ID_NUM_USES: i64 = 1;
+pub const DEFAULT_TOKEN_TTL_MINUTES: i64 = 60;
+pub const MAX_TOKEN_TTL_MINUTES: i64 = 8 * 60;
+
+/// Maps the requested number of uses to what is stored, `None` being
+/// unlimited.
+fn secret_id_num_uses(requested: Option<i64>) -> Option<i64> {
+    match requested {
+        // Zero explicitly asks for unlimited uses
+        Some(0) => None,
+        Some(uses) => Some(uses),
+        None => Some(DEFAULT_SECRET_ID_NUM_USES),
+    }
+}
+
+/// Matches an unexpired secret ID of the role.
+fn secret_id_filter(app_role_id: ObjectId, secret_id: &str) -> Document {
+    doc! {
+        "app_role_id": app_role_id,
+        "secret_id_hash": hash_token(secret_id),
+        "expiresAt": { "$gt": bson::DateTime::now() },
+    }
+}
+
+/// Conditional decrement so concurrent logins can't overspend.
+fn spend_filter(mut filter: Document, id: ObjectId) -> Document {
+    filter.insert("_id", id);
+    filter.insert("uses_remaining", doc! { "$gt": 0 });
+    filter
+}
+
+/*---------------------------------------------------------------------------
+    AppRoles let workloads login with a two-part credential: the role ID,
+    which is not secret and can be baked into an image, and a short-lived,
+    use-limited secret ID delivered at deploy time. Only a hash of each
+    secret ID is stored.
+---------------------------------------------------------------------------*/
+pub struct AppRoleRepository {
+    roles: Collection<AppRoleDocument>,
+    secret_ids: Collection<SecretIdDocument>,
+}
+
+impl AppRoleRepository {
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        roles_collection: &str,
+        secret_ids_collection: &str,
+    ) -> Self {
+        let database = client.database(db_name);
+        Self {
+            roles: database.collection::<AppRoleDocument>(roles_collection),
+            secret_ids: database.collection::<SecretIdDocument>(secret_ids_collection),
+        }
+    }
+
+    /*------------------
+    CREATE a new role
+    -------------------*/
+    pub async fn create_role(
+        &self,
+        role: &AppRole,
+        created_by: &str,
+        org_id: Option<ObjectId>,
+    ) -> Result<AppRoleDocument> {
+        if self
+            .roles
+            .find_one(doc! { "name": &role.name, "created_by": created_by })
+            .await?
+            .is_some()
+        {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "An AppRole with this name already exists.",
+            )));
+        }
+
+        let document = AppRoleDocument {
+            id: ObjectId::new(),
+            name: role.name.clone(),
+            role_id: generate_role_id(),
+            scopes: role.scopes.clone(),
+            secret_id_ttl_minutes: role
+                .secret_id_ttl_minutes
+                .unwrap_or(DEFAULT_SECRET_ID_TTL_MINUTES),
+            secret_id_num_uses: secret_id_num_uses(role.secret_id_num_uses),
+            token_ttl_minutes: role
+                .token_ttl_minutes
+                .unwrap_or(DEFAULT_TOKEN_TTL_MINUTES)
+                .min(MAX_TOKEN_TTL_MINUTES),
+            created_by: created_by.to_string(),
+            created_at: Utc::now(),
+            org_id,
+        };
+
+        self.roles.insert_one(&document).await?;
+        Ok(document)
+    }
+
+    /*------------------
+    GET a role by name
+    -------------------*/
+    pub async fn get_role(&self, name: &str, created_by: &str) -> Result<Option<AppRoleDocument>> {
+        self.roles
+            .find_one(doc! { "name": name, "created_by": created_by })
+            .await
+    }
+
+    /*------------------
+    LIST all roles
+    -------------------*/
+    pub async fn list_roles(&self, created_by: &str) -> Result<Vec<AppRoleDocument>> {
+        let cursor = self.roles.find(doc! { "created_by": created_by }).await?;
+        cursor.try_collect().await
+    }
+
+    /*----------------------------------------
+    DELETE a role and all its secret IDs
+    -----------------------------------------*/
+    pub async fn delete_role(
+        &self,
+        name: &str,
+        created_by: &str,
+    ) -> Result<Option<AppRoleDocument>> {
+        let role = self
+            .roles
+            .find_one_and_delete(doc! { "name": name, "created_by": created_by })
+            .await?;
+        if let Some(role) = &role {
+            self.secret_ids
+                .delete_many(doc! { "app_role_id": role.id })
+                .await?;
+        }
+        Ok(role)
+    }
+
+    /*----------------------------------------------------
+    DELETE every role of a user and all their secret IDs
+    -----------------------------------------------------*/
+    pub async fn delete_owned_by(&self, created_by: &str) -> Result<u64> {
+        let roles = self.list_roles(created_by).await?;
+        let ids: Vec<ObjectId> = roles.iter().map(|role| role.id).collect();
+        self.secret_ids
+            .delete_many(doc! { "app_role_id": { "$in": &ids } })
+            .await?;
+        let result = self
+            .roles
+            .delete_many(doc! { "_id": { "$in": &ids } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+
+    /*-----------------------------------------------------
+    CREATE a secret ID, returning the secret ID this once
+    ------------------------------------------------------*/
+    pub async fn create_secret_id(
+        &self,
+        role: &AppRoleDocument,
+    ) -> Result<(SecretIdDocument, String)> {
+        let secret_id = generate_secret_id();
+        let document = SecretIdDocument {
+            id: ObjectId::new(),
+            app_role_id: role.id,
+            secret_id_hash: hash_token(&secret_id),
+            uses_remaining: role.secret_id_num_uses,
+            expires_at: Utc::now() + Duration::minutes(role.secret_id_ttl_minutes),
+            created_at: Utc::now(),
+        };
+
+        self.secret_ids.insert_one(&document).await?;
+        Ok((document, secret_id))
+    }
+
+    /*------------------------------------------------------------------
+    CONSUME one use of a secret ID, returning its role when the pair
+    is valid. Exhausted secret IDs are removed.
+    -------------------------------------------------------------------*/
+    pub async fn consume_secret_id(
+        &self,
+        role_id: &str,
+        secret_id: &str,
+    ) -> Result<Option<AppRoleDocument>> {
+        let Some(role) = self.roles.find_one(doc! { "role_id": role_id }).await? else {
+            return Ok(None);
+        };
+
+        let filter = secret_id_filter(role.id, secret_id);
+        let Some(document) = self.secret_ids.find_one(filter.clone()).await? else {
+            return Ok(None);
+        };
+
+        if document.uses_remaining.is_some() {
+            let filter = spend_filter(filter, document.id);
+            let update = doc! { "$inc": { "uses_remaining": -1 } };
+            let Some(consumed) = self.secret_ids.find_one_and_update(filter, update).await? else {
+                return Ok(None);
+            };
+            // The returned document is from before the decrement
+            if consumed.uses_remaining == Some(1) {
+                self.secret_ids
+                    .delete_one(doc! { "_id": consumed.id })
+                    .await?;
+            }
+        }
+
+        Ok(Some(role))
+    }
+}
+
+/*---------------------------------------------------------------------------
+    The repository tests need MongoDB, so they're ignored by default. Point
+    [ECS_TEST_MONGODB_URI] at a throwaway server, e.g.
+
+        docker run --rm -d -p 27017:27017 mongo
+        ECS_TEST_MONGODB_URI=mongodb://127.0.0.1:27017 \
+        cargo test app_roles -- --include-ignored
+---------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    async fn repository() -> AppRoleRepository {
+        let uri = std::env::var("ECS_TEST_MONGODB_URI").expect("ECS_TEST_MONGODB_URI");
+        let client = Client::with_uri_str(uri).await.unwrap();
+        let db_name = format!("ecs_test_app_roles_{}", std::process::id());
+        AppRoleRepository::new(&client, &db_name, "app_roles", "secret_ids")
+    }
+
+    fn role(name: &str, secret_id_num_uses: Option<i64>) -> AppRole {
+        AppRole {
+            name: name.to_string(),
+            scopes: vec!["read".to_string()],
+            secret_id_ttl_minutes: None,
+            secret_id_num_uses,
+            token_ttl_minutes: None,
+        }
+    }
+
+    #[test]
+    fn zero_uses_means_unlimited() {
+        assert_eq!(secret_id_num_uses(Some(0)), None);
+        assert_eq!(secret_id_num_uses(Some(3)), Some(3));
+        assert_eq!(secret_id_num_uses(None), Some(DEFAULT_SECRET_ID_NUM_USES));
+    }
+
+    #[test]
+    fn filters_match_unexpired_unspent_secret_ids() {
+        let role_id = ObjectId::new();
+        let filter = secret_id_filter(role_id, "secret");
+        assert_eq!(filter.get_object_id("app_role_id").unwrap(), role_id);
+        assert_eq!(
+            filter.get_str("secret_id_hash").unwrap(),
+            hash_token("secret")
+        );
+        let expiry = filter.get_document("expiresAt").unwrap();
+        assert!(expiry.get_datetime("$gt").unwrap() <= &bson::DateTime::now());
+
+        let id = ObjectId::new();
+        let spend = spend_filter(filter.clone(), id);
+        assert_eq!(spend.get_object_id("_id").unwrap(), id);
+        assert_eq!(
+            spend.get_document("uses_remaining").unwrap(),
+            &doc! { "$gt": 0 }
+        );
+        // Still scoped to the role, hash and expiry
+        for key in filter.keys() {
+            assert_eq!(spend.get(key), filter.get(key));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs MongoDB, see above"]
+    fn secret_ids_are_spent_and_removed() {
+        block_on(async {
+            let repository = repository().await;
+            let stored = repository
+                .create_role(&role("spent", Some(2)), "owner@example.com", None)
+                .await
+                .unwrap();
+            let (_, secret_id) = repository.create_secret_id(&stored).await.unwrap();
+
+            for _ in 0..2 {
+                let consumed = repository
+                    .consume_secret_id(&stored.role_id, &secret_id)
+                    .await
+                    .unwrap();
+                assert_eq!(consumed.map(|r| r.id), Some(stored.id));
+            }
+            assert!(
+                repository
+                    .consume_secret_id(&stored.role_id, &secret_id)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            // Deleted on exhaustion rather than left at zero
+            assert_eq!(
+                repository
+                    .secret_ids
+                    .count_documents(doc! { "app_role_id": stored.id })
+                    .await
+                    .unwrap(),
+                0
+            );
+            // The role ID alone, or with a wrong secret ID, never logs in
+            assert!(
+                repository
+                    .consume_secret_id(&stored.role_id, "wrong")
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+
+            repository.roles.drop().await.unwrap();
+            repository.secret_ids.drop().await.unwrap();
+        });
+    }
+
+    #[test]
+    #[ignore = "needs MongoDB, see above"]
+    fn unlimited_and_expired_secret_ids() {
+        block_on(async {
+            let repository = repository().await;
+            let stored = repository
+                .create_role(&role("unlimited", Some(0)), "owner@example.com", None)
+                .await
+                .unwrap();
+            assert_eq!(stored.secret_id_num_uses, None);
+            let (document, secret_id) = repository.create_secret_id(&stored).await.unwrap();
+            assert_eq!(document.uses_remaining, None);
+
+            for _ in 0..5 {
+                assert!(
+                    repository
+                        .consume_secret_id(&stored.role_id, &secret_id)
+                        .await
+                        .unwrap()
+                        .is_some()
+                );
+            }
+
+            repository
+                .secret_ids
+                .update_one(
+                    doc! { "_id": document.id },
+                    doc! { "$set": { "expiresAt": bson::DateTime::from_millis(0) } },
+                )
+                .await
+                .unwrap();
+            assert!(
+                repository
+                    .consume_secret_id(&stored.role_id, &secret_id)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+
+            repository.roles.drop().await.unwrap();
+            repository.secret_ids.drop().await.unwrap();
+        });
+    }
+}
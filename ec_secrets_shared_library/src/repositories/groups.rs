@@ -0,0 +1,93 @@
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection,
+    bson::doc,
+    error::{Error, Result},
+    options::ReturnDocument,
+};
+
+use crate::models::GroupDocument;
+
+/*---------------------------------------------------------------------------
+    Groups and the permissions they grant. Membership is stored on the
+    user documents, see `UserRepository::add_to_group`.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct GroupRepository {
+    collection: Collection<GroupDocument>,
+}
+
+impl GroupRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<GroupDocument>(collection_name);
+        Self { collection }
+    }
+
+    /*------------------
+    CREATE a new group
+    -------------------*/
+    pub async fn create_group(&self, group: &GroupDocument) -> Result<()> {
+        if self.get_group(&group.name).await?.is_some() {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "A group with this name already exists.",
+            )));
+        }
+        self.collection.insert_one(group).await?;
+        Ok(())
+    }
+
+    /*-----------------
+    GET group by name
+    ------------------*/
+    pub async fn get_group(&self, name: &str) -> Result<Option<GroupDocument>> {
+        self.collection.find_one(doc! { "name": name }).await
+    }
+
+    /*-------------
+    LIST groups
+    --------------*/
+    pub async fn list_groups(&self) -> Result<Vec<GroupDocument>> {
+        self.collection.find(doc! {}).await?.try_collect().await
+    }
+
+    /*-------------------------
+    LIST the groups in `names`
+    --------------------------*/
+    pub async fn find_groups(&self, names: &[String]) -> Result<Vec<GroupDocument>> {
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.collection
+            .find(doc! { "name": { "$in": names } })
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*---------------------------
+    REPLACE a group's permissions
+    ----------------------------*/
+    pub async fn set_permissions(
+        &self,
+        name: &str,
+        permissions: &[String],
+    ) -> Result<Option<GroupDocument>> {
+        let update = doc! { "$set": { "permissions": permissions } };
+        self.collection
+            .find_one_and_update(doc! { "name": name }, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*-------------
+    DELETE a group
+    --------------*/
+    pub async fn delete_group(&self, name: &str) -> Result<Option<GroupDocument>> {
+        self.collection
+            .find_one_and_delete(doc! { "name": name })
+            .await
+    }
+}
@@ -0,0 +1,71 @@
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection,
+    bson::{doc, oid::ObjectId},
+    error::Result,
+};
+
+use crate::models::InvitationDocument;
+
+/*---------------------------------------------------------------------------
+    Admin issued invitations, the only way to join once self-registration
+    is closed. Inviting an address again replaces its earlier invitation,
+    accepting one deletes it.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct InvitationRepository {
+    collection: Collection<InvitationDocument>,
+}
+
+impl InvitationRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<InvitationDocument>(collection_name);
+        Self { collection }
+    }
+
+    /*-------------------------------------------------
+    CREATE an invitation, replacing earlier ones
+    --------------------------------------------------*/
+    pub async fn create_invitation(&self, invitation: &InvitationDocument) -> Result<()> {
+        self.collection
+            .delete_many(doc! { "email": &invitation.email })
+            .await?;
+        self.collection.insert_one(invitation).await?;
+        Ok(())
+    }
+
+    /*-----------------------
+    LIST all invitations
+    ------------------------*/
+    pub async fn list_invitations(&self) -> Result<Vec<InvitationDocument>> {
+        let cursor = self
+            .collection
+            .find(doc! {})
+            .sort(doc! { "createdAt": -1 })
+            .await?;
+        cursor.try_collect().await
+    }
+
+    /*-----------------------
+    DELETE an invitation
+    ------------------------*/
+    pub async fn delete_invitation(&self, id: &ObjectId) -> Result<Option<InvitationDocument>> {
+        self.collection
+            .find_one_and_delete(doc! { "_id": id })
+            .await
+    }
+
+    /*------------------------------------------------------
+    CONSUME an unexpired invitation, None if it isn't valid
+    -------------------------------------------------------*/
+    pub async fn consume_invitation(&self, token_hash: &str) -> Result<Option<InvitationDocument>> {
+        let filter = doc! {
+            "token_hash": token_hash,
+            "expiresAt": { "$gt": bson::DateTime::from_chrono(Utc::now()) },
+        };
+        self.collection.find_one_and_delete(filter).await
+    }
+}
@@ -1,24 +1,124 @@
 use crate::models::KeyPairDocument;
+use crate::utils::seal::master_key;
+use crate::utils::secret::{SecretBytes, SecretString};
+use crate::utils::vault::{EnvelopeParams, decrypt, encrypt, reencrypt};
 
 use base64::{Engine as _, engine::general_purpose};
 use bson::doc;
 use chrono::Utc;
-use mongodb::{Client, Collection};
+use mongodb::{
+    Client, Collection, IndexModel,
+    error::{ErrorKind, WriteFailure},
+    options::IndexOptions,
+};
 use pasetors::{
-    keys::{AsymmetricKeyPair, Generate},
+    keys::{AsymmetricKeyPair, AsymmetricPublicKey, AsymmetricSecretKey, Generate},
     version4::V4,
 };
+use std::time::{Duration, Instant};
+use tokio::sync::{OnceCell, RwLock};
+
+/// Slot of the key pair in use, unique across the collection.
+const CURRENT_SLOT: &str = "current";
+/// How long decoded keys are trusted before the database is checked for a
+/// rotated key pair. Rotating on this instance invalidates right away.
+const KEY_CACHE_TTL: Duration = Duration::from_secs(300);
+const DUPLICATE_KEY: i32 = 11000;
 
 /*---------------------------------------------------------------------------
-    The KeyRepository provides a basic secrets management mechanism that
-    generates and stores cryptographically secure symmetric keys for
-    authentication or encryption purposes.
+    The KeyRepository holds the Ed25519 key pair that signs session tokens.
+    The secret key is stored encrypted with the master key and only ever
+    decrypted in memory; documents written before that are encrypted in
+    place the next time the key pair is loaded with the master key around.
 
-    Note: While this struct is called KeyPairDocument,
-    it currently only stores a symmetric key.
+    Decoded keys are cached in the repository, which the server shares
+    between requests, so verifying a token doesn't query the database.
+    The key pair lives in the `current` slot, guarded by a unique index,
+    so instances starting together all end up with the same one.
 ---------------------------------------------------------------------------*/
 pub struct KeyRepository {
     collection: Collection<KeyPairDocument>,
+    indexed: OnceCell<()>,
+    cache: RwLock<Option<CachedKeys>>,
+}
+
+struct CachedKeys {
+    public_key: AsymmetricPublicKey<V4>,
+    /// None while the master key is unavailable (sealed)
+    secret_key: Option<AsymmetricSecretKey<V4>>,
+    loaded_at: Instant,
+}
+
+impl CachedKeys {
+    fn is_fresh(&self) -> bool {
+        self.loaded_at.elapsed() < KEY_CACHE_TTL
+    }
+}
+
+fn seal_private_key(secret: &[u8]) -> Result<String, String> {
+    let master_key = master_key().map_err(|e| e.to_string())?;
+    seal_private_key_with(secret, master_key.as_bytes())
+}
+
+fn seal_private_key_with(secret: &[u8], master_key: &[u8]) -> Result<String, String> {
+    let encoded = SecretString::new(general_purpose::STANDARD.encode(secret));
+    let encrypted = encrypt(encoded.as_bytes(), master_key)
+        .map_err(|e| format!("Failed to encrypt the signing key: {e}"))?;
+    Ok(general_purpose::STANDARD.encode(encrypted))
+}
+
+fn generate_key_pair() -> Result<KeyPairDocument, String> {
+    let kp = AsymmetricKeyPair::<V4>::generate().map_err(|e| e.to_string())?;
+    Ok(KeyPairDocument {
+        slot: Some(CURRENT_SLOT.to_string()),
+        private_key: seal_private_key(kp.secret.as_bytes())?,
+        public_key: general_purpose::STANDARD.encode(kp.public.as_bytes()),
+        encrypted: true,
+        created_at: Utc::now(),
+    })
+}
+
+fn decode_public_key(kp: &KeyPairDocument) -> Result<AsymmetricPublicKey<V4>, String> {
+    let decoded = general_purpose::STANDARD
+        .decode(&kp.public_key)
+        .map_err(|e| e.to_string())?;
+    AsymmetricPublicKey::<V4>::from(decoded.as_slice()).map_err(|e| e.to_string())
+}
+
+fn decode_secret_key(kp: &KeyPairDocument) -> Result<AsymmetricSecretKey<V4>, String> {
+    decode_secret_key_with(kp, || master_key().map_err(|e| e.to_string()))
+}
+
+/// Decodes the secret key, asking for the master key only when it is
+/// stored encrypted.
+fn decode_secret_key_with(
+    kp: &KeyPairDocument,
+    master_key: impl FnOnce() -> Result<SecretString, String>,
+) -> Result<AsymmetricSecretKey<V4>, String> {
+    let private_key = general_purpose::STANDARD
+        .decode(&kp.private_key)
+        .map(SecretBytes::new)
+        .map_err(|e| e.to_string())?;
+    let private_key = if kp.encrypted {
+        let master_key = master_key()?;
+        let encoded = decrypt(&private_key, master_key.as_bytes())
+            .map_err(|_| "Failed to decrypt the signing key".to_string())?;
+        general_purpose::STANDARD
+            .decode(encoded)
+            .map(SecretBytes::new)
+            .map_err(|e| e.to_string())?
+    } else {
+        private_key
+    };
+    AsymmetricSecretKey::<V4>::from(private_key.as_slice()).map_err(|e| e.to_string())
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        ErrorKind::Command(e) => e.code == DUPLICATE_KEY,
+        _ => false,
+    }
 }
 
 impl KeyRepository {
@@ -26,31 +126,264 @@ impl KeyRepository {
         let collection = client
             .database(db_name)
             .collection::<KeyPairDocument>(collection_name);
-        Self { collection }
+        Self {
+            collection,
+            indexed: OnceCell::new(),
+            cache: RwLock::new(None),
+        }
+    }
+
+    async fn ensure_index(&self) -> Result<(), String> {
+        self.indexed
+            .get_or_try_init(|| async {
+                let options = IndexOptions::builder()
+                    .unique(true)
+                    .partial_filter_expression(doc! { "slot": { "$exists": true } })
+                    .build();
+                let index = IndexModel::builder()
+                    .keys(doc! { "slot": 1 })
+                    .options(options)
+                    .build();
+                self.collection
+                    .create_index(index)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .copied()
+    }
+
+    async fn current(&self) -> Result<Option<KeyPairDocument>, String> {
+        self.collection
+            .find_one(doc! { "slot": CURRENT_SLOT })
+            .await
+            .map_err(|e| e.to_string())
     }
 
     pub async fn get_or_create_key_pair(&self) -> Result<KeyPairDocument, String> {
-        let kp = AsymmetricKeyPair::<V4>::generate().map_err(|e| e.to_string())?;
-        let private_key = general_purpose::STANDARD.encode(kp.secret.as_bytes());
-        let public_key = general_purpose::STANDARD.encode(kp.public.as_bytes());
-        let key_pair = KeyPairDocument {
-            private_key,
-            public_key,
-            created_at: Utc::now(),
-        };
-        let cursor = self
+        self.ensure_index().await?;
+        if let Some(key_pair) = self.current().await? {
+            return self.encrypt_legacy(key_pair).await;
+        }
+
+        // A key pair from before slots existed becomes the current one
+        let adopted = self
             .collection
-            .find_one(doc! {})
+            .update_one(
+                doc! { "slot": { "$exists": false } },
+                doc! { "$set": { "slot": CURRENT_SLOT } },
+            )
+            .await;
+        match adopted {
+            Err(e) if !is_duplicate_key(&e) => return Err(e.to_string()),
+            Ok(result) if result.modified_count > 0 => {
+                if let Some(key_pair) = self.current().await? {
+                    return self.encrypt_legacy(key_pair).await;
+                }
+            }
+            _ => {}
+        }
+
+        // Only one concurrent upsert inserts, the others match it or hit
+        // the unique index, and every caller reads back the stored pair
+        let mut key_pair = bson::to_document(&generate_key_pair()?).map_err(|e| e.to_string())?;
+        // Set from the filter on insert
+        key_pair.remove("slot");
+        let upserted = self
+            .collection
+            .update_one(
+                doc! { "slot": CURRENT_SLOT },
+                doc! { "$setOnInsert": key_pair },
+            )
+            .upsert(true)
+            .await;
+        if let Err(e) = upserted
+            && !is_duplicate_key(&e)
+        {
+            return Err(e.to_string());
+        }
+        self.current()
+            .await?
+            .ok_or_else(|| "Failed to store the signing key pair".to_string())
+    }
+
+    /// Encrypts a secret key stored in plaintext, left as is while the
+    /// master key is unavailable (sealed).
+    async fn encrypt_legacy(&self, key_pair: KeyPairDocument) -> Result<KeyPairDocument, String> {
+        if key_pair.encrypted || master_key().is_err() {
+            return Ok(key_pair);
+        }
+        let secret = general_purpose::STANDARD
+            .decode(&key_pair.private_key)
+            .map(SecretBytes::new)
+            .map_err(|e| e.to_string())?;
+        let private_key = seal_private_key(&secret)?;
+        self.collection
+            .update_one(
+                doc! { "slot": CURRENT_SLOT, "encrypted": { "$ne": true } },
+                doc! { "$set": { "private_key": &private_key, "encrypted": true } },
+            )
             .await
             .map_err(|e| e.to_string())?;
-        if let Some(doc) = cursor {
-            return Ok(doc);
-        } else {
-            self.collection
-                .insert_one(key_pair.clone())
-                .await
-                .map_err(|e| e.to_string())?;
+        Ok(KeyPairDocument {
+            private_key,
+            encrypted: true,
+            ..key_pair
+        })
+    }
+
+    async fn load(&self) -> Result<(), String> {
+        let kp = self.get_or_create_key_pair().await?;
+        *self.cache.write().await = Some(CachedKeys {
+            public_key: decode_public_key(&kp)?,
+            secret_key: decode_secret_key(&kp).ok(),
+            loaded_at: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Whether a signing key pair was ever stored, current or not.
+    pub async fn has_key_pair(&self) -> Result<bool, String> {
+        self.collection
+            .find_one(doc! {})
+            .await
+            .map(|key_pair| key_pair.is_some())
+            .map_err(|e| e.to_string())
+    }
+
+    /// The key verifying session tokens.
+    pub async fn public_key(&self) -> Result<AsymmetricPublicKey<V4>, String> {
+        if let Some(keys) = self
+            .cache
+            .read()
+            .await
+            .as_ref()
+            .filter(|keys| keys.is_fresh())
+        {
+            return Ok(keys.public_key.clone());
+        }
+        self.load().await?;
+        match self.cache.read().await.as_ref() {
+            Some(keys) => Ok(keys.public_key.clone()),
+            None => Err("The signing key pair is not loaded".into()),
+        }
+    }
+
+    /// The key signing session tokens, needs the master key.
+    pub async fn secret_key(&self) -> Result<AsymmetricSecretKey<V4>, String> {
+        let cached = self
+            .cache
+            .read()
+            .await
+            .as_ref()
+            .and_then(|keys| keys.is_fresh().then(|| keys.secret_key.clone()).flatten());
+        if let Some(secret_key) = cached {
+            return Ok(secret_key);
         }
+        let kp = self.get_or_create_key_pair().await?;
+        let secret_key = decode_secret_key(&kp)?;
+        *self.cache.write().await = Some(CachedKeys {
+            public_key: decode_public_key(&kp)?,
+            secret_key: Some(secret_key.clone()),
+            loaded_at: Instant::now(),
+        });
+        Ok(secret_key)
+    }
+
+    /// Drops the decoded keys, the next use loads them again.
+    pub async fn invalidate(&self) {
+        *self.cache.write().await = None;
+    }
+
+    /*---------------------------------------------------------------
+    Replace the key pair, which invalidates every session token.
+    Other instances pick the new pair up within the cache TTL.
+    ----------------------------------------------------------------*/
+    pub async fn rotate_key_pair(&self) -> Result<KeyPairDocument, String> {
+        self.ensure_index().await?;
+        let key_pair = generate_key_pair()?;
+        let mut fields = bson::to_document(&key_pair).map_err(|e| e.to_string())?;
+        fields.remove("slot");
+        self.collection
+            .update_one(doc! { "slot": CURRENT_SLOT }, doc! { "$set": fields })
+            .upsert(true)
+            .await
+            .map_err(|e| e.to_string())?;
+        self.invalidate().await;
         Ok(key_pair)
     }
+
+    /*---------------------------------------------------------------
+    RE-ENCRYPT the stored secret key with `params`. Returns whether
+    it changed; the key pair itself stays the same.
+    ----------------------------------------------------------------*/
+    pub async fn reencrypt_key_pair(&self, params: EnvelopeParams) -> Result<bool, String> {
+        let key_pair = self.get_or_create_key_pair().await?;
+        if !key_pair.encrypted {
+            return Err("The signing key is not encrypted".into());
+        }
+        let master_key = master_key().map_err(|e| e.to_string())?;
+        let encrypted = general_purpose::STANDARD
+            .decode(&key_pair.private_key)
+            .map_err(|e| e.to_string())?;
+        let Some(updated) = reencrypt(&encrypted, master_key.as_bytes(), params)
+            .map_err(|e| format!("Failed to re-encrypt the signing key: {e}"))?
+        else {
+            return Ok(false);
+        };
+        let result = self
+            .collection
+            .update_one(
+                doc! { "slot": CURRENT_SLOT, "private_key": &key_pair.private_key },
+                doc! { "$set": { "private_key": general_purpose::STANDARD.encode(updated) } },
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(result.modified_count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed_key_pair(master_key: &str) -> (AsymmetricKeyPair<V4>, KeyPairDocument) {
+        let kp = AsymmetricKeyPair::<V4>::generate().unwrap();
+        let document = KeyPairDocument {
+            slot: Some(CURRENT_SLOT.to_string()),
+            private_key: seal_private_key_with(kp.secret.as_bytes(), master_key.as_bytes())
+                .unwrap(),
+            public_key: general_purpose::STANDARD.encode(kp.public.as_bytes()),
+            encrypted: true,
+            created_at: Utc::now(),
+        };
+        (kp, document)
+    }
+
+    #[test]
+    fn private_key_round_trip() {
+        let (kp, document) = sealed_key_pair("master key");
+        assert_ne!(
+            document.private_key,
+            general_purpose::STANDARD.encode(kp.secret.as_bytes())
+        );
+        let secret_key = decode_secret_key_with(&document, || Ok("master key".into())).unwrap();
+        assert_eq!(secret_key.as_bytes(), kp.secret.as_bytes());
+        assert_eq!(
+            decode_public_key(&document).unwrap().as_bytes(),
+            kp.public.as_bytes()
+        );
+    }
+
+    #[test]
+    fn wrong_master_key_is_rejected() {
+        let (_, document) = sealed_key_pair("master key");
+        assert_eq!(
+            decode_secret_key_with(&document, || Ok("another key".into())).err(),
+            Some("Failed to decrypt the signing key".to_string())
+        );
+        // Sealed: no master key to decrypt with
+        assert!(decode_secret_key_with(&document, || Err("sealed".into())).is_err());
+    }
 }
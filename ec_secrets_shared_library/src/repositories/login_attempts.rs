@@ -0,0 +1,158 @@
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection,
+    bson::{doc, oid::ObjectId},
+    error::Result,
+    options::ReturnDocument,
+};
+
+use crate::models::{LockoutEventDocument, LoginAttemptDocument};
+
+/// Failures allowed before an account is locked.
+pub const MAX_FAILURES: i64 = 5;
+pub const BASE_LOCKOUT_SECONDS: i64 = 60;
+pub const MAX_LOCKOUT_SECONDS: i64 = 60 * 60;
+/// Failures older than this no longer count towards a lockout.
+pub const FAILURE_WINDOW_HOURS: i64 = 24;
+
+/// Progressive lockout: one minute at [MAX_FAILURES], doubling with every
+/// further failure up to an hour.
+pub fn lockout_duration(failures: i64) -> Option<Duration> {
+    if failures < MAX_FAILURES {
+        return None;
+    }
+    let doublings = (failures - MAX_FAILURES).min(16) as u32;
+    let seconds = BASE_LOCKOUT_SECONDS
+        .saturating_mul(2i64.pow(doublings))
+        .min(MAX_LOCKOUT_SECONDS);
+    Some(Duration::seconds(seconds))
+}
+
+/*---------------------------------------------------------------------------
+    Failed logins per account, shared by every server instance and the
+    CLI. Lockouts are additionally recorded as events for auditing.
+---------------------------------------------------------------------------*/
+pub struct LoginAttemptRepository {
+    attempts: Collection<LoginAttemptDocument>,
+    events: Collection<LockoutEventDocument>,
+}
+
+impl LoginAttemptRepository {
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        attempts_collection: &str,
+        events_collection: &str,
+    ) -> Self {
+        let database = client.database(db_name);
+        Self {
+            attempts: database.collection::<LoginAttemptDocument>(attempts_collection),
+            events: database.collection::<LockoutEventDocument>(events_collection),
+        }
+    }
+
+    /*-------------------------------------------
+    GET the end of an account's active lockout
+    --------------------------------------------*/
+    pub async fn locked_until(&self, account: &str) -> Result<Option<DateTime<Utc>>> {
+        let attempts = self.attempts.find_one(doc! { "_id": account }).await?;
+        Ok(attempts
+            .and_then(|attempts| attempts.locked_until)
+            .filter(|locked_until| *locked_until > Utc::now()))
+    }
+
+    /*--------------------------------------------------------------
+    RECORD a failed login, returning the lockout it triggered if any
+    ---------------------------------------------------------------*/
+    pub async fn record_failure(
+        &self,
+        account: &str,
+        ip: Option<&str>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let now = Utc::now();
+        let window_start = now - Duration::hours(FAILURE_WINDOW_HOURS);
+        self.attempts
+            .delete_one(doc! {
+                "_id": account,
+                "lastFailureAt": { "$lt": bson::DateTime::from_chrono(window_start) },
+            })
+            .await?;
+
+        let update = doc! {
+            "$inc": { "failures": 1 },
+            "$set": { "lastFailureAt": bson::DateTime::from_chrono(now) },
+        };
+        let Some(attempts) = self
+            .attempts
+            .find_one_and_update(doc! { "_id": account }, update)
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let Some(duration) = lockout_duration(attempts.failures) else {
+            return Ok(None);
+        };
+        let locked_until = now + duration;
+        self.attempts
+            .update_one(
+                doc! { "_id": account },
+                doc! { "$set": { "lockedUntil": bson::DateTime::from_chrono(locked_until) } },
+            )
+            .await?;
+        self.events
+            .insert_one(LockoutEventDocument {
+                id: ObjectId::new(),
+                account: account.to_string(),
+                ip: ip.map(str::to_string),
+                failures: attempts.failures,
+                locked_until,
+                created_at: now,
+            })
+            .await?;
+
+        Ok(Some(locked_until))
+    }
+
+    /*----------------------------------------
+    CLEAR failures after a successful login
+    -----------------------------------------*/
+    pub async fn record_success(&self, account: &str) -> Result<()> {
+        self.attempts.delete_one(doc! { "_id": account }).await?;
+        Ok(())
+    }
+
+    /*---------------------------------
+    LIST lockout events of an account
+    ----------------------------------*/
+    pub async fn list_lockout_events(&self, account: &str) -> Result<Vec<LockoutEventDocument>> {
+        let cursor = self
+            .events
+            .find(doc! { "account": account })
+            .sort(doc! { "createdAt": -1 })
+            .await?;
+        cursor.try_collect().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockout_is_progressive_and_capped() {
+        assert_eq!(lockout_duration(MAX_FAILURES - 1), None);
+        assert_eq!(lockout_duration(MAX_FAILURES), Some(Duration::seconds(60)));
+        assert_eq!(
+            lockout_duration(MAX_FAILURES + 1),
+            Some(Duration::seconds(120))
+        );
+        assert_eq!(
+            lockout_duration(MAX_FAILURES + 100),
+            Some(Duration::seconds(MAX_LOCKOUT_SECONDS))
+        );
+    }
+}
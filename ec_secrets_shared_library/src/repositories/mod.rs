@@ -1,3 +1,13 @@
+pub mod app_roles;
+pub mod groups;
+pub mod invitations;
 pub mod keys;
+pub mod login_attempts;
+pub mod organizations;
+pub mod policies;
+pub mod seal;
+pub mod service_accounts;
+pub mod settings;
+pub mod user_tokens;
 pub mod users;
 pub mod vault;
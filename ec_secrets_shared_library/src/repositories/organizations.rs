@@ -0,0 +1,115 @@
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection,
+    bson::{doc, oid::ObjectId},
+    error::{Error, Result},
+};
+
+use crate::models::OrganizationDocument;
+
+/*---------------------------------------------------------------------------
+    Organizations partition the vault: every entry belongs to one and is
+    encrypted with its key. Membership is stored on the user documents,
+    see `UserRepository::add_to_org`.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct OrganizationRepository {
+    collection: Collection<OrganizationDocument>,
+}
+
+impl OrganizationRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<OrganizationDocument>(collection_name);
+        Self { collection }
+    }
+
+    /*-------------------------
+    CREATE a new organization
+    --------------------------*/
+    pub async fn create_organization(&self, org: &OrganizationDocument) -> Result<()> {
+        if self.get_organization(&org.name).await?.is_some() {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "An organization with this name already exists.",
+            )));
+        }
+        self.collection.insert_one(org).await?;
+        Ok(())
+    }
+
+    /*-------------------------
+    GET organization by name
+    --------------------------*/
+    pub async fn get_organization(&self, name: &str) -> Result<Option<OrganizationDocument>> {
+        self.collection.find_one(doc! { "name": name }).await
+    }
+
+    /*-----------------------
+    GET organization by id
+    ------------------------*/
+    pub async fn get_organization_by_id(
+        &self,
+        id: &ObjectId,
+    ) -> Result<Option<OrganizationDocument>> {
+        self.collection.find_one(doc! { "_id": id }).await
+    }
+
+    /*-----------------------------------
+    Whether any organization exists yet
+    ------------------------------------*/
+    pub async fn has_organizations(&self) -> Result<bool> {
+        Ok(self.collection.find_one(doc! {}).await?.is_some())
+    }
+
+    /*-------------------
+    LIST organizations
+    --------------------*/
+    pub async fn list_organizations(&self) -> Result<Vec<OrganizationDocument>> {
+        self.collection.find(doc! {}).await?.try_collect().await
+    }
+
+    /*---------------------------------
+    LIST the organizations in `ids`
+    ----------------------------------*/
+    pub async fn find_organizations(&self, ids: &[ObjectId]) -> Result<Vec<OrganizationDocument>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.collection
+            .find(doc! { "_id": { "$in": ids } })
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*-------------------------------------------------------------
+    REPLACE the wrapped key of an organization, unless it changed
+    since it was read
+    --------------------------------------------------------------*/
+    pub async fn replace_encryption_key(
+        &self,
+        id: &ObjectId,
+        current: &str,
+        encryption_key: &str,
+    ) -> Result<bool> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "_id": id, "encryption_key": current },
+                doc! { "$set": { "encryption_key": encryption_key } },
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /*----------------------
+    DELETE an organization
+    -----------------------*/
+    pub async fn delete_organization(&self, name: &str) -> Result<Option<OrganizationDocument>> {
+        self.collection
+            .find_one_and_delete(doc! { "name": name })
+            .await
+    }
+}
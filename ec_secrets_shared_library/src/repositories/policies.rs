@@ -0,0 +1,172 @@
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection,
+    bson::{Document, doc, to_bson},
+    error::{Error, Result},
+    options::ReturnDocument,
+};
+
+use crate::models::{PolicyDocument, PolicyRule, PrincipalKind};
+
+/*---------------------------------------------------------------------------
+    Access policies on secret paths. Attachments live on the policy itself
+    so every policy that applies to a caller is found with one query.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct PolicyRepository {
+    collection: Collection<PolicyDocument>,
+}
+
+impl PolicyRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<PolicyDocument>(collection_name);
+        Self { collection }
+    }
+
+    /*-------------------
+    CREATE a new policy
+    --------------------*/
+    pub async fn create_policy(&self, policy: &PolicyDocument) -> Result<()> {
+        if self.get_policy(&policy.name).await?.is_some() {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "A policy with this name already exists.",
+            )));
+        }
+        self.collection.insert_one(policy).await?;
+        Ok(())
+    }
+
+    /*------------------
+    GET policy by name
+    -------------------*/
+    pub async fn get_policy(&self, name: &str) -> Result<Option<PolicyDocument>> {
+        self.collection.find_one(doc! { "name": name }).await
+    }
+
+    /*-------------
+    LIST policies
+    --------------*/
+    pub async fn list_policies(&self) -> Result<Vec<PolicyDocument>> {
+        self.collection.find(doc! {}).await?.try_collect().await
+    }
+
+    /*------------------------------------------------------
+    LIST the policies attached to a user, any of its groups
+    or a service account
+    -------------------------------------------------------*/
+    pub async fn applicable(
+        &self,
+        user: Option<&str>,
+        groups: &[String],
+        service_account: Option<&str>,
+    ) -> Result<Vec<PolicyDocument>> {
+        let mut attachments: Vec<Document> = Vec::new();
+        if let Some(user) = user {
+            attachments.push(doc! { "users": user });
+        }
+        if !groups.is_empty() {
+            attachments.push(doc! { "groups": { "$in": groups } });
+        }
+        if let Some(service_account) = service_account {
+            attachments.push(doc! { "service_accounts": service_account });
+        }
+        if attachments.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.collection
+            .find(doc! { "$or": attachments })
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*---------------------------
+    REPLACE a policy's rules
+    ----------------------------*/
+    pub async fn update_rules(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        rules: &[PolicyRule],
+    ) -> Result<Option<PolicyDocument>> {
+        let update = doc! { "$set": {
+            "description": description,
+            "rules": to_bson(rules)?,
+        } };
+        self.collection
+            .find_one_and_update(doc! { "name": name }, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*-----------------------------
+    ATTACH a policy to a principal
+    ------------------------------*/
+    pub async fn attach(
+        &self,
+        name: &str,
+        kind: PrincipalKind,
+        id: &str,
+    ) -> Result<Option<PolicyDocument>> {
+        let update = doc! { "$addToSet": { kind.field(): id } };
+        self.collection
+            .find_one_and_update(doc! { "name": name }, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*-----------------------------------------------------------
+    DETACH a policy from a principal, None if it wasn't attached
+    ------------------------------------------------------------*/
+    pub async fn detach(
+        &self,
+        name: &str,
+        kind: PrincipalKind,
+        id: &str,
+    ) -> Result<Option<PolicyDocument>> {
+        let filter = doc! { "name": name, kind.field(): id };
+        let update = doc! { "$pull": { kind.field(): id } };
+        self.collection
+            .find_one_and_update(filter, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*-------------------------------------------------
+    DETACH every policy from a principal that is gone
+    --------------------------------------------------*/
+    pub async fn detach_all(&self, kind: PrincipalKind, id: &str) -> Result<u64> {
+        let update = doc! { "$pull": { kind.field(): id } };
+        let result = self
+            .collection
+            .update_many(doc! { kind.field(): id }, update)
+            .await?;
+        Ok(result.modified_count)
+    }
+
+    /*------------------------------------------
+    RENAME a user's attachments on email change
+    -------------------------------------------*/
+    pub async fn rename_user(&self, email: &str, new_email: &str) -> Result<()> {
+        let filter = doc! { "users": email };
+        self.collection
+            .update_many(filter.clone(), doc! { "$addToSet": { "users": new_email } })
+            .await?;
+        self.collection
+            .update_many(filter, doc! { "$pull": { "users": email } })
+            .await?;
+        Ok(())
+    }
+
+    /*---------------
+    DELETE a policy
+    ----------------*/
+    pub async fn delete_policy(&self, name: &str) -> Result<Option<PolicyDocument>> {
+        self.collection
+            .find_one_and_delete(doc! { "name": name })
+            .await
+    }
+}
@@ -0,0 +1,45 @@
+use mongodb::{Client, Collection, bson::doc, error::Result};
+
+use crate::models::SealDocument;
+
+/// Id of the single document holding the seal configuration.
+const SEAL_ID: &str = "master";
+
+/*---------------------------------------------------------------------------
+    Seal configuration of the instance, written once when the master key
+    is split into Shamir shares.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct SealRepository {
+    collection: Collection<SealDocument>,
+}
+
+impl SealRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<SealDocument>(collection_name);
+        Self { collection }
+    }
+
+    /*------------------------
+    GET the seal configuration
+    -------------------------*/
+    pub async fn get_seal(&self) -> Result<Option<SealDocument>> {
+        self.collection.find_one(doc! { "_id": SEAL_ID }).await
+    }
+
+    /*--------------------------------------------------------------
+    STORE the seal configuration, false if the instance was already
+    initialized. Concurrent initializations can't both succeed.
+    ---------------------------------------------------------------*/
+    pub async fn initialize(&self, seal: &SealDocument) -> Result<bool> {
+        let seal = bson::to_document(seal)?;
+        let result = self
+            .collection
+            .update_one(doc! { "_id": SEAL_ID }, doc! { "$setOnInsert": seal })
+            .upsert(true)
+            .await?;
+        Ok(result.upserted_id.is_some())
+    }
+}
@@ -0,0 +1,226 @@
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection,
+    bson::{doc, oid::ObjectId},
+    error::{Error, Result},
+};
+
+use crate::models::{ApiKeyDocument, ServiceAccountDocument};
+use crate::utils::auth::{generate_api_key, hash_token, parse_api_key};
+
+/*---------------------------------------------------------------------------
+    Service accounts are machine identities owned by the user that created
+    them. They authenticate with API keys of the form
+    `ecs_<key id>_<secret>`; only a hash of the secret part is stored, so
+    the full key can only be shown once at creation time.
+---------------------------------------------------------------------------*/
+pub struct ServiceAccountRepository {
+    accounts: Collection<ServiceAccountDocument>,
+    api_keys: Collection<ApiKeyDocument>,
+}
+
+impl ServiceAccountRepository {
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        accounts_collection: &str,
+        api_keys_collection: &str,
+    ) -> Self {
+        let database = client.database(db_name);
+        Self {
+            accounts: database.collection::<ServiceAccountDocument>(accounts_collection),
+            api_keys: database.collection::<ApiKeyDocument>(api_keys_collection),
+        }
+    }
+
+    /*---------------------------
+    CREATE a new service account
+    ----------------------------*/
+    pub async fn create_service_account(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        created_by: &str,
+        org_id: Option<ObjectId>,
+    ) -> Result<ServiceAccountDocument> {
+        if self
+            .accounts
+            .find_one(doc! { "name": name, "created_by": created_by })
+            .await?
+            .is_some()
+        {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "A service account with this name already exists.",
+            )));
+        }
+
+        let account = ServiceAccountDocument {
+            id: ObjectId::new(),
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            created_by: created_by.to_string(),
+            created_at: Utc::now(),
+            org_id,
+        };
+
+        self.accounts.insert_one(&account).await?;
+        Ok(account)
+    }
+
+    /*-------------------------
+    GET service account by id
+    --------------------------*/
+    pub async fn get_service_account(
+        &self,
+        id: &ObjectId,
+        created_by: &str,
+    ) -> Result<Option<ServiceAccountDocument>> {
+        self.accounts
+            .find_one(doc! { "_id": id, "created_by": created_by })
+            .await
+    }
+
+    /*-------------------------------------------
+    FIND a service account of any owner by id
+    --------------------------------------------*/
+    pub async fn find_service_account(
+        &self,
+        id: &ObjectId,
+    ) -> Result<Option<ServiceAccountDocument>> {
+        self.accounts.find_one(doc! { "_id": id }).await
+    }
+
+    /*------------------------
+    LIST all service accounts
+    -------------------------*/
+    pub async fn list_service_accounts(
+        &self,
+        created_by: &str,
+    ) -> Result<Vec<ServiceAccountDocument>> {
+        let cursor = self
+            .accounts
+            .find(doc! { "created_by": created_by })
+            .await?;
+        cursor.try_collect().await
+    }
+
+    /*---------------------------------------
+    DELETE a service account and all its keys
+    ----------------------------------------*/
+    pub async fn delete_service_account(
+        &self,
+        id: &ObjectId,
+        created_by: &str,
+    ) -> Result<Option<ServiceAccountDocument>> {
+        let account = self
+            .accounts
+            .find_one_and_delete(doc! { "_id": id, "created_by": created_by })
+            .await?;
+        if account.is_some() {
+            self.api_keys
+                .delete_many(doc! { "service_account_id": id })
+                .await?;
+        }
+        Ok(account)
+    }
+
+    /*------------------------------------------------------
+    DELETE every service account of a user and their keys
+    -------------------------------------------------------*/
+    pub async fn delete_owned_by(&self, created_by: &str) -> Result<u64> {
+        let accounts = self.list_service_accounts(created_by).await?;
+        let ids: Vec<ObjectId> = accounts.iter().map(|account| account.id).collect();
+        // Keys first, so a failure never leaves keys without an account
+        self.api_keys
+            .delete_many(doc! { "service_account_id": { "$in": &ids } })
+            .await?;
+        let result = self
+            .accounts
+            .delete_many(doc! { "_id": { "$in": &ids } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+
+    /*--------------------------------------------------
+    CREATE an API key, returning the key only this once
+    ---------------------------------------------------*/
+    pub async fn create_api_key(
+        &self,
+        service_account_id: &ObjectId,
+        scopes: &[String],
+        expires_in_days: Option<i64>,
+    ) -> Result<(ApiKeyDocument, String)> {
+        let id = ObjectId::new();
+        let (api_key, key_hash) = generate_api_key(&id);
+
+        let document = ApiKeyDocument {
+            id,
+            service_account_id: *service_account_id,
+            key_hash,
+            scopes: scopes.to_vec(),
+            expires_at: expires_in_days.map(|days| Utc::now() + Duration::days(days)),
+            revoked_at: None,
+            created_at: Utc::now(),
+        };
+
+        self.api_keys.insert_one(&document).await?;
+        Ok((document, api_key))
+    }
+
+    /*------------------------------
+    LIST API keys of an account
+    ------------------------------*/
+    pub async fn list_api_keys(
+        &self,
+        service_account_id: &ObjectId,
+    ) -> Result<Vec<ApiKeyDocument>> {
+        let cursor = self
+            .api_keys
+            .find(doc! { "service_account_id": service_account_id })
+            .await?;
+        cursor.try_collect().await
+    }
+
+    /*--------------
+    REVOKE an API key
+    ---------------*/
+    pub async fn revoke_api_key(
+        &self,
+        service_account_id: &ObjectId,
+        key_id: &ObjectId,
+    ) -> Result<Option<ApiKeyDocument>> {
+        let filter = doc! {
+            "_id": key_id,
+            "service_account_id": service_account_id,
+            "revokedAt": null,
+        };
+        let update = doc! { "$set": { "revokedAt": bson::DateTime::now() } };
+        self.api_keys.find_one_and_update(filter, update).await
+    }
+
+    /*------------------------------------------------------------
+    VERIFY an API key, returning the key and its account if active
+    -------------------------------------------------------------*/
+    pub async fn verify_api_key(
+        &self,
+        api_key: &str,
+    ) -> Result<Option<(ApiKeyDocument, ServiceAccountDocument)>> {
+        let Some((key_id, secret)) = parse_api_key(api_key) else {
+            return Ok(None);
+        };
+        let Some(key) = self.api_keys.find_one(doc! { "_id": key_id }).await? else {
+            return Ok(None);
+        };
+        if key.key_hash != hash_token(secret) || !key.is_active() {
+            return Ok(None);
+        }
+
+        let account = self
+            .accounts
+            .find_one(doc! { "_id": key.service_account_id })
+            .await?;
+        Ok(account.map(|account| (key, account)))
+    }
+}
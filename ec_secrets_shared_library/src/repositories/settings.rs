@@ -0,0 +1,57 @@
+use mongodb::{Client, Collection, bson::doc, error::Result, options::ReturnDocument};
+
+use crate::models::SettingsDocument;
+
+/// Id of the single document holding instance wide settings.
+const SETTINGS_ID: &str = "global";
+
+/*---------------------------------------------------------------------------
+    Instance wide settings managed by admins, stored as a single document.
+    Missing settings fall back to their defaults.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct SettingsRepository {
+    collection: Collection<SettingsDocument>,
+}
+
+impl SettingsRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<SettingsDocument>(collection_name);
+        Self { collection }
+    }
+
+    /*-------------
+    GET settings
+    --------------*/
+    pub async fn get_settings(&self) -> Result<SettingsDocument> {
+        Ok(self
+            .collection
+            .find_one(doc! { "_id": SETTINGS_ID })
+            .await?
+            .unwrap_or_default())
+    }
+
+    /*---------------------------
+    SET whether MFA is required
+    ----------------------------*/
+    pub async fn set_require_mfa(
+        &self,
+        require_mfa: bool,
+        updated_by: &str,
+    ) -> Result<SettingsDocument> {
+        let update = doc! { "$set": {
+            "require_mfa": require_mfa,
+            "updated_by": updated_by,
+            "updatedAt": bson::DateTime::now(),
+        } };
+        Ok(self
+            .collection
+            .find_one_and_update(doc! { "_id": SETTINGS_ID }, update)
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?
+            .unwrap_or_default())
+    }
+}
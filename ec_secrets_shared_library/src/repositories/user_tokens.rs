@@ -0,0 +1,77 @@
+use chrono::{Duration, Utc};
+use mongodb::{
+    Client, Collection,
+    bson::{doc, oid::ObjectId, to_bson},
+    error::Result,
+};
+
+use crate::models::{UserTokenDocument, UserTokenPurpose};
+
+/*---------------------------------------------------------------------------
+    Single use, expiring tokens mailed to users for password resets and
+    email verification. Issuing a token replaces any earlier token of the
+    same purpose, redeeming it deletes it.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct UserTokenRepository {
+    collection: Collection<UserTokenDocument>,
+}
+
+impl UserTokenRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<UserTokenDocument>(collection_name);
+        Self { collection }
+    }
+
+    /*--------------------------------------------
+    CREATE a token, replacing earlier ones
+    ---------------------------------------------*/
+    pub async fn create_token(
+        &self,
+        user_id: &ObjectId,
+        purpose: UserTokenPurpose,
+        token_hash: &str,
+        lifetime: Duration,
+    ) -> Result<UserTokenDocument> {
+        self.delete_tokens(user_id, purpose).await?;
+
+        let now = Utc::now();
+        let token = UserTokenDocument {
+            id: ObjectId::new(),
+            user_id: *user_id,
+            purpose,
+            token_hash: token_hash.to_string(),
+            expires_at: now + lifetime,
+            created_at: now,
+        };
+        self.collection.insert_one(&token).await?;
+        Ok(token)
+    }
+
+    /*-------------------------------------------------
+    CONSUME an unexpired token, None if it isn't valid
+    --------------------------------------------------*/
+    pub async fn consume_token(
+        &self,
+        purpose: UserTokenPurpose,
+        token_hash: &str,
+    ) -> Result<Option<UserTokenDocument>> {
+        let filter = doc! {
+            "purpose": to_bson(&purpose)?,
+            "token_hash": token_hash,
+            "expiresAt": { "$gt": bson::DateTime::now() },
+        };
+        self.collection.find_one_and_delete(filter).await
+    }
+
+    /*------------------------------------
+    DELETE a user's tokens of a purpose
+    -------------------------------------*/
+    pub async fn delete_tokens(&self, user_id: &ObjectId, purpose: UserTokenPurpose) -> Result<()> {
+        let filter = doc! { "user_id": user_id, "purpose": to_bson(&purpose)? };
+        self.collection.delete_many(filter).await?;
+        Ok(())
+    }
+}
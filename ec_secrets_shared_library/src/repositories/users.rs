@@ -5,11 +5,11 @@ use mongodb::{
     Client, Collection,
     bson::{doc, oid::ObjectId},
     error::{Error, Result},
-    options::ClientOptions,
+    options::{ClientOptions, ReturnDocument},
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{models::UserDocument, utils::auth::hash_password};
+use crate::models::UserDocument;
 
 #[derive(Debug)]
 pub struct UserRepository {
@@ -27,8 +27,18 @@ impl UserRepository {
     /*-----------------
     CREATE a new user
     --------------------*/
-    pub async fn create_user(&self, email: &str, password: &str) -> Result<UserDocument> {
-        if let Some(_) = self.collection.find_one(doc! { "email": email }).await? {
+    pub async fn create_user(
+        &self,
+        email: &str,
+        password: &str,
+        admin: bool,
+    ) -> Result<UserDocument> {
+        if self
+            .collection
+            .find_one(doc! { "email": email })
+            .await?
+            .is_some()
+        {
             return Err(Error::from(std::io::Error::new(
                 std::io::ErrorKind::AlreadyExists,
                 "A user with this email already exists.",
@@ -40,6 +50,13 @@ impl UserRepository {
             email: email.to_string(),
             password: password.to_string(),
             created_at: Utc::now(),
+            admin,
+            mfa: None,
+            email_verified: false,
+            token_version: 0,
+            groups: Vec::new(),
+            orgs: Vec::new(),
+            e2e_public_key: None,
         };
 
         self.collection.insert_one(&user).await?;
@@ -96,6 +113,45 @@ impl UserRepository {
         Ok(user)
     }
 
+    /*----------------------------------------------------
+    SET a new password hash, revoking the user's tokens
+    -----------------------------------------------------*/
+    pub async fn set_password(&self, id: &ObjectId, password: &str) -> Result<()> {
+        let update = doc! {
+            "$set": { "password": password },
+            "$inc": { "tokenVersion": 1 },
+        };
+        self.collection
+            .update_one(doc! { "_id": id }, update)
+            .await?;
+        Ok(())
+    }
+
+    /*------------------------------------------------------------
+    CHANGE the email address, unverified until confirmed again
+    -------------------------------------------------------------*/
+    pub async fn change_email(&self, id: &ObjectId, email: &str) -> Result<Option<UserDocument>> {
+        let update = doc! {
+            "$set": { "email": email, "emailVerified": false },
+            "$inc": { "tokenVersion": 1 },
+        };
+        self.collection
+            .find_one_and_update(doc! { "_id": id }, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*--------------------------
+    MARK the email as verified
+    ---------------------------*/
+    pub async fn set_email_verified(&self, id: &ObjectId) -> Result<()> {
+        let update = doc! { "$set": { "emailVerified": true } };
+        self.collection
+            .update_one(doc! { "_id": id }, update)
+            .await?;
+        Ok(())
+    }
+
     /*-------------
     DELETE a user
     ---------------*/
@@ -106,6 +162,290 @@ impl UserRepository {
         Ok(user)
     }
 
+    /*--------------------------------------
+    ADD a user to a group, None if unknown
+    ---------------------------------------*/
+    pub async fn add_to_group(&self, email: &str, group: &str) -> Result<Option<UserDocument>> {
+        let update = doc! { "$addToSet": { "groups": group } };
+        self.collection
+            .find_one_and_update(doc! { "email": email }, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*--------------------------------------------------------------
+    REMOVE a user from a group, revoking tokens carrying the group.
+    None if the user isn't a member.
+    ---------------------------------------------------------------*/
+    pub async fn remove_from_group(
+        &self,
+        email: &str,
+        group: &str,
+    ) -> Result<Option<UserDocument>> {
+        let filter = doc! { "email": email, "groups": group };
+        let update = doc! {
+            "$pull": { "groups": group },
+            "$inc": { "tokenVersion": 1 },
+        };
+        self.collection
+            .find_one_and_update(filter, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*--------------------------------------------------
+    REMOVE every member from a deleted group
+    ---------------------------------------------------*/
+    pub async fn remove_group(&self, group: &str) -> Result<u64> {
+        let update = doc! {
+            "$pull": { "groups": group },
+            "$inc": { "tokenVersion": 1 },
+        };
+        let result = self
+            .collection
+            .update_many(doc! { "groups": group }, update)
+            .await?;
+        Ok(result.modified_count)
+    }
+
+    /*---------------------
+    LIST a group's members
+    ----------------------*/
+    pub async fn list_group_members(&self, group: &str) -> Result<Vec<UserDocument>> {
+        self.collection
+            .find(doc! { "groups": group })
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*----------------------------------------------
+    ADD a user to an organization, None if unknown
+    -----------------------------------------------*/
+    pub async fn add_to_org(&self, email: &str, org: &ObjectId) -> Result<Option<UserDocument>> {
+        let update = doc! { "$addToSet": { "orgs": org } };
+        self.collection
+            .find_one_and_update(doc! { "email": email }, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*--------------------------------------------------------------
+    REMOVE a user from an organization, revoking the user's tokens.
+    None if the user isn't a member.
+    ---------------------------------------------------------------*/
+    pub async fn remove_from_org(
+        &self,
+        email: &str,
+        org: &ObjectId,
+    ) -> Result<Option<UserDocument>> {
+        let filter = doc! { "email": email, "orgs": org };
+        let update = doc! {
+            "$pull": { "orgs": org },
+            "$inc": { "tokenVersion": 1 },
+        };
+        self.collection
+            .find_one_and_update(filter, update)
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*--------------------------------------------------
+    REMOVE every member from a deleted organization
+    ---------------------------------------------------*/
+    pub async fn remove_org(&self, org: &ObjectId) -> Result<u64> {
+        let update = doc! {
+            "$pull": { "orgs": org },
+            "$inc": { "tokenVersion": 1 },
+        };
+        let result = self
+            .collection
+            .update_many(doc! { "orgs": org }, update)
+            .await?;
+        Ok(result.modified_count)
+    }
+
+    /*-----------------------------
+    LIST an organization's members
+    ------------------------------*/
+    pub async fn list_org_members(&self, org: &ObjectId) -> Result<Vec<UserDocument>> {
+        self.collection
+            .find(doc! { "orgs": org })
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*--------------------------------------------------------------
+    FIND the members of `org` among `emails` and the members of
+    `groups`, the people a value gets sealed for
+    ---------------------------------------------------------------*/
+    pub async fn find_recipients(
+        &self,
+        org: &ObjectId,
+        emails: &[String],
+        groups: &[String],
+    ) -> Result<Vec<UserDocument>> {
+        let filter = doc! {
+            "orgs": org,
+            "$or": [
+                { "email": { "$in": emails } },
+                { "groups": { "$in": groups } },
+            ],
+        };
+        self.collection.find(filter).await?.try_collect().await
+    }
+
+    /*----------------------------------------------------
+    SET the public key values are end-to-end encrypted
+    for, replacing the previous one
+    -----------------------------------------------------*/
+    pub async fn set_e2e_public_key(&self, id: &ObjectId, public_key: &str) -> Result<()> {
+        self.collection
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "e2e_public_key": public_key } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /*----------------------------------------------
+    ADD every user without an organization to `org`
+    -----------------------------------------------*/
+    pub async fn adopt_users(&self, org: &ObjectId) -> Result<u64> {
+        let filter = doc! { "$or": [
+            { "orgs": { "$exists": false } },
+            { "orgs": { "$size": 0 } },
+        ] };
+        let update = doc! { "$set": { "orgs": [org] } };
+        let result = self.collection.update_many(filter, update).await?;
+        Ok(result.modified_count)
+    }
+
+    /*-----------------------
+    COUNT admin accounts
+    ------------------------*/
+    pub async fn count_admins(&self) -> Result<u64> {
+        self.collection
+            .count_documents(doc! { "admin": true })
+            .await
+    }
+
+    /*-----------------------------------------------------
+    START MFA enrollment, replacing any pending enrollment
+    ------------------------------------------------------*/
+    pub async fn start_mfa_enrollment(
+        &self,
+        id: &ObjectId,
+        encrypted_secret: &str,
+    ) -> Result<Option<UserDocument>> {
+        let filter = doc! { "_id": id, "mfa.enabled": { "$ne": true } };
+        let update = doc! { "$set": { "mfa": {
+            "secret": encrypted_secret,
+            "enabled": false,
+            "recovery_codes": [],
+            "last_used_step": null,
+        } } };
+        self.collection.find_one_and_update(filter, update).await
+    }
+
+    /*------------------------------------------
+    ENABLE MFA once the first code is verified
+    -------------------------------------------*/
+    pub async fn enable_mfa(
+        &self,
+        id: &ObjectId,
+        recovery_code_hashes: &[String],
+        step: i64,
+    ) -> Result<Option<UserDocument>> {
+        let filter = doc! { "_id": id, "mfa.enabled": false };
+        let update = doc! { "$set": {
+            "mfa.enabled": true,
+            "mfa.recovery_codes": recovery_code_hashes,
+            "mfa.last_used_step": step,
+        } };
+        self.collection.find_one_and_update(filter, update).await
+    }
+
+    /*-------------------------------------------------------------
+    RECORD a used TOTP step, fails if it (or a later one) was used
+    --------------------------------------------------------------*/
+    pub async fn record_totp_step(&self, id: &ObjectId, step: i64) -> Result<bool> {
+        let filter = doc! {
+            "_id": id,
+            "$or": [
+                { "mfa.last_used_step": null },
+                { "mfa.last_used_step": { "$lt": step } },
+            ],
+        };
+        let update = doc! { "$set": { "mfa.last_used_step": step } };
+        let result = self.collection.update_one(filter, update).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    /*----------------------------------
+    CONSUME a single use recovery code
+    -----------------------------------*/
+    pub async fn consume_recovery_code(&self, id: &ObjectId, code_hash: &str) -> Result<bool> {
+        let filter = doc! { "_id": id, "mfa.recovery_codes": code_hash };
+        let update = doc! { "$pull": { "mfa.recovery_codes": code_hash } };
+        let result = self.collection.update_one(filter, update).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    /*------------------------
+    REPLACE recovery codes
+    -------------------------*/
+    pub async fn replace_recovery_codes(
+        &self,
+        id: &ObjectId,
+        recovery_code_hashes: &[String],
+    ) -> Result<()> {
+        let filter = doc! { "_id": id, "mfa.enabled": true };
+        let update = doc! { "$set": { "mfa.recovery_codes": recovery_code_hashes } };
+        self.collection.update_one(filter, update).await?;
+        Ok(())
+    }
+
+    /*------------------------------------------
+    FIND users with a TOTP secret, enrolled or not
+    -------------------------------------------*/
+    pub async fn list_mfa_users(&self) -> Result<Vec<UserDocument>> {
+        self.collection
+            .find(doc! { "mfa.secret": { "$exists": true } })
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*-------------------------------------------------------------
+    REPLACE the encrypted TOTP secret, unless it changed since it
+    was read
+    --------------------------------------------------------------*/
+    pub async fn replace_mfa_secret(
+        &self,
+        id: &ObjectId,
+        current: &str,
+        encrypted_secret: &str,
+    ) -> Result<bool> {
+        let filter = doc! { "_id": id, "mfa.secret": current };
+        let update = doc! { "$set": { "mfa.secret": encrypted_secret } };
+        let result = self.collection.update_one(filter, update).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    /*-------------
+    DISABLE MFA
+    --------------*/
+    pub async fn disable_mfa(&self, id: &ObjectId) -> Result<()> {
+        let update = doc! { "$unset": { "mfa": "" } };
+        self.collection
+            .update_one(doc! { "_id": id }, update)
+            .await?;
+        Ok(())
+    }
+
     /*-------------
     GET all users
     ---------------*/
@@ -2,42 +2,483 @@
 use anyhow::anyhow;
 use base64::prelude::BASE64_STANDARD;
 use base64::{Engine, engine::general_purpose};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
 use chrono::Utc;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use futures::stream::TryStreamExt;
+use log::warn;
 use mongodb::{
     Client, Collection,
-    bson::{doc, oid::ObjectId},
-    error::Result,
+    bson::{Bson, Document, doc, oid::ObjectId},
+    error::{Error, Result},
+    gridfs::{GridFsBucket, GridFsUploadStream},
+    options::GridFsBucketOptions,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error as ThisError;
+use tokio::sync::{Mutex, RwLock};
 
-use crate::models::VaultDocument;
-use crate::utils::vault::{decrypt, encrypt};
+use crate::models::{FileMetadata, OrganizationDocument, SecretFile, VaultDocument};
+use crate::utils::seal::{self, SealError, master_key};
+use crate::utils::secret::{SecretBytes, SecretString};
+use crate::utils::vault::stream::{
+    self, DEFAULT_CHUNK_SIZE, EncryptWriter, StreamError, StreamHeader,
+};
+use crate::utils::vault::{
+    DecryptError, EnvelopeParams, KdfParams, binding_required, decrypt, decrypt_bound, encrypt,
+    encrypt_bound, is_bound, reencrypt, reencrypt_bound,
+};
+
+/// Encrypted values above this size go to GridFS instead of the entry.
+pub const INLINE_VALUE_LIMIT: usize = 256 * 1024;
+/// Layout version of the context values are bound to, see [entry_context].
+const ENTRY_CONTEXT_VERSION: u8 = 1;
+const DEFAULT_MAX_FILE_SIZE: u64 = 50 * 1024 * 1024;
+/// How long an unwrapped organization key is reused before the next
+/// request unwraps it again.
+const ORG_KEY_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Largest binary value accepted, [ECS_MAX_FILE_SIZE] bytes (50 MiB by
+/// default).
+pub fn max_file_size() -> u64 {
+    std::env::var("ECS_MAX_FILE_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_MAX_FILE_SIZE)
+}
+
+fn vault_error(message: String) -> Error {
+    Error::from(std::io::Error::other(message))
+}
+
+/// Associated data every value is encrypted with: the id, organization,
+/// key and author of its entry. Fields are length prefixed, so distinct
+/// entries never share a context.
+fn entry_context(id: &ObjectId, org_id: &ObjectId, key: &str, created_by: &str) -> Vec<u8> {
+    let mut context = b"ecs-vault-entry".to_vec();
+    context.push(ENTRY_CONTEXT_VERSION);
+    context.extend_from_slice(&id.bytes());
+    context.extend_from_slice(&org_id.bytes());
+    for field in [key, created_by] {
+        context.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        context.extend_from_slice(field.as_bytes());
+    }
+    context
+}
+
+#[derive(ThisError, Debug)]
+pub enum RevealError {
+    /// The stored value doesn't decrypt for the entry holding it: moved
+    /// from another entry, tampered with or corrupted.
+    #[error("Vault entry {id} failed its integrity check: {source}")]
+    Integrity { id: ObjectId, source: DecryptError },
+    #[error(transparent)]
+    Database(#[from] Error),
+}
+
+#[derive(ThisError, Debug)]
+pub enum UploadError {
+    #[error("Values are limited to {limit} bytes")]
+    TooLarge { limit: u64 },
+    #[error("Failed to read the upload: {0}")]
+    Read(std::io::Error),
+    #[error(transparent)]
+    Database(#[from] Error),
+}
+
+/// Key material of a GridFS blob: the organization key followed by the
+/// context of its entry, so a blob attached to another entry derives a
+/// different key and fails to decrypt.
+fn blob_key_material(encryption_key: &[u8], context: &[u8]) -> SecretBytes {
+    SecretBytes::new([encryption_key, context].concat())
+}
+
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| vault_error(e.to_string()))
+}
+
+/// Encrypts `head` followed by the rest of `reader` into `upload` as a
+/// stream, draining each sealed chunk into the upload as it's written.
+/// Returns the plaintext size.
+async fn encrypt_into<R: AsyncRead + Unpin>(
+    upload: &mut GridFsUploadStream,
+    material: SecretBytes,
+    head: &[u8],
+    reader: &mut R,
+    limit: u64,
+) -> std::result::Result<u64, UploadError> {
+    // Deriving the stream key runs Argon2
+    let mut writer = blocking(move || EncryptWriter::new(Vec::new(), &material))
+        .await?
+        .map_err(|e| vault_error(format!("Failed to encrypt vault entry: {e}")))?;
+    let write_error =
+        |e: std::io::Error| vault_error(format!("Failed to encrypt vault entry: {e}"));
+
+    let mut size = head.len() as u64;
+    writer.write_all(head).map_err(write_error)?;
+    let mut buffer = SecretBytes::new(vec![0u8; DEFAULT_CHUNK_SIZE as usize]);
+    loop {
+        let sealed = std::mem::take(writer.get_mut());
+        upload.write_all(&sealed).await.map_err(Error::from)?;
+        let read = reader.read(&mut buffer).await.map_err(UploadError::Read)?;
+        if read == 0 {
+            break;
+        }
+        size += read as u64;
+        if size > limit {
+            return Err(UploadError::TooLarge { limit });
+        }
+        writer.write_all(&buffer[..read]).map_err(write_error)?;
+    }
+    let last = writer.finish().map_err(write_error)?;
+    upload.write_all(&last).await.map_err(Error::from)?;
+    Ok(size)
+}
 
-#[derive(Debug)]
+/// Decrypts a blob written by [encrypt_into].
+fn open_blob_stream(
+    material: &[u8],
+    encrypted: &[u8],
+) -> std::result::Result<SecretBytes, DecryptError> {
+    let mut plaintext = SecretBytes::default();
+    match stream::decrypt_stream(encrypted, &mut *plaintext, material) {
+        Ok(_) => Ok(plaintext),
+        Err(StreamError::Cipher) => Err(DecryptError::Integrity),
+        Err(StreamError::Hashing(e)) => Err(DecryptError::Hashing(e)),
+        Err(_) => Err(DecryptError::Envelope("invalid stream")),
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Vault entries of every organization. Organization keys are wrapped
+    with the master key (see `utils::seal`), entries are only reachable
+    through the per-organization `OrgVault`. Large binary values live in
+    the `<collection>_blobs` GridFS bucket.
+---------------------------------------------------------------------------*/
 pub struct VaultRepository {
     collection: Collection<VaultDocument>,
-    encryption_key: String,
+    blobs: GridFsBucket,
+    org_keys: RwLock<HashMap<ObjectId, CachedOrgKey>>,
+}
+
+/// An unwrapped organization key. Unwrapping runs Argon2, too slow to
+/// repeat on every request.
+struct CachedOrgKey {
+    /// The wrapped key it was unwrapped from, re-wrapped keys miss
+    wrapped: String,
+    key: SecretString,
+    loaded_at: Instant,
+}
+
+impl CachedOrgKey {
+    fn matches(&self, org: &OrganizationDocument) -> bool {
+        self.wrapped == org.encryption_key && self.loaded_at.elapsed() < ORG_KEY_CACHE_TTL
+    }
+}
+
+impl fmt::Debug for VaultRepository {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VaultRepository")
+            .field("collection", &self.collection.name())
+            .finish_non_exhaustive()
+    }
 }
 
 impl VaultRepository {
     /// Create a new repository with a MongoDB collection and a shared SecretVault instance
     pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
-        let collection = client
-            .database(db_name)
-            .collection::<VaultDocument>(collection_name);
-
-        let encryption_key = format!(
-            "{}",
-            std::env::var("ECS_ENCRYPTION_KEY").expect("ECS_ENCRYPTION_KEY must be set")
+        let database = client.database(db_name);
+        let collection = database.collection::<VaultDocument>(collection_name);
+        let blobs = database.gridfs_bucket(
+            GridFsBucketOptions::builder()
+                .bucket_name(format!("{collection_name}_blobs"))
+                .build(),
         );
 
         Self {
             collection,
+            blobs,
+            org_keys: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /*---------------------------------------------------------------
+    A new organization key, returned encrypted with the master key
+    ----------------------------------------------------------------*/
+    pub async fn generate_org_key(&self) -> std::result::Result<String, SealError> {
+        let master_key = master_key()?;
+        let mut key = SecretBytes::new(vec![0u8; 32]);
+        OsRng.fill_bytes(&mut key);
+        let encoded = SecretString::new(BASE64_STANDARD.encode(&key));
+        let wrapped =
+            tokio::task::spawn_blocking(move || encrypt(encoded.as_bytes(), master_key.as_bytes()))
+                .await
+                .map_err(|e| SealError::Internal(e.to_string()))?
+                .map_err(|e| SealError::Internal(e.to_string()))?;
+        Ok(BASE64_STANDARD.encode(wrapped))
+    }
+
+    /*---------------------------------------------------------------
+    The key of `org` re-wrapped with `params`, None if it already
+    uses them
+    ----------------------------------------------------------------*/
+    pub fn rewrap_org_key(
+        &self,
+        org: &OrganizationDocument,
+        params: EnvelopeParams,
+    ) -> std::result::Result<Option<String>, SealError> {
+        let master_key = master_key()?;
+        let rewrap_error = |e: String| {
+            SealError::Internal(format!("Failed to re-wrap the key of {}: {e}", org.name))
+        };
+        let wrapped = BASE64_STANDARD
+            .decode(&org.encryption_key)
+            .map_err(|e| rewrap_error(e.to_string()))?;
+        let rewrapped = reencrypt(&wrapped, master_key.as_bytes(), params)
+            .map_err(|e| rewrap_error(e.to_string()))?;
+        Ok(rewrapped.map(|rewrapped| BASE64_STANDARD.encode(rewrapped)))
+    }
+
+    /*---------------------------------------------------------------
+    The vault of one organization. There is no other way to reach
+    vault entries, so every query is confined to the organization.
+    ----------------------------------------------------------------*/
+    pub async fn org_vault(
+        &self,
+        org: &OrganizationDocument,
+    ) -> std::result::Result<OrgVault<'_>, SealError> {
+        // Checked first so cached keys are never used while sealed
+        let master_key = master_key()?;
+        let encryption_key = match self.cached_org_key(org).await {
+            Some(key) => key,
+            None => self.unwrap_org_key(org, master_key).await?,
+        };
+        Ok(OrgVault {
+            collection: &self.collection,
+            blobs: &self.blobs,
+            org_id: org.id,
             encryption_key,
+        })
+    }
+
+    async fn cached_org_key(&self, org: &OrganizationDocument) -> Option<SecretString> {
+        self.org_keys
+            .read()
+            .await
+            .get(&org.id)
+            .filter(|cached| cached.matches(org))
+            .map(|cached| cached.key.clone())
+    }
+
+    async fn unwrap_org_key(
+        &self,
+        org: &OrganizationDocument,
+        master_key: SecretString,
+    ) -> std::result::Result<SecretString, SealError> {
+        let unwrap_error = |e: String| {
+            SealError::Internal(format!("Failed to unwrap the key of {}: {e}", org.name))
+        };
+        let wrapped = BASE64_STANDARD
+            .decode(&org.encryption_key)
+            .map_err(|e| unwrap_error(e.to_string()))?;
+        let key = tokio::task::spawn_blocking(move || decrypt(&wrapped, master_key.as_bytes()))
+            .await
+            .map_err(|e| unwrap_error(e.to_string()))?
+            .map_err(|e| unwrap_error(e.to_string()))?
+            .into_string()
+            .map_err(|e| unwrap_error(e.to_string()))?;
+        self.org_keys.write().await.insert(
+            org.id,
+            CachedOrgKey {
+                wrapped: org.encryption_key.clone(),
+                key: key.clone(),
+                loaded_at: Instant::now(),
+            },
+        );
+        Ok(key)
+    }
+
+    /*--------------------------------------------------------------
+    MOVE entries stored before organizations existed into `org`,
+    re-encrypting them with its key and binding them to their new
+    identity. Returns the number moved.
+    ---------------------------------------------------------------*/
+    pub async fn adopt_legacy_secrets(&self, org: &OrgVault<'_>) -> Result<u64> {
+        let master_key = master_key().map_err(|e| vault_error(e.to_string()))?;
+        let legacy: Vec<VaultDocument> = self
+            .collection
+            .find(doc! { "org_id": { "$exists": false } })
+            .await?
+            .try_collect()
+            .await?;
+        let mut adopted = 0;
+        for secret in legacy {
+            let encoded_value = BASE64_STANDARD
+                .decode(&secret.value)
+                .map_err(|e| vault_error(format!("Failed to decode entry {}: {e}", secret.id)))?;
+            let value = decrypt(&encoded_value, master_key.as_bytes())
+                .map_err(|e| vault_error(format!("Failed to decrypt entry {}: {e}", secret.id)))?;
+            let update = doc! { "$set": {
+                "value": org.seal(&secret.id, &secret.key, &secret.created_by, value).await?,
+                "org_id": org.org_id,
+            } };
+            let filter = doc! { "_id": secret.id, "org_id": { "$exists": false } };
+            adopted += self
+                .collection
+                .update_one(filter, update)
+                .await?
+                .modified_count;
+        }
+        Ok(adopted)
+    }
+
+    /*-----------------------------------------------
+    Whether any entry is stored, in an organization
+    or from before organizations existed
+    ------------------------------------------------*/
+    pub async fn has_entries(&self) -> Result<bool> {
+        Ok(self.collection.find_one(doc! {}).await?.is_some())
+    }
+
+    /*------------------------------------------
+    DELETE every entry of a deleted organization
+    -------------------------------------------*/
+    pub async fn remove_org(&self, org_id: &ObjectId) -> Result<u64> {
+        let blob_ids: Vec<ObjectId> = self
+            .collection
+            .find(doc! { "org_id": org_id, "file.blob_id": { "$exists": true } })
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?
+            .into_iter()
+            .filter_map(|entry| entry.file.and_then(|file| file.blob_id))
+            .collect();
+        for blob_id in blob_ids {
+            self.blobs.delete(Bson::ObjectId(blob_id)).await?;
+        }
+        let result = self
+            .collection
+            .delete_many(doc! { "org_id": org_id })
+            .await?;
+        self.org_keys.write().await.remove(org_id);
+        Ok(result.deleted_count)
+    }
+
+    /*----------------------------------------
+    UNSHARE every entry with a deleted group
+    -----------------------------------------*/
+    pub async fn remove_group(&self, group: &str) -> Result<u64> {
+        let update = doc! { "$pull": { "groups": group } };
+        let result = self
+            .collection
+            .update_many(doc! { "groups": group }, update)
+            .await?;
+        Ok(result.modified_count)
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Entries of a single organization, encrypted with its key. Every filter
+    goes through `scoped`, which pins the organization id.
+---------------------------------------------------------------------------*/
+pub struct OrgVault<'a> {
+    collection: &'a Collection<VaultDocument>,
+    blobs: &'a GridFsBucket,
+    org_id: ObjectId,
+    encryption_key: SecretString,
+}
+
+impl fmt::Debug for OrgVault<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrgVault")
+            .field("org_id", &self.org_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OrgVault<'_> {
+    pub fn org_id(&self) -> &ObjectId {
+        &self.org_id
+    }
+
+    fn scoped(&self, mut filter: Document) -> Document {
+        filter.insert("org_id", self.org_id);
+        filter
+    }
+
+    fn context(&self, entry: &VaultDocument) -> Vec<u8> {
+        entry_context(&entry.id, &self.org_id, &entry.key, &entry.created_by)
+    }
+
+    fn blob_key(&self, id: &ObjectId, key: &str, created_by: &str) -> SecretBytes {
+        let context = entry_context(id, &self.org_id, key, created_by);
+        blob_key_material(self.encryption_key.as_bytes(), &context)
+    }
+
+    /// Decrypts a GridFS blob off the executor: a stream, or an envelope
+    /// like inline values for blobs stored by earlier releases.
+    async fn open_blob(
+        &self,
+        entry: &VaultDocument,
+        encrypted: Vec<u8>,
+    ) -> std::result::Result<SecretBytes, RevealError> {
+        if !stream::is_stream(&encrypted) {
+            return self.open(entry, encrypted).await;
+        }
+        let material = self.blob_key(&entry.id, &entry.key, &entry.created_by);
+        blocking(move || open_blob_stream(&material, &encrypted))
+            .await?
+            .map_err(|source| RevealError::Integrity {
+                id: entry.id,
+                source,
+            })
+    }
+
+    /// Encrypts a value off the executor, the key derivation is Argon2.
+    async fn seal(
+        &self,
+        id: &ObjectId,
+        key: &str,
+        created_by: &str,
+        value: SecretBytes,
+    ) -> Result<String> {
+        let context = entry_context(id, &self.org_id, key, created_by);
+        let encryption_key = self.encryption_key.clone();
+        let encrypted_value =
+            blocking(move || encrypt_bound(&value, encryption_key.as_bytes(), &context))
+                .await?
+                .map_err(|e| vault_error(format!("Failed to encrypt vault entry: {e}")))?;
+        Ok(BASE64_STANDARD.encode(encrypted_value)) // Use base64 for safe string storage
+    }
+
+    /// Decrypts a value off the executor, like [Self::seal].
+    async fn open(
+        &self,
+        entry: &VaultDocument,
+        encrypted: Vec<u8>,
+    ) -> std::result::Result<SecretBytes, RevealError> {
+        if !is_bound(&encrypted) && !binding_required() {
+            warn!(
+                "Vault entry {} isn't bound to its entry yet, run `admin re-encrypt` \
+                 and set ECS_VAULT_REQUIRE_BINDING=true",
+                entry.id
+            );
         }
+        let context = self.context(entry);
+        let encryption_key = self.encryption_key.clone();
+        blocking(move || decrypt_bound(&encrypted, encryption_key.as_bytes(), &context))
+            .await?
+            .map_err(|source| RevealError::Integrity {
+                id: entry.id,
+                source,
+            })
     }
 
     /*-----------------
@@ -49,89 +490,575 @@ impl VaultRepository {
         value: &str,
         created_by: &str,
     ) -> Result<VaultDocument> {
-        let encrypted_value = encrypt(value.as_bytes(), self.encryption_key.as_bytes()).unwrap();
+        self.insert_text(key, value, created_by, false).await
+    }
+
+    /*--------------------------------------------------------------
+    CREATE a secret sealed by the client (`utils::vault::e2e`). The
+    base64 sealed value is encrypted again like any other value.
+    ---------------------------------------------------------------*/
+    pub async fn create_sealed_secret(
+        &self,
+        key: &str,
+        sealed: &str,
+        created_by: &str,
+    ) -> Result<VaultDocument> {
+        self.insert_text(key, sealed, created_by, true).await
+    }
 
+    async fn insert_text(
+        &self,
+        key: &str,
+        value: &str,
+        created_by: &str,
+        end_to_end: bool,
+    ) -> Result<VaultDocument> {
+        let id = ObjectId::new();
         let secret = VaultDocument {
-            id: ObjectId::new(),
+            id,
             key: key.to_string(),
-            value: general_purpose::STANDARD.encode(encrypted_value), // Use base64 for safe string storage
+            value: self
+                .seal(
+                    &id,
+                    key,
+                    created_by,
+                    SecretBytes::new(value.as_bytes().to_vec()),
+                )
+                .await?
+                .into(),
             created_by: created_by.to_string(),
             created_at: Utc::now(),
+            groups: Vec::new(),
+            org_id: Some(self.org_id),
+            file: None,
+            end_to_end,
         };
 
         self.collection.insert_one(&secret).await?;
         Ok(secret)
     }
 
+    /*--------------------------------------------------------------
+    REPLACE the sealed value of an end-to-end encrypted secret, e.g.
+    with recipients added. False if the entry changed since `entry`
+    was read.
+    ---------------------------------------------------------------*/
+    pub async fn replace_sealed(&self, entry: &VaultDocument, sealed: &str) -> Result<bool> {
+        let sealed = SecretBytes::new(sealed.as_bytes().to_vec());
+        let value = self
+            .seal(&entry.id, &entry.key, &entry.created_by, sealed)
+            .await?;
+        let filter = self.scoped(doc! {
+            "_id": entry.id,
+            "end_to_end": true,
+            "value": entry.value.as_str(),
+        });
+        let update = doc! { "$set": { "value": value } };
+        Ok(self
+            .collection
+            .update_one(filter, update)
+            .await?
+            .modified_count
+            > 0)
+    }
+
+    /*--------------------------------------------------------------
+    CREATE a binary secret from bytes in memory, see
+    [Self::create_file_from].
+    ---------------------------------------------------------------*/
+    pub async fn create_file(
+        &self,
+        key: &str,
+        file: &SecretFile,
+        created_by: &str,
+        limit: u64,
+    ) -> std::result::Result<VaultDocument, UploadError> {
+        let (file_name, content_type) = (file.file_name.clone(), file.content_type.clone());
+        let mut data = file.data.as_slice();
+        self.create_file_from(key, file_name, content_type, &mut data, created_by, limit)
+            .await
+    }
+
+    /*--------------------------------------------------------------
+    CREATE a binary secret from `reader`, of at most `limit` bytes.
+    Values up to INLINE_VALUE_LIMIT are kept in the entry. Larger
+    ones are encrypted chunk by chunk (`utils::vault::stream`)
+    straight into a GridFS upload, so they're never held in memory
+    whole.
+    ---------------------------------------------------------------*/
+    pub async fn create_file_from<R: AsyncRead + Unpin>(
+        &self,
+        key: &str,
+        file_name: Option<String>,
+        content_type: String,
+        reader: &mut R,
+        created_by: &str,
+        limit: u64,
+    ) -> std::result::Result<VaultDocument, UploadError> {
+        let id = ObjectId::new();
+        let mut head = SecretBytes::default();
+        (&mut *reader)
+            .take(INLINE_VALUE_LIMIT as u64 + 1)
+            .read_to_end(&mut head)
+            .await
+            .map_err(UploadError::Read)?;
+        if head.len() as u64 > limit {
+            return Err(UploadError::TooLarge { limit });
+        }
+
+        let (value, blob_id, size) = if head.len() > INLINE_VALUE_LIMIT {
+            let blob_id = ObjectId::new();
+            let mut upload = self
+                .blobs
+                .open_upload_stream(key)
+                .id(Bson::ObjectId(blob_id))
+                .await?;
+            let material = self.blob_key(&id, key, created_by);
+            let size = match encrypt_into(&mut upload, material, &head, reader, limit).await {
+                Ok(size) => size,
+                Err(e) => {
+                    upload.abort().await.ok();
+                    return Err(e);
+                }
+            };
+            upload.close().await.map_err(Error::from)?;
+            (String::new(), Some(blob_id), size)
+        } else {
+            let size = head.len() as u64;
+            (self.seal(&id, key, created_by, head).await?, None, size)
+        };
+        let secret = VaultDocument {
+            id,
+            key: key.to_string(),
+            value: value.into(),
+            created_by: created_by.to_string(),
+            created_at: Utc::now(),
+            groups: Vec::new(),
+            org_id: Some(self.org_id),
+            file: Some(FileMetadata {
+                file_name,
+                content_type,
+                size,
+                blob_id,
+            }),
+            end_to_end: false,
+        };
+
+        if let Err(e) = self.collection.insert_one(&secret).await {
+            if let Some(blob_id) = blob_id {
+                self.blobs.delete(Bson::ObjectId(blob_id)).await.ok();
+            }
+            return Err(e.into());
+        }
+        Ok(secret)
+    }
+
+    /*------------------------------------------------------------------
+    The lookups below return entries still encrypted, callers go through
+    the policy engine (`utils::policy::AuthorizedVault`) and reveal the
+    values they are allowed to read.
+    -------------------------------------------------------------------*/
+
     /*---------------
-    GET secret by id
+    FIND secret by id
     ---------------*/
-    pub async fn get_secret_by_id(&self, id: &str, subject: &str) -> Result<Option<String>> {
-        let object_id = ObjectId::parse_str(id).unwrap();
-        let filter = doc! { "_id": object_id, "created_by": subject };
+    pub async fn find_by_id(&self, id: &ObjectId) -> Result<Option<VaultDocument>> {
+        self.collection
+            .find_one(self.scoped(doc! { "_id": id }))
+            .await
+    }
+
+    /*------------------
+    FIND secrets by key
+    ------------------*/
+    pub async fn find_by_key(&self, key: &str) -> Result<Vec<VaultDocument>> {
+        self.collection
+            .find(self.scoped(doc! { "key": key }))
+            .await?
+            .try_collect()
+            .await
+    }
 
-        if let Some(secret) = self.collection.find_one(filter).await? {
-            let encoded_value = BASE64_STANDARD.decode(&secret.value).unwrap();
-            let decrypted_value = decrypt(&encoded_value, &self.encryption_key.as_bytes()).unwrap();
-            return Ok(Some(String::from_utf8_lossy(&decrypted_value).to_string()));
+    /*---------------------
+    FIND secrets by author
+    ---------------------*/
+    pub async fn find_by_author(&self, created_by: &str) -> Result<Vec<VaultDocument>> {
+        self.collection
+            .find(self.scoped(doc! { "created_by": created_by }))
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*------------------------------------------------------------
+    FIND the secrets a caller may reach: its own, those shared
+    with its groups and those whose key matches `key_patterns`
+    (anchored regular expressions)
+    -------------------------------------------------------------*/
+    pub async fn find_candidates(
+        &self,
+        subject: &str,
+        groups: &[String],
+        key_patterns: &[String],
+    ) -> Result<Vec<VaultDocument>> {
+        let mut filters = vec![doc! { "created_by": subject }];
+        if !groups.is_empty() {
+            filters.push(doc! { "groups": { "$in": groups } });
         }
-        Ok(None)
+        for pattern in key_patterns {
+            filters.push(doc! { "key": { "$regex": pattern } });
+        }
+        self.collection
+            .find(self.scoped(doc! { "$or": filters }))
+            .await?
+            .try_collect()
+            .await
     }
 
-    /*-----------------
-    GET secret by author
-    -------------------*/
-    pub async fn get_secret_by_author(&self, created_by: &str) -> Result<Vec<VaultDocument>> {
-        let filter = doc! { "created_by": created_by };
-        let mut cursor = self.collection.find(filter).await?;
-        let mut secrets = Vec::new();
-
-        while let Some(mut secret) = cursor.try_next().await? {
-            if let Ok(encoded_value) = BASE64_STANDARD.decode(&secret.value) {
-                if let Ok(decrypted_value) = decrypt(&encoded_value, self.encryption_key.as_bytes())
-                {
-                    secret.value = String::from_utf8_lossy(&decrypted_value).to_string();
+    /// Decrypts the value of a stored secret, None for entries of another
+    /// organization. Binary values come back base64 encoded, except those
+    /// in GridFS, which only [Self::reveal_bytes] reads and which are None
+    /// here.
+    pub async fn reveal(
+        &self,
+        secret: &VaultDocument,
+    ) -> std::result::Result<Option<SecretString>, RevealError> {
+        if secret.org_id != Some(self.org_id) || Self::in_gridfs(secret) {
+            return Ok(None);
+        }
+        let decrypted_value = self.open(secret, self.decode_value(secret)?).await?;
+        Ok(Some(match &secret.file {
+            Some(_) => BASE64_STANDARD.encode(&decrypted_value).into(),
+            None => String::from_utf8_lossy(&decrypted_value)
+                .into_owned()
+                .into(),
+        }))
+    }
+
+    /// The decrypted bytes of a secret, wherever they are stored.
+    pub async fn reveal_bytes(
+        &self,
+        secret: &VaultDocument,
+    ) -> std::result::Result<Option<SecretBytes>, RevealError> {
+        if secret.org_id != Some(self.org_id) {
+            return Ok(None);
+        }
+        match secret.file.as_ref().and_then(|file| file.blob_id) {
+            Some(blob_id) => {
+                let encrypted = self.read_blob(blob_id).await?;
+                self.open_blob(secret, encrypted).await.map(Some)
+            }
+            None => self
+                .open(secret, self.decode_value(secret)?)
+                .await
+                .map(Some),
+        }
+    }
+
+    fn in_gridfs(secret: &VaultDocument) -> bool {
+        secret
+            .file
+            .as_ref()
+            .is_some_and(|file| file.blob_id.is_some())
+    }
+
+    fn decode_value(&self, secret: &VaultDocument) -> std::result::Result<Vec<u8>, RevealError> {
+        BASE64_STANDARD
+            .decode(&secret.value)
+            .map_err(|_| RevealError::Integrity {
+                id: secret.id,
+                source: DecryptError::Envelope("not base64"),
+            })
+    }
+
+    /// The secret with its value decrypted.
+    pub async fn decrypted(
+        &self,
+        mut secret: VaultDocument,
+    ) -> std::result::Result<VaultDocument, RevealError> {
+        if let Some(value) = self.reveal(&secret).await? {
+            secret.value = value;
+        }
+        Ok(secret)
+    }
+
+    /*---------------------------------------------------------------
+    RE-ENCRYPT every entry with `params` and bind it to its entry,
+    inline values and GridFS blobs alike. Blobs are streams, always
+    ChaCha20-Poly1305, so only their key derivation follows `params`;
+    envelope blobs of earlier releases become streams. Entries that
+    can't be decrypted are left as they are. Returns the number
+    re-encrypted and the number that failed.
+    ----------------------------------------------------------------*/
+    pub async fn reencrypt_entries(&self, params: EnvelopeParams) -> Result<(u64, u64)> {
+        let entries: Vec<VaultDocument> = self
+            .collection
+            .find(self.scoped(doc! {}))
+            .await?
+            .try_collect()
+            .await?;
+        let (mut reencrypted, mut failed) = (0, 0);
+        for entry in entries {
+            let blob_id = entry.file.as_ref().and_then(|file| file.blob_id);
+            let updated = match blob_id {
+                Some(blob_id) => {
+                    let encrypted = self.read_blob(blob_id).await?;
+                    self.restream_blob(&entry, encrypted, params.kdf).await
                 }
+                None => match BASE64_STANDARD.decode(&entry.value) {
+                    Ok(encrypted) => reencrypt_bound(
+                        &encrypted,
+                        self.encryption_key.as_bytes(),
+                        params,
+                        &self.context(&entry),
+                    )
+                    .map_err(|e| e.to_string()),
+                    Err(_) => Err("not base64".to_string()),
+                },
+            };
+            let updated = match updated {
+                Ok(Some(updated)) => updated,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Failed to re-encrypt vault entry {}: {e}", entry.id);
+                    failed += 1;
+                    continue;
+                }
+            };
+            let modified = match blob_id {
+                Some(blob_id) => self.replace_blob(&entry, blob_id, &updated).await?,
+                None => {
+                    let filter =
+                        self.scoped(doc! { "_id": entry.id, "value": entry.value.as_str() });
+                    let update = doc! { "$set": { "value": BASE64_STANDARD.encode(updated) } };
+                    self.collection
+                        .update_one(filter, update)
+                        .await?
+                        .modified_count
+                        > 0
+                }
+            };
+            if modified {
+                reencrypted += 1;
             }
-            secrets.push(secret);
         }
+        Ok((reencrypted, failed))
+    }
+
+    /// The blob encrypted again as a stream with `kdf`, None if it
+    /// already is one.
+    async fn restream_blob(
+        &self,
+        entry: &VaultDocument,
+        encrypted: Vec<u8>,
+        kdf: KdfParams,
+    ) -> std::result::Result<Option<Vec<u8>>, String> {
+        let current = encrypted
+            .get(..stream::HEADER_LEN)
+            .and_then(|header| StreamHeader::parse(header.try_into().ok()?).ok());
+        if current.is_some_and(|header| header.kdf == kdf) {
+            return Ok(None);
+        }
+        let plaintext = self
+            .open_blob(entry, encrypted)
+            .await
+            .map_err(|e| e.to_string())?;
+        let material = self.blob_key(&entry.id, &entry.key, &entry.created_by);
+        blocking(move || {
+            let mut writer =
+                EncryptWriter::with_params(Vec::new(), &material, kdf, stream::DEFAULT_CHUNK_SIZE)
+                    .map_err(|e| e.to_string())?;
+            writer.write_all(&plaintext).map_err(|e| e.to_string())?;
+            writer.finish().map(Some).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    async fn read_blob(&self, blob_id: ObjectId) -> Result<Vec<u8>> {
+        let mut download = self
+            .blobs
+            .open_download_stream(Bson::ObjectId(blob_id))
+            .await?;
+        let mut encrypted = Vec::new();
+        download.read_to_end(&mut encrypted).await?;
+        Ok(encrypted)
+    }
 
-        Ok(secrets)
+    /// Uploads `encrypted` as a new blob and points the entry at it, unless
+    /// the entry changed meanwhile. The blob no longer referenced is deleted.
+    async fn replace_blob(
+        &self,
+        entry: &VaultDocument,
+        blob_id: ObjectId,
+        encrypted: &[u8],
+    ) -> Result<bool> {
+        let new_blob_id = ObjectId::new();
+        let mut upload = self
+            .blobs
+            .open_upload_stream(&entry.key)
+            .id(Bson::ObjectId(new_blob_id))
+            .await?;
+        upload.write_all(encrypted).await?;
+        upload.close().await?;
+        let filter = self.scoped(doc! { "_id": entry.id, "file.blob_id": blob_id });
+        let update = doc! { "$set": { "file.blob_id": new_blob_id } };
+        let modified = match self.collection.update_one(filter, update).await {
+            Ok(result) => result.modified_count > 0,
+            Err(e) => {
+                self.blobs.delete(Bson::ObjectId(new_blob_id)).await.ok();
+                return Err(e);
+            }
+        };
+        let unused = if modified { blob_id } else { new_blob_id };
+        self.blobs.delete(Bson::ObjectId(unused)).await?;
+        Ok(modified)
     }
 
     /*-------------
     DELETE a secret
     ---------------*/
-    pub async fn delete_secret(&self, id: &str, subject: &str) -> Result<Option<String>> {
-        let object_id = ObjectId::parse_str(id).unwrap();
-        let filter = doc! { "_id": object_id, "created_by": subject };
+    pub async fn delete_by_id(&self, id: &ObjectId) -> Result<Option<VaultDocument>> {
+        let deleted = self
+            .collection
+            .find_one_and_delete(self.scoped(doc! { "_id": id }))
+            .await?;
+        if let Some(blob_id) = deleted
+            .as_ref()
+            .and_then(|entry| entry.file.as_ref())
+            .and_then(|file| file.blob_id)
+        {
+            self.blobs.delete(Bson::ObjectId(blob_id)).await?;
+        }
+        Ok(deleted)
+    }
+
+    /*--------------------------------------------------------
+    SHARE the author's entries under `key` with a group,
+    returns the number of entries that were matched
+    ---------------------------------------------------------*/
+    pub async fn share_secret(&self, key: &str, created_by: &str, group: &str) -> Result<u64> {
+        let filter = self.scoped(doc! { "key": key, "created_by": created_by });
+        let update = doc! { "$addToSet": { "groups": group } };
+        let result = self.collection.update_many(filter, update).await?;
+        Ok(result.matched_count)
+    }
+
+    /*------------------------------------------
+    UNSHARE the author's entries under `key`
+    -------------------------------------------*/
+    pub async fn unshare_secret(&self, key: &str, created_by: &str, group: &str) -> Result<u64> {
+        let filter = self.scoped(doc! { "key": key, "created_by": created_by, "groups": group });
+        let update = doc! { "$pull": { "groups": group } };
+        let result = self.collection.update_many(filter, update).await?;
+        Ok(result.modified_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if let Some(secret) = self.collection.find_one_and_delete(filter).await? {
-            let encoded_value = BASE64_STANDARD.decode(&secret.value).unwrap();
-            let decrypted_value = decrypt(&encoded_value, &self.encryption_key.as_bytes()).unwrap();
-            return Ok(Some(String::from_utf8_lossy(&decrypted_value).to_string()));
+    fn entry(org_id: ObjectId, value: String, blob_id: Option<ObjectId>) -> VaultDocument {
+        VaultDocument {
+            id: ObjectId::new(),
+            key: "files/backup".into(),
+            value: value.into(),
+            created_by: "user@domain.com".into(),
+            created_at: Utc::now(),
+            groups: Vec::new(),
+            org_id: Some(org_id),
+            file: Some(FileMetadata {
+                file_name: Some("backup.tar".into()),
+                content_type: "application/octet-stream".into(),
+                size: 300 * 1024,
+                blob_id,
+            }),
+            end_to_end: false,
         }
+    }
+
+    #[test]
+    fn large_files_stay_out_of_lists() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        // Never connects, reveal doesn't touch the database
+        let client = runtime
+            .block_on(Client::with_uri_str("mongodb://127.0.0.1:9"))
+            .unwrap();
+        let database = client.database("test");
+        let (collection, blobs) = (database.collection("vault"), database.gridfs_bucket(None));
+        let vault = OrgVault {
+            collection: &collection,
+            blobs: &blobs,
+            org_id: ObjectId::new(),
+            encryption_key: "organization key".into(),
+        };
+
+        runtime.block_on(async {
+            let data = vec![7u8; 300 * 1024];
+            let mut inline = entry(vault.org_id, String::new(), None);
+            inline.value = vault
+                .seal(
+                    &inline.id,
+                    &inline.key,
+                    &inline.created_by,
+                    SecretBytes::new(data.clone()),
+                )
+                .await
+                .unwrap()
+                .into();
+            let revealed = vault.reveal(&inline).await.unwrap().unwrap();
+            assert_eq!(*revealed, BASE64_STANDARD.encode(&data));
 
-        Ok(None)
+            // Above the inline limit create_file leaves the value empty and uses a blob
+            let large = entry(vault.org_id, String::new(), Some(ObjectId::new()));
+            assert!(vault.reveal(&large).await.unwrap().is_none());
+            let listed = [
+                vault.decrypted(large.clone()).await.unwrap(),
+                vault.decrypted(inline).await.unwrap(),
+            ];
+            assert!(listed[0].value.is_empty());
+        });
     }
 
-    /*-------------
-    LIST all secrets
-    ---------------*/
-    pub async fn list_secrets(&self, subject: &str) -> Result<Vec<VaultDocument>> {
-        let mut cursor = self.collection.find(doc! {"created_by": subject}).await?;
-        let mut secrets = Vec::new();
-
-        while let Some(mut secret) = cursor.try_next().await? {
-            if let Ok(encoded_value) = BASE64_STANDARD.decode(&secret.value) {
-                if let Ok(decrypted_value) = decrypt(&encoded_value, self.encryption_key.as_bytes())
-                {
-                    secret.value = String::from_utf8_lossy(&decrypted_value).to_string();
-                }
-            }
-            secrets.push(secret);
+    #[test]
+    fn cached_org_keys_expire_and_follow_rewraps() {
+        let org = OrganizationDocument {
+            id: ObjectId::new(),
+            name: "payments".into(),
+            description: None,
+            encryption_key: "wrapped".into(),
+            created_by: "admin@example.com".into(),
+            created_at: Utc::now(),
+        };
+        let cached = |wrapped: &str, loaded_at| CachedOrgKey {
+            wrapped: wrapped.into(),
+            key: "organization key".into(),
+            loaded_at,
+        };
+        assert!(cached("wrapped", Instant::now()).matches(&org));
+        assert!(!cached("rewrapped", Instant::now()).matches(&org));
+        if let Some(stale) = Instant::now().checked_sub(ORG_KEY_CACHE_TTL) {
+            assert!(!cached("wrapped", stale).matches(&org));
         }
+    }
+
+    #[test]
+    fn blobs_are_bound_to_their_entry() {
+        let (org_id, id) = (ObjectId::new(), ObjectId::new());
+        let context = entry_context(&id, &org_id, "files/backup", "user@domain.com");
+        let material = blob_key_material(b"organization key", &context);
+        let data = vec![7u8; 3 * DEFAULT_CHUNK_SIZE as usize + 1];
+        let mut writer = EncryptWriter::new(Vec::new(), &material).unwrap();
+        writer.write_all(&data).unwrap();
+        let encrypted = writer.finish().unwrap();
 
-        Ok(secrets)
+        assert_eq!(*open_blob_stream(&material, &encrypted).unwrap(), data);
+        let moved = entry_context(&ObjectId::new(), &org_id, "files/backup", "user@domain.com");
+        let result = open_blob_stream(&blob_key_material(b"organization key", &moved), &encrypted);
+        assert!(matches!(result, Err(DecryptError::Integrity)));
     }
 }
@@ -0,0 +1,344 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use chrono::{Duration, Utc};
+use mongodb::bson::oid::ObjectId;
+use thiserror::Error;
+
+use crate::{
+    models::{Invitation, InvitationDocument, UserDocument, UserRole, UserTokenPurpose},
+    repositories::{
+        invitations::InvitationRepository, policies::PolicyRepository,
+        user_tokens::UserTokenRepository, users::UserRepository,
+    },
+    utils::{
+        auth::hash_token,
+        mailer::{Email, Mailer},
+        password::{hash_password_async, validate_password, verify_password_async},
+    },
+};
+
+/// Lifetime of mailed password reset tokens.
+pub const PASSWORD_RESET_MINUTES: i64 = 30;
+/// Lifetime of mailed email verification tokens.
+pub const EMAIL_VERIFICATION_HOURS: i64 = 24;
+/// Default and maximum lifetime of invitations.
+pub const INVITATION_HOURS: i64 = 72;
+pub const MAX_INVITATION_HOURS: i64 = 30 * 24;
+
+#[derive(Error, Debug)]
+pub enum AccountError {
+    #[error("Invalid current password")]
+    InvalidPassword,
+    #[error("Invalid or expired token")]
+    InvalidToken,
+    #[error("{0}")]
+    Policy(String),
+    #[error("A user with this email already exists")]
+    EmailTaken,
+    #[error("Self-registration is disabled, ask an admin for an invitation")]
+    RegistrationClosed,
+    #[error("An admin account already exists")]
+    AdminExists,
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<mongodb::error::Error> for AccountError {
+    fn from(error: mongodb::error::Error) -> Self {
+        Self::Internal(error.to_string())
+    }
+}
+
+fn generate_token() -> String {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    URL_SAFE_NO_PAD.encode(token)
+}
+
+/// Hashes a new password after checking it against the password policy.
+async fn new_password_hash(password: &str) -> Result<String, AccountError> {
+    validate_password(password).map_err(AccountError::Policy)?;
+    hash_password_async(password)
+        .await
+        .map_err(AccountError::Internal)
+}
+
+/*---------------------------------------------------------------------------
+    Registration mode, set with ECS_REGISTRATION:
+
+    open    anyone can create an account through /setup (default)
+    closed  accounts are created through invitations only, the first
+            admin is bootstrapped from the CLI
+---------------------------------------------------------------------------*/
+pub fn registration_open() -> bool {
+    !std::env::var("ECS_REGISTRATION").is_ok_and(|mode| mode.eq_ignore_ascii_case("closed"))
+}
+
+/// Creates the first admin account, refused once any admin exists.
+pub async fn bootstrap_admin(
+    email: &str,
+    password: &str,
+    user_repo: &UserRepository,
+) -> Result<UserDocument, AccountError> {
+    if user_repo.count_admins().await? > 0 {
+        return Err(AccountError::AdminExists);
+    }
+    if user_repo.get_user_by_email(email).await?.is_some() {
+        return Err(AccountError::EmailTaken);
+    }
+    let password_hash = new_password_hash(password).await?;
+    Ok(user_repo.create_user(email, &password_hash, true).await?)
+}
+
+/*---------------------------------------------------------------------------
+    Invite an address to join with a preassigned role. The token is mailed
+    to the address and returned once, only its hash is stored.
+---------------------------------------------------------------------------*/
+pub async fn invite_user(
+    invitation: &Invitation,
+    invited_by: &str,
+    user_repo: &UserRepository,
+    invitation_repo: &InvitationRepository,
+    mailer: &dyn Mailer,
+) -> Result<(InvitationDocument, String), AccountError> {
+    let hours = invitation.expires_in_hours.unwrap_or(INVITATION_HOURS);
+    if !(1..=MAX_INVITATION_HOURS).contains(&hours) {
+        return Err(AccountError::Policy(format!(
+            "Invitations expire after 1 to {MAX_INVITATION_HOURS} hours"
+        )));
+    }
+    if user_repo
+        .get_user_by_email(&invitation.email)
+        .await?
+        .is_some()
+    {
+        return Err(AccountError::EmailTaken);
+    }
+
+    let token = generate_token();
+    let now = Utc::now();
+    let document = InvitationDocument {
+        id: ObjectId::new(),
+        email: invitation.email.clone(),
+        role: invitation.role,
+        token_hash: hash_token(&token),
+        invited_by: invited_by.to_string(),
+        expires_at: now + Duration::hours(hours),
+        created_at: now,
+    };
+    invitation_repo.create_invitation(&document).await?;
+
+    let link = std::env::var("ECS_PUBLIC_URL")
+        .map(|url| {
+            format!(
+                "\n\nOr open {}/pages/register.html?invitation={token}",
+                url.trim_end_matches('/')
+            )
+        })
+        .unwrap_or_default();
+    mailer
+        .send(&Email {
+            to: document.email.clone(),
+            subject: "You have been invited to Lock Smith".into(),
+            body: format!(
+                "{invited_by} invited you to join as {}. Accept the invitation before \
+                 {} with this token:\n\n{token}{link}",
+                document.role.as_str(),
+                document.expires_at.to_rfc3339()
+            ),
+        })
+        .map_err(AccountError::Internal)?;
+
+    Ok((document, token))
+}
+
+/// Redeems an invitation, creating the account with the invited role.
+pub async fn accept_invitation(
+    token: &str,
+    password: &str,
+    user_repo: &UserRepository,
+    invitation_repo: &InvitationRepository,
+) -> Result<UserDocument, AccountError> {
+    // Checked first so a rejected password doesn't burn the invitation
+    let password_hash = new_password_hash(password).await?;
+    let invitation = invitation_repo
+        .consume_invitation(&hash_token(token))
+        .await?
+        .ok_or(AccountError::InvalidToken)?;
+
+    if user_repo
+        .get_user_by_email(&invitation.email)
+        .await?
+        .is_some()
+    {
+        return Err(AccountError::EmailTaken);
+    }
+    let mut user = user_repo
+        .create_user(
+            &invitation.email,
+            &password_hash,
+            invitation.role == UserRole::Admin,
+        )
+        .await?;
+    // The token could only be read from the invited mailbox
+    user_repo.set_email_verified(&user.id).await?;
+    user.email_verified = true;
+    Ok(user)
+}
+
+/*---------------------------------------------------------------------------
+    Change the password of a logged in user. Every token issued to the
+    user so far is revoked, as are outstanding reset tokens.
+---------------------------------------------------------------------------*/
+pub async fn change_password(
+    user: &UserDocument,
+    current_password: &str,
+    new_password: &str,
+    user_repo: &UserRepository,
+    token_repo: &UserTokenRepository,
+) -> Result<(), AccountError> {
+    if !verify_password_async(current_password, &user.password)
+        .await
+        .map_err(AccountError::Internal)?
+    {
+        return Err(AccountError::InvalidPassword);
+    }
+    let password_hash = new_password_hash(new_password).await?;
+    user_repo.set_password(&user.id, &password_hash).await?;
+    token_repo
+        .delete_tokens(&user.id, UserTokenPurpose::PasswordReset)
+        .await?;
+    Ok(())
+}
+
+/*---------------------------------------------------------------------------
+    Mail a password reset token. Unknown addresses are ignored silently so
+    the endpoint can't be used to find out which accounts exist.
+---------------------------------------------------------------------------*/
+pub async fn request_password_reset(
+    email: &str,
+    user_repo: &UserRepository,
+    token_repo: &UserTokenRepository,
+    mailer: &dyn Mailer,
+) -> Result<(), AccountError> {
+    let Some(user) = user_repo.get_user_by_email(email).await? else {
+        return Ok(());
+    };
+
+    let token = generate_token();
+    token_repo
+        .create_token(
+            &user.id,
+            UserTokenPurpose::PasswordReset,
+            &hash_token(&token),
+            Duration::minutes(PASSWORD_RESET_MINUTES),
+        )
+        .await?;
+    mailer
+        .send(&Email {
+            to: user.email,
+            subject: "Reset your password".into(),
+            body: format!(
+                "A password reset was requested for your account. Use this token \
+                 within {PASSWORD_RESET_MINUTES} minutes to choose a new password:\n\n\
+                 {token}\n\nIf you didn't request a reset you can ignore this email."
+            ),
+        })
+        .map_err(AccountError::Internal)
+}
+
+/// Redeems a reset token, sets the new password and revokes the user's tokens.
+pub async fn reset_password(
+    token: &str,
+    new_password: &str,
+    user_repo: &UserRepository,
+    token_repo: &UserTokenRepository,
+) -> Result<UserDocument, AccountError> {
+    // Checked first so a rejected password doesn't burn the token
+    let password_hash = new_password_hash(new_password).await?;
+    let reset = token_repo
+        .consume_token(UserTokenPurpose::PasswordReset, &hash_token(token))
+        .await?
+        .ok_or(AccountError::InvalidToken)?;
+    let user = user_repo
+        .get_user_by_id(&reset.user_id.to_hex())
+        .await?
+        .ok_or(AccountError::InvalidToken)?;
+    user_repo.set_password(&user.id, &password_hash).await?;
+    Ok(user)
+}
+
+/*---------------------------------------------------------------------------
+    Change the email address after confirming the current password. The
+    new address starts out unverified and gets a verification email.
+    Policies attached to the old address follow the user.
+---------------------------------------------------------------------------*/
+pub async fn change_email(
+    user: &UserDocument,
+    email: &str,
+    current_password: &str,
+    user_repo: &UserRepository,
+    token_repo: &UserTokenRepository,
+    policy_repo: &PolicyRepository,
+    mailer: &dyn Mailer,
+) -> Result<UserDocument, AccountError> {
+    if !verify_password_async(current_password, &user.password)
+        .await
+        .map_err(AccountError::Internal)?
+    {
+        return Err(AccountError::InvalidPassword);
+    }
+    if let Some(existing) = user_repo.get_user_by_email(email).await?
+        && existing.id != user.id
+    {
+        return Err(AccountError::EmailTaken);
+    }
+    let updated = user_repo
+        .change_email(&user.id, email)
+        .await?
+        .ok_or_else(|| AccountError::Internal("User not found".into()))?;
+    policy_repo.rename_user(&user.email, &updated.email).await?;
+    send_email_verification(&updated, token_repo, mailer).await?;
+    Ok(updated)
+}
+
+/// Mails a verification token to the user's current address.
+pub async fn send_email_verification(
+    user: &UserDocument,
+    token_repo: &UserTokenRepository,
+    mailer: &dyn Mailer,
+) -> Result<(), AccountError> {
+    let token = generate_token();
+    token_repo
+        .create_token(
+            &user.id,
+            UserTokenPurpose::EmailVerification,
+            &hash_token(&token),
+            Duration::hours(EMAIL_VERIFICATION_HOURS),
+        )
+        .await?;
+    mailer
+        .send(&Email {
+            to: user.email.clone(),
+            subject: "Verify your email address".into(),
+            body: format!(
+                "Confirm this email address with the following token, valid for \
+                 {EMAIL_VERIFICATION_HOURS} hours:\n\n{token}"
+            ),
+        })
+        .map_err(AccountError::Internal)
+}
+
+/// Redeems a verification token and marks the address as verified.
+pub async fn verify_email(
+    token: &str,
+    user_repo: &UserRepository,
+    token_repo: &UserTokenRepository,
+) -> Result<(), AccountError> {
+    let verification = token_repo
+        .consume_token(UserTokenPurpose::EmailVerification, &hash_token(token))
+        .await?
+        .ok_or(AccountError::InvalidToken)?;
+    user_repo.set_email_verified(&verification.user_id).await?;
+    Ok(())
+}
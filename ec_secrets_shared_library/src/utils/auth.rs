@@ -1,65 +1,288 @@
 use crate::{
-    models::{User, UserCredentials},
-    repositories::keys::KeyRepository,
+    models::{UserCredentials, UserDocument},
+    repositories::{
+        app_roles::AppRoleRepository, keys::KeyRepository,
+        service_accounts::ServiceAccountRepository, users::UserRepository,
+    },
+    utils::{
+        key_provider::{self, KeyName},
+        mfa::verify_second_factor,
+        password::{hash_password_async, needs_rehash, verify_password_async},
+    },
 };
-use base64::{Engine as _, engine::general_purpose::STANDARD};
-use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::{Duration, Utc};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use mongodb::bson::oid::ObjectId;
 use pasetors::{
-    claims::Claims,
-    keys::{AsymmetricPublicKey, AsymmetricSecretKey},
+    Public,
+    claims::{Claims, ClaimsValidationRules},
     public,
+    token::UntrustedToken,
     version4::V4,
 };
 use sha2::{Digest, Sha256};
 
-pub async fn decode_keys(
-    repo: &KeyRepository,
-) -> Result<(AsymmetricSecretKey<V4>, AsymmetricPublicKey<V4>), String> {
-    let kp = repo
-        .get_or_create_key_pair()
-        .await
-        .map_err(|e| e.to_string())?;
-    let decoded_private_key = STANDARD.decode(kp.private_key).map_err(|e| e.to_string())?;
-    let decoded_private_key = &decoded_private_key.as_slice();
-    let private_key =
-        AsymmetricSecretKey::<V4>::from(decoded_private_key).map_err(|e| e.to_string())?;
-    let decoded_public_key = STANDARD.decode(kp.public_key).map_err(|e| e.to_string())?;
-    let decoded_public_key = &decoded_public_key.as_slice();
-    let public_key =
-        AsymmetricPublicKey::<V4>::from(decoded_public_key).map_err(|e| e.to_string())?;
-    Ok((private_key, public_key))
-}
-
 /*---------------------------------------------
 Authorize the user via password verification.
+
+Accounts with MFA (or any account when MFA is
+required) only get a short lived, single purpose
+token here: a challenge to exchange for a
+session with a TOTP code, or an enrollment
+token when they still have to enroll.
 ----------------------------------------------*/
+pub const MFA_CLAIM: &str = "mfa";
+pub const MFA_CHALLENGE: &str = "challenge";
+pub const MFA_ENROLL: &str = "enroll";
+/// Claim binding user tokens to [UserDocument::token_version].
+pub const TOKEN_VERSION_CLAIM: &str = "token_version";
+/// Claim listing the groups of the user when the token was issued.
+pub const GROUPS_CLAIM: &str = "groups";
+/// Claim naming the organization (hex id) whose vault the token acts on.
+pub const ORG_CLAIM: &str = "org";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginOutcome {
+    Authenticated(String),
+    MfaRequired(String),
+    EnrollmentRequired(String),
+}
 
 pub async fn authorize_user(
-    user: &User,
+    user: &UserDocument,
     credentials: &UserCredentials,
+    require_mfa: bool,
+    user_repo: &UserRepository,
     repo: &KeyRepository,
-) -> Result<String, String> {
-    if !verify(&credentials.password, &user.password).map_err(|e| e.to_string())? {
+) -> Result<LoginOutcome, String> {
+    if !verify_password_async(&credentials.password, &user.password).await? {
         return Err("Invalid credentials".into());
     }
-    let mut claims = Claims::new().map_err(|e| e.to_string())?;
-    let ecs_authentication_key = std::env::var_os("ECS_AUTHENTICATION_KEY")
-        .expect("[ECS_AUTHENTICATION_KEY] must be set...")
-        .into_string()
-        .unwrap();
+    if needs_rehash(&user.password) {
+        upgrade_password_hash(user, &credentials.password, user_repo).await;
+    }
+
+    if user.mfa_enabled() {
+        let claims = pending_mfa_claims(user, MFA_CHALLENGE, Duration::minutes(5))?;
+        return Ok(LoginOutcome::MfaRequired(sign_claims(&claims, repo).await?));
+    }
+    if require_mfa {
+        let claims = pending_mfa_claims(user, MFA_ENROLL, Duration::minutes(15))?;
+        return Ok(LoginOutcome::EnrollmentRequired(
+            sign_claims(&claims, repo).await?,
+        ));
+    }
+    Ok(LoginOutcome::Authenticated(
+        issue_user_token(user, repo).await?,
+    ))
+}
+
+/// Replaces a bcrypt or outdated Argon2 hash once the password is known.
+/// Failures are only logged, the login itself already succeeded.
+async fn upgrade_password_hash(user: &UserDocument, password: &str, user_repo: &UserRepository) {
+    let upgraded = match hash_password_async(password).await {
+        Ok(hash) => user_repo
+            .update_user(&user.id.to_hex(), None, Some(&hash))
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = upgraded {
+        warn!("Failed to upgrade password hash: {e}");
+    }
+}
+
+/// Second login step, exchanges a challenge token and a TOTP or recovery
+/// code for a session token.
+pub async fn complete_mfa_login(
+    mfa_token: &str,
+    code: &str,
+    user_repo: &UserRepository,
+    key_repo: &KeyRepository,
+) -> Result<String, String> {
+    let claims = verify_token(mfa_token, key_repo).await?;
+    if pending_mfa(&claims) != Some(MFA_CHALLENGE) {
+        return Err("Invalid MFA token".into());
+    }
+    let user = token_user(&claims, user_repo).await?;
+    if token_version(&claims) != user.token_version {
+        return Err("Invalid MFA token".into());
+    }
+    if !verify_second_factor(user_repo, &user, code).await? {
+        return Err("Invalid MFA code".into());
+    }
+    issue_user_token(&user, key_repo).await
+}
+
+/// Full session token for a user, admins carry an `admin` claim. The
+/// user's first organization is the active one.
+pub async fn issue_user_token(user: &UserDocument, repo: &KeyRepository) -> Result<String, String> {
+    issue_org_token(user, user.orgs.first(), repo).await
+}
+
+/// Session token for a user acting on the vault of `org`. Membership is
+/// checked by the caller.
+pub async fn issue_org_token(
+    user: &UserDocument,
+    org: Option<&ObjectId>,
+    repo: &KeyRepository,
+) -> Result<String, String> {
+    let mut claims = user_claims(user, Utc::now() + Duration::hours(8))?;
+    if user.admin {
+        claims
+            .add_additional("admin", true)
+            .map_err(|e| e.to_string())?;
+    }
+    claims
+        .add_additional(GROUPS_CLAIM, user.groups.clone())
+        .map_err(|e| e.to_string())?;
+    add_org_claim(&mut claims, org)?;
+    sign_claims(&claims, repo).await
+}
+
+fn add_org_claim(claims: &mut Claims, org: Option<&ObjectId>) -> Result<(), String> {
+    match org {
+        Some(org) => claims
+            .add_additional(ORG_CLAIM, org.to_hex())
+            .map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// The active organization of a token, None when it has none.
+pub fn token_org(claims: &Claims) -> Option<ObjectId> {
+    claims
+        .get_claim(ORG_CLAIM)
+        .and_then(|org| org.as_str())
+        .and_then(|org| ObjectId::parse_str(org).ok())
+}
+
+/// Groups a token was issued with. Removing a member revokes their
+/// tokens, so the claim never lists a group the user has left.
+pub fn token_groups(claims: &Claims) -> Vec<String> {
+    claims
+        .get_claim(GROUPS_CLAIM)
+        .and_then(|groups| groups.as_array())
+        .map(|groups| {
+            groups
+                .iter()
+                .filter_map(|group| group.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Session claims of a user, bound to the user's current token version.
+fn user_claims(user: &UserDocument, expiration: DateTime<Utc>) -> Result<Claims, String> {
+    let mut claims = session_claims(&user.email, expiration)?;
+    claims
+        .add_additional(TOKEN_VERSION_CLAIM, user.token_version)
+        .map_err(|e| e.to_string())?;
+    Ok(claims)
+}
+
+/// Rejects user tokens issued before the user's last password or email
+/// change. Machine identities have their own credentials, their tokens
+/// only need the owner to still exist and belong to the token's org.
+pub async fn ensure_token_current(claims: &Claims, repo: &UserRepository) -> Result<(), String> {
+    let user = token_user(claims, repo).await?;
+    if is_machine_identity(claims) {
+        return if acts_in(&user, token_org(claims).as_ref()) {
+            Ok(())
+        } else {
+            Err("Token has been revoked".into())
+        };
+    }
+    if token_version(claims) != user.token_version {
+        return Err("Token has been revoked".into());
+    }
+    Ok(())
+}
+
+/// Tokens issued before token versions were introduced count as version 0.
+fn token_version(claims: &Claims) -> i64 {
+    claims
+        .get_claim(TOKEN_VERSION_CLAIM)
+        .and_then(|version| version.as_i64())
+        .unwrap_or(0)
+}
+
+fn pending_mfa_claims(
+    user: &UserDocument,
+    purpose: &str,
+    lifetime: Duration,
+) -> Result<Claims, String> {
+    let mut claims = user_claims(user, Utc::now() + lifetime)?;
+    claims
+        .add_additional(MFA_CLAIM, purpose)
+        .map_err(|e| e.to_string())?;
+    Ok(claims)
+}
+
+/// The pending MFA step a token was issued for, None for session tokens.
+pub fn pending_mfa(claims: &Claims) -> Option<&str> {
+    claims.get_claim(MFA_CLAIM).and_then(|mfa| mfa.as_str())
+}
+
+pub fn is_admin(claims: &Claims) -> bool {
+    claims
+        .get_claim("admin")
+        .and_then(|admin| admin.as_bool())
+        .unwrap_or(false)
+}
+
+/// Loads the user a token was issued to.
+pub async fn token_user(claims: &Claims, repo: &UserRepository) -> Result<UserDocument, String> {
+    let subject = claims
+        .get_claim("sub")
+        .and_then(|subject| subject.as_str())
+        .ok_or("Token has no subject")?;
+    repo.get_user_by_email(subject)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "User not found".into())
+}
+
+/// Verifies a PASETO issued by this system and returns its claims.
+pub async fn verify_token(token: &str, repo: &KeyRepository) -> Result<Claims, String> {
+    let untrusted_token =
+        UntrustedToken::<Public, V4>::try_from(token).map_err(|e| e.to_string())?;
+    let public_key = repo.public_key().await?;
+    let trusted_token = public::verify(
+        &public_key,
+        &untrusted_token,
+        &ClaimsValidationRules::new(),
+        None,
+        None,
+    )
+    .map_err(|_| "Invalid or expired token".to_string())?;
+    trusted_token
+        .payload_claims()
+        .cloned()
+        .ok_or_else(|| "Token has no payload".into())
+}
 
-    let expiration = Utc::now() + Duration::hours(8);
-    let expiration = expiration.to_rfc3339();
+/*---------------------------------------------
+Base claims shared by every token we issue.
+----------------------------------------------*/
+pub fn session_claims(subject: &str, expiration: DateTime<Utc>) -> Result<Claims, String> {
+    let mut claims = Claims::new().map_err(|e| e.to_string())?;
+    let ecs_authentication_key =
+        key_provider::require_key(KeyName::Authentication).map_err(|e| e.to_string())?;
 
     let mut hasher = Sha256::new();
-    hasher.update(format!("{}{}", user.email, ecs_authentication_key)); // Unique to current system
+    // Unique to current system
+    hasher.update(subject.as_bytes());
+    hasher.update(ecs_authentication_key.as_bytes());
     let nonce = format!("{:x}", hasher.finalize());
 
+    claims.subject(subject).map_err(|e| e.to_string())?;
     claims
-        .subject(&credentials.email)
+        .expiration(&expiration.to_rfc3339())
         .map_err(|e| e.to_string())?;
-    claims.expiration(&expiration).map_err(|e| e.to_string())?;
     claims
         .issuer("https://www.embraconnect.com")
         .map_err(|e| e.to_string())?;
@@ -67,11 +290,239 @@ pub async fn authorize_user(
         .add_additional("nonce", nonce)
         .map_err(|e| e.to_string())?;
     // claims.add_additional("aud", vec!["https://www.embraconnect.com"]).map_err(|e|e.to_string())?;
-    let (private_key, _public_key) = decode_keys(repo).await.map_err(|e| e.to_string())?;
-    let token = public::sign(&private_key, &claims, None, None).map_err(|e| e.to_string())?;
-    Ok(token)
+    Ok(claims)
+}
+
+pub async fn sign_claims(claims: &Claims, repo: &KeyRepository) -> Result<String, String> {
+    let private_key = repo.secret_key().await?;
+    public::sign(&private_key, claims, None, None).map_err(|e| e.to_string())
+}
+
+/*---------------------------------------------
+Service account API keys.
+
+Keys look like `ecs_<key id>_<secret>`. The id
+locates the stored key, the secret is compared
+against its SHA-256 hash.
+----------------------------------------------*/
+pub const API_KEY_PREFIX: &str = "ecs";
+pub const SCOPE_SECRETS_READ: &str = "secrets:read";
+pub const SCOPE_SECRETS_WRITE: &str = "secrets:write";
+pub const SCOPES: [&str; 2] = [SCOPE_SECRETS_READ, SCOPE_SECRETS_WRITE];
+
+/// Returns the full API key and the hash to store for it.
+pub fn generate_api_key(key_id: &ObjectId) -> (String, String) {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let secret = URL_SAFE_NO_PAD.encode(secret);
+    let key_hash = hash_token(&secret);
+    (
+        format!("{API_KEY_PREFIX}_{}_{secret}", key_id.to_hex()),
+        key_hash,
+    )
+}
+
+/// Hex SHA-256 of a random token: API key secrets, secret IDs and emailed
+/// tokens. Only suited to high-entropy values, never to passwords.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Splits an API key into its key id and secret.
+pub fn parse_api_key(api_key: &str) -> Option<(ObjectId, &str)> {
+    let mut parts = api_key.splitn(3, '_');
+    if parts.next()? != API_KEY_PREFIX {
+        return None;
+    }
+    let key_id = ObjectId::parse_str(parts.next()?).ok()?;
+    let secret = parts.next().filter(|secret| !secret.is_empty())?;
+    Some((key_id, secret))
+}
+
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(&format!("{API_KEY_PREFIX}_"))
 }
 
-pub fn hash_password(password: String) -> Result<String, String> {
-    hash(password, DEFAULT_COST).map_err(|e| e.to_string())
-}
\ No newline at end of file
+/// Verifies an API key and returns the claims it grants. Service accounts
+/// act on behalf of their owner, restricted to the key's scopes.
+pub async fn authenticate_api_key(
+    api_key: &str,
+    repo: &ServiceAccountRepository,
+    user_repo: &UserRepository,
+) -> Result<Claims, String> {
+    let Some((key, account)) = repo
+        .verify_api_key(api_key)
+        .await
+        .map_err(|e| e.to_string())?
+    else {
+        return Err("Invalid credentials".into());
+    };
+    machine_owner(&account.created_by, account.org_id.as_ref(), user_repo).await?;
+
+    // Exchanged tokens never outlive the key they were issued for
+    let session_expiration = Utc::now() + Duration::hours(8);
+    let expiration = key.expires_at.map_or(session_expiration, |expires_at| {
+        expires_at.min(session_expiration)
+    });
+
+    let mut claims = session_claims(&account.created_by, expiration)?;
+    claims
+        .add_additional("service_account", account.id.to_hex())
+        .map_err(|e| e.to_string())?;
+    claims
+        .add_additional("scopes", key.scopes)
+        .map_err(|e| e.to_string())?;
+    add_org_claim(&mut claims, account.org_id.as_ref())?;
+    Ok(claims)
+}
+
+/*---------------------------------------------
+AppRole credentials.
+
+The role ID is public, the secret ID is random
+and only its SHA-256 hash is stored.
+----------------------------------------------*/
+pub fn generate_role_id() -> String {
+    let mut role_id = [0u8; 16];
+    OsRng.fill_bytes(&mut role_id);
+    role_id.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn generate_secret_id() -> String {
+    let mut secret_id = [0u8; 32];
+    OsRng.fill_bytes(&mut secret_id);
+    URL_SAFE_NO_PAD.encode(secret_id)
+}
+
+/// Verifies a role ID / secret ID pair, consuming one use of the secret
+/// ID, and returns claims carrying the role's scopes.
+pub async fn authenticate_app_role(
+    role_id: &str,
+    secret_id: &str,
+    repo: &AppRoleRepository,
+    user_repo: &UserRepository,
+) -> Result<Claims, String> {
+    let Some(role) = repo
+        .consume_secret_id(role_id, secret_id)
+        .await
+        .map_err(|e| e.to_string())?
+    else {
+        return Err("Invalid credentials".into());
+    };
+    machine_owner(&role.created_by, role.org_id.as_ref(), user_repo).await?;
+
+    let expiration = Utc::now() + Duration::minutes(role.token_ttl_minutes);
+    let mut claims = session_claims(&role.created_by, expiration)?;
+    claims
+        .add_additional("app_role", role.name)
+        .map_err(|e| e.to_string())?;
+    claims
+        .add_additional("scopes", role.scopes)
+        .map_err(|e| e.to_string())?;
+    add_org_claim(&mut claims, role.org_id.as_ref())?;
+    Ok(claims)
+}
+
+/// The user a machine identity acts for. Credentials stop working once
+/// the owner is deleted or leaves the organization they were created in.
+async fn machine_owner(
+    owner: &str,
+    org: Option<&ObjectId>,
+    repo: &UserRepository,
+) -> Result<UserDocument, String> {
+    let user = repo
+        .get_user_by_email(owner)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Invalid credentials")?;
+    if !acts_in(&user, org) {
+        return Err("Invalid credentials".into());
+    }
+    Ok(user)
+}
+
+/// Whether the owner of a machine identity still belongs to its org.
+fn acts_in(owner: &UserDocument, org: Option<&ObjectId>) -> bool {
+    org.is_none_or(|org| owner.orgs.contains(org))
+}
+
+/// Whether the claims grant `scope`. Tokens issued to users carry no
+/// scopes and are unrestricted, machine tokens are limited to theirs.
+pub fn has_scope(claims: &Claims, scope: &str) -> bool {
+    let granted = claims
+        .get_claim("scopes")
+        .and_then(|scopes| scopes.as_array());
+    match granted {
+        Some(scopes) => scopes.iter().any(|granted| granted.as_str() == Some(scope)),
+        None => !is_machine_identity(claims),
+    }
+}
+
+/// Service account and AppRole tokens act on behalf of their owner and
+/// can't manage identities themselves.
+pub fn is_machine_identity(claims: &Claims) -> bool {
+    claims.get_claim("service_account").is_some() || claims.get_claim("app_role").is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_key_round_trip() {
+        let key_id = ObjectId::new();
+        let (api_key, key_hash) = generate_api_key(&key_id);
+        assert!(is_api_key(&api_key));
+
+        let (parsed_id, secret) = parse_api_key(&api_key).expect("Failed to parse API key");
+        assert_eq!(parsed_id, key_id);
+        assert_eq!(hash_token(secret), key_hash);
+
+        assert!(parse_api_key("ecs_not-an-id_secret").is_none());
+        assert!(parse_api_key(&format!("ecs_{}_", key_id.to_hex())).is_none());
+    }
+
+    #[test]
+    fn scopes() {
+        let user = Claims::new().unwrap();
+        assert!(has_scope(&user, SCOPE_SECRETS_WRITE));
+
+        let mut machine = Claims::new().unwrap();
+        machine.add_additional("service_account", "id").unwrap();
+        machine
+            .add_additional("scopes", vec![SCOPE_SECRETS_READ])
+            .unwrap();
+        assert!(has_scope(&machine, SCOPE_SECRETS_READ));
+        assert!(!has_scope(&machine, SCOPE_SECRETS_WRITE));
+
+        let mut workload = Claims::new().unwrap();
+        workload.add_additional("app_role", "deploy").unwrap();
+        assert!(!has_scope(&workload, SCOPE_SECRETS_READ));
+        workload
+            .add_additional("scopes", vec![SCOPE_SECRETS_WRITE])
+            .unwrap();
+        assert!(has_scope(&workload, SCOPE_SECRETS_WRITE));
+        assert!(!has_scope(&workload, SCOPE_SECRETS_READ));
+    }
+
+    #[test]
+    fn machine_identities_act_in_their_owners_orgs() {
+        let (member_of, other) = (ObjectId::new(), ObjectId::new());
+        let owner = UserDocument {
+            id: ObjectId::new(),
+            email: "owner@example.com".into(),
+            password: String::new(),
+            created_at: Utc::now(),
+            admin: false,
+            mfa: None,
+            email_verified: true,
+            token_version: 0,
+            groups: Vec::new(),
+            orgs: vec![member_of],
+            e2e_public_key: None,
+        };
+        assert!(acts_in(&owner, Some(&member_of)));
+        assert!(!acts_in(&owner, Some(&other)));
+        assert!(acts_in(&owner, None));
+    }
+}
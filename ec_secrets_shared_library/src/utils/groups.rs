@@ -0,0 +1,174 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use thiserror::Error;
+
+use crate::{
+    models::{Group, GroupDocument, PrincipalKind, UserDocument},
+    repositories::{
+        groups::GroupRepository, policies::PolicyRepository, users::UserRepository,
+        vault::VaultRepository,
+    },
+    utils::{
+        auth::SCOPES,
+        names::{InvalidName, validate_name},
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum GroupError {
+    #[error("Group '{0}' not found")]
+    NotFound(String),
+    #[error("A group with this name already exists")]
+    Exists,
+    #[error("User '{0}' not found")]
+    UserNotFound(String),
+    #[error("'{0}' is not a member of the group")]
+    NotMember(String),
+    #[error("Secret '{0}' not found")]
+    SecretNotFound(String),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<mongodb::error::Error> for GroupError {
+    fn from(error: mongodb::error::Error) -> Self {
+        Self::Internal(error.to_string())
+    }
+}
+
+impl From<InvalidName> for GroupError {
+    fn from(error: InvalidName) -> Self {
+        Self::Invalid(error.to_string())
+    }
+}
+
+fn validate_permissions(permissions: &[String]) -> Result<(), GroupError> {
+    match permissions
+        .iter()
+        .find(|permission| !SCOPES.contains(&permission.as_str()))
+    {
+        Some(permission) => Err(GroupError::Invalid(format!(
+            "Unknown permission '{permission}'"
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub async fn get_group(name: &str, repo: &GroupRepository) -> Result<GroupDocument, GroupError> {
+    repo.get_group(name)
+        .await?
+        .ok_or_else(|| GroupError::NotFound(name.to_string()))
+}
+
+/*---------------------------------------------------------------------------
+    Groups let vault access follow team membership: entries are shared with
+    a group once, and members get the group's permissions on them for as
+    long as they belong to it. Onboarding and offboarding is a single
+    membership change.
+---------------------------------------------------------------------------*/
+pub async fn create_group(
+    group: &Group,
+    created_by: &str,
+    repo: &GroupRepository,
+) -> Result<GroupDocument, GroupError> {
+    validate_name("Group", &group.name)?;
+    validate_permissions(&group.permissions)?;
+    if repo.get_group(&group.name).await?.is_some() {
+        return Err(GroupError::Exists);
+    }
+
+    let document = GroupDocument {
+        id: ObjectId::new(),
+        name: group.name.clone(),
+        description: group.description.clone(),
+        permissions: group.permissions.clone(),
+        created_by: created_by.to_string(),
+        created_at: Utc::now(),
+    };
+    repo.create_group(&document).await?;
+    Ok(document)
+}
+
+/// Replaces the permissions a group grants, effective immediately.
+pub async fn set_permissions(
+    name: &str,
+    permissions: &[String],
+    repo: &GroupRepository,
+) -> Result<GroupDocument, GroupError> {
+    validate_permissions(permissions)?;
+    repo.set_permissions(name, permissions)
+        .await?
+        .ok_or_else(|| GroupError::NotFound(name.to_string()))
+}
+
+/// Deletes a group, removing its members, unsharing its entries and
+/// detaching its policies.
+pub async fn delete_group(
+    name: &str,
+    repo: &GroupRepository,
+    user_repo: &UserRepository,
+    vault_repo: &VaultRepository,
+    policy_repo: &PolicyRepository,
+) -> Result<GroupDocument, GroupError> {
+    let group = repo
+        .delete_group(name)
+        .await?
+        .ok_or_else(|| GroupError::NotFound(name.to_string()))?;
+    user_repo.remove_group(name).await?;
+    vault_repo.remove_group(name).await?;
+    policy_repo.detach_all(PrincipalKind::Group, name).await?;
+    Ok(group)
+}
+
+/// Adds a user to a group. The membership shows up in the user's next token.
+pub async fn add_member(
+    name: &str,
+    email: &str,
+    repo: &GroupRepository,
+    user_repo: &UserRepository,
+) -> Result<UserDocument, GroupError> {
+    get_group(name, repo).await?;
+    user_repo
+        .add_to_group(email, name)
+        .await?
+        .ok_or_else(|| GroupError::UserNotFound(email.to_string()))
+}
+
+/// Removes a user from a group, revoking the user's tokens.
+pub async fn remove_member(
+    name: &str,
+    email: &str,
+    repo: &GroupRepository,
+    user_repo: &UserRepository,
+) -> Result<(), GroupError> {
+    get_group(name, repo).await?;
+    if user_repo.remove_from_group(email, name).await?.is_some() {
+        return Ok(());
+    }
+    match user_repo.get_user_by_email(email).await? {
+        Some(_) => Err(GroupError::NotMember(email.to_string())),
+        None => Err(GroupError::UserNotFound(email.to_string())),
+    }
+}
+
+pub async fn list_members(
+    name: &str,
+    repo: &GroupRepository,
+    user_repo: &UserRepository,
+) -> Result<Vec<UserDocument>, GroupError> {
+    get_group(name, repo).await?;
+    Ok(user_repo.list_group_members(name).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions() {
+        assert!(validate_permissions(&["secrets:read".into()]).is_ok());
+        assert!(validate_permissions(&["secrets:admin".into()]).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use super::{KeyName, KeyProvider, KeyProviderError, required_env};
+use crate::utils::{
+    secret::{SecretBytes, SecretString},
+    vault::{decrypt, encrypt},
+};
+
+/*---------------------------------------------------------------------------
+    Keys in a local file, encrypted with a passphrase (Argon2id derived,
+    see `utils::vault::encrypt`). The file is read from [ECS_KEYFILE] and
+    the passphrase from [ECS_KEYFILE_PASSPHRASE], or from the file named
+    by [ECS_KEYFILE_PASSPHRASE_FILE] so it can come from a mounted secret.
+---------------------------------------------------------------------------*/
+pub struct KeyfileProvider {
+    keys: BTreeMap<String, SecretString>,
+}
+
+impl KeyfileProvider {
+    pub fn from_env() -> Result<Self, KeyProviderError> {
+        let path = required_env("ECS_KEYFILE")?;
+        let passphrase = match std::env::var("ECS_KEYFILE_PASSPHRASE_FILE") {
+            Ok(file) => {
+                let contents = fs::read_to_string(&file)
+                    .map(SecretString::new)
+                    .map_err(|e| {
+                        KeyProviderError::Keyfile(format!("Failed to read {file}: {e}"))
+                    })?;
+                SecretString::from(contents.trim_end_matches(['\r', '\n']))
+            }
+            Err(_) => SecretString::new(required_env("ECS_KEYFILE_PASSPHRASE")?),
+        };
+        Self::open(Path::new(&path), &passphrase)
+    }
+
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, KeyProviderError> {
+        let encrypted = fs::read(path).map_err(|e| {
+            KeyProviderError::Keyfile(format!("Failed to read {}: {e}", path.display()))
+        })?;
+        let contents = decrypt(&encrypted, passphrase.as_bytes()).map_err(|_| {
+            KeyProviderError::Keyfile("Failed to decrypt the keyfile (wrong passphrase?)".into())
+        })?;
+        let keys = serde_json::from_slice(&contents)
+            .map_err(|e| KeyProviderError::Keyfile(format!("Malformed keyfile: {e}")))?;
+        Ok(Self { keys })
+    }
+
+    /// Writes `keys` to a new keyfile at `path`, readable by the owner only.
+    pub fn create(
+        path: &Path,
+        passphrase: &str,
+        keys: &[(KeyName, SecretString)],
+    ) -> Result<(), KeyProviderError> {
+        let keys: BTreeMap<&str, &str> = keys
+            .iter()
+            .map(|(name, key)| (name.id(), key.as_str()))
+            .collect();
+        let contents = serde_json::to_vec(&keys)
+            .map(SecretBytes::new)
+            .map_err(|e| KeyProviderError::Keyfile(e.to_string()))?;
+        let encrypted = encrypt(&contents, passphrase.as_bytes())
+            .map_err(|e| KeyProviderError::Keyfile(e.to_string()))?;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let write_error =
+            |e: std::io::Error| KeyProviderError::Keyfile(format!("{}: {e}", path.display()));
+        let mut file = options.open(path).map_err(write_error)?;
+        std::io::Write::write_all(&mut file, &encrypted).map_err(write_error)
+    }
+}
+
+impl KeyProvider for KeyfileProvider {
+    fn name(&self) -> &'static str {
+        "keyfile"
+    }
+
+    fn load(&self, name: KeyName) -> Result<Option<SecretString>, KeyProviderError> {
+        Ok(self.keys.get(name.id()).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyfile_round_trip() {
+        let path = std::env::temp_dir().join(format!("ecs-keyfile-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let keys = [(KeyName::Encryption, SecretString::from("encryption key"))];
+        KeyfileProvider::create(&path, "passphrase", &keys).unwrap();
+        // Never overwrites an existing keyfile
+        assert!(KeyfileProvider::create(&path, "passphrase", &keys).is_err());
+
+        let provider = KeyfileProvider::open(&path, "passphrase").unwrap();
+        assert_eq!(
+            provider.load(KeyName::Encryption).unwrap().as_deref(),
+            Some(&"encryption key".to_string())
+        );
+        assert_eq!(provider.load(KeyName::Authentication).unwrap(), None);
+        assert!(KeyfileProvider::open(&path, "wrong").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{KeyName, KeyProvider, KeyProviderError, required_env};
+use crate::utils::secret::{SecretBytes, SecretString};
+
+/*---------------------------------------------------------------------------
+    Keys held as ciphertexts that only a KMS-style HTTP service can
+    decrypt. The ciphertext of each key comes from `<variable>_CIPHERTEXT`
+    (e.g. [ECS_ENCRYPTION_KEY_CIPHERTEXT]) and is sent with
+    [ECS_KMS_KEY_ID] to `POST [ECS_KMS_URL]/v1/decrypt`:
+
+        {"key_id": "...", "ciphertext": "<base64>"} -> {"plaintext": "<base64>"}
+
+    `/v1/encrypt` works the other way around and produces the ciphertexts.
+    [ECS_KMS_TOKEN], when set, is sent as a bearer token. Requests give up
+    after [CONNECT_TIMEOUT] and [READ_TIMEOUT] so an unreachable service
+    fails startup instead of hanging it.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct KmsProvider {
+    url: String,
+    key_id: String,
+    token: Option<SecretString>,
+    agent: ureq::Agent,
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize)]
+struct DecryptResponse {
+    plaintext: SecretString,
+}
+
+#[derive(Deserialize)]
+struct EncryptResponse {
+    ciphertext: String,
+}
+
+impl KmsProvider {
+    pub fn new(url: &str, key_id: &str, token: Option<SecretString>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            key_id: key_id.to_string(),
+            token,
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(CONNECT_TIMEOUT)
+                .timeout_read(READ_TIMEOUT)
+                .build(),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, KeyProviderError> {
+        Ok(Self::new(
+            &required_env("ECS_KMS_URL")?,
+            &required_env("ECS_KMS_KEY_ID")?,
+            std::env::var("ECS_KMS_TOKEN").ok().map(SecretString::new),
+        ))
+    }
+
+    /// The environment variable holding the ciphertext of `name`.
+    pub fn ciphertext_var(name: KeyName) -> String {
+        format!("{}_CIPHERTEXT", name.env_var())
+    }
+
+    fn post(
+        &self,
+        path: &str,
+        body: serde_json::Value,
+    ) -> Result<ureq::Response, KeyProviderError> {
+        let mut request = self.agent.post(&format!("{}{path}", self.url));
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token.as_str()));
+        }
+        request.send_json(body).map_err(|e| match e {
+            ureq::Error::Status(status, _) => {
+                KeyProviderError::Kms(format!("{path} answered with status {status}"))
+            }
+            e => KeyProviderError::Kms(e.to_string()),
+        })
+    }
+
+    pub fn decrypt(&self, ciphertext: &str) -> Result<SecretString, KeyProviderError> {
+        let response: DecryptResponse = self
+            .post(
+                "/v1/decrypt",
+                json!({ "key_id": self.key_id, "ciphertext": ciphertext }),
+            )?
+            .into_json()
+            .map_err(|e| KeyProviderError::Kms(format!("Malformed decrypt response: {e}")))?;
+        let plaintext = STANDARD
+            .decode(response.plaintext.as_bytes())
+            .map(SecretBytes::new)
+            .map_err(|e| KeyProviderError::Kms(format!("Malformed plaintext: {e}")))?;
+        plaintext
+            .into_string()
+            .map_err(|_| KeyProviderError::Kms("The decrypted key is not valid UTF-8".into()))
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, KeyProviderError> {
+        let response: EncryptResponse = self
+            .post(
+                "/v1/encrypt",
+                json!({ "key_id": self.key_id, "plaintext": STANDARD.encode(plaintext) }),
+            )?
+            .into_json()
+            .map_err(|e| KeyProviderError::Kms(format!("Malformed encrypt response: {e}")))?;
+        Ok(response.ciphertext)
+    }
+}
+
+impl KeyProvider for KmsProvider {
+    fn name(&self) -> &'static str {
+        "kms"
+    }
+
+    fn load(&self, name: KeyName) -> Result<Option<SecretString>, KeyProviderError> {
+        match std::env::var(Self::ciphertext_var(name)) {
+            Ok(ciphertext) if !ciphertext.is_empty() => self.decrypt(&ciphertext).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// A KMS whose "encryption" reverses the bytes, for two requests.
+    fn mock_kms() -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr());
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests().take(2) {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let body: Value = serde_json::from_str(&body).unwrap();
+                assert_eq!(body["key_id"], "master");
+                let reversed = |field: &str| {
+                    let mut bytes = STANDARD.decode(body[field].as_str().unwrap()).unwrap();
+                    bytes.reverse();
+                    STANDARD.encode(bytes)
+                };
+                let response = match request.url() {
+                    "/v1/encrypt" => json!({ "ciphertext": reversed("plaintext") }),
+                    _ => json!({ "plaintext": reversed("ciphertext") }),
+                };
+                request
+                    .respond(tiny_http::Response::from_string(response.to_string()))
+                    .unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn kms_round_trip() {
+        let provider = KmsProvider::new(&mock_kms(), "master", None);
+        let ciphertext = provider.encrypt("secret key").unwrap();
+        assert_ne!(STANDARD.decode(&ciphertext).unwrap(), b"secret key");
+        assert_eq!(
+            provider.decrypt(&ciphertext).unwrap().as_str(),
+            "secret key"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+/*---------------------------------------------------------------------------
+    Where master key material comes from. [ECS_KEY_PROVIDER] picks one of
+
+    - `env` (default): [ECS_ENCRYPTION_KEY] and [ECS_AUTHENTICATION_KEY]
+    - `keyfile`: a local file encrypted with a passphrase
+    - `pkcs11`: data objects on a PKCS#11 token, e.g. an HSM or SoftHSM
+    - `kms`: ciphertexts decrypted by a KMS-style HTTP service
+
+    Keys are loaded once per process, see [load_keys]. Providers block on
+    files, tokens or the network, so servers load them at startup rather
+    than inside a request. The provider is dropped right after, so it
+    doesn't hold a token session or module open.
+---------------------------------------------------------------------------*/
+use std::sync::OnceLock;
+
+use thiserror::Error;
+
+use crate::utils::secret::SecretString;
+
+pub mod keyfile;
+pub mod kms;
+pub mod pkcs11;
+
+pub use keyfile::KeyfileProvider;
+pub use kms::KmsProvider;
+pub use pkcs11::Pkcs11Provider;
+
+/// Environment variable selecting the provider.
+pub const PROVIDER_ENV: &str = "ECS_KEY_PROVIDER";
+
+/// The master keys a provider can supply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyName {
+    /// Wraps organization keys and TOTP secrets
+    Encryption,
+    /// Mixed into session token nonces
+    Authentication,
+}
+
+impl KeyName {
+    pub const ALL: [KeyName; 2] = [KeyName::Encryption, KeyName::Authentication];
+
+    /// The environment variable the `env` provider reads.
+    pub fn env_var(self) -> &'static str {
+        match self {
+            KeyName::Encryption => "ECS_ENCRYPTION_KEY",
+            KeyName::Authentication => "ECS_AUTHENTICATION_KEY",
+        }
+    }
+
+    /// Short name used in keyfiles, token labels and KMS requests.
+    pub fn id(self) -> &'static str {
+        match self {
+            KeyName::Encryption => "encryption",
+            KeyName::Authentication => "authentication",
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum KeyProviderError {
+    #[error("Unknown key provider '{0}', expected env, keyfile, pkcs11 or kms")]
+    UnknownProvider(String),
+    #[error("[{0}] must be set")]
+    MissingConfig(&'static str),
+    #[error("No {} key available, set [{}] or configure [ECS_KEY_PROVIDER]", .0.id(), .0.env_var())]
+    MissingKey(KeyName),
+    #[error("{0}")]
+    Keyfile(String),
+    #[error("PKCS#11: {0}")]
+    Pkcs11(String),
+    #[error("KMS: {0}")]
+    Kms(String),
+}
+
+pub trait KeyProvider: Send + Sync {
+    /// Provider name as accepted by [ECS_KEY_PROVIDER].
+    fn name(&self) -> &'static str;
+
+    /// Loads one key, None if the provider has no such key.
+    fn load(&self, name: KeyName) -> Result<Option<SecretString>, KeyProviderError>;
+}
+
+/// Reads keys from environment variables, the historical behavior.
+#[derive(Debug, Default)]
+pub struct EnvProvider;
+
+impl KeyProvider for EnvProvider {
+    fn name(&self) -> &'static str {
+        "env"
+    }
+
+    fn load(&self, name: KeyName) -> Result<Option<SecretString>, KeyProviderError> {
+        Ok(std::env::var(name.env_var())
+            .ok()
+            .filter(|key| !key.is_empty())
+            .map(SecretString::new))
+    }
+}
+
+pub(crate) fn required_env(name: &'static str) -> Result<String, KeyProviderError> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .ok_or(KeyProviderError::MissingConfig(name))
+}
+
+/// The provider configured through [ECS_KEY_PROVIDER].
+pub fn configured_provider() -> Result<Box<dyn KeyProvider>, KeyProviderError> {
+    let provider = std::env::var(PROVIDER_ENV).unwrap_or_else(|_| "env".into());
+    match provider.to_ascii_lowercase().as_str() {
+        "" | "env" => Ok(Box::new(EnvProvider)),
+        "keyfile" => Ok(Box::new(KeyfileProvider::from_env()?)),
+        "pkcs11" => Ok(Box::new(Pkcs11Provider::from_env()?)),
+        "kms" => Ok(Box::new(KmsProvider::from_env()?)),
+        _ => Err(KeyProviderError::UnknownProvider(provider)),
+    }
+}
+
+#[derive(Debug)]
+struct LoadedKeys {
+    encryption: Option<SecretString>,
+    authentication: Option<SecretString>,
+}
+
+static KEYS: OnceLock<LoadedKeys> = OnceLock::new();
+
+fn loaded_keys() -> Result<&'static LoadedKeys, KeyProviderError> {
+    if let Some(keys) = KEYS.get() {
+        return Ok(keys);
+    }
+    let provider = configured_provider()?;
+    log::info!(
+        "Loading master keys with the {} key provider.",
+        provider.name()
+    );
+    let keys = LoadedKeys {
+        encryption: provider.load(KeyName::Encryption)?,
+        authentication: provider.load(KeyName::Authentication)?,
+    };
+    Ok(KEYS.get_or_init(|| keys))
+}
+
+/// Loads the master keys unless they already are. Blocking; failures
+/// aren't kept, so a transient provider error is retried on the next call.
+pub fn load_keys() -> Result<(), KeyProviderError> {
+    loaded_keys().map(|_| ())
+}
+
+/// A master key from the configured provider, loaded on first use.
+pub fn key(name: KeyName) -> Result<Option<SecretString>, KeyProviderError> {
+    let keys = loaded_keys()?;
+    Ok(match name {
+        KeyName::Encryption => keys.encryption.clone(),
+        KeyName::Authentication => keys.authentication.clone(),
+    })
+}
+
+/// Like [key] but the key has to exist.
+pub fn require_key(name: KeyName) -> Result<SecretString, KeyProviderError> {
+    key(name)?.ok_or(KeyProviderError::MissingKey(name))
+}
@@ -0,0 +1,217 @@
+use cryptoki::{
+    context::{CInitializeArgs, CInitializeFlags, Pkcs11},
+    error::{Error, RvError},
+    object::{Attribute, AttributeType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    slot::Slot,
+    types::AuthPin,
+};
+use log::warn;
+
+use super::{KeyName, KeyProvider, KeyProviderError, required_env};
+use crate::utils::secret::{SecretBytes, SecretString};
+
+/*---------------------------------------------------------------------------
+    Keys stored as private data objects (CKO_DATA) on a PKCS#11 token,
+    labelled `<prefix><key>`, e.g. `ecs-encryption`. Configuration:
+
+    - [ECS_PKCS11_MODULE]: path of the PKCS#11 library, for SoftHSM
+      usually /usr/lib/softhsm/libsofthsm2.so
+    - [ECS_PKCS11_PIN]: user PIN of the token
+    - [ECS_PKCS11_SLOT]: slot id, the first slot with a token by default
+    - [ECS_PKCS11_LABEL_PREFIX]: label prefix, `ecs-` by default
+
+    The module is driven through the `cryptoki` crate and finalized when
+    the provider is dropped, which happens once the keys are loaded.
+---------------------------------------------------------------------------*/
+fn pkcs11_error(error: Error) -> KeyProviderError {
+    KeyProviderError::Pkcs11(error.to_string())
+}
+
+pub struct Pkcs11Provider {
+    context: Pkcs11,
+    // False when the module was already initialized by someone else, who
+    // then also finalizes it
+    initialized: bool,
+    pin: AuthPin,
+    slot: Option<u64>,
+    label_prefix: String,
+}
+
+impl Drop for Pkcs11Provider {
+    fn drop(&mut self) {
+        if self.initialized
+            && let Err(e) = self.context.clone().finalize()
+        {
+            warn!("Failed to finalize the PKCS#11 module: {e}");
+        }
+    }
+}
+
+impl Pkcs11Provider {
+    pub fn new(
+        module: &str,
+        pin: &str,
+        slot: Option<u64>,
+        label_prefix: &str,
+    ) -> Result<Self, KeyProviderError> {
+        let context = Pkcs11::new(module)
+            .map_err(|e| KeyProviderError::Pkcs11(format!("Failed to load {module}: {e}")))?;
+        let initialized =
+            match context.initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK)) {
+                Ok(()) => true,
+                Err(Error::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => false,
+                Err(e) => return Err(pkcs11_error(e)),
+            };
+        Ok(Self {
+            context,
+            initialized,
+            pin: AuthPin::from(pin),
+            slot,
+            label_prefix: label_prefix.to_string(),
+        })
+    }
+
+    pub fn from_env() -> Result<Self, KeyProviderError> {
+        let slot = match std::env::var("ECS_PKCS11_SLOT") {
+            Ok(slot) => Some(
+                slot.parse()
+                    .map_err(|_| KeyProviderError::Pkcs11(format!("Invalid slot id '{slot}'")))?,
+            ),
+            Err(_) => None,
+        };
+        Self::new(
+            &required_env("ECS_PKCS11_MODULE")?,
+            &required_env("ECS_PKCS11_PIN")?,
+            slot,
+            &std::env::var("ECS_PKCS11_LABEL_PREFIX").unwrap_or_else(|_| "ecs-".into()),
+        )
+    }
+
+    fn label(&self, name: KeyName) -> String {
+        format!("{}{}", self.label_prefix, name.id())
+    }
+
+    fn slot(&self) -> Result<Slot, KeyProviderError> {
+        if let Some(slot) = self.slot {
+            return Slot::try_from(slot).map_err(pkcs11_error);
+        }
+        self.context
+            .get_slots_with_token()
+            .map_err(pkcs11_error)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| KeyProviderError::Pkcs11("No slot with a token".into()))
+    }
+
+    /// A session logged in as the user, closed when dropped.
+    fn open_session(&self, read_write: bool) -> Result<Session, KeyProviderError> {
+        let slot = self.slot()?;
+        let session = if read_write {
+            self.context.open_rw_session(slot)
+        } else {
+            self.context.open_ro_session(slot)
+        }
+        .map_err(pkcs11_error)?;
+        match session.login(UserType::User, Some(&self.pin)) {
+            Ok(()) | Err(Error::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => Ok(session),
+            Err(e) => Err(pkcs11_error(e)),
+        }
+    }
+
+    fn find_object(
+        &self,
+        session: &Session,
+        label: &str,
+    ) -> Result<Option<ObjectHandle>, KeyProviderError> {
+        let template = [
+            Attribute::Class(ObjectClass::DATA),
+            Attribute::Label(label.as_bytes().to_vec()),
+        ];
+        Ok(session
+            .find_objects(&template)
+            .map_err(pkcs11_error)?
+            .into_iter()
+            .next())
+    }
+
+    /// Stores `key` as the token object for `name`.
+    pub fn store(&self, name: KeyName, key: &str) -> Result<(), KeyProviderError> {
+        let session = self.open_session(true)?;
+        let label = self.label(name);
+        if self.find_object(&session, &label)?.is_some() {
+            return Err(KeyProviderError::Pkcs11(format!(
+                "The token already holds an object labelled {label}"
+            )));
+        }
+        let template = [
+            Attribute::Class(ObjectClass::DATA),
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Label(label.into_bytes()),
+            Attribute::Value(key.as_bytes().to_vec()),
+        ];
+        session
+            .create_object(&template)
+            .map(|_| ())
+            .map_err(pkcs11_error)
+    }
+}
+
+impl KeyProvider for Pkcs11Provider {
+    fn name(&self) -> &'static str {
+        "pkcs11"
+    }
+
+    fn load(&self, name: KeyName) -> Result<Option<SecretString>, KeyProviderError> {
+        let session = self.open_session(false)?;
+        let Some(object) = self.find_object(&session, &self.label(name))? else {
+            return Ok(None);
+        };
+        let attributes = session
+            .get_attributes(object, &[AttributeType::Value])
+            .map_err(pkcs11_error)?;
+        let Some(Attribute::Value(value)) = attributes.into_iter().next() else {
+            return Err(KeyProviderError::Pkcs11(
+                "The stored key has no readable value".into(),
+            ));
+        };
+        SecretBytes::new(value)
+            .into_string()
+            .map(Some)
+            .map_err(|_| KeyProviderError::Pkcs11("The stored key is not valid UTF-8".into()))
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Needs SoftHSM, so it's ignored by default. Initialize a token and point
+    [ECS_TEST_PKCS11_MODULE] and [ECS_TEST_PKCS11_PIN] at it, e.g.
+
+        softhsm2-util --init-token --free --label ecs --pin 1234 --so-pin 1234
+        ECS_TEST_PKCS11_MODULE=/usr/lib/softhsm/libsofthsm2.so \
+        ECS_TEST_PKCS11_PIN=1234 cargo test softhsm -- --ignored
+---------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a SoftHSM token, see above"]
+    fn softhsm_round_trip() {
+        let module = std::env::var("ECS_TEST_PKCS11_MODULE").expect("ECS_TEST_PKCS11_MODULE");
+        let pin = std::env::var("ECS_TEST_PKCS11_PIN").expect("ECS_TEST_PKCS11_PIN");
+        let prefix = format!("ecs-test-{}-", std::process::id());
+        let provider = Pkcs11Provider::new(&module, &pin, None, &prefix).unwrap();
+        assert_eq!(provider.load(KeyName::Encryption).unwrap(), None);
+        provider.store(KeyName::Encryption, "hsm key").unwrap();
+        assert!(provider.store(KeyName::Encryption, "other").is_err());
+        assert_eq!(
+            provider
+                .load(KeyName::Encryption)
+                .unwrap()
+                .as_ref()
+                .map(|key| key.as_str()),
+            Some("hsm key")
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::{env, fs, path::PathBuf, sync::Arc};
+
+use chrono::Utc;
+use log::info;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/*---------------------------------------------------------------------------
+    Delivers account emails (password resets, email verification). Pick an
+    implementation with ECS_MAILER:
+
+    log   writes the message to the application log (default)
+    file  writes one .eml file per message into ECS_MAILER_DIR (./mail)
+
+    Other transports plug in by implementing the trait.
+---------------------------------------------------------------------------*/
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), String>;
+}
+
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> Result<(), String> {
+        info!("Mail to {} | {}\n{}", email.to, email.subject, email.body);
+        Ok(())
+    }
+}
+
+pub struct FileMailer {
+    pub directory: PathBuf,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), String> {
+        fs::create_dir_all(&self.directory).map_err(|e| e.to_string())?;
+        let recipient: String = email
+            .to
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = self.directory.join(format!(
+            "{}-{recipient}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.6f")
+        ));
+        let message = format!(
+            "To: {}\r\nSubject: {}\r\nDate: {}\r\n\r\n{}\r\n",
+            email.to,
+            email.subject,
+            Utc::now().to_rfc2822(),
+            email.body
+        );
+        fs::write(&path, message).map_err(|e| e.to_string())
+    }
+}
+
+/// The mailer selected through the environment.
+pub fn mailer_from_env() -> Result<Arc<dyn Mailer>, String> {
+    match env::var("ECS_MAILER").as_deref() {
+        Ok("log") | Err(_) => Ok(Arc::new(LogMailer)),
+        Ok("file") => Ok(Arc::new(FileMailer {
+            directory: env::var("ECS_MAILER_DIR")
+                .unwrap_or_else(|_| "./mail".into())
+                .into(),
+        })),
+        Ok(other) => Err(format!("Unknown mailer '{other}', expected log or file")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_mailer_writes_one_message_per_email() {
+        let directory = env::temp_dir().join(format!("ecs-mail-{}", std::process::id()));
+        let mailer = FileMailer {
+            directory: directory.clone(),
+        };
+        mailer
+            .send(&Email {
+                to: "user@domain.com".into(),
+                subject: "Verify your email address".into(),
+                body: "token".into(),
+            })
+            .unwrap();
+
+        let messages: Vec<_> = fs::read_dir(&directory).unwrap().collect();
+        assert_eq!(messages.len(), 1);
+        let message = fs::read_to_string(messages[0].as_ref().unwrap().path()).unwrap();
+        assert!(message.starts_with("To: user@domain.com\r\nSubject: Verify your email address"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    models::UserDocument,
+    repositories::users::UserRepository,
+    utils::{
+        password::{hash_password, run_blocking, verify_password},
+        seal::master_key,
+        secret::SecretString,
+        vault::{EnvelopeParams, decrypt, encrypt, reencrypt},
+    },
+};
+
+pub const ISSUER: &str = "Lock Smith";
+pub const DIGITS: u32 = 6;
+pub const PERIOD_SECONDS: i64 = 30;
+/// Steps accepted either side of the current one to allow for clock drift
+pub const SKEW_STEPS: i64 = 1;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/*---------------------------------------------------------------------------
+    TOTP (RFC 6238) with the parameters authenticator apps assume by
+    default: HMAC-SHA1, 6 digits and a 30 second period.
+---------------------------------------------------------------------------*/
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// Provisioning URI rendered as a QR code by authenticator apps.
+pub fn otpauth_uri(secret: &str, account: &str) -> String {
+    let label = format!("{ISSUER}:{account}");
+    format!(
+        "otpauth://totp/{}?secret={secret}&issuer={}&algorithm=SHA1&digits={DIGITS}&period={PERIOD_SECONDS}",
+        percent_encode(&label),
+        percent_encode(ISSUER)
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> Result<u32, String> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).map_err(|e| e.to_string())?;
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    Ok(binary % 10u32.pow(digits))
+}
+
+pub fn time_step(unix_seconds: i64) -> i64 {
+    unix_seconds.div_euclid(PERIOD_SECONDS)
+}
+
+/// Returns the time step a code matches within the allowed skew, so the
+/// caller can reject codes that were already used.
+pub fn verify_code(secret: &str, code: &str, unix_seconds: i64) -> Result<Option<i64>, String> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+    let code: u32 = code.parse().map_err(|_| "Invalid code".to_string())?;
+    let secret = BASE32_NOPAD
+        .decode(secret.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    let current = time_step(unix_seconds);
+    for step in current - SKEW_STEPS..=current + SKEW_STEPS {
+        if step >= 0 && hotp(&secret, step as u64, DIGITS)? == code {
+            return Ok(Some(step));
+        }
+    }
+    Ok(None)
+}
+
+/*---------------------------------------------------------------------------
+    Recovery codes are single use, 80 random bits written as
+    `xxxx-xxxx-xxxx-xxxx`. Only salted Argon2id hashes are stored, like
+    passwords.
+---------------------------------------------------------------------------*/
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut code = [0u8; 10];
+            OsRng.fill_bytes(&mut code);
+            let code = BASE32_NOPAD.encode(&code).to_lowercase();
+            format!(
+                "{}-{}-{}-{}",
+                &code[..4],
+                &code[4..8],
+                &code[8..12],
+                &code[12..]
+            )
+        })
+        .collect()
+}
+
+/// Ignores case, whitespace and dashes.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> Result<String, String> {
+    hash_password(&normalize_recovery_code(code))
+}
+
+/// Whether `code` matches a stored hash. Codes issued before hashes were
+/// salted are stored as plain SHA-256 and still accepted until replaced.
+pub fn verify_recovery_code(code: &str, hash: &str) -> Result<bool, String> {
+    let normalized = normalize_recovery_code(code);
+    if hash.starts_with("$argon2") {
+        verify_password(&normalized, hash)
+    } else {
+        Ok(format!("{:x}", Sha256::digest(normalized.as_bytes())) == hash)
+    }
+}
+
+async fn hash_recovery_codes(codes: &[String]) -> Result<Vec<String>, String> {
+    let codes = Zeroizing::new(codes.to_vec());
+    run_blocking(move || codes.iter().map(|code| hash_recovery_code(code)).collect()).await
+}
+
+pub fn is_totp_code(code: &str) -> bool {
+    let code = code.trim();
+    code.len() == DIGITS as usize && code.chars().all(|c| c.is_ascii_digit())
+}
+
+/*---------------------------------------------------------------------------
+    TOTP secrets are stored encrypted with the master key, which wraps the
+    organization vault keys as well. A sealed vault can't check codes.
+---------------------------------------------------------------------------*/
+fn encryption_key() -> Result<SecretString, String> {
+    master_key().map_err(|e| e.to_string())
+}
+
+pub fn seal_secret(secret: &str) -> Result<String, String> {
+    let sealed =
+        encrypt(secret.as_bytes(), encryption_key()?.as_bytes()).map_err(|e| e.to_string())?;
+    Ok(STANDARD.encode(sealed))
+}
+
+pub fn open_secret(sealed: &str) -> Result<SecretString, String> {
+    let sealed = STANDARD.decode(sealed).map_err(|e| e.to_string())?;
+    let secret = decrypt(&sealed, encryption_key()?.as_bytes()).map_err(|e| e.to_string())?;
+    secret.into_string().map_err(|e| e.to_string())
+}
+
+/// The sealed secret re-encrypted with `params`, None if it already uses
+/// them.
+pub fn reseal_secret(sealed: &str, params: EnvelopeParams) -> Result<Option<String>, String> {
+    let sealed = STANDARD.decode(sealed).map_err(|e| e.to_string())?;
+    let resealed =
+        reencrypt(&sealed, encryption_key()?.as_bytes(), params).map_err(|e| e.to_string())?;
+    Ok(resealed.map(|resealed| STANDARD.encode(resealed)))
+}
+
+/// A pending enrollment, shown to the user once to configure their app.
+#[derive(Debug, Clone)]
+pub struct Enrollment {
+    pub secret: SecretString,
+    pub otpauth_uri: SecretString,
+}
+
+/// Starts (or restarts) enrollment. MFA stays disabled until
+/// [confirm_enrollment] verifies a first code.
+pub async fn begin_enrollment(
+    repo: &UserRepository,
+    user: &UserDocument,
+) -> Result<Enrollment, String> {
+    if user.mfa_enabled() {
+        return Err("MFA is already enabled for this account".into());
+    }
+    let secret = generate_secret();
+    repo.start_mfa_enrollment(&user.id, &seal_secret(&secret)?)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("MFA is already enabled for this account")?;
+
+    Ok(Enrollment {
+        otpauth_uri: otpauth_uri(&secret, &user.email).into(),
+        secret: secret.into(),
+    })
+}
+
+/// Verifies the first code of a pending enrollment, enables MFA and
+/// returns the recovery codes, which are only available this once.
+pub async fn confirm_enrollment(
+    repo: &UserRepository,
+    user: &UserDocument,
+    code: &str,
+) -> Result<Vec<String>, String> {
+    let Some(mfa) = user.mfa.as_ref().filter(|mfa| !mfa.enabled) else {
+        return Err("No pending MFA enrollment, please start enrollment first".into());
+    };
+    let Some(step) = verify_code(&open_secret(&mfa.secret)?, code, Utc::now().timestamp())? else {
+        return Err("Invalid MFA code".into());
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let hashes = hash_recovery_codes(&recovery_codes).await?;
+    repo.enable_mfa(&user.id, &hashes, step)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("No pending MFA enrollment, please start enrollment first")?;
+    Ok(recovery_codes)
+}
+
+/// Checks a TOTP or recovery code for an enrolled user. Used TOTP steps
+/// and recovery codes are consumed so neither can be replayed.
+pub async fn verify_second_factor(
+    repo: &UserRepository,
+    user: &UserDocument,
+    code: &str,
+) -> Result<bool, String> {
+    let Some(mfa) = user.mfa.as_ref().filter(|mfa| mfa.enabled) else {
+        return Ok(false);
+    };
+
+    if is_totp_code(code) {
+        return match verify_code(&open_secret(&mfa.secret)?, code, Utc::now().timestamp())? {
+            Some(step) => repo
+                .record_totp_step(&user.id, step)
+                .await
+                .map_err(|e| e.to_string()),
+            None => Ok(false),
+        };
+    }
+
+    let (code, hashes) = (Zeroizing::new(code.to_owned()), mfa.recovery_codes.clone());
+    let matched = run_blocking(move || {
+        for hash in hashes {
+            if verify_recovery_code(&code, &hash)? {
+                return Ok(Some(hash));
+            }
+        }
+        Ok(None)
+    })
+    .await?;
+    match matched {
+        // Consuming fails if a concurrent login used the code first
+        Some(hash) => repo
+            .consume_recovery_code(&user.id, &hash)
+            .await
+            .map_err(|e| e.to_string()),
+        None => Ok(false),
+    }
+}
+
+/// Replaces all recovery codes after verifying a current code.
+pub async fn regenerate_recovery_codes(
+    repo: &UserRepository,
+    user: &UserDocument,
+    code: &str,
+) -> Result<Vec<String>, String> {
+    if !verify_second_factor(repo, user, code).await? {
+        return Err("Invalid MFA code".into());
+    }
+    let recovery_codes = generate_recovery_codes();
+    let hashes = hash_recovery_codes(&recovery_codes).await?;
+    repo.replace_recovery_codes(&user.id, &hashes)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(recovery_codes)
+}
+
+/// Turns MFA off after verifying a current code.
+pub async fn disable(repo: &UserRepository, user: &UserDocument, code: &str) -> Result<(), String> {
+    if !verify_second_factor(repo, user, code).await? {
+        return Err("Invalid MFA code".into());
+    }
+    repo.disable_mfa(&user.id).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA1 secret "12345678901234567890"
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc_6238_vectors() {
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+        ];
+        for (time, expected) in vectors {
+            let code = hotp(RFC_SECRET, time_step(time) as u64, 8).unwrap();
+            assert_eq!(code, expected, "time {time}");
+        }
+    }
+
+    #[test]
+    fn verifies_codes_within_skew() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        assert_eq!(verify_code(&secret, "287082", 59).unwrap(), Some(1));
+        assert_eq!(verify_code(&secret, "287082", 89).unwrap(), Some(1));
+        assert_eq!(verify_code(&secret, "287082", 150).unwrap(), None);
+        assert_eq!(verify_code(&secret, "28708", 59).unwrap(), None);
+    }
+
+    #[test]
+    fn recovery_codes_normalize() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        let code = &codes[0];
+        assert_eq!(normalize_recovery_code(code).len(), 16);
+
+        let hash = hash_recovery_code(code).unwrap();
+        assert_ne!(hash, hash_recovery_code(code).unwrap(), "hashes are salted");
+        let retyped = format!(" {} ", code.to_uppercase().replace('-', ""));
+        assert!(verify_recovery_code(&retyped, &hash).unwrap());
+        assert!(!verify_recovery_code(&codes[1], &hash).unwrap());
+
+        let legacy = format!("{:x}", Sha256::digest(b"abcd2345"));
+        assert!(verify_recovery_code("ABCD-2345", &legacy).unwrap());
+    }
+
+    #[test]
+    fn otpauth_uri_escapes_label() {
+        let uri = otpauth_uri("JBSWY3DPEHPK3PXP", "user@example.com");
+        assert!(
+            uri.starts_with(
+                "otpauth://totp/Lock%20Smith%3Auser@example.com?secret=JBSWY3DPEHPK3PXP"
+            )
+        );
+        assert!(uri.contains("issuer=Lock%20Smith"));
+    }
+}
@@ -1,2 +1,16 @@
+pub mod account;
 pub mod auth;
+pub mod groups;
+pub mod key_provider;
+pub mod mailer;
+pub mod mfa;
+pub mod names;
+pub mod organizations;
+pub mod password;
+pub mod policy;
+pub mod recipients;
+pub mod reencryption;
+pub mod seal;
+pub mod secret;
+pub mod template;
 pub mod vault;
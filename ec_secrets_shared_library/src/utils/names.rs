@@ -0,0 +1,47 @@
+use thiserror::Error;
+
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// A group, policy or organization name that doesn't follow [validate_name].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{kind} names have 1 to {MAX_NAME_LENGTH} letters, digits, '-', '_' or '.'")]
+pub struct InvalidName {
+    /// What is being named, e.g. "Group"
+    pub kind: &'static str,
+}
+
+/*---------------------------------------------------------------------------
+    Names of groups, policies and organizations double as path segments
+    and CLI arguments, so they share one restricted alphabet. Modules map
+    [InvalidName] into their own error type.
+---------------------------------------------------------------------------*/
+pub fn validate_name(kind: &'static str, name: &str) -> Result<(), InvalidName> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(InvalidName { kind })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(validate_name("Group", "platform-team").is_ok());
+        assert!(validate_name("Organization", "acme.prod_1").is_ok());
+        assert!(validate_name("Group", "").is_err());
+        assert!(validate_name("Group", "ops team").is_err());
+        assert!(validate_name("Policy", &"a".repeat(MAX_NAME_LENGTH + 1)).is_err());
+        assert_eq!(
+            validate_name("Policy", "a/b").unwrap_err().to_string(),
+            "Policy names have 1 to 64 letters, digits, '-', '_' or '.'"
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use thiserror::Error;
+
+use crate::{
+    models::{Organization, OrganizationDocument, UserDocument},
+    repositories::{
+        keys::KeyRepository, organizations::OrganizationRepository, users::UserRepository,
+        vault::VaultRepository,
+    },
+    utils::{
+        auth::issue_org_token,
+        names::{InvalidName, validate_name},
+        seal::SealError,
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum OrganizationError {
+    #[error("Organization '{0}' not found")]
+    NotFound(String),
+    #[error("An organization with this name already exists")]
+    Exists,
+    #[error("User '{0}' not found")]
+    UserNotFound(String),
+    #[error("'{0}' is not a member of the organization")]
+    NotMember(String),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("The vault is sealed")]
+    Sealed,
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<mongodb::error::Error> for OrganizationError {
+    fn from(error: mongodb::error::Error) -> Self {
+        Self::Internal(error.to_string())
+    }
+}
+
+impl From<InvalidName> for OrganizationError {
+    fn from(error: InvalidName) -> Self {
+        Self::Invalid(error.to_string())
+    }
+}
+
+impl From<SealError> for OrganizationError {
+    fn from(error: SealError) -> Self {
+        match error {
+            SealError::Sealed => Self::Sealed,
+            error => Self::Internal(error.to_string()),
+        }
+    }
+}
+
+pub async fn get_organization(
+    name: &str,
+    repo: &OrganizationRepository,
+) -> Result<OrganizationDocument, OrganizationError> {
+    repo.get_organization(name)
+        .await?
+        .ok_or_else(|| OrganizationError::NotFound(name.to_string()))
+}
+
+/*---------------------------------------------------------------------------
+    Organizations partition the vault. Each one has its own encryption key,
+    wrapped with the master key, and a token only ever reaches the vault of
+    its active organization (the `org` claim).
+---------------------------------------------------------------------------*/
+pub async fn create_organization(
+    org: &Organization,
+    created_by: &str,
+    repo: &OrganizationRepository,
+    user_repo: &UserRepository,
+    vault_repo: &VaultRepository,
+) -> Result<OrganizationDocument, OrganizationError> {
+    validate_name("Organization", &org.name)?;
+    if repo.get_organization(&org.name).await?.is_some() {
+        return Err(OrganizationError::Exists);
+    }
+
+    let document = OrganizationDocument {
+        id: ObjectId::new(),
+        name: org.name.clone(),
+        description: org.description.clone(),
+        encryption_key: vault_repo.generate_org_key().await?,
+        created_by: created_by.to_string(),
+        created_at: Utc::now(),
+    };
+    repo.create_organization(&document).await?;
+    // The creator joins, unless it's a machine identity or unknown
+    user_repo.add_to_org(created_by, &document.id).await?;
+    Ok(document)
+}
+
+/// Name of the personal organization of `user`.
+pub fn personal_org_name(user: &UserDocument) -> String {
+    format!("personal-{}", user.id.to_hex())
+}
+
+/// The organization of a user that belongs to none, created and joined
+/// on first use. It shares the user's id, so concurrent first requests
+/// can't create two.
+pub async fn personal_organization(
+    user: &UserDocument,
+    repo: &OrganizationRepository,
+    user_repo: &UserRepository,
+    vault_repo: &VaultRepository,
+) -> Result<OrganizationDocument, OrganizationError> {
+    if let Some(org) = repo.get_organization_by_id(&user.id).await? {
+        return if user.orgs.contains(&org.id) {
+            Ok(org)
+        } else {
+            Err(OrganizationError::NotMember(user.email.clone()))
+        };
+    }
+    // Members of other organizations have to pick one of them
+    if !user.orgs.is_empty() {
+        return Err(OrganizationError::NotMember(user.email.clone()));
+    }
+
+    let document = OrganizationDocument {
+        id: user.id,
+        name: personal_org_name(user),
+        description: Some("Personal organization".to_string()),
+        encryption_key: vault_repo.generate_org_key().await?,
+        created_by: user.email.clone(),
+        created_at: Utc::now(),
+    };
+    if let Err(error) = repo.create_organization(&document).await {
+        // Lost the race, the other request inserted the same id
+        return match repo.get_organization_by_id(&user.id).await? {
+            Some(org) => Ok(org),
+            None => Err(error.into()),
+        };
+    }
+    user_repo.add_to_org(&user.email, &document.id).await?;
+    Ok(document)
+}
+
+/// Deletes an organization together with its vault entries, removing its
+/// members and revoking their tokens.
+pub async fn delete_organization(
+    name: &str,
+    repo: &OrganizationRepository,
+    user_repo: &UserRepository,
+    vault_repo: &VaultRepository,
+) -> Result<OrganizationDocument, OrganizationError> {
+    let org = repo
+        .delete_organization(name)
+        .await?
+        .ok_or_else(|| OrganizationError::NotFound(name.to_string()))?;
+    user_repo.remove_org(&org.id).await?;
+    vault_repo.remove_org(&org.id).await?;
+    Ok(org)
+}
+
+/// Adds a user to an organization, the user can switch to it right away.
+pub async fn add_member(
+    name: &str,
+    email: &str,
+    repo: &OrganizationRepository,
+    user_repo: &UserRepository,
+) -> Result<UserDocument, OrganizationError> {
+    let org = get_organization(name, repo).await?;
+    user_repo
+        .add_to_org(email, &org.id)
+        .await?
+        .ok_or_else(|| OrganizationError::UserNotFound(email.to_string()))
+}
+
+/// Removes a user from an organization, revoking the user's tokens.
+pub async fn remove_member(
+    name: &str,
+    email: &str,
+    repo: &OrganizationRepository,
+    user_repo: &UserRepository,
+) -> Result<(), OrganizationError> {
+    let org = get_organization(name, repo).await?;
+    if user_repo.remove_from_org(email, &org.id).await?.is_some() {
+        return Ok(());
+    }
+    match user_repo.get_user_by_email(email).await? {
+        Some(_) => Err(OrganizationError::NotMember(email.to_string())),
+        None => Err(OrganizationError::UserNotFound(email.to_string())),
+    }
+}
+
+pub async fn list_members(
+    name: &str,
+    repo: &OrganizationRepository,
+    user_repo: &UserRepository,
+) -> Result<Vec<UserDocument>, OrganizationError> {
+    let org = get_organization(name, repo).await?;
+    Ok(user_repo.list_org_members(&org.id).await?)
+}
+
+/// The organizations a user belongs to.
+pub async fn user_organizations(
+    user: &UserDocument,
+    repo: &OrganizationRepository,
+) -> Result<Vec<OrganizationDocument>, OrganizationError> {
+    Ok(repo.find_organizations(&user.orgs).await?)
+}
+
+/// Issues a session token for the user with `name` as active organization.
+/// Only members can switch, admins included.
+pub async fn switch_organization(
+    name: &str,
+    user: &UserDocument,
+    repo: &OrganizationRepository,
+    key_repo: &KeyRepository,
+) -> Result<String, OrganizationError> {
+    let org = get_organization(name, repo).await?;
+    if !user.orgs.contains(&org.id) {
+        return Err(OrganizationError::NotMember(user.email.clone()));
+    }
+    issue_org_token(user, Some(&org.id), key_repo)
+        .await
+        .map_err(OrganizationError::Internal)
+}
+
+/// Users and vault entries moved into an organization by [adopt_legacy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adopted {
+    pub users: u64,
+    pub secrets: u64,
+}
+
+/// Moves users without an organization and entries stored before
+/// organizations existed into `name`, re-encrypting the entries with the
+/// organization's key.
+pub async fn adopt_legacy(
+    name: &str,
+    repo: &OrganizationRepository,
+    user_repo: &UserRepository,
+    vault_repo: &VaultRepository,
+) -> Result<Adopted, OrganizationError> {
+    let org = get_organization(name, repo).await?;
+    let vault = vault_repo.org_vault(&org).await?;
+    let secrets = vault_repo.adopt_legacy_secrets(&vault).await?;
+    let users = user_repo.adopt_users(&org.id).await?;
+    Ok(Adopted { users, secrets })
+}
+
+/*---------------------------------------------------------------------------
+    Needs MongoDB, so it's ignored by default. Point [ECS_TEST_MONGODB_URI]
+    at a throwaway server, e.g.
+
+        ECS_TEST_MONGODB_URI=mongodb://127.0.0.1:27017 \
+        cargo test removed_members -- --include-ignored
+---------------------------------------------------------------------------*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        repositories::service_accounts::ServiceAccountRepository,
+        utils::auth::{SCOPE_SECRETS_READ, authenticate_api_key, ensure_token_current, token_org},
+    };
+
+    #[test]
+    fn personal_org_names_are_valid() {
+        let user = UserDocument {
+            id: ObjectId::new(),
+            email: "user@example.com".into(),
+            password: String::new(),
+            created_at: Utc::now(),
+            admin: false,
+            mfa: None,
+            email_verified: true,
+            token_version: 0,
+            groups: Vec::new(),
+            orgs: Vec::new(),
+            e2e_public_key: None,
+        };
+        let name = personal_org_name(&user);
+        assert!(validate_name("Organization", &name).is_ok());
+        assert!(name.ends_with(&user.id.to_hex()));
+    }
+
+    #[test]
+    #[ignore = "needs MongoDB, see above"]
+    fn users_without_an_org_get_a_personal_one() {
+        // SAFETY: set before any key is loaded, no other test reads it
+        unsafe { std::env::set_var("ECS_ENCRYPTION_KEY", "test encryption key") };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let uri = std::env::var("ECS_TEST_MONGODB_URI").expect("ECS_TEST_MONGODB_URI");
+            let client = mongodb::Client::with_uri_str(uri).await.unwrap();
+            let db_name = format!("ecs_test_personal_orgs_{}", std::process::id());
+            let repo = OrganizationRepository::new(&client, &db_name, "organizations");
+            let user_repo = UserRepository::new(&client, &db_name, "users");
+            let vault_repo = VaultRepository::new(&client, &db_name, "vault");
+
+            let user = user_repo
+                .create_user("user@example.com", "hash", false)
+                .await
+                .unwrap();
+            let org = personal_organization(&user, &repo, &user_repo, &vault_repo)
+                .await
+                .unwrap();
+            assert_eq!(org.id, user.id);
+            assert_eq!(org.name, personal_org_name(&user));
+            assert!(vault_repo.org_vault(&org).await.is_ok());
+
+            // Joined, so the next login is active in it
+            let user = user_repo
+                .get_user_by_email(&user.email)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(user.orgs, vec![org.id]);
+            let again = personal_organization(&user, &repo, &user_repo, &vault_repo)
+                .await
+                .unwrap();
+            assert_eq!(again.encryption_key, org.encryption_key);
+
+            // Members of other organizations don't get one
+            let member = user_repo
+                .create_user("member@example.com", "hash", false)
+                .await
+                .unwrap();
+            let member = user_repo
+                .add_to_org(&member.email, &org.id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(matches!(
+                personal_organization(&member, &repo, &user_repo, &vault_repo).await,
+                Err(OrganizationError::NotMember(_))
+            ));
+
+            client.database(&db_name).drop().await.unwrap();
+        });
+    }
+
+    #[test]
+    #[ignore = "needs MongoDB, see above"]
+    fn removed_members_api_keys_get_no_org_token() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let uri = std::env::var("ECS_TEST_MONGODB_URI").expect("ECS_TEST_MONGODB_URI");
+            let client = mongodb::Client::with_uri_str(uri).await.unwrap();
+            let db_name = format!("ecs_test_org_members_{}", std::process::id());
+            let repo = OrganizationRepository::new(&client, &db_name, "organizations");
+            let user_repo = UserRepository::new(&client, &db_name, "users");
+            let service_account_repo =
+                ServiceAccountRepository::new(&client, &db_name, "service_accounts", "api_keys");
+
+            // The wrapped key is never unwrapped here
+            let org = OrganizationDocument {
+                id: ObjectId::new(),
+                name: "payments".into(),
+                description: None,
+                encryption_key: "wrapped".into(),
+                created_by: "admin@example.com".into(),
+                created_at: Utc::now(),
+            };
+            repo.create_organization(&org).await.unwrap();
+            let member = user_repo
+                .create_user("member@example.com", "hash", false)
+                .await
+                .unwrap();
+            add_member(&org.name, &member.email, &repo, &user_repo)
+                .await
+                .unwrap();
+            let account = service_account_repo
+                .create_service_account("ci", None, &member.email, Some(org.id))
+                .await
+                .unwrap();
+            let (_, api_key) = service_account_repo
+                .create_api_key(&account.id, &[SCOPE_SECRETS_READ.to_string()], None)
+                .await
+                .unwrap();
+
+            let claims = authenticate_api_key(&api_key, &service_account_repo, &user_repo)
+                .await
+                .unwrap();
+            assert_eq!(token_org(&claims), Some(org.id));
+
+            remove_member(&org.name, &member.email, &repo, &user_repo)
+                .await
+                .unwrap();
+            assert!(
+                authenticate_api_key(&api_key, &service_account_repo, &user_repo)
+                    .await
+                    .is_err()
+            );
+            // Tokens exchanged before the removal stop working too
+            assert!(ensure_token_current(&claims, &user_repo).await.is_err());
+
+            client.database(&db_name).drop().await.unwrap();
+        });
+    }
+}
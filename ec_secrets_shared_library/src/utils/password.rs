@@ -0,0 +1,358 @@
+use std::{collections::HashSet, env, fs, sync::OnceLock};
+
+use argon2::{Config, Variant, Version};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use sha1::{Digest, Sha1};
+use zeroize::Zeroizing;
+
+/*---------------------------------------------------------------------------
+    Password policy, configured through the environment:
+
+    ECS_PASSWORD_MIN_LENGTH         minimum length in characters (12)
+    ECS_PASSWORD_MAX_LENGTH         maximum length in characters (128)
+    ECS_PASSWORD_CHARACTER_CLASSES  required classes out of lowercase,
+                                    uppercase, digit and symbol
+                                    (lowercase,uppercase,digit)
+    ECS_BREACHED_PASSWORDS_FILE     optional list of breached passwords, one
+                                    per line, either plain text or SHA-1 hex
+                                    (`HASH` or `HASH:count` as published by
+                                    Have I Been Pwned)
+---------------------------------------------------------------------------*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharacterClass {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "lowercase" => Ok(Self::Lowercase),
+            "uppercase" => Ok(Self::Uppercase),
+            "digit" => Ok(Self::Digit),
+            "symbol" => Ok(Self::Symbol),
+            other => Err(format!("Unknown password character class '{other}'")),
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_lowercase(),
+            Self::Uppercase => c.is_uppercase(),
+            Self::Digit => c.is_numeric(),
+            Self::Symbol => !c.is_alphanumeric(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Lowercase => "a lowercase letter",
+            Self::Uppercase => "an uppercase letter",
+            Self::Digit => "a digit",
+            Self::Symbol => "a symbol",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub character_classes: Vec<CharacterClass>,
+    /// Lowercased plain text entries of the breached password list
+    breached: HashSet<String>,
+    /// Uppercase SHA-1 hex digests of the breached password list
+    breached_sha1: HashSet<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: 128,
+            character_classes: vec![
+                CharacterClass::Lowercase,
+                CharacterClass::Uppercase,
+                CharacterClass::Digit,
+            ],
+            breached: HashSet::new(),
+            breached_sha1: HashSet::new(),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Result<Self, String> {
+        let mut policy = Self::default();
+        if let Some(min_length) = env_number("ECS_PASSWORD_MIN_LENGTH")? {
+            policy.min_length = min_length;
+        }
+        if let Some(max_length) = env_number("ECS_PASSWORD_MAX_LENGTH")? {
+            policy.max_length = max_length;
+        }
+        if policy.min_length > policy.max_length {
+            return Err("ECS_PASSWORD_MIN_LENGTH exceeds ECS_PASSWORD_MAX_LENGTH".into());
+        }
+        if let Ok(classes) = env::var("ECS_PASSWORD_CHARACTER_CLASSES") {
+            policy.character_classes = classes
+                .split(',')
+                .filter(|class| !class.trim().is_empty())
+                .map(CharacterClass::parse)
+                .collect::<Result<_, _>>()?;
+        }
+        if let Ok(path) = env::var("ECS_BREACHED_PASSWORDS_FILE") {
+            let list = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read breached password list {path}: {e}"))?;
+            policy.load_breached(&list);
+        }
+        Ok(policy)
+    }
+
+    /// Adds the entries of a breached password list, see [PasswordPolicy].
+    pub fn load_breached(&mut self, list: &str) {
+        for line in list.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let digest = line.split_once(':').map_or(line, |(digest, _)| digest);
+            if digest.len() == 40 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                self.breached_sha1.insert(digest.to_uppercase());
+            } else {
+                self.breached.insert(line.to_lowercase());
+            }
+        }
+    }
+
+    /// Checks a new password, listing every rule it breaks.
+    pub fn validate(&self, password: &str) -> Result<(), String> {
+        let length = password.chars().count();
+        let mut violations = Vec::new();
+
+        if length < self.min_length {
+            violations.push(format!("be at least {} characters long", self.min_length));
+        }
+        if length > self.max_length {
+            violations.push(format!("be at most {} characters long", self.max_length));
+        }
+        for class in &self.character_classes {
+            if !password.chars().any(|c| class.matches(c)) {
+                violations.push(format!("contain {}", class.name()));
+            }
+        }
+        if self.is_breached(password) {
+            violations.push("not appear in a known data breach".to_string());
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Password must {}", violations.join(", ")))
+        }
+    }
+
+    fn is_breached(&self, password: &str) -> bool {
+        if self.breached.contains(&password.to_lowercase()) {
+            return true;
+        }
+        if self.breached_sha1.is_empty() {
+            return false;
+        }
+        let digest = Sha1::digest(password.as_bytes());
+        self.breached_sha1
+            .contains(&data_encoding::HEXUPPER.encode(&digest))
+    }
+}
+
+/// Validates a new password against the policy from the environment.
+pub fn validate_password(password: &str) -> Result<(), String> {
+    static POLICY: OnceLock<Result<PasswordPolicy, String>> = OnceLock::new();
+    POLICY
+        .get_or_init(PasswordPolicy::from_env)
+        .as_ref()
+        .map_err(Clone::clone)?
+        .validate(password)
+}
+
+/*---------------------------------------------------------------------------
+    Argon2id password hashing, tunable through the environment:
+
+    ECS_ARGON2_MEMORY_KIB   memory cost in KiB (19456)
+    ECS_ARGON2_ITERATIONS   time cost (2)
+    ECS_ARGON2_PARALLELISM  lanes (1)
+
+    Hashes created with other parameters, and bcrypt hashes from earlier
+    releases, still verify and are reported by [needs_rehash].
+---------------------------------------------------------------------------*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        let config = Config::owasp2();
+        Self {
+            memory_kib: config.mem_cost,
+            iterations: config.time_cost,
+            parallelism: config.lanes,
+        }
+    }
+}
+
+impl Argon2Params {
+    pub fn from_env() -> Result<Self, String> {
+        let mut params = Self::default();
+        if let Some(memory_kib) = env_number("ECS_ARGON2_MEMORY_KIB")? {
+            params.memory_kib = memory_kib;
+        }
+        if let Some(iterations) = env_number("ECS_ARGON2_ITERATIONS")? {
+            params.iterations = iterations;
+        }
+        if let Some(parallelism) = env_number("ECS_ARGON2_PARALLELISM")? {
+            params.parallelism = parallelism;
+        }
+        Ok(params)
+    }
+
+    fn config(&self) -> Config<'static> {
+        Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: self.memory_kib,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            ..Config::owasp2()
+        }
+    }
+
+    /// Parameter section of PHC strings hashed with these parameters.
+    fn encoded(&self) -> String {
+        format!(
+            "m={},t={},p={}",
+            self.memory_kib, self.iterations, self.parallelism
+        )
+    }
+}
+
+fn argon2_params() -> Result<Argon2Params, String> {
+    static PARAMS: OnceLock<Result<Argon2Params, String>> = OnceLock::new();
+    PARAMS.get_or_init(Argon2Params::from_env).clone()
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    hash_password_with(password, &argon2_params()?)
+}
+
+pub fn hash_password_with(password: &str, params: &Argon2Params) -> Result<String, String> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    argon2::hash_encoded(password.as_bytes(), &salt, &params.config()).map_err(|e| e.to_string())
+}
+
+/// Verifies a password against an Argon2 or legacy bcrypt hash.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, String> {
+    if hash.starts_with("$argon2") {
+        argon2::verify_encoded(hash, password.as_bytes()).map_err(|e| e.to_string())
+    } else {
+        bcrypt::verify(password, hash).map_err(|e| e.to_string())
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Argon2 is slow and memory hungry on purpose. Async callers use these
+    variants, which run it on Tokio's blocking pool so a burst of logins
+    cannot stall the executor threads.
+---------------------------------------------------------------------------*/
+pub async fn run_blocking<T, F>(work: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+pub async fn hash_password_async(password: &str) -> Result<String, String> {
+    let password = Zeroizing::new(password.to_owned());
+    run_blocking(move || hash_password(&password)).await
+}
+
+pub async fn verify_password_async(password: &str, hash: &str) -> Result<bool, String> {
+    let (password, hash) = (Zeroizing::new(password.to_owned()), hash.to_owned());
+    run_blocking(move || verify_password(&password, &hash)).await
+}
+
+/// Whether a stored hash should be replaced after the next successful login.
+pub fn needs_rehash(hash: &str) -> bool {
+    match argon2_params() {
+        Ok(params) => needs_rehash_with(hash, &params),
+        Err(_) => false,
+    }
+}
+
+pub fn needs_rehash_with(hash: &str, params: &Argon2Params) -> bool {
+    // $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+    let mut parts = hash.split('$').skip(1);
+    let current = (parts.next(), parts.next(), parts.next());
+    current
+        != (
+            Some("argon2id"),
+            Some("v=19"),
+            Some(params.encoded().as_str()),
+        )
+}
+
+pub(crate) fn env_number<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{name} must be a number")),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Argon2Params = Argon2Params {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn policy_lists_violations() {
+        let mut policy = PasswordPolicy::default();
+        assert!(policy.validate("Correct-Horse-42").is_ok());
+
+        let err = policy.validate("").unwrap_err();
+        assert!(err.contains("at least 12 characters"));
+        assert!(err.contains("an uppercase letter"));
+        assert!(err.contains("a digit"));
+
+        // "Password123!" and, by SHA-1, "Tr0ub4dor&3xyz"
+        policy.load_breached("password123!\n28a3a91021e8fa93faa7f4ed3f7ccc354e66307a:12\n");
+        assert!(policy.validate("Password123!").is_err());
+        assert!(policy.validate("Tr0ub4dor&3xyz").is_err());
+        assert!(policy.validate("Correct-Horse-42").is_ok());
+    }
+
+    #[test]
+    fn argon2id_round_trip_and_bcrypt_upgrade() {
+        let hash = hash_password_with("Correct-Horse-42", &FAST).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("Correct-Horse-42", &hash).unwrap());
+        assert!(!verify_password("wrong", &hash).unwrap());
+        assert!(!needs_rehash_with(&hash, &FAST));
+        assert!(needs_rehash_with(&hash, &Argon2Params::default()));
+
+        let legacy = bcrypt::hash("Correct-Horse-42", 4).unwrap();
+        assert!(verify_password("Correct-Horse-42", &legacy).unwrap());
+        assert!(needs_rehash_with(&legacy, &FAST));
+    }
+}
@@ -0,0 +1,1247 @@
+use std::{fmt, str::FromStr};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chrono::Utc;
+use futures::io::AsyncRead;
+use mongodb::bson::oid::ObjectId;
+use pasetors::claims::Claims;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    models::{
+        Capability, GroupDocument, Policy, PolicyAttachment, PolicyCheck, PolicyDocument,
+        PolicyRule, PolicyRules, PrincipalKind, SecretFile, ServiceAccountDocument, UserDocument,
+        VaultDocument,
+    },
+    repositories::{
+        groups::GroupRepository,
+        organizations::OrganizationRepository,
+        policies::PolicyRepository,
+        service_accounts::ServiceAccountRepository,
+        users::UserRepository,
+        vault::{OrgVault, RevealError, UploadError, VaultRepository, max_file_size},
+    },
+    utils::{
+        auth::{
+            SCOPE_SECRETS_READ, SCOPE_SECRETS_WRITE, is_machine_identity, token_groups, token_org,
+            token_user,
+        },
+        names::{InvalidName, validate_name},
+        organizations::{OrganizationError, personal_organization},
+        seal::SealError,
+        secret::SecretString,
+        vault::e2e,
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum PolicyError {
+    #[error("Access denied: {0}")]
+    Denied(String),
+    #[error("Policy '{0}' not found")]
+    NotFound(String),
+    #[error("A policy with this name already exists")]
+    Exists,
+    #[error("{0} not found")]
+    PrincipalNotFound(String),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("The vault is sealed")]
+    Sealed,
+    #[error("Values are limited to {limit} bytes")]
+    TooLarge { limit: u64 },
+    #[error("{0}")]
+    Integrity(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<mongodb::error::Error> for PolicyError {
+    fn from(error: mongodb::error::Error) -> Self {
+        Self::Internal(error.to_string())
+    }
+}
+
+impl From<OrganizationError> for PolicyError {
+    fn from(error: OrganizationError) -> Self {
+        match error {
+            OrganizationError::Sealed => Self::Sealed,
+            OrganizationError::NotMember(_) => {
+                Self::Denied("the token has no active organization, log in again".into())
+            }
+            error => Self::Internal(error.to_string()),
+        }
+    }
+}
+
+impl From<InvalidName> for PolicyError {
+    fn from(error: InvalidName) -> Self {
+        Self::Invalid(error.to_string())
+    }
+}
+
+impl From<RevealError> for PolicyError {
+    fn from(error: RevealError) -> Self {
+        match error {
+            RevealError::Integrity { .. } => Self::Integrity(error.to_string()),
+            RevealError::Database(error) => error.into(),
+        }
+    }
+}
+
+impl From<UploadError> for PolicyError {
+    fn from(error: UploadError) -> Self {
+        match error {
+            UploadError::TooLarge { limit } => Self::TooLarge { limit },
+            UploadError::Read(_) => Self::Invalid(error.to_string()),
+            UploadError::Database(error) => error.into(),
+        }
+    }
+}
+
+impl From<SealError> for PolicyError {
+    fn from(error: SealError) -> Self {
+        match error {
+            SealError::Sealed => Self::Sealed,
+            error => Self::Internal(error.to_string()),
+        }
+    }
+}
+
+// Path globs match secret keys: `*` matches within one path segment and
+// `**` matches across segments, so `payments/*/prod/*` matches
+// `payments/api/prod/db_password` but not `payments/api/prod/db/password`.
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[char], path: &[char]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            ['*', '*', rest @ ..] => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+            ['*', rest @ ..] => {
+                for i in 0..=path.len() {
+                    if matches(rest, &path[i..]) {
+                        return true;
+                    }
+                    if path.get(i) == Some(&'/') {
+                        break;
+                    }
+                }
+                false
+            }
+            [c, rest @ ..] => path.first() == Some(c) && matches(rest, &path[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    matches(&pattern, &path)
+}
+
+/// Anchored regular expression equivalent to a path glob, used to find
+/// candidate entries in the database.
+pub fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '\\' | '.' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '^' | '$' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+impl Capability {
+    /// Token scope an operation with this capability needs.
+    pub fn scope(&self) -> &'static str {
+        match self {
+            Self::Read | Self::List => SCOPE_SECRETS_READ,
+            Self::Create | Self::Update | Self::Delete | Self::Deny => SCOPE_SECRETS_WRITE,
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(capability: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|known| known.as_str() == capability)
+            .ok_or_else(|| format!("Unknown capability '{capability}'"))
+    }
+}
+
+impl FromStr for PrincipalKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        [Self::User, Self::Group, Self::ServiceAccount]
+            .into_iter()
+            .find(|known| known.as_str() == kind)
+            .ok_or_else(|| format!("Unknown principal kind '{kind}'"))
+    }
+}
+
+// Text form of a rule, one per line in policy files:
+//
+//     read,list  payments/*/prod/*
+//     deny       payments/legacy/**
+//
+// Empty lines and lines starting with `#` are ignored.
+impl FromStr for PolicyRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let mut parts = rule.split_whitespace();
+        let (Some(capabilities), Some(path), None) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!(
+                "Invalid rule '{rule}', expected '<capability,...> <path glob>'"
+            ));
+        };
+        let capabilities = capabilities
+            .split(',')
+            .map(|capability| capability.trim().parse())
+            .collect::<Result<Vec<Capability>, String>>()?;
+        Ok(PolicyRule {
+            path: path.to_string(),
+            capabilities,
+        })
+    }
+}
+
+impl fmt::Display for PolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capabilities: Vec<&str> = self.capabilities.iter().map(Capability::as_str).collect();
+        write!(f, "{} {}", capabilities.join(","), self.path)
+    }
+}
+
+/// Parses a policy file into its rules.
+pub fn parse_rules(text: &str) -> Result<Vec<PolicyRule>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+/// Values of end-to-end encrypted entries have to be base64 sealed values.
+/// Whether they open is up to their recipients, the server can't tell.
+fn validate_sealed(sealed: &str) -> Result<(), PolicyError> {
+    match STANDARD.decode(sealed) {
+        Ok(data) if e2e::is_sealed(&data) => Ok(()),
+        _ => Err(PolicyError::Invalid(
+            "The value isn't a base64 end-to-end sealed value".into(),
+        )),
+    }
+}
+
+fn validate_rules(rules: &[PolicyRule]) -> Result<(), PolicyError> {
+    if rules.is_empty() {
+        return Err(PolicyError::Invalid(
+            "A policy needs at least one rule".into(),
+        ));
+    }
+    for rule in rules {
+        if rule.path.is_empty() || rule.path.contains(char::is_whitespace) {
+            return Err(PolicyError::Invalid(format!(
+                "Invalid path glob '{}'",
+                rule.path
+            )));
+        }
+        if rule.capabilities.is_empty() {
+            return Err(PolicyError::Invalid(format!(
+                "The rule for '{}' grants no capabilities",
+                rule.path
+            )));
+        }
+    }
+    Ok(())
+}
+
+/*---------------------------------------------------------------------------
+    Policy management, admins only.
+---------------------------------------------------------------------------*/
+pub async fn create_policy(
+    policy: &Policy,
+    created_by: &str,
+    repo: &PolicyRepository,
+) -> Result<PolicyDocument, PolicyError> {
+    validate_name("Policy", &policy.name)?;
+    validate_rules(&policy.rules)?;
+    if repo.get_policy(&policy.name).await?.is_some() {
+        return Err(PolicyError::Exists);
+    }
+
+    let document = PolicyDocument {
+        id: ObjectId::new(),
+        name: policy.name.clone(),
+        description: policy.description.clone(),
+        rules: policy.rules.clone(),
+        users: Vec::new(),
+        groups: Vec::new(),
+        service_accounts: Vec::new(),
+        created_by: created_by.to_string(),
+        created_at: Utc::now(),
+    };
+    repo.create_policy(&document).await?;
+    Ok(document)
+}
+
+pub async fn get_policy(
+    name: &str,
+    repo: &PolicyRepository,
+) -> Result<PolicyDocument, PolicyError> {
+    repo.get_policy(name)
+        .await?
+        .ok_or_else(|| PolicyError::NotFound(name.to_string()))
+}
+
+/// Replaces the rules of a policy, effective on the next vault call.
+pub async fn update_policy(
+    name: &str,
+    rules: &PolicyRules,
+    repo: &PolicyRepository,
+) -> Result<PolicyDocument, PolicyError> {
+    validate_rules(&rules.rules)?;
+    repo.update_rules(name, rules.description.as_deref(), &rules.rules)
+        .await?
+        .ok_or_else(|| PolicyError::NotFound(name.to_string()))
+}
+
+pub async fn delete_policy(
+    name: &str,
+    repo: &PolicyRepository,
+) -> Result<PolicyDocument, PolicyError> {
+    repo.delete_policy(name)
+        .await?
+        .ok_or_else(|| PolicyError::NotFound(name.to_string()))
+}
+
+/// Attaches a policy to an existing user, group or service account.
+pub async fn attach_policy(
+    name: &str,
+    attachment: &PolicyAttachment,
+    repo: &PolicyRepository,
+    user_repo: &UserRepository,
+    group_repo: &GroupRepository,
+    service_account_repo: &ServiceAccountRepository,
+) -> Result<PolicyDocument, PolicyError> {
+    let exists = match attachment.kind {
+        PrincipalKind::User => user_repo.get_user_by_email(&attachment.id).await?.is_some(),
+        PrincipalKind::Group => group_repo.get_group(&attachment.id).await?.is_some(),
+        PrincipalKind::ServiceAccount => match ObjectId::parse_str(&attachment.id) {
+            Ok(id) => service_account_repo
+                .find_service_account(&id)
+                .await?
+                .is_some(),
+            Err(_) => false,
+        },
+    };
+    if !exists {
+        return Err(PolicyError::PrincipalNotFound(principal_label(attachment)));
+    }
+    repo.attach(name, attachment.kind, &attachment.id)
+        .await?
+        .ok_or_else(|| PolicyError::NotFound(name.to_string()))
+}
+
+pub async fn detach_policy(
+    name: &str,
+    attachment: &PolicyAttachment,
+    repo: &PolicyRepository,
+) -> Result<PolicyDocument, PolicyError> {
+    if let Some(policy) = repo.detach(name, attachment.kind, &attachment.id).await? {
+        return Ok(policy);
+    }
+    get_policy(name, repo).await?;
+    Err(PolicyError::Invalid(format!(
+        "The policy isn't attached to {}",
+        principal_label(attachment)
+    )))
+}
+
+fn principal_label(attachment: &PolicyAttachment) -> String {
+    let kind = match attachment.kind {
+        PrincipalKind::User => "User",
+        PrincipalKind::Group => "Group",
+        PrincipalKind::ServiceAccount => "Service account",
+    };
+    format!("{kind} '{}'", attachment.id)
+}
+
+/*---------------------------------------------------------------------------
+    Who a vault call is made for. Ownership is decided by `subject`, which
+    for service accounts and AppRoles is the owner they act for.
+---------------------------------------------------------------------------*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub subject: String,
+    /// Whether policies attached to the subject's user account apply
+    pub user: bool,
+    pub groups: Vec<String>,
+    pub service_account: Option<String>,
+    /// Scopes of machine tokens, None for unrestricted user tokens
+    pub scopes: Option<Vec<String>>,
+}
+
+impl Principal {
+    pub fn from_claims(claims: &Claims) -> Self {
+        let string_claim = |name: &str| {
+            claims
+                .get_claim(name)
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
+        };
+        let scopes = claims
+            .get_claim("scopes")
+            .and_then(|scopes| scopes.as_array())
+            .map(|scopes| {
+                scopes
+                    .iter()
+                    .filter_map(|scope| scope.as_str().map(str::to_string))
+                    .collect()
+            });
+        let machine = is_machine_identity(claims);
+        Self {
+            subject: string_claim("sub").unwrap_or_default(),
+            user: !machine,
+            groups: if machine {
+                Vec::new()
+            } else {
+                token_groups(claims)
+            },
+            service_account: string_claim("service_account"),
+            scopes: if machine {
+                Some(scopes.unwrap_or_default())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// A user with its current group memberships.
+    pub fn for_user(user: &UserDocument) -> Self {
+        Self {
+            subject: user.email.clone(),
+            user: true,
+            groups: user.groups.clone(),
+            service_account: None,
+            scopes: None,
+        }
+    }
+
+    /// A service account regardless of the scopes of its API keys.
+    pub fn for_service_account(account: &ServiceAccountDocument) -> Self {
+        Self {
+            subject: account.created_by.clone(),
+            user: false,
+            groups: Vec::new(),
+            service_account: Some(account.id.to_hex()),
+            scopes: None,
+        }
+    }
+}
+
+/// The outcome of evaluating a capability on a path, with the reasons.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Decision {
+    pub allowed: bool,
+    pub capability: Capability,
+    pub path: String,
+    pub reasons: Vec<String>,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on '{}' is {}: {}",
+            self.capability.as_str(),
+            self.path,
+            if self.allowed { "allowed" } else { "denied" },
+            self.reasons.join("; ")
+        )
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Evaluates the policies attached to a principal. In order:
+
+    1. machine tokens need the scope of the capability
+    2. a matching `deny` rule denies, even the owner of the entry
+    3. a matching rule granting the capability allows
+    4. owners can do anything with their entries and create new ones
+    5. entries shared with a group allow what the group's permissions grant
+    6. anything else is denied
+---------------------------------------------------------------------------*/
+#[derive(Debug, Clone)]
+pub struct PolicyEngine {
+    principal: Principal,
+    policies: Vec<PolicyDocument>,
+    groups: Vec<GroupDocument>,
+}
+
+impl PolicyEngine {
+    pub fn new(
+        principal: Principal,
+        policies: Vec<PolicyDocument>,
+        groups: Vec<GroupDocument>,
+    ) -> Self {
+        Self {
+            principal,
+            policies,
+            groups,
+        }
+    }
+
+    /// Loads the policies and groups that apply to `principal`.
+    pub async fn load(
+        principal: Principal,
+        policy_repo: &PolicyRepository,
+        group_repo: &GroupRepository,
+    ) -> Result<Self, PolicyError> {
+        let policies = policy_repo
+            .applicable(
+                principal.user.then_some(principal.subject.as_str()),
+                &principal.groups,
+                principal.service_account.as_deref(),
+            )
+            .await?;
+        let groups = group_repo.find_groups(&principal.groups).await?;
+        Ok(Self::new(principal, policies, groups))
+    }
+
+    pub fn principal(&self) -> &Principal {
+        &self.principal
+    }
+
+    fn matching_rules<'p>(
+        &'p self,
+        path: &'p str,
+    ) -> impl Iterator<Item = (&'p PolicyDocument, &'p PolicyRule)> {
+        self.policies
+            .iter()
+            .flat_map(|policy| policy.rules.iter().map(move |rule| (policy, rule)))
+            .filter(move |(_, rule)| glob_matches(&rule.path, path))
+    }
+
+    /// Decides `capability` on `path`, `entry` being the stored secret
+    /// the operation targets, None when creating or when nothing is stored.
+    pub fn evaluate(
+        &self,
+        capability: Capability,
+        path: &str,
+        entry: Option<&VaultDocument>,
+    ) -> Decision {
+        let decide = |allowed: bool, reasons: Vec<String>| Decision {
+            allowed,
+            capability,
+            path: path.to_string(),
+            reasons,
+        };
+
+        let scope = capability.scope();
+        if let Some(scopes) = &self.principal.scopes
+            && !scopes.iter().any(|granted| granted == scope)
+        {
+            return decide(false, vec![format!("the token lacks the '{scope}' scope")]);
+        }
+
+        let denials: Vec<String> = self
+            .matching_rules(path)
+            .filter(|(_, rule)| rule.capabilities.contains(&Capability::Deny))
+            .map(|(policy, rule)| format!("policy '{}' denies '{}'", policy.name, rule.path))
+            .collect();
+        if !denials.is_empty() {
+            return decide(false, denials);
+        }
+
+        let grants: Vec<String> = self
+            .matching_rules(path)
+            .filter(|(_, rule)| rule.capabilities.contains(&capability))
+            .map(|(policy, rule)| {
+                format!(
+                    "policy '{}' grants {} on '{}'",
+                    policy.name,
+                    capability.as_str(),
+                    rule.path
+                )
+            })
+            .collect();
+        if !grants.is_empty() {
+            return decide(true, grants);
+        }
+
+        match entry {
+            Some(entry) if entry.created_by == self.principal.subject => {
+                decide(true, vec!["the caller owns the entry".into()])
+            }
+            None if capability == Capability::Create => decide(
+                true,
+                vec!["new entries are created in the caller's own vault".into()],
+            ),
+            Some(entry) => {
+                let shared: Vec<String> = self
+                    .groups
+                    .iter()
+                    .filter(|group| {
+                        entry.groups.contains(&group.name)
+                            && group.permissions.iter().any(|granted| granted == scope)
+                    })
+                    .map(|group| {
+                        format!(
+                            "the entry is shared with group '{}' which grants '{scope}'",
+                            group.name
+                        )
+                    })
+                    .collect();
+                if shared.is_empty() {
+                    decide(
+                        false,
+                        vec![format!(
+                            "no policy grants {} on '{path}' and the caller neither owns the entry nor gets it through a group",
+                            capability.as_str()
+                        )],
+                    )
+                } else {
+                    decide(true, shared)
+                }
+            }
+            None => decide(
+                false,
+                vec![format!(
+                    "no policy grants {} on '{path}'",
+                    capability.as_str()
+                )],
+            ),
+        }
+    }
+
+    /// Whether an entry is related to the principal at all. Unrelated
+    /// entries are reported as missing rather than denied.
+    fn visible(&self, entry: &VaultDocument) -> bool {
+        entry.created_by == self.principal.subject
+            || entry
+                .groups
+                .iter()
+                .any(|group| self.principal.groups.contains(group))
+            || self.matching_rules(&entry.key).next().is_some()
+    }
+
+    /// Path globs of the rules granting `capability`, as regular expressions.
+    fn granted_patterns(&self, capability: Capability) -> Vec<String> {
+        self.policies
+            .iter()
+            .flat_map(|policy| policy.rules.iter())
+            .filter(|rule| rule.capabilities.contains(&capability))
+            .map(|rule| glob_to_regex(&rule.path))
+            .collect()
+    }
+}
+
+/*---------------------------------------------------------------------------
+    The vault as seen by a principal. Every call is checked by the policy
+    engine before the organization's vault is used, and only values the
+    principal may read are decrypted.
+---------------------------------------------------------------------------*/
+pub struct AuthorizedVault<'a> {
+    vault: OrgVault<'a>,
+    engine: PolicyEngine,
+}
+
+impl<'a> AuthorizedVault<'a> {
+    pub async fn new(
+        principal: Principal,
+        vault: OrgVault<'a>,
+        policy_repo: &PolicyRepository,
+        group_repo: &GroupRepository,
+    ) -> Result<Self, PolicyError> {
+        let engine = PolicyEngine::load(principal, policy_repo, group_repo).await?;
+        Ok(Self { vault, engine })
+    }
+
+    /// The vault of the token's active organization, or of the user's
+    /// personal organization for tokens without one.
+    pub async fn for_claims(
+        claims: &Claims,
+        vault_repo: &'a VaultRepository,
+        org_repo: &OrganizationRepository,
+        policy_repo: &PolicyRepository,
+        group_repo: &GroupRepository,
+        user_repo: &UserRepository,
+    ) -> Result<Self, PolicyError> {
+        let vault = org_vault(claims, vault_repo, org_repo, user_repo).await?;
+        Self::new(
+            Principal::from_claims(claims),
+            vault,
+            policy_repo,
+            group_repo,
+        )
+        .await
+    }
+
+    pub fn org_id(&self) -> &ObjectId {
+        self.vault.org_id()
+    }
+
+    pub fn engine(&self) -> &PolicyEngine {
+        &self.engine
+    }
+
+    fn subject(&self) -> &str {
+        &self.engine.principal.subject
+    }
+
+    fn authorize(
+        &self,
+        capability: Capability,
+        path: &str,
+        entry: Option<&VaultDocument>,
+    ) -> Result<(), PolicyError> {
+        let decision = self.engine.evaluate(capability, path, entry);
+        if decision.allowed {
+            Ok(())
+        } else {
+            Err(PolicyError::Denied(decision.to_string()))
+        }
+    }
+
+    fn allows(&self, capability: Capability, entry: &VaultDocument) -> bool {
+        self.engine
+            .evaluate(capability, &entry.key, Some(entry))
+            .allowed
+    }
+
+    /// Entries stored under `key`, the principal's own first.
+    async fn entries_by_key(&self, key: &str) -> Result<Vec<VaultDocument>, PolicyError> {
+        let mut entries = self.vault.find_by_key(key).await?;
+        entries.sort_by_key(|entry| entry.created_by != self.subject());
+        Ok(entries)
+    }
+
+    async fn visible_entry(&self, id: &str) -> Result<Option<VaultDocument>, PolicyError> {
+        let Ok(id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        Ok(self
+            .vault
+            .find_by_id(&id)
+            .await?
+            .filter(|entry| self.engine.visible(entry)))
+    }
+
+    pub async fn create_secret(
+        &self,
+        key: &str,
+        value: &str,
+    ) -> Result<VaultDocument, PolicyError> {
+        self.authorize(Capability::Create, key, None)?;
+        Ok(self.vault.create_secret(key, value, self.subject()).await?)
+    }
+
+    /// Stores a value sealed by the client, see [validate_sealed].
+    pub async fn create_sealed_secret(
+        &self,
+        key: &str,
+        sealed: &str,
+    ) -> Result<VaultDocument, PolicyError> {
+        self.authorize(Capability::Create, key, None)?;
+        validate_sealed(sealed)?;
+        Ok(self
+            .vault
+            .create_sealed_secret(key, sealed, self.subject())
+            .await?)
+    }
+
+    /// Stores bytes as a binary entry, up to [max_file_size].
+    pub async fn create_file(
+        &self,
+        key: &str,
+        file: &SecretFile,
+    ) -> Result<VaultDocument, PolicyError> {
+        self.authorize(Capability::Create, key, None)?;
+        Ok(self
+            .vault
+            .create_file(key, file, self.subject(), max_file_size())
+            .await?)
+    }
+
+    /// Streams `reader` into a binary entry, up to `limit` bytes and
+    /// [max_file_size].
+    pub async fn create_file_from<R: AsyncRead + Unpin>(
+        &self,
+        key: &str,
+        file_name: Option<String>,
+        content_type: String,
+        reader: &mut R,
+        limit: u64,
+    ) -> Result<VaultDocument, PolicyError> {
+        self.authorize(Capability::Create, key, None)?;
+        let limit = limit.min(max_file_size());
+        Ok(self
+            .vault
+            .create_file_from(key, file_name, content_type, reader, self.subject(), limit)
+            .await?)
+    }
+
+    /// The value of a readable entry, binary values base64 encoded.
+    async fn reveal(&self, entry: &VaultDocument) -> Result<Option<SecretString>, PolicyError> {
+        if entry
+            .file
+            .as_ref()
+            .is_some_and(|file| file.blob_id.is_some())
+        {
+            let data = self.vault.reveal_bytes(entry).await?;
+            return Ok(data.map(|data| STANDARD.encode(data).into()));
+        }
+        Ok(self.vault.reveal(entry).await?)
+    }
+
+    async fn reveal_file(&self, entry: &VaultDocument) -> Result<Option<SecretFile>, PolicyError> {
+        let Some(data) = self.vault.reveal_bytes(entry).await? else {
+            return Ok(None);
+        };
+        Ok(Some(match &entry.file {
+            Some(file) => SecretFile {
+                file_name: file.file_name.clone(),
+                content_type: file.content_type.clone(),
+                data,
+            },
+            None => SecretFile {
+                file_name: None,
+                content_type: "text/plain; charset=utf-8".into(),
+                data,
+            },
+        }))
+    }
+
+    /// The readable entry the principal gets for `key`, its own first.
+    /// Errs when the only entries under `key` are visible but unreadable.
+    async fn readable_entry(&self, key: &str) -> Result<Option<VaultDocument>, PolicyError> {
+        let mut entries = self.entries_by_key(key).await?;
+        if let Some(index) = entries
+            .iter()
+            .position(|entry| self.allows(Capability::Read, entry))
+        {
+            return Ok(Some(entries.swap_remove(index)));
+        }
+        match entries.iter().find(|entry| self.engine.visible(entry)) {
+            Some(entry) => self
+                .authorize(Capability::Read, key, Some(entry))
+                .map(|_| None),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_secret_by_id(&self, id: &str) -> Result<Option<SecretString>, PolicyError> {
+        let Some(entry) = self.visible_entry(id).await? else {
+            return Ok(None);
+        };
+        self.authorize(Capability::Read, &entry.key, Some(&entry))?;
+        self.reveal(&entry).await
+    }
+
+    pub async fn get_secret_by_key(&self, key: &str) -> Result<Option<SecretString>, PolicyError> {
+        match self.readable_entry(key).await? {
+            Some(entry) => self.reveal(&entry).await,
+            None => Ok(None),
+        }
+    }
+
+    /// The entry [Self::get_secret_by_key] reads, value decrypted, for
+    /// callers that need to tell end-to-end encrypted values apart.
+    pub async fn get_entry_by_key(&self, key: &str) -> Result<Option<VaultDocument>, PolicyError> {
+        let Some(mut entry) = self.readable_entry(key).await? else {
+            return Ok(None);
+        };
+        let Some(value) = self.reveal(&entry).await? else {
+            return Ok(None);
+        };
+        entry.value = value;
+        Ok(Some(entry))
+    }
+
+    /// The raw bytes of an entry, text entries included.
+    pub async fn get_file_by_id(&self, id: &str) -> Result<Option<SecretFile>, PolicyError> {
+        let Some(entry) = self.visible_entry(id).await? else {
+            return Ok(None);
+        };
+        self.authorize(Capability::Read, &entry.key, Some(&entry))?;
+        self.reveal_file(&entry).await
+    }
+
+    pub async fn get_file_by_key(&self, key: &str) -> Result<Option<SecretFile>, PolicyError> {
+        match self.readable_entry(key).await? {
+            Some(entry) => self.reveal_file(&entry).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Entries the principal can both list and read, values decrypted.
+    pub async fn list_secrets(&self) -> Result<Vec<VaultDocument>, PolicyError> {
+        let candidates = self.list_candidates().await?;
+        self.decrypt_readable(candidates).await
+    }
+
+    /// The readable ones of `entries`, each decrypted off the executor.
+    async fn decrypt_readable(
+        &self,
+        entries: Vec<VaultDocument>,
+    ) -> Result<Vec<VaultDocument>, PolicyError> {
+        let mut decrypted = Vec::new();
+        for entry in entries {
+            if self.allows(Capability::Read, &entry) {
+                decrypted.push(self.vault.decrypted(entry).await?);
+            }
+        }
+        Ok(decrypted)
+    }
+
+    pub async fn list_secret_keys(&self) -> Result<Vec<String>, PolicyError> {
+        let mut keys: Vec<String> = self
+            .list_candidates()
+            .await?
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    async fn list_candidates(&self) -> Result<Vec<VaultDocument>, PolicyError> {
+        let candidates = self
+            .vault
+            .find_candidates(
+                self.subject(),
+                &self.engine.principal.groups,
+                &self.engine.granted_patterns(Capability::List),
+            )
+            .await?;
+        Ok(candidates
+            .into_iter()
+            .filter(|entry| self.allows(Capability::List, entry))
+            .collect())
+    }
+
+    /// Readable entries of an author, values decrypted.
+    pub async fn secrets_by_author(
+        &self,
+        created_by: &str,
+    ) -> Result<Vec<VaultDocument>, PolicyError> {
+        let entries = self.vault.find_by_author(created_by).await?;
+        self.decrypt_readable(entries).await
+    }
+
+    /// Deletes an entry, returning it as it was stored.
+    pub async fn delete_secret(&self, id: &str) -> Result<Option<VaultDocument>, PolicyError> {
+        let Some(entry) = self.visible_entry(id).await? else {
+            return Ok(None);
+        };
+        self.authorize(Capability::Delete, &entry.key, Some(&entry))?;
+        Ok(self.vault.delete_by_id(&entry.id).await?)
+    }
+
+    /// Shares the principal's entries under `key` with a group, returns
+    /// false when the principal has no entry under `key`.
+    pub async fn share_secret(&self, key: &str, group: &str) -> Result<bool, PolicyError> {
+        let Some(entry) = self.own_entry(key).await? else {
+            return Ok(false);
+        };
+        self.authorize(Capability::Update, key, Some(&entry))?;
+        Ok(self.vault.share_secret(key, self.subject(), group).await? > 0)
+    }
+
+    pub async fn unshare_secret(&self, key: &str, group: &str) -> Result<bool, PolicyError> {
+        let Some(entry) = self.own_entry(key).await? else {
+            return Ok(false);
+        };
+        self.authorize(Capability::Update, key, Some(&entry))?;
+        Ok(self
+            .vault
+            .unshare_secret(key, self.subject(), group)
+            .await?
+            > 0)
+    }
+
+    /// Replaces the sealed value of the principal's end-to-end encrypted
+    /// entry under `key`, e.g. with recipients added. Returns false when
+    /// the principal has no entry under `key`.
+    pub async fn reseal_secret(&self, key: &str, sealed: &str) -> Result<bool, PolicyError> {
+        let Some(entry) = self.own_entry(key).await? else {
+            return Ok(false);
+        };
+        self.authorize(Capability::Update, key, Some(&entry))?;
+        if !entry.end_to_end {
+            return Err(PolicyError::Invalid(format!(
+                "'{key}' isn't end-to-end encrypted"
+            )));
+        }
+        validate_sealed(sealed)?;
+        if self.vault.replace_sealed(&entry, sealed).await? {
+            Ok(true)
+        } else {
+            Err(PolicyError::Invalid(format!(
+                "'{key}' was changed meanwhile, please try again"
+            )))
+        }
+    }
+
+    async fn own_entry(&self, key: &str) -> Result<Option<VaultDocument>, PolicyError> {
+        Ok(self
+            .entries_by_key(key)
+            .await?
+            .into_iter()
+            .find(|entry| entry.created_by == self.subject()))
+    }
+
+    /// Explains how `capability` on `path` is decided for the principal,
+    /// against the entry the principal would get for `path`.
+    pub async fn explain(
+        &self,
+        capability: Capability,
+        path: &str,
+    ) -> Result<Decision, PolicyError> {
+        if capability == Capability::Create {
+            return Ok(self.engine.evaluate(capability, path, None));
+        }
+        let entries = self.entries_by_key(path).await?;
+        let entry = entries
+            .iter()
+            .find(|entry| self.allows(capability, entry))
+            .or_else(|| entries.iter().find(|entry| self.engine.visible(entry)));
+        let mut decision = self.engine.evaluate(capability, path, entry);
+        if entry.is_none() {
+            decision
+                .reasons
+                .push("no secret the caller can see is stored at this path".into());
+        }
+        Ok(decision)
+    }
+}
+
+/// The vault of the organization a token is active in. Tokens of users
+/// that belong to no organization use the user's personal one.
+pub async fn org_vault<'a>(
+    claims: &Claims,
+    vault_repo: &'a VaultRepository,
+    org_repo: &OrganizationRepository,
+    user_repo: &UserRepository,
+) -> Result<OrgVault<'a>, PolicyError> {
+    let org = match token_org(claims) {
+        Some(org_id) => org_repo
+            .get_organization_by_id(&org_id)
+            .await?
+            .ok_or_else(|| {
+                PolicyError::Denied("the token's organization no longer exists".into())
+            })?,
+        None => {
+            let user = token_user(claims, user_repo)
+                .await
+                .map_err(PolicyError::Denied)?;
+            personal_organization(&user, org_repo, user_repo, vault_repo).await?
+        }
+    };
+    Ok(vault_repo.org_vault(&org).await?)
+}
+
+/// The principal a policy check is about: the caller, or the user or
+/// service account named in the check.
+pub async fn check_principal(
+    check: &PolicyCheck,
+    claims: &Claims,
+    user_repo: &UserRepository,
+    service_account_repo: &ServiceAccountRepository,
+) -> Result<Principal, PolicyError> {
+    if let Some(email) = &check.user {
+        let user = user_repo
+            .get_user_by_email(email)
+            .await?
+            .ok_or_else(|| PolicyError::PrincipalNotFound(format!("User '{email}'")))?;
+        return Ok(Principal::for_user(&user));
+    }
+    if let Some(id) = &check.service_account {
+        let not_found = || PolicyError::PrincipalNotFound(format!("Service account '{id}'"));
+        let id = ObjectId::parse_str(id).map_err(|_| not_found())?;
+        let account = service_account_repo
+            .find_service_account(&id)
+            .await?
+            .ok_or_else(not_found)?;
+        return Ok(Principal::for_service_account(&account));
+    }
+    Ok(Principal::from_claims(claims))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(name: &str, rules: &str) -> PolicyDocument {
+        PolicyDocument {
+            id: ObjectId::new(),
+            name: name.into(),
+            description: None,
+            rules: parse_rules(rules).unwrap(),
+            users: Vec::new(),
+            groups: Vec::new(),
+            service_accounts: Vec::new(),
+            created_by: "admin@domain.com".into(),
+            created_at: Utc::now(),
+        }
+    }
+
+    fn entry(key: &str, created_by: &str, groups: &[&str]) -> VaultDocument {
+        VaultDocument {
+            id: ObjectId::new(),
+            key: key.into(),
+            value: SecretString::default(),
+            created_by: created_by.into(),
+            created_at: Utc::now(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+            org_id: None,
+            file: None,
+            end_to_end: false,
+        }
+    }
+
+    fn user(groups: &[&str]) -> Principal {
+        Principal {
+            subject: "user@domain.com".into(),
+            user: true,
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+            service_account: None,
+            scopes: None,
+        }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("payments/*/prod/*", "payments/api/prod/db"));
+        assert!(!glob_matches(
+            "payments/*/prod/*",
+            "payments/api/prod/db/password"
+        ));
+        assert!(!glob_matches(
+            "payments/*/prod/*",
+            "payments/api/staging/db"
+        ));
+        assert!(glob_matches("payments/**", "payments/api/prod/db/password"));
+        assert!(glob_matches("*", "db"));
+        assert!(!glob_matches("*", "app/db"));
+        assert_eq!(glob_to_regex("a.b/*/**"), r"^a\.b/[^/]*/.*$");
+    }
+
+    #[test]
+    fn rule_syntax() {
+        let rules =
+            parse_rules("# payments\nread,list payments/*/prod/*\n\ndeny payments/legacy/**\n")
+                .unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].capabilities, [Capability::Read, Capability::List]);
+        assert_eq!(rules[1].to_string(), "deny payments/legacy/**");
+        assert!(parse_rules("write payments/*").is_err());
+        assert!(parse_rules("read").is_err());
+    }
+
+    #[test]
+    fn grants_ownership_and_groups() {
+        let engine = PolicyEngine::new(
+            user(&["platform"]),
+            vec![policy("payments", "read,list payments/*/prod/*")],
+            vec![GroupDocument {
+                id: ObjectId::new(),
+                name: "platform".into(),
+                description: None,
+                permissions: vec![SCOPE_SECRETS_READ.into()],
+                created_by: "admin@domain.com".into(),
+                created_at: Utc::now(),
+            }],
+        );
+        let other = entry("payments/api/prod/db", "other@domain.com", &[]);
+        let decision = engine.evaluate(Capability::Read, &other.key, Some(&other));
+        assert!(decision.allowed);
+        assert_eq!(
+            decision.reasons,
+            ["policy 'payments' grants read on 'payments/*/prod/*'"]
+        );
+        assert!(
+            !engine
+                .evaluate(Capability::Delete, &other.key, Some(&other))
+                .allowed
+        );
+
+        let own = entry("app/db", "user@domain.com", &[]);
+        assert!(
+            engine
+                .evaluate(Capability::Delete, &own.key, Some(&own))
+                .allowed
+        );
+
+        let shared = entry("app/db", "other@domain.com", &["platform"]);
+        assert!(
+            engine
+                .evaluate(Capability::Read, &shared.key, Some(&shared))
+                .allowed
+        );
+        assert!(
+            !engine
+                .evaluate(Capability::Update, &shared.key, Some(&shared))
+                .allowed
+        );
+        assert!(
+            engine
+                .evaluate(Capability::Create, "anything", None)
+                .allowed
+        );
+    }
+
+    #[test]
+    fn deny_overrides_and_scopes() {
+        let mut principal = user(&[]);
+        let engine = PolicyEngine::new(
+            principal.clone(),
+            vec![
+                policy("payments", "read payments/**"),
+                policy("freeze", "deny payments/legacy/**"),
+            ],
+            Vec::new(),
+        );
+        let own = entry("payments/legacy/key", "user@domain.com", &[]);
+        let decision = engine.evaluate(Capability::Read, &own.key, Some(&own));
+        assert!(!decision.allowed);
+        assert_eq!(
+            decision.reasons,
+            ["policy 'freeze' denies 'payments/legacy/**'"]
+        );
+        assert!(
+            !engine
+                .evaluate(Capability::Create, "payments/legacy/new", None)
+                .allowed
+        );
+
+        principal.scopes = Some(vec![SCOPE_SECRETS_READ.into()]);
+        let engine = PolicyEngine::new(principal, Vec::new(), Vec::new());
+        let own = entry("app/db", "user@domain.com", &[]);
+        assert!(
+            engine
+                .evaluate(Capability::Read, &own.key, Some(&own))
+                .allowed
+        );
+        assert!(
+            !engine
+                .evaluate(Capability::Delete, &own.key, Some(&own))
+                .allowed
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use mongodb::bson::oid::ObjectId;
+use thiserror::Error;
+
+use crate::{
+    models::{E2eRecipient, UserDocument},
+    repositories::{groups::GroupRepository, users::UserRepository},
+    utils::vault::e2e::RecipientKey,
+};
+
+#[derive(Error, Debug)]
+pub enum RecipientError {
+    #[error("Invalid public key, expected a base64 X25519 public key")]
+    InvalidKey,
+    #[error("{} not found in the organization", .0.join(", "))]
+    NotFound(Vec<String>),
+    #[error("Group '{0}' not found")]
+    GroupNotFound(String),
+    #[error("No public key registered for {}, run `e2e init` first", .0.join(", "))]
+    MissingKeys(Vec<String>),
+    #[error("No recipients given")]
+    Empty,
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<mongodb::error::Error> for RecipientError {
+    fn from(error: mongodb::error::Error) -> Self {
+        Self::Internal(error.to_string())
+    }
+}
+
+/*---------------------------------------------------------------------------
+    End-to-end encrypted values (`utils::vault::e2e`) are sealed by clients
+    for the public keys of their recipients. Users register their public
+    key once, clients then look recipients up by email or group, within
+    the caller's organization. Values only reach members who had a key
+    when they were sealed; later ones are added by a recipient.
+---------------------------------------------------------------------------*/
+pub async fn register_public_key(
+    user: &UserDocument,
+    public_key: &str,
+    user_repo: &UserRepository,
+) -> Result<RecipientKey, RecipientError> {
+    let key = RecipientKey::from_base64(public_key).map_err(|_| RecipientError::InvalidKey)?;
+    user_repo
+        .set_e2e_public_key(&user.id, &key.to_base64())
+        .await?;
+    Ok(key)
+}
+
+/// Public keys of the members of `org` among `emails` and the members of
+/// `groups`. Errs when any of them has no key, rather than sealing a value
+/// some recipients can't open.
+pub async fn find_recipients(
+    org: &ObjectId,
+    emails: &[String],
+    groups: &[String],
+    user_repo: &UserRepository,
+    group_repo: &GroupRepository,
+) -> Result<Vec<E2eRecipient>, RecipientError> {
+    if emails.is_empty() && groups.is_empty() {
+        return Err(RecipientError::Empty);
+    }
+    for group in groups {
+        if group_repo.get_group(group).await?.is_none() {
+            return Err(RecipientError::GroupNotFound(group.clone()));
+        }
+    }
+    let users = user_repo.find_recipients(org, emails, groups).await?;
+
+    let unknown: Vec<String> = emails
+        .iter()
+        .filter(|email| !users.iter().any(|user| &user.email == *email))
+        .cloned()
+        .collect();
+    if !unknown.is_empty() {
+        return Err(RecipientError::NotFound(unknown));
+    }
+    let missing: Vec<String> = users
+        .iter()
+        .filter(|user| user.e2e_public_key.is_none())
+        .map(|user| user.email.clone())
+        .collect();
+    if !missing.is_empty() {
+        return Err(RecipientError::MissingKeys(missing));
+    }
+    Ok(users
+        .into_iter()
+        .filter_map(|user| {
+            user.e2e_public_key.map(|public_key| E2eRecipient {
+                email: user.email,
+                public_key,
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    repositories::{
+        keys::KeyRepository, organizations::OrganizationRepository, users::UserRepository,
+        vault::VaultRepository,
+    },
+    utils::{
+        mfa,
+        seal::{SealError, master_key},
+        vault::EnvelopeParams,
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum ReencryptionError {
+    #[error("{0}")]
+    Config(String),
+    #[error("The vault is sealed")]
+    Sealed,
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<mongodb::error::Error> for ReencryptionError {
+    fn from(error: mongodb::error::Error) -> Self {
+        Self::Internal(error.to_string())
+    }
+}
+
+impl From<SealError> for ReencryptionError {
+    fn from(error: SealError) -> Self {
+        match error {
+            SealError::Sealed => Self::Sealed,
+            error => Self::Internal(error.to_string()),
+        }
+    }
+}
+
+/// Counts of a re-encryption run. Ciphertexts already using the current
+/// parameters aren't counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reencrypted {
+    pub org_keys: u64,
+    pub secrets: u64,
+    pub signing_keys: u64,
+    pub mfa_secrets: u64,
+    /// Vault entries and TOTP secrets that couldn't be decrypted
+    pub failed: u64,
+}
+
+/*---------------------------------------------------------------------------
+    Moves everything encrypted at rest to the envelope parameters configured
+    for new data (see `utils::vault`): organization keys, vault entries and
+    their GridFS blobs, the token signing key and TOTP secrets. Every write
+    is conditional on the ciphertext it replaces and ciphertexts already up
+    to date are skipped, so an interrupted run can simply be started again.
+    Entries from before organizations existed are re-encrypted when they're
+    adopted instead.
+---------------------------------------------------------------------------*/
+pub async fn reencrypt_all(
+    org_repo: &OrganizationRepository,
+    vault_repo: &VaultRepository,
+    key_repo: &KeyRepository,
+    user_repo: &UserRepository,
+) -> Result<Reencrypted, ReencryptionError> {
+    let params = EnvelopeParams::from_env().map_err(ReencryptionError::Config)?;
+    master_key()?;
+    let mut reencrypted = Reencrypted::default();
+
+    for org in org_repo.list_organizations().await? {
+        if let Some(wrapped) = vault_repo.rewrap_org_key(&org, params)?
+            && org_repo
+                .replace_encryption_key(&org.id, &org.encryption_key, &wrapped)
+                .await?
+        {
+            reencrypted.org_keys += 1;
+        }
+        let (secrets, failed) = vault_repo
+            .org_vault(&org)
+            .await?
+            .reencrypt_entries(params)
+            .await?;
+        reencrypted.secrets += secrets;
+        reencrypted.failed += failed;
+    }
+
+    if key_repo
+        .reencrypt_key_pair(params)
+        .await
+        .map_err(ReencryptionError::Internal)?
+    {
+        reencrypted.signing_keys += 1;
+    }
+
+    for user in user_repo.list_mfa_users().await? {
+        let Some(current) = user.mfa.as_ref().map(|mfa| &mfa.secret) else {
+            continue;
+        };
+        match mfa::reseal_secret(current, params) {
+            Ok(Some(resealed)) => {
+                if user_repo
+                    .replace_mfa_secret(&user.id, current, &resealed)
+                    .await?
+                {
+                    reencrypted.mfa_secrets += 1;
+                }
+            }
+            Ok(None) => {}
+            Err(_) => reencrypted.failed += 1,
+        }
+    }
+    Ok(reencrypted)
+}
@@ -0,0 +1,360 @@
+use std::sync::{LazyLock, Mutex, RwLock};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sharks::{Share, Sharks};
+use thiserror::Error;
+
+use crate::{
+    models::{SealDocument, SealInit},
+    repositories::{
+        keys::KeyRepository, organizations::OrganizationRepository, seal::SealRepository,
+        users::UserRepository, vault::VaultRepository,
+    },
+    utils::{
+        key_provider::{self, KeyName},
+        secret::{SecretBytes, SecretString},
+        vault::{decrypt, encrypt},
+    },
+};
+
+/// Value encrypted with the master key at initialization, a recovered key
+/// that decrypts it is the right one.
+const SEAL_CHECK: &[u8] = b"locksmith-seal-check";
+
+#[derive(Error, Debug)]
+pub enum SealError {
+    #[error("The vault is sealed")]
+    Sealed,
+    #[error("Sealed mode is not enabled, set [ECS_SEAL_MODE] to shamir")]
+    Disabled,
+    #[error("The vault has not been initialized")]
+    NotInitialized,
+    #[error("The vault is already initialized")]
+    AlreadyInitialized,
+    #[error(
+        "Stored data is encrypted with a master key the key provider doesn't have, \
+         make it available to initialize"
+    )]
+    KeyUnavailable,
+    #[error("{0}")]
+    InvalidShare(String),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<mongodb::error::Error> for SealError {
+    fn from(error: mongodb::error::Error) -> Self {
+        Self::Internal(error.to_string())
+    }
+}
+
+/*---------------------------------------------------------------------------
+    The master key of this process. Without [ECS_SEAL_MODE]=shamir it comes
+    from the key provider (see `utils::key_provider`). In sealed mode the process starts without it
+    and only holds it in memory once enough Shamir shares were submitted,
+    until it is sealed again.
+---------------------------------------------------------------------------*/
+struct MasterKey {
+    key: RwLock<Option<SecretString>>,
+    /// Shares submitted so far towards unsealing
+    shares: Mutex<Vec<SecretBytes>>,
+}
+
+static MASTER_KEY: LazyLock<MasterKey> = LazyLock::new(|| MasterKey {
+    key: RwLock::new(None),
+    shares: Mutex::new(Vec::new()),
+});
+
+/// Whether the instance runs in sealed mode ([ECS_SEAL_MODE]=shamir).
+pub fn sealed_mode() -> bool {
+    std::env::var("ECS_SEAL_MODE").is_ok_and(|mode| mode.eq_ignore_ascii_case("shamir"))
+}
+
+/// The master key, which wraps organization keys and TOTP secrets.
+/// Outside sealed mode it comes from the key provider, a failed load is
+/// retried on the next call.
+pub fn master_key() -> Result<SecretString, SealError> {
+    if let Some(key) = MASTER_KEY.key.read().unwrap().as_ref() {
+        return Ok(key.clone());
+    }
+    if sealed_mode() {
+        return Err(SealError::Sealed);
+    }
+    let key = key_provider::require_key(KeyName::Encryption)
+        .map_err(|e| SealError::Internal(e.to_string()))?;
+    *MASTER_KEY.key.write().unwrap() = Some(key.clone());
+    Ok(key)
+}
+
+pub fn is_sealed() -> bool {
+    sealed_mode() && MASTER_KEY.key.read().unwrap().is_none()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealStatus {
+    pub sealed_mode: bool,
+    pub initialized: bool,
+    pub sealed: bool,
+    pub shares: u8,
+    pub threshold: u8,
+    /// Shares submitted towards unsealing
+    pub progress: u8,
+}
+
+pub async fn status(repo: &SealRepository) -> Result<SealStatus, SealError> {
+    let seal = repo.get_seal().await?;
+    Ok(SealStatus {
+        sealed_mode: sealed_mode(),
+        initialized: seal.is_some(),
+        sealed: is_sealed(),
+        shares: seal.as_ref().map_or(0, |seal| seal.shares),
+        threshold: seal.as_ref().map_or(0, |seal| seal.threshold),
+        progress: MASTER_KEY.shares.lock().unwrap().len() as u8,
+    })
+}
+
+/// Splits `key` into `shares` base64 shares, any `threshold` of which
+/// recover it.
+pub fn split_key(key: &str, shares: u8, threshold: u8) -> Result<Vec<SecretString>, SealError> {
+    if threshold == 0 || threshold > shares {
+        return Err(SealError::Invalid(
+            "The threshold has to be between 1 and the number of shares".into(),
+        ));
+    }
+    Ok(Sharks(threshold)
+        .dealer(key.as_bytes())
+        .take(shares as usize)
+        .map(|share| SecretString::new(STANDARD.encode(SecretBytes::new(Vec::from(&share)))))
+        .collect())
+}
+
+/// Recovers the key from decoded shares and checks it against `check`.
+pub fn recover_key(
+    shares: &[SecretBytes],
+    threshold: u8,
+    check: &str,
+) -> Result<SecretString, SealError> {
+    let shares = shares
+        .iter()
+        .map(|share| Share::try_from(share.as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| SealError::InvalidShare(e.to_string()))?;
+    let key = Sharks(threshold)
+        .recover(&shares)
+        .map(SecretBytes::new)
+        .map_err(|e| SealError::InvalidShare(e.to_string()))?;
+    let check = STANDARD
+        .decode(check)
+        .map_err(|e| SealError::Internal(e.to_string()))?;
+    match decrypt(&check, &key) {
+        Ok(value) if value.as_slice() == SEAL_CHECK => key
+            .into_string()
+            .map_err(|_| SealError::InvalidShare("The shares don't match".into())),
+        _ => Err(SealError::InvalidShare(
+            "The shares don't recover the master key".into(),
+        )),
+    }
+}
+
+/// Digest of a decoded share, stored to tell wrong shares apart.
+fn share_digest(share: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(share))
+}
+
+/// Whether anything is stored encrypted with a master key: the signing
+/// key, organization keys, vault entries or TOTP secrets.
+pub async fn holds_encrypted_data(
+    key_repo: &KeyRepository,
+    org_repo: &OrganizationRepository,
+    vault_repo: &VaultRepository,
+    user_repo: &UserRepository,
+) -> Result<bool, SealError> {
+    Ok(key_repo.has_key_pair().await.map_err(SealError::Internal)?
+        || org_repo.has_organizations().await?
+        || vault_repo.has_entries().await?
+        || !user_repo.list_mfa_users().await?.is_empty())
+}
+
+/// The master key to split: the existing one, or a new one when nothing
+/// is stored yet. A new key over stored data would leave it undecryptable.
+fn key_to_split(
+    existing_key: Option<SecretString>,
+    holds_encrypted_data: bool,
+) -> Result<SecretString, SealError> {
+    match existing_key {
+        Some(key) => Ok(key),
+        None if holds_encrypted_data => Err(SealError::KeyUnavailable),
+        None => {
+            let mut key = SecretBytes::new(vec![0u8; 32]);
+            OsRng.fill_bytes(&mut key);
+            Ok(SecretString::new(STANDARD.encode(&key)))
+        }
+    }
+}
+
+/*---------------------------------------------------------------------------
+    Initializes sealed mode, returning the shares which are shown once.
+    An existing master key from the key provider is split so stored data
+    stays readable (remove it from the provider afterwards). A new master
+    key is only generated while nothing is stored encrypted. The instance
+    stays sealed.
+---------------------------------------------------------------------------*/
+pub async fn initialize(
+    init: &SealInit,
+    existing_key: Option<SecretString>,
+    holds_encrypted_data: bool,
+    initialized_by: &str,
+    repo: &SealRepository,
+) -> Result<Vec<SecretString>, SealError> {
+    let (shares, threshold) = (init.shares, init.threshold);
+    if !sealed_mode() {
+        return Err(SealError::Disabled);
+    }
+    let key = key_to_split(existing_key, holds_encrypted_data)?;
+    let key_shares = split_key(&key, shares, threshold)?;
+    let share_digests = key_shares
+        .iter()
+        .map(|share| {
+            STANDARD
+                .decode(share.as_str())
+                .map(|share| share_digest(&SecretBytes::new(share)))
+                .map_err(|e| SealError::Internal(e.to_string()))
+        })
+        .collect::<Result<_, _>>()?;
+    let check =
+        encrypt(SEAL_CHECK, key.as_bytes()).map_err(|e| SealError::Internal(e.to_string()))?;
+    let seal = SealDocument {
+        shares,
+        threshold,
+        check: STANDARD.encode(check),
+        share_digests,
+        initialized_by: initialized_by.to_string(),
+        initialized_at: Utc::now(),
+    };
+    if !repo.initialize(&seal).await? {
+        return Err(SealError::AlreadyInitialized);
+    }
+    Ok(key_shares)
+}
+
+/*---------------------------------------------------------------------------
+    Submits one share. Once the threshold is reached the master key is
+    recovered and the vault unsealed. Shares are checked against their
+    stored digests first, so a wrong share is refused on its own and
+    can't reset the progress of others. Seals from before digests were
+    stored can only tell at recovery, where wrong shares reset the
+    progress.
+---------------------------------------------------------------------------*/
+pub async fn unseal(share: &str, repo: &SealRepository) -> Result<SealStatus, SealError> {
+    if !sealed_mode() {
+        return Err(SealError::Disabled);
+    }
+    let Some(seal) = repo.get_seal().await? else {
+        return Err(SealError::NotInitialized);
+    };
+    if !is_sealed() {
+        return status(repo).await;
+    }
+    let share = STANDARD
+        .decode(share.trim())
+        .map(SecretBytes::new)
+        .map_err(|_| SealError::InvalidShare("The share is not valid base64".into()))?;
+    if share.len() < 2 {
+        return Err(SealError::InvalidShare("The share is too short".into()));
+    }
+    if !seal.share_digests.is_empty() && !seal.share_digests.contains(&share_digest(&share)) {
+        return Err(SealError::InvalidShare(
+            "The share doesn't belong to this vault".into(),
+        ));
+    }
+
+    {
+        let mut shares = MASTER_KEY.shares.lock().unwrap();
+        // The first byte identifies the share, submitting one twice is a no-op
+        if !shares.iter().any(|submitted| submitted[0] == share[0]) {
+            shares.push(share);
+        }
+        if shares.len() >= seal.threshold as usize {
+            let recovered = recover_key(&shares, seal.threshold, &seal.check);
+            shares.clear();
+            *MASTER_KEY.key.write().unwrap() = Some(recovered?);
+        }
+    }
+    status(repo).await
+}
+
+/// Wipes the master key from memory, the vault stays sealed until it is
+/// unsealed again.
+pub fn seal() -> Result<(), SealError> {
+    if !sealed_mode() {
+        return Err(SealError::Disabled);
+    }
+    MASTER_KEY.shares.lock().unwrap().clear();
+    *MASTER_KEY.key.write().unwrap() = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_recover_the_key() {
+        let key = "IRwTgHBtmblSfAXpYOuvf4ZIhSY32JoP8TLIxeLuCrg=";
+        let check = STANDARD.encode(encrypt(SEAL_CHECK, key.as_bytes()).unwrap());
+        let shares: Vec<SecretBytes> = split_key(key, 5, 3)
+            .unwrap()
+            .iter()
+            .map(|share| SecretBytes::new(STANDARD.decode(share.as_str()).unwrap()))
+            .collect();
+
+        assert_eq!(recover_key(&shares[1..4], 3, &check).unwrap().as_str(), key);
+        assert!(recover_key(&shares[..2], 3, &check).is_err());
+
+        let other = STANDARD.encode(encrypt(SEAL_CHECK, b"another key").unwrap());
+        assert!(recover_key(&shares[..3], 3, &other).is_err());
+        assert!(split_key(key, 2, 3).is_err());
+    }
+
+    #[test]
+    fn digests_tell_shares_apart() {
+        let key = "IRwTgHBtmblSfAXpYOuvf4ZIhSY32JoP8TLIxeLuCrg=";
+        let shares: Vec<Vec<u8>> = split_key(key, 3, 2)
+            .unwrap()
+            .iter()
+            .map(|share| STANDARD.decode(share.as_str()).unwrap())
+            .collect();
+        let digests: Vec<String> = shares.iter().map(|share| share_digest(share)).collect();
+        assert!(
+            shares
+                .iter()
+                .all(|share| digests.contains(&share_digest(share)))
+        );
+
+        let mut tampered = shares[0].clone();
+        tampered[1] ^= 1;
+        assert!(!digests.contains(&share_digest(&tampered)));
+    }
+
+    #[test]
+    fn new_keys_only_for_empty_databases() {
+        let existing = SecretString::new("existing key".into());
+        for holds_data in [false, true] {
+            let key = key_to_split(Some(existing.clone()), holds_data).unwrap();
+            assert_eq!(key.as_str(), "existing key");
+        }
+
+        let generated = key_to_split(None, false).unwrap();
+        assert_eq!(STANDARD.decode(generated.as_str()).unwrap().len(), 32);
+        assert!(matches!(
+            key_to_split(None, true),
+            Err(SealError::KeyUnavailable)
+        ));
+    }
+}
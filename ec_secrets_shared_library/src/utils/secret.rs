@@ -0,0 +1,129 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    str::Utf8Error,
+};
+
+use schemars::{JsonSchema, r#gen::SchemaGenerator, schema::Schema};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::{Zeroize, Zeroizing};
+
+/*---------------------------------------------------------------------------
+    Plaintext values, passwords and key material. The wrapped value is
+    zeroed when dropped, every clone wiping its own copy, and Debug prints
+    it redacted, so logging a struct that holds one doesn't leak it.
+    Serialization is transparent: API bodies and documents carry the value
+    itself.
+---------------------------------------------------------------------------*/
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Sensitive<T: Zeroize>(Zeroizing<T>);
+
+pub type SecretString = Sensitive<String>;
+pub type SecretBytes = Sensitive<Vec<u8>>;
+
+impl<T: Zeroize> Sensitive<T> {
+    pub fn new(value: T) -> Self {
+        Self(Zeroizing::new(value))
+    }
+}
+
+impl SecretBytes {
+    /// The bytes as a string, without copying them.
+    pub fn into_string(mut self) -> Result<SecretString, Utf8Error> {
+        let bytes = std::mem::take(&mut *self.0);
+        String::from_utf8(bytes).map(Sensitive::new).map_err(|e| {
+            let error = e.utf8_error();
+            // Wiped when dropped
+            Sensitive::new(e.into_bytes());
+            error
+        })
+    }
+}
+
+impl<T: Zeroize> Deref for Sensitive<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> DerefMut for Sensitive<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> AsRef<[u8]> for Sensitive<T> {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl<T: Zeroize> From<T> for Sensitive<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self::new(value.to_string())
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<T: Zeroize + Serialize> Serialize for Sensitive<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Zeroize + Deserialize<'de>> Deserialize<'de> for Sensitive<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl<T: Zeroize + JsonSchema> JsonSchema for Sensitive<T> {
+    fn is_referenceable() -> bool {
+        T::is_referenceable()
+    }
+
+    fn schema_name() -> String {
+        T::schema_name()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        T::json_schema(generator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Credentials {
+        email: String,
+        password: SecretString,
+    }
+
+    #[test]
+    fn redacted_but_serialized() {
+        let credentials: Credentials =
+            serde_json::from_str(r#"{"email":"a@example.com","password":"hunter2"}"#).unwrap();
+        assert_eq!(credentials.password.as_str(), "hunter2");
+        assert!(!format!("{credentials:?}").contains("hunter2"));
+        assert!(
+            serde_json::to_string(&credentials)
+                .unwrap()
+                .contains("hunter2")
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use log::trace;
+use thiserror::Error;
+
+use crate::utils::{
+    policy::{AuthorizedVault, PolicyError},
+    secret::SecretString,
+    vault::e2e::{self, E2eError, RecipientKeyPair},
+};
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+const DIRECTIVE: &str = "secret";
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("unterminated placeholder starting on line {0}")]
+    Unterminated(usize),
+    #[error("malformed secret reference on line {line}: `{placeholder}`")]
+    Malformed { line: usize, placeholder: String },
+    #[error("missing secrets: {}", .0.join(", "))]
+    MissingSecrets(Vec<String>),
+    #[error("error looking up secret '{0}': {1}")]
+    Lookup(String, PolicyError),
+    #[error("secret '{0}' is end-to-end encrypted, no key pair to open it with")]
+    Sealed(String),
+    #[error("error opening end-to-end encrypted secret '{0}': {1}")]
+    Open(String, E2eError),
+    #[error("error writing rendered file: {0}")]
+    Fs(io::Error),
+}
+
+/// A piece of a parsed template: literal text or a secret reference.
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Secret(String),
+}
+
+/*---------------------------------------------------------------------------
+    Templates reference secrets with `{{ secret "path/to/key" }}`. Other
+    `{{ ... }}` placeholders are left untouched so templates can still be
+    consumed by tools that use the same delimiters.
+---------------------------------------------------------------------------*/
+fn parse(template: &str) -> Result<Vec<Segment<'_>>, TemplateError> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    let line_of = |rest: &str| {
+        template[..template.len() - rest.len()]
+            .matches('\n')
+            .count()
+            + 1
+    };
+
+    while let Some(start) = rest.find(OPEN) {
+        let after_open = &rest[start + OPEN.len()..];
+        let Some(end) = after_open.find(CLOSE) else {
+            return Err(TemplateError::Unterminated(line_of(&rest[start..])));
+        };
+        let inner = after_open[..end].trim();
+        let placeholder_len = start + OPEN.len() + end + CLOSE.len();
+
+        match inner.strip_prefix(DIRECTIVE) {
+            Some(argument) if argument.starts_with(char::is_whitespace) => {
+                let key = parse_key(argument.trim()).ok_or_else(|| TemplateError::Malformed {
+                    line: line_of(&rest[start..]),
+                    placeholder: rest[start..placeholder_len].to_string(),
+                })?;
+                segments.push(Segment::Text(&rest[..start]));
+                segments.push(Segment::Secret(key));
+            }
+            _ => segments.push(Segment::Text(&rest[..placeholder_len])),
+        }
+        rest = &rest[placeholder_len..];
+    }
+    segments.push(Segment::Text(rest));
+
+    Ok(segments)
+}
+
+fn parse_key(argument: &str) -> Option<String> {
+    let key = argument.strip_prefix('"')?.strip_suffix('"')?;
+    if key.is_empty() || key.contains('"') {
+        return None;
+    }
+    Some(key.to_string())
+}
+
+/// Returns the distinct secret keys referenced by a template, sorted.
+pub fn secret_references(template: &str) -> Result<Vec<String>, TemplateError> {
+    let keys: BTreeSet<String> = parse(template)?
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Secret(key) => Some(key),
+            Segment::Text(_) => None,
+        })
+        .collect();
+    Ok(keys.into_iter().collect())
+}
+
+/// Renders a template with already resolved secret values. Every missing
+/// key is reported at once rather than failing on the first one.
+pub fn render(
+    template: &str,
+    secrets: &HashMap<String, SecretString>,
+) -> Result<SecretString, TemplateError> {
+    let segments = parse(template)?;
+
+    let missing: BTreeSet<String> = segments
+        .iter()
+        .filter_map(|segment| match segment {
+            Segment::Secret(key) if !secrets.contains_key(key) => Some(key.clone()),
+            _ => None,
+        })
+        .collect();
+    if !missing.is_empty() {
+        return Err(TemplateError::MissingSecrets(missing.into_iter().collect()));
+    }
+
+    let parts: Vec<&str> = segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text,
+            Segment::Secret(key) => secrets[key].as_str(),
+        })
+        .collect();
+    // Sized upfront, growing the string would leave copies behind
+    let mut rendered = SecretString::new(String::with_capacity(
+        parts.iter().map(|part| part.len()).sum(),
+    ));
+    parts.iter().for_each(|part| rendered.push_str(part));
+    Ok(rendered)
+}
+
+/// Resolves every reference through `AuthorizedVault::get_entry_by_key`,
+/// so only secrets the caller's policies allow are rendered. `qualify` maps
+/// a referenced key to the stored key, e.g. to apply a namespace prefix.
+/// End-to-end encrypted secrets are opened with `key_pair`.
+pub async fn render_from_vault(
+    template: &str,
+    vault: &AuthorizedVault<'_>,
+    qualify: impl Fn(&str) -> String,
+    key_pair: Option<&RecipientKeyPair>,
+) -> Result<SecretString, TemplateError> {
+    let mut secrets = HashMap::new();
+    for key in secret_references(template)? {
+        trace!("Resolving secret reference");
+        let stored_key = qualify(&key);
+        let Some(entry) = vault
+            .get_entry_by_key(&stored_key)
+            .await
+            .map_err(|error| TemplateError::Lookup(stored_key.clone(), error))?
+        else {
+            continue;
+        };
+        let value = if entry.end_to_end {
+            let key_pair = key_pair.ok_or_else(|| TemplateError::Sealed(stored_key.clone()))?;
+            e2e::open_value(&entry.value, key_pair, &entry.key)
+                .map_err(|error| TemplateError::Open(stored_key.clone(), error))?
+        } else {
+            entry.value
+        };
+        secrets.insert(key, value);
+    }
+    render(template, &secrets)
+}
+
+/// Writes rendered output readable only by the owner (0600 on unix).
+pub fn write_rendered(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), TemplateError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(TemplateError::Fs)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(TemplateError::Fs)?;
+    }
+    file.write_all(contents.as_ref()).map_err(TemplateError::Fs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "database:\n  password: {{ secret \"db/password\" }}\n  user: {{secret \"db/user\"}}\n  host: {{ .Host }}\n";
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            secret_references(TEMPLATE).expect("Failed to parse template"),
+            vec!["db/password".to_string(), "db/user".to_string()]
+        );
+    }
+
+    #[test]
+    fn renders_and_keeps_foreign_placeholders() {
+        let secrets = HashMap::from([
+            ("db/password".to_string(), "hunter2".into()),
+            ("db/user".to_string(), "admin".into()),
+        ]);
+        assert_eq!(
+            render(TEMPLATE, &secrets)
+                .expect("Failed to render template")
+                .as_str(),
+            "database:\n  password: hunter2\n  user: admin\n  host: {{ .Host }}\n"
+        );
+    }
+
+    #[test]
+    fn reports_all_missing_secrets() {
+        match render(TEMPLATE, &HashMap::new()) {
+            Err(TemplateError::MissingSecrets(keys)) => {
+                assert_eq!(keys, vec!["db/password", "db/user"])
+            }
+            other => panic!("expected missing secrets, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_references() {
+        assert!(matches!(
+            parse("a\n{{ secret db/password }}"),
+            Err(TemplateError::Malformed { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse("{{ secret \"db\" "),
+            Err(TemplateError::Unterminated(1))
+        ));
+    }
+}
@@ -0,0 +1,480 @@
+/*---------------------------------------------------------------------------
+    End-to-end encrypted values, sealed and opened by clients so the
+    server only ever stores ciphertext. Each recipient holds an X25519
+    key pair; its public key is registered with the server, its private
+    key never leaves the client.
+
+    A value is encrypted with a random content key, which is wrapped for
+    every recipient: an ephemeral X25519 agreement with the recipient's
+    public key, HKDF-SHA256 over the shared secret, and XChaCha20-Poly1305.
+    Integers are big endian:
+
+        magic "ECSE" | version (1) | recipient count (u16)
+        | per recipient: key id (16) | ephemeral public key (32)
+                         | nonce (24) | wrapped content key and tag (48)
+        | nonce (24) | ciphertext and tag
+
+    The content is authenticated along with the caller's context, e.g. the
+    key of the vault entry, so a sealed value moved to another entry no
+    longer opens. Recipients can be added by anyone able to open the value
+    ([add_recipients]); removing one means sealing the value again.
+
+    Vault values go through [seal_value] and [open_value], which use the
+    key of the entry as context.
+---------------------------------------------------------------------------*/
+use std::fmt;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305,
+    aead::{Aead, OsRng, Payload, generic_array::GenericArray, rand_core::RngCore},
+};
+use orion::hazardous::{
+    ecc::x25519::{self, PrivateKey, PublicKey},
+    kdf::hkdf::sha256,
+};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::utils::secret::{SecretBytes, SecretString, Sensitive};
+
+pub const SEALED_MAGIC: &[u8; 4] = b"ECSE";
+pub const SEALED_VERSION: u8 = 1;
+pub const KEY_LEN: usize = 32;
+pub const KEY_ID_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+const STANZA_LEN: usize = KEY_ID_LEN + KEY_LEN + NONCE_LEN + KEY_LEN + TAG_LEN;
+const HEADER_LEN: usize = SEALED_MAGIC.len() + 1 + 2;
+const WRAP_INFO: &[u8] = b"ecs-e2e-wrap";
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E2eError {
+    #[error("invalid public key")]
+    PublicKey,
+    #[error("invalid private key")]
+    PrivateKey,
+    #[error("a sealed value needs at least one recipient")]
+    NoRecipients,
+    #[error("too many recipients")]
+    TooManyRecipients,
+    #[error("invalid sealed value: {0}")]
+    Format(&'static str),
+    #[error("unsupported sealed value version {0}")]
+    UnsupportedVersion(u8),
+    #[error("the value isn't sealed for this key")]
+    NotARecipient,
+    #[error("error decrypting the sealed value (modified, or sealed for another entry)")]
+    Cipher,
+}
+
+/// The public half of a recipient's key pair, what values are sealed for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RecipientKey([u8; KEY_LEN]);
+
+impl RecipientKey {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, E2eError> {
+        bytes.try_into().map(Self).map_err(|_| E2eError::PublicKey)
+    }
+
+    /// Parses the base64 form registered with the server.
+    pub fn from_base64(encoded: &str) -> Result<Self, E2eError> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|_| E2eError::PublicKey)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+
+    /// Truncated SHA-256 of the key, naming its stanza in sealed values.
+    pub fn key_id(&self) -> [u8; KEY_ID_LEN] {
+        let digest = Sha256::digest(self.0);
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&digest[..KEY_ID_LEN]);
+        id
+    }
+
+    /// The key id in hex, for people comparing keys.
+    pub fn fingerprint(&self) -> String {
+        self.key_id()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+impl fmt::Debug for RecipientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RecipientKey({})", self.fingerprint())
+    }
+}
+
+/// A recipient's key pair. Only the public key is ever sent to the server.
+#[derive(Clone)]
+pub struct RecipientKeyPair {
+    public_key: RecipientKey,
+    private_key: SecretBytes,
+}
+
+impl RecipientKeyPair {
+    pub fn generate() -> Self {
+        let mut private_key = SecretBytes::new(vec![0u8; KEY_LEN]);
+        OsRng.fill_bytes(&mut private_key);
+        Self::from_private_key(&private_key).expect("a generated key has the right length")
+    }
+
+    pub fn from_private_key(private_key: &[u8]) -> Result<Self, E2eError> {
+        let public_key = public_key_of(&x25519_private_key(private_key)?)?;
+        Ok(Self {
+            public_key,
+            private_key: Sensitive::new(private_key.to_vec()),
+        })
+    }
+
+    pub fn public_key(&self) -> &RecipientKey {
+        &self.public_key
+    }
+
+    /// The raw private key, to be stored encrypted by the client.
+    pub fn private_key(&self) -> &SecretBytes {
+        &self.private_key
+    }
+}
+
+impl fmt::Debug for RecipientKeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecipientKeyPair")
+            .field("public_key", &self.public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+fn x25519_private_key(bytes: &[u8]) -> Result<PrivateKey, E2eError> {
+    PrivateKey::from_slice(bytes).map_err(|_| E2eError::PrivateKey)
+}
+
+fn public_key_of(private_key: &PrivateKey) -> Result<RecipientKey, E2eError> {
+    let public_key = PublicKey::try_from(private_key).map_err(|_| E2eError::PrivateKey)?;
+    Ok(RecipientKey(public_key.to_bytes()))
+}
+
+/// Key wrapping the content key for one recipient, derived from the
+/// agreement between an ephemeral key and the recipient's key. Either
+/// side computes it: the sender with the ephemeral private key and the
+/// recipient's public key, the recipient the other way round.
+fn wrapping_key(
+    private_key: &PrivateKey,
+    peer: &RecipientKey,
+    ephemeral_public: &RecipientKey,
+    recipient: &RecipientKey,
+) -> Result<SecretBytes, E2eError> {
+    let peer = PublicKey::from_slice(peer.as_bytes()).map_err(|_| E2eError::PublicKey)?;
+    let shared = x25519::key_agreement(private_key, &peer).map_err(|_| E2eError::PublicKey)?;
+    let salt = [ephemeral_public.as_bytes().as_slice(), recipient.as_bytes()].concat();
+    let mut key = SecretBytes::new(vec![0u8; KEY_LEN]);
+    sha256::derive_key(
+        &salt,
+        shared.unprotected_as_bytes(),
+        Some(WRAP_INFO),
+        &mut key,
+    )
+    .map_err(|_| E2eError::Cipher)?;
+    Ok(key)
+}
+
+fn header(count: usize) -> Result<Vec<u8>, E2eError> {
+    let count = u16::try_from(count).map_err(|_| E2eError::TooManyRecipients)?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(SEALED_MAGIC);
+    header.push(SEALED_VERSION);
+    header.extend_from_slice(&count.to_be_bytes());
+    Ok(header)
+}
+
+/// The recipient's stanza: key id, ephemeral public key, nonce and the
+/// wrapped content key.
+fn wrap(content_key: &[u8], recipient: &RecipientKey) -> Result<Vec<u8>, E2eError> {
+    let ephemeral = RecipientKeyPair::generate();
+    let ephemeral_key = x25519_private_key(ephemeral.private_key())?;
+    let key = wrapping_key(&ephemeral_key, recipient, ephemeral.public_key(), recipient)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut stanza = Vec::with_capacity(STANZA_LEN);
+    stanza.extend_from_slice(&recipient.key_id());
+    stanza.extend_from_slice(ephemeral.public_key().as_bytes());
+    stanza.extend_from_slice(&nonce);
+    let wrapped = XChaCha20Poly1305::new(GenericArray::from_slice(&key))
+        .encrypt(
+            GenericArray::from_slice(&nonce),
+            Payload {
+                msg: content_key,
+                aad: &stanza[..KEY_ID_LEN + KEY_LEN],
+            },
+        )
+        .map_err(|_| E2eError::Cipher)?;
+    stanza.extend_from_slice(&wrapped);
+    Ok(stanza)
+}
+
+/// Encrypts `plaintext` for `recipients`, duplicates ignored. `context`
+/// has to be passed again to [open].
+pub fn seal(
+    plaintext: &[u8],
+    recipients: &[RecipientKey],
+    context: &[u8],
+) -> Result<Vec<u8>, E2eError> {
+    let mut unique: Vec<&RecipientKey> = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        if !unique.contains(&recipient) {
+            unique.push(recipient);
+        }
+    }
+    if unique.is_empty() {
+        return Err(E2eError::NoRecipients);
+    }
+
+    let mut content_key = SecretBytes::new(vec![0u8; KEY_LEN]);
+    OsRng.fill_bytes(&mut content_key);
+    let mut sealed = header(unique.len())?;
+    for recipient in unique {
+        sealed.extend_from_slice(&wrap(&content_key, recipient)?);
+    }
+
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let aad = [&sealed[..SEALED_MAGIC.len() + 1], context].concat();
+    let ciphertext = XChaCha20Poly1305::new(GenericArray::from_slice(&content_key))
+        .encrypt(
+            GenericArray::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| E2eError::Cipher)?;
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+struct Sealed<'a> {
+    stanzas: Vec<&'a [u8]>,
+    nonce: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+fn parse(data: &[u8]) -> Result<Sealed<'_>, E2eError> {
+    if data.len() < HEADER_LEN || !data.starts_with(SEALED_MAGIC) {
+        return Err(E2eError::Format("not a sealed value"));
+    }
+    if data[4] != SEALED_VERSION {
+        return Err(E2eError::UnsupportedVersion(data[4]));
+    }
+    let count = u16::from_be_bytes([data[5], data[6]]) as usize;
+    if count == 0 {
+        return Err(E2eError::NoRecipients);
+    }
+    let body_start = HEADER_LEN + count * STANZA_LEN;
+    if data.len() < body_start + NONCE_LEN + TAG_LEN {
+        return Err(E2eError::Format("truncated"));
+    }
+    Ok(Sealed {
+        stanzas: data[HEADER_LEN..body_start].chunks(STANZA_LEN).collect(),
+        nonce: &data[body_start..body_start + NONCE_LEN],
+        ciphertext: &data[body_start + NONCE_LEN..],
+    })
+}
+
+/// Whether `data` is structurally a sealed value. Says nothing about
+/// whether it opens, which only recipients can tell.
+pub fn is_sealed(data: &[u8]) -> bool {
+    parse(data).is_ok()
+}
+
+/// Key ids of the recipients of a sealed value, see [RecipientKey::key_id].
+pub fn recipient_ids(data: &[u8]) -> Result<Vec<[u8; KEY_ID_LEN]>, E2eError> {
+    Ok(parse(data)?
+        .stanzas
+        .into_iter()
+        .map(|stanza| stanza[..KEY_ID_LEN].try_into().expect("stanza length"))
+        .collect())
+}
+
+fn unwrap_content_key(
+    sealed: &Sealed<'_>,
+    key_pair: &RecipientKeyPair,
+) -> Result<SecretBytes, E2eError> {
+    let key_id = key_pair.public_key().key_id();
+    let stanza = sealed
+        .stanzas
+        .iter()
+        .find(|stanza| stanza[..KEY_ID_LEN] == key_id)
+        .ok_or(E2eError::NotARecipient)?;
+    let ephemeral = RecipientKey::from_bytes(&stanza[KEY_ID_LEN..KEY_ID_LEN + KEY_LEN])?;
+    let private_key = x25519_private_key(key_pair.private_key())?;
+    let key = wrapping_key(&private_key, &ephemeral, &ephemeral, key_pair.public_key())?;
+
+    let nonce_start = KEY_ID_LEN + KEY_LEN;
+    XChaCha20Poly1305::new(GenericArray::from_slice(&key))
+        .decrypt(
+            GenericArray::from_slice(&stanza[nonce_start..nonce_start + NONCE_LEN]),
+            Payload {
+                msg: &stanza[nonce_start + NONCE_LEN..],
+                aad: &stanza[..nonce_start],
+            },
+        )
+        .map(Sensitive::new)
+        .map_err(|_| E2eError::Cipher)
+}
+
+/// Decrypts a value sealed for `key_pair` with the same `context`.
+pub fn open(
+    data: &[u8],
+    key_pair: &RecipientKeyPair,
+    context: &[u8],
+) -> Result<SecretBytes, E2eError> {
+    let sealed = parse(data)?;
+    let content_key = unwrap_content_key(&sealed, key_pair)?;
+    let aad = [&data[..SEALED_MAGIC.len() + 1], context].concat();
+    XChaCha20Poly1305::new(GenericArray::from_slice(&content_key))
+        .decrypt(
+            GenericArray::from_slice(sealed.nonce),
+            Payload {
+                msg: sealed.ciphertext,
+                aad: &aad,
+            },
+        )
+        .map(Sensitive::new)
+        .map_err(|_| E2eError::Cipher)
+}
+
+/// Wraps the content key of a value `key_pair` can open for `recipients`
+/// too, leaving the ciphertext as is. Existing recipients are skipped, so
+/// the value comes back unchanged when there is nobody to add.
+pub fn add_recipients(
+    data: &[u8],
+    key_pair: &RecipientKeyPair,
+    recipients: &[RecipientKey],
+) -> Result<Vec<u8>, E2eError> {
+    let sealed = parse(data)?;
+    let content_key = unwrap_content_key(&sealed, key_pair)?;
+    let mut stanzas: Vec<Vec<u8>> = sealed
+        .stanzas
+        .iter()
+        .map(|stanza| stanza.to_vec())
+        .collect();
+    for recipient in recipients {
+        let key_id = recipient.key_id();
+        if !stanzas.iter().any(|stanza| stanza[..KEY_ID_LEN] == key_id) {
+            stanzas.push(wrap(&content_key, recipient)?);
+        }
+    }
+
+    let mut resealed = header(stanzas.len())?;
+    for stanza in stanzas {
+        resealed.extend_from_slice(&stanza);
+    }
+    resealed.extend_from_slice(sealed.nonce);
+    resealed.extend_from_slice(sealed.ciphertext);
+    Ok(resealed)
+}
+
+/// Seals a vault value for the entry under `key`, base64 encoded as
+/// `create/vault/entry` takes it with `end_to_end` set.
+pub fn seal_value(value: &str, recipients: &[RecipientKey], key: &str) -> Result<String, E2eError> {
+    Ok(STANDARD.encode(seal(value.as_bytes(), recipients, key.as_bytes())?))
+}
+
+/// Opens the base64 sealed value of the entry under `key`.
+pub fn open_value(
+    sealed: &str,
+    key_pair: &RecipientKeyPair,
+    key: &str,
+) -> Result<SecretString, E2eError> {
+    let data = STANDARD
+        .decode(sealed)
+        .map_err(|_| E2eError::Format("not base64"))?;
+    open(&data, key_pair, key.as_bytes())?
+        .into_string()
+        .map_err(|_| E2eError::Format("not text"))
+}
+
+/// [add_recipients] for a base64 sealed value.
+pub fn add_value_recipients(
+    sealed: &str,
+    key_pair: &RecipientKeyPair,
+    recipients: &[RecipientKey],
+) -> Result<String, E2eError> {
+    let data = STANDARD
+        .decode(sealed)
+        .map_err(|_| E2eError::Format("not base64"))?;
+    Ok(STANDARD.encode(add_recipients(&data, key_pair, recipients)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_for_every_recipient() {
+        let (alice, bob, eve) = (
+            RecipientKeyPair::generate(),
+            RecipientKeyPair::generate(),
+            RecipientKeyPair::generate(),
+        );
+        let sealed = seal(
+            b"hunter2",
+            &[*alice.public_key(), *bob.public_key()],
+            b"db/password",
+        )
+        .unwrap();
+        assert_eq!(recipient_ids(&sealed).unwrap().len(), 2);
+        assert_eq!(*open(&sealed, &alice, b"db/password").unwrap(), b"hunter2");
+        assert_eq!(*open(&sealed, &bob, b"db/password").unwrap(), b"hunter2");
+        assert!(matches!(
+            open(&sealed, &eve, b"db/password"),
+            Err(E2eError::NotARecipient)
+        ));
+        assert!(matches!(
+            open(&sealed, &alice, b"db/user"),
+            Err(E2eError::Cipher)
+        ));
+
+        let shared = add_recipients(&sealed, &bob, &[*eve.public_key()]).unwrap();
+        assert_eq!(*open(&shared, &eve, b"db/password").unwrap(), b"hunter2");
+        assert_eq!(*open(&shared, &alice, b"db/password").unwrap(), b"hunter2");
+        assert!(add_recipients(&sealed, &eve, &[*eve.public_key()]).is_err());
+
+        let restored = RecipientKeyPair::from_private_key(alice.private_key()).unwrap();
+        assert_eq!(restored.public_key(), alice.public_key());
+        assert_eq!(
+            RecipientKey::from_base64(&alice.public_key().to_base64()).unwrap(),
+            *alice.public_key()
+        );
+    }
+
+    #[test]
+    fn values_bound_to_their_key() {
+        let (alice, bob) = (RecipientKeyPair::generate(), RecipientKeyPair::generate());
+        let sealed = seal_value("hunter2", &[*alice.public_key()], "db/password").unwrap();
+        assert_eq!(
+            open_value(&sealed, &alice, "db/password").unwrap().as_str(),
+            "hunter2"
+        );
+        assert!(open_value(&sealed, &alice, "db/user").is_err());
+
+        let shared = add_value_recipients(&sealed, &alice, &[*bob.public_key()]).unwrap();
+        assert_eq!(
+            open_value(&shared, &bob, "db/password").unwrap().as_str(),
+            "hunter2"
+        );
+    }
+}
@@ -0,0 +1,1029 @@
+pub mod e2e;
+pub mod stream;
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read},
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::Aes256Gcm;
+use argon2::{Config, Variant, Version};
+use chacha20poly1305::{
+    ChaCha20Poly1305, KeyInit, XChaCha20Poly1305,
+    aead::{Aead, OsRng, Payload, generic_array::GenericArray, rand_core::RngCore},
+};
+use log::{info, trace};
+use serde_derive::{Deserialize, Serialize};
+use tar::{Archive, Builder};
+use thiserror::Error;
+
+use crate::utils::{
+    password::env_number,
+    secret::{SecretBytes, Sensitive},
+};
+
+pub use stream::{DecryptReader, EncryptWriter, StreamError};
+
+/// Format of earlier releases, still accepted by [decrypt]: ChaCha20-Poly1305
+/// with the default Argon2 costs and no version.
+#[derive(Serialize, Deserialize)]
+struct PrecryptorFile {
+    data: Vec<u8>,
+    nonce: [u8; 12],
+    salt: [u8; 32],
+}
+
+/*---------------------------------------------------------------------------
+    Ciphertexts written by [encrypt] are self-describing envelopes,
+    integers big endian:
+
+        magic "ECSV" | version (2) | cipher | kdf (1, Argon2id)
+        | memory KiB (u32) | iterations (u32) | lanes (u32)
+        | salt (32) | nonce (12 or 24) | ciphertext and tag
+
+    Everything before the ciphertext is authenticated as associated data.
+    Version 3 envelopes, written by [encrypt_bound], also authenticate a
+    context the caller passes in again to decrypt, such as the identity of
+    the record holding the ciphertext, so a ciphertext copied elsewhere no
+    longer decrypts. New envelopes use the cipher and Argon2id costs configured through
+
+    ECS_VAULT_CIPHER                 xchacha20-poly1305 (default),
+                                     chacha20-poly1305 or aes-256-gcm
+    ECS_VAULT_ARGON2_MEMORY_KIB      memory cost in KiB (19456)
+    ECS_VAULT_ARGON2_ITERATIONS      time cost (2)
+    ECS_VAULT_ARGON2_PARALLELISM     lanes (1)
+    ECS_VAULT_REQUIRE_BINDING        true to reject unbound ciphertexts in
+                                     [decrypt_bound] (false)
+
+    while [decrypt] follows whatever an envelope names. [reencrypt] moves
+    older ciphertexts to the current parameters.
+---------------------------------------------------------------------------*/
+pub const ENVELOPE_MAGIC: &[u8; 4] = b"ECSV";
+pub const ENVELOPE_VERSION: u8 = 2;
+pub const BOUND_ENVELOPE_VERSION: u8 = 3;
+const KDF_ARGON2ID: u8 = 1;
+const SALT_LEN: usize = 32;
+const KDF_HEADER_LEN: usize = 12;
+const ENVELOPE_PREFIX_LEN: usize = ENVELOPE_MAGIC.len() + 3 + KDF_HEADER_LEN + SALT_LEN;
+
+// Upper bounds checked before deriving a key from untrusted parameters
+const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+const MAX_ITERATIONS: u32 = 64;
+const MAX_LANES: u32 = 64;
+
+/// AEAD ciphers an envelope can name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    ChaCha20Poly1305,
+    Aes256Gcm,
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub const ALL: [Cipher; 3] = [
+        Cipher::ChaCha20Poly1305,
+        Cipher::Aes256Gcm,
+        Cipher::XChaCha20Poly1305,
+    ];
+
+    pub fn id(self) -> u8 {
+        match self {
+            Cipher::ChaCha20Poly1305 => 1,
+            Cipher::Aes256Gcm => 2,
+            Cipher::XChaCha20Poly1305 => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|cipher| cipher.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::ChaCha20Poly1305 => "chacha20-poly1305",
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::XChaCha20Poly1305 => "xchacha20-poly1305",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        Self::ALL.into_iter().find(|cipher| cipher.name() == name)
+    }
+
+    fn nonce_len(self) -> usize {
+        match self {
+            Cipher::ChaCha20Poly1305 | Cipher::Aes256Gcm => 12,
+            Cipher::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    fn seal(
+        self,
+        key: &[u8],
+        nonce: &[u8],
+        payload: Payload<'_, '_>,
+    ) -> Result<Vec<u8>, chacha20poly1305::Error> {
+        match self {
+            Cipher::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).encrypt(GenericArray::from_slice(nonce), payload)
+            }
+            Cipher::Aes256Gcm => {
+                Aes256Gcm::new(key.into()).encrypt(GenericArray::from_slice(nonce), payload)
+            }
+            Cipher::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new(key.into()).encrypt(GenericArray::from_slice(nonce), payload)
+            }
+        }
+    }
+
+    fn open(
+        self,
+        key: &[u8],
+        nonce: &[u8],
+        payload: Payload<'_, '_>,
+    ) -> Result<SecretBytes, chacha20poly1305::Error> {
+        let plaintext =
+            match self {
+                Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into())
+                    .decrypt(GenericArray::from_slice(nonce), payload),
+                Cipher::Aes256Gcm => {
+                    Aes256Gcm::new(key.into()).decrypt(GenericArray::from_slice(nonce), payload)
+                }
+                Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into())
+                    .decrypt(GenericArray::from_slice(nonce), payload),
+            };
+        plaintext.map(Sensitive::new)
+    }
+}
+
+impl std::fmt::Display for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Argon2id costs of the key derivation, stored in each envelope and in
+/// stream headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        let config = Config::owasp2();
+        Self {
+            memory_kib: config.mem_cost,
+            iterations: config.time_cost,
+            lanes: config.lanes,
+        }
+    }
+}
+
+impl KdfParams {
+    pub fn from_env() -> Result<Self, String> {
+        let mut params = Self::default();
+        if let Some(memory_kib) = env_number("ECS_VAULT_ARGON2_MEMORY_KIB")? {
+            params.memory_kib = memory_kib;
+        }
+        if let Some(iterations) = env_number("ECS_VAULT_ARGON2_ITERATIONS")? {
+            params.iterations = iterations;
+        }
+        if let Some(lanes) = env_number("ECS_VAULT_ARGON2_PARALLELISM")? {
+            params.lanes = lanes;
+        }
+        params.validate().map_err(str::to_string)?;
+        Ok(params)
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !(8 * self.lanes.max(1)..=MAX_MEMORY_KIB).contains(&self.memory_kib) {
+            return Err("argon2 memory cost out of range");
+        }
+        if !(1..=MAX_ITERATIONS).contains(&self.iterations) {
+            return Err("argon2 iterations out of range");
+        }
+        if !(1..=MAX_LANES).contains(&self.lanes) {
+            return Err("argon2 lanes out of range");
+        }
+        Ok(())
+    }
+
+    /// Whether every cost is at most the one of `max`.
+    pub fn within(&self, max: &KdfParams) -> bool {
+        self.memory_kib <= max.memory_kib
+            && self.iterations <= max.iterations
+            && self.lanes <= max.lanes
+    }
+
+    /// A 32 byte key derived from `encryption_key` with Argon2id.
+    pub fn derive_key(
+        &self,
+        encryption_key: &[u8],
+        salt: &[u8],
+    ) -> Result<SecretBytes, argon2::Error> {
+        let config = Config {
+            hash_length: 32,
+            lanes: self.lanes,
+            mem_cost: self.memory_kib,
+            time_cost: self.iterations,
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            ..Config::owasp2()
+        };
+        argon2::hash_raw(encryption_key, salt, &config).map(Sensitive::new)
+    }
+
+    fn to_bytes(self) -> [u8; KDF_HEADER_LEN] {
+        let mut bytes = [0u8; KDF_HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.memory_kib.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.iterations.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.lanes.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let u32_at = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+        Self {
+            memory_kib: u32_at(0),
+            iterations: u32_at(4),
+            lanes: u32_at(8),
+        }
+    }
+}
+
+/// Cipher and key derivation of an envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvelopeParams {
+    pub cipher: Cipher,
+    pub kdf: KdfParams,
+}
+
+impl Default for EnvelopeParams {
+    fn default() -> Self {
+        Self {
+            cipher: Cipher::XChaCha20Poly1305,
+            kdf: KdfParams::default(),
+        }
+    }
+}
+
+impl EnvelopeParams {
+    /// Parameters of new envelopes, see the module comment.
+    pub fn from_env() -> Result<Self, String> {
+        let cipher = match std::env::var("ECS_VAULT_CIPHER") {
+            Ok(name) => Cipher::from_name(&name).ok_or_else(|| {
+                let names: Vec<&str> = Cipher::ALL.iter().map(|cipher| cipher.name()).collect();
+                format!("ECS_VAULT_CIPHER must be one of {}", names.join(", "))
+            })?,
+            Err(_) => Self::default().cipher,
+        };
+        Ok(Self {
+            cipher,
+            kdf: KdfParams::from_env()?,
+        })
+    }
+
+    /// Parameters of the legacy, unversioned format.
+    fn legacy() -> Self {
+        Self {
+            cipher: Cipher::ChaCha20Poly1305,
+            kdf: KdfParams::default(),
+        }
+    }
+}
+
+/// The parameters `data` was encrypted with, legacy data included. None if
+/// it isn't a readable envelope.
+pub fn envelope_params(data: &[u8]) -> Option<EnvelopeParams> {
+    if !data.starts_with(ENVELOPE_MAGIC) {
+        return bincode::deserialize::<PrecryptorFile>(data)
+            .ok()
+            .map(|_| EnvelopeParams::legacy());
+    }
+    parse_envelope(data).ok().map(|envelope| envelope.params)
+}
+
+/// Whether [decrypt_bound] rejects ciphertexts without a bound context,
+/// once [reencrypt_bound] has bound every existing one.
+pub fn binding_required() -> bool {
+    std::env::var("ECS_VAULT_REQUIRE_BINDING")
+        .ok()
+        .and_then(|required| required.parse().ok())
+        .unwrap_or(false)
+}
+
+/// Whether `data` is an envelope bound to a context.
+pub fn is_bound(data: &[u8]) -> bool {
+    data.starts_with(ENVELOPE_MAGIC) && parse_envelope(data).is_ok_and(|envelope| envelope.bound)
+}
+
+struct Envelope<'a> {
+    params: EnvelopeParams,
+    bound: bool,
+    salt: &'a [u8],
+    nonce: &'a [u8],
+    header: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+fn parse_envelope(data: &[u8]) -> Result<Envelope<'_>, DecryptError> {
+    if data.len() < ENVELOPE_PREFIX_LEN {
+        return Err(DecryptError::Envelope("truncated header"));
+    }
+    let bound = match data[4] {
+        ENVELOPE_VERSION => false,
+        BOUND_ENVELOPE_VERSION => true,
+        version => return Err(DecryptError::UnsupportedVersion(version)),
+    };
+    let cipher = Cipher::from_id(data[5]).ok_or(DecryptError::UnsupportedCipher(data[5]))?;
+    if data[6] != KDF_ARGON2ID {
+        return Err(DecryptError::Envelope("unsupported key derivation"));
+    }
+    let kdf = KdfParams::from_bytes(&data[7..7 + KDF_HEADER_LEN]);
+    kdf.validate().map_err(DecryptError::Envelope)?;
+
+    let header_len = ENVELOPE_PREFIX_LEN + cipher.nonce_len();
+    if data.len() < header_len {
+        return Err(DecryptError::Envelope("truncated header"));
+    }
+    Ok(Envelope {
+        params: EnvelopeParams { cipher, kdf },
+        bound,
+        salt: &data[ENVELOPE_PREFIX_LEN - SALT_LEN..ENVELOPE_PREFIX_LEN],
+        nonce: &data[ENVELOPE_PREFIX_LEN..header_len],
+        header: &data[..header_len],
+        ciphertext: &data[header_len..],
+    })
+}
+
+#[derive(Error, Debug)]
+pub enum EncryptError {
+    #[error("failed to generate key from encryption key")]
+    Hashing(argon2::Error),
+    #[error("error running the cipher on data")]
+    Cipher(chacha20poly1305::Error),
+    #[error("error serializing data to binary format: {0}")]
+    Serialize(bincode::Error),
+    #[error("invalid encryption settings: {0}")]
+    Config(String),
+}
+/// Encrypts some data and returns the result, an envelope with the cipher
+/// and key derivation costs configured in the environment
+///
+/// # Examples
+///
+/// ```no_run
+/// use ec_secrets_shared_library::utils::vault::encrypt;
+///
+/// let encrypted_data = encrypt(b"example text", b"encryption key").expect("Failed to encrypt");
+/// // and now you can write it to a file:
+/// // fs::write("encrypted_text.txt", encrypted_data).expect("Failed to write to file");
+/// ```
+///
+pub fn encrypt(data: &[u8], encryption_key: &[u8]) -> Result<Vec<u8>, EncryptError> {
+    let params = EnvelopeParams::from_env().map_err(EncryptError::Config)?;
+    encrypt_with(data, encryption_key, params)
+}
+
+/// Encrypts some data into an envelope with the given parameters.
+pub fn encrypt_with(
+    data: &[u8],
+    encryption_key: &[u8],
+    params: EnvelopeParams,
+) -> Result<Vec<u8>, EncryptError> {
+    seal_envelope(data, encryption_key, params, None)
+}
+
+/// Encrypts some data bound to `context`, which [decrypt_bound] needs to
+/// be given again. The context itself isn't stored.
+pub fn encrypt_bound(
+    data: &[u8],
+    encryption_key: &[u8],
+    context: &[u8],
+) -> Result<Vec<u8>, EncryptError> {
+    let params = EnvelopeParams::from_env().map_err(EncryptError::Config)?;
+    seal_envelope(data, encryption_key, params, Some(context))
+}
+
+fn seal_envelope(
+    data: &[u8],
+    encryption_key: &[u8],
+    params: EnvelopeParams,
+    context: Option<&[u8]>,
+) -> Result<Vec<u8>, EncryptError> {
+    params
+        .kdf
+        .validate()
+        .map_err(|e| EncryptError::Config(e.to_string()))?;
+
+    trace!("Generating salt");
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+
+    trace!("Generating key");
+    let key = params
+        .kdf
+        .derive_key(encryption_key, &salt)
+        .map_err(EncryptError::Hashing)?;
+
+    trace!("Generating nonce");
+    let mut nonce = vec![0u8; params.cipher.nonce_len()];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut envelope = Vec::with_capacity(ENVELOPE_PREFIX_LEN + nonce.len() + data.len() + 16);
+    envelope.extend_from_slice(ENVELOPE_MAGIC);
+    let version = match context {
+        Some(_) => BOUND_ENVELOPE_VERSION,
+        None => ENVELOPE_VERSION,
+    };
+    envelope.extend_from_slice(&[version, params.cipher.id(), KDF_ARGON2ID]);
+    envelope.extend_from_slice(&params.kdf.to_bytes());
+    envelope.extend_from_slice(&salt);
+    envelope.extend_from_slice(&nonce);
+
+    info!("Encrypting");
+    let aad = [envelope.as_slice(), context.unwrap_or_default()].concat();
+    let ciphertext = params
+        .cipher
+        .seal(
+            &key,
+            &nonce,
+            Payload {
+                msg: data,
+                aad: &aad,
+            },
+        )
+        .map_err(EncryptError::Cipher)?;
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+#[derive(Error, Debug)]
+pub enum DecryptError {
+    #[error("failed to generate decryption key from encryption key")]
+    Hashing(argon2::Error),
+    #[error("failed to deserialize encrypted file from binary format")]
+    Deserialize(bincode::Error),
+    #[error("error decrypting data (possibly invalid encryption key)")]
+    Cipher(chacha20poly1305::Error),
+    #[error("invalid envelope: {0}")]
+    Envelope(&'static str),
+    #[error("unsupported envelope version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unsupported cipher {0}")]
+    UnsupportedCipher(u8),
+    #[error("the ciphertext doesn't belong here (moved or tampered with)")]
+    Integrity,
+    #[error("the ciphertext isn't bound to its context")]
+    Unbound,
+}
+/// Decrypts some data and returns the result. Envelopes of any supported
+/// cipher are accepted, as is data encrypted by earlier releases.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ec_secrets_shared_library::utils::vault::{decrypt, encrypt};
+///
+/// let encrypted_data = encrypt(b"example text", b"encryption key").expect("Failed to encrypt");
+///
+/// let data = decrypt(&encrypted_data, b"example passowrd").expect("Failed to decrypt");
+///
+/// // Print value to stdout:
+/// // println!("data: {}", String::from_utf8(data.clone()).expect("Data is not a utf8 string"));
+/// // or write to a file:
+/// // fs::write("text.txt", data).expect("Failed to write to file");
+/// ```
+///
+pub fn decrypt(data: &[u8], encryption_key: &[u8]) -> Result<SecretBytes, DecryptError> {
+    open_envelope(data, encryption_key, None)
+}
+
+/// Decrypts data encrypted by [encrypt_bound] with the same `context`,
+/// failing with [DecryptError::Integrity] for any other context. Unbound
+/// ciphertexts are still accepted unless [binding_required].
+pub fn decrypt_bound(
+    data: &[u8],
+    encryption_key: &[u8],
+    context: &[u8],
+) -> Result<SecretBytes, DecryptError> {
+    if binding_required() && !is_bound(data) {
+        return Err(DecryptError::Unbound);
+    }
+    open_envelope(data, encryption_key, Some(context))
+}
+
+fn open_envelope(
+    data: &[u8],
+    encryption_key: &[u8],
+    context: Option<&[u8]>,
+) -> Result<SecretBytes, DecryptError> {
+    if !data.starts_with(ENVELOPE_MAGIC) {
+        return decrypt_legacy(data, encryption_key);
+    }
+    let envelope = parse_envelope(data)?;
+    if envelope.bound && context.is_none() {
+        return Err(DecryptError::Integrity);
+    }
+
+    trace!("Generating key");
+    let key = envelope
+        .params
+        .kdf
+        .derive_key(encryption_key, envelope.salt)
+        .map_err(DecryptError::Hashing)?;
+
+    info!("Decrypting");
+    let context = context.filter(|_| envelope.bound).unwrap_or_default();
+    let aad = [envelope.header, context].concat();
+    envelope
+        .params
+        .cipher
+        .open(
+            &key,
+            envelope.nonce,
+            Payload {
+                msg: envelope.ciphertext,
+                aad: &aad,
+            },
+        )
+        .map_err(|e| {
+            if envelope.bound {
+                DecryptError::Integrity
+            } else {
+                DecryptError::Cipher(e)
+            }
+        })
+}
+
+fn decrypt_legacy(data: &[u8], encryption_key: &[u8]) -> Result<SecretBytes, DecryptError> {
+    trace!("Decoding");
+    let decoded: PrecryptorFile = bincode::deserialize(data).map_err(DecryptError::Deserialize)?;
+
+    trace!("Generating key");
+    let key = KdfParams::default()
+        .derive_key(encryption_key, &decoded.salt)
+        .map_err(DecryptError::Hashing)?;
+
+    info!("Decrypting");
+    Cipher::ChaCha20Poly1305
+        .open(&key, &decoded.nonce, decoded.data.as_slice().into())
+        .map_err(DecryptError::Cipher)
+}
+
+/// Data in the format of earlier releases, for tests of backward compatibility.
+#[cfg(test)]
+pub(crate) fn encrypt_legacy(data: &[u8], encryption_key: &[u8]) -> Vec<u8> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+    let key = KdfParams::default()
+        .derive_key(encryption_key, &salt)
+        .unwrap();
+    let file = PrecryptorFile {
+        data: Cipher::ChaCha20Poly1305
+            .seal(&key, &nonce, data.into())
+            .unwrap(),
+        nonce,
+        salt,
+    };
+    bincode::serialize(&file).unwrap()
+}
+
+#[derive(Error, Debug)]
+pub enum ReencryptError {
+    #[error("{0}")]
+    Decrypt(DecryptError),
+    #[error("{0}")]
+    Encrypt(EncryptError),
+}
+/// Re-encrypts `data` with `params` unless it already uses them. Returns
+/// None when nothing had to change.
+pub fn reencrypt(
+    data: &[u8],
+    encryption_key: &[u8],
+    params: EnvelopeParams,
+) -> Result<Option<Vec<u8>>, ReencryptError> {
+    if data.starts_with(ENVELOPE_MAGIC) && envelope_params(data) == Some(params) {
+        return Ok(None);
+    }
+    let plaintext = decrypt(data, encryption_key).map_err(ReencryptError::Decrypt)?;
+    encrypt_with(&plaintext, encryption_key, params)
+        .map(Some)
+        .map_err(ReencryptError::Encrypt)
+}
+
+/// Like [reencrypt], binding the result to `context`. Unbound data is
+/// bound even when [binding_required], so this is how it gets migrated.
+pub fn reencrypt_bound(
+    data: &[u8],
+    encryption_key: &[u8],
+    params: EnvelopeParams,
+    context: &[u8],
+) -> Result<Option<Vec<u8>>, ReencryptError> {
+    if is_bound(data) && envelope_params(data) == Some(params) {
+        return Ok(None);
+    }
+    let plaintext =
+        open_envelope(data, encryption_key, Some(context)).map_err(ReencryptError::Decrypt)?;
+    seal_envelope(&plaintext, encryption_key, params, Some(context))
+        .map(Some)
+        .map_err(ReencryptError::Encrypt)
+}
+
+#[derive(Error, Debug)]
+pub enum FsEncryptError {
+    #[error("error writing data to file system: {0}")]
+    Fs(io::Error),
+    #[error("error encrypting file contents: {0}")]
+    Encrypt(EncryptError),
+    #[error("error encrypting file contents: {0}")]
+    Stream(StreamError),
+}
+/// Encrypts file data and outputs it to the specified output file. The file
+/// is streamed in chunks (see [stream]), so its size isn't bound by memory.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ec_secrets_shared_library::utils::vault::encrypt_file;
+/// use std::path::Path;
+///
+/// encrypt_file(Path::new("example.txt"), Path::new("encrypted_example.txt"), b"encryption key").expect("Failed to encrypt the file");
+/// // Now the encrypted_example.txt is encrypted
+/// ```
+///
+pub fn encrypt_file(
+    path: &Path,
+    output_path: &Path,
+    encryption_key: &[u8],
+) -> Result<(), FsEncryptError> {
+    trace!("Reading file");
+    let mut input = BufReader::new(File::open(path).map_err(FsEncryptError::Fs)?);
+    write_atomically(output_path, FsEncryptError::Fs, |output| {
+        trace!("Encrypting to file");
+        stream::encrypt_stream(&mut input, BufWriter::new(output), encryption_key)
+            .map(drop)
+            .map_err(FsEncryptError::Stream)
+    })
+}
+
+#[derive(Error, Debug)]
+pub enum FsDecryptError {
+    #[error("error writing encrypted data to file system")]
+    Fs(io::Error),
+    #[error("error decrypting file contents")]
+    Decrypt(DecryptError),
+    #[error("error decrypting file contents: {0}")]
+    Stream(StreamError),
+}
+/// Decrypts file data and output it to the specified output file. Both the
+/// chunked format and files written by earlier versions are accepted.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ec_secrets_shared_library::utils::vault::decrypt_file;
+/// use std::path::Path;
+///
+/// decrypt_file(Path::new("encrypted_example.txt"), Path::new("example.txt"), b"encryption key").expect("Failed to decrypt the file");
+/// // Now the example.txt is decrypted
+/// ```
+///
+pub fn decrypt_file(
+    path: &Path,
+    output_path: &Path,
+    encryption_key: &[u8],
+) -> Result<(), FsDecryptError> {
+    trace!("Reading file");
+    let input = BufReader::new(File::open(path).map_err(FsDecryptError::Fs)?);
+    write_atomically(output_path, FsDecryptError::Fs, |output| {
+        trace!("Decrypting to file");
+        let mut output = BufWriter::new(output);
+        stream::decrypt_stream(input, &mut output, encryption_key)
+            .map_err(FsDecryptError::Stream)?;
+        output
+            .into_inner()
+            .map_err(|error| FsDecryptError::Fs(error.into_error()))?;
+        Ok(())
+    })
+}
+
+/// Writes `output_path` through a temporary file next to it that is renamed
+/// into place once `write` succeeded, so input and output may be one file.
+fn write_atomically<E>(
+    output_path: &Path,
+    fs_error: impl Fn(io::Error) -> E,
+    write: impl FnOnce(File) -> Result<(), E>,
+) -> Result<(), E> {
+    let file_name = output_path
+        .file_name()
+        .ok_or_else(|| fs_error(io::Error::new(io::ErrorKind::InvalidInput, "no file name")))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{:016x}.tmp", OsRng.next_u64()));
+    let temp_path: PathBuf = output_path.with_file_name(temp_name);
+
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .map_err(&fs_error)?;
+    let result = write(file).and_then(|()| fs::rename(&temp_path, output_path).map_err(&fs_error));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+#[derive(Error, Debug)]
+pub enum EncryptDirectoryError {
+    #[error("error creating archive to encrypt: {0}")]
+    Archive(io::Error),
+    #[error("no filename found for path")]
+    NoFilename,
+    #[error("error encrypting archive contents: {0}")]
+    Encrypt(EncryptError),
+    #[error("error writing encrypted archive to file: {0}")]
+    Fs(io::Error),
+    #[error("error encrypting archive contents: {0}")]
+    Stream(StreamError),
+}
+/// Encrypts a directory and outputs it to the specified output file
+///
+/// note: the output is a file but when you decrypt it, it will be a directory again it's simply an encrypted tar file
+///
+/// # Examples
+///
+/// ```no_run
+/// use ec_secrets_shared_library::utils::vault::encrypt_directory;
+/// use std::path::Path;
+///
+/// encrypt_directory(Path::new("example"), Path::new("example.dir"), b"encryption key").expect("Failed to encrypt directory");
+/// // Now the example.dir is encrypted
+/// ```
+///
+pub fn encrypt_directory(
+    path: &Path,
+    output_path: &Path,
+    encryption_key: &[u8],
+) -> Result<(), EncryptDirectoryError> {
+    let name = path.file_name().ok_or(EncryptDirectoryError::NoFilename)?;
+    write_atomically(output_path, EncryptDirectoryError::Fs, |output| {
+        let writer = EncryptWriter::new(BufWriter::new(output), encryption_key)
+            .map_err(EncryptDirectoryError::Stream)?;
+        let mut archive = Builder::new(writer);
+
+        trace!("Adding folder to encrypted archive");
+        archive
+            .append_dir_all(name, path)
+            .map_err(EncryptDirectoryError::Archive)?;
+        archive
+            .into_inner()
+            .map_err(EncryptDirectoryError::Archive)?
+            .finish()
+            .map_err(|error| EncryptDirectoryError::Stream(stream::stream_error(error)))?;
+        Ok(())
+    })
+}
+#[derive(Error, Debug)]
+pub enum DecryptDirectoryError {
+    #[error("error reading encrypted data from filesystem: {0}")]
+    Fs(io::Error),
+    #[error("error decrypting archive: {0}")]
+    Decrypt(DecryptError),
+    #[error("error unpacking archive: {0}")]
+    Archive(io::Error),
+    #[error("error decrypting archive: {0}")]
+    Stream(StreamError),
+}
+/// Decrypts a directory and extracts it to the specified output directory
+///
+/// note: the encrypted directory is a file but when its decrypted it will be a directory and the output path is not what the folder name should be its where to extract the file
+///
+/// Nothing is written to `output_path` unless the whole stream authenticates,
+/// and existing entries there are never replaced.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ec_secrets_shared_library::utils::vault::decrypt_directory;
+/// use std::path::Path;
+///
+/// decrypt_directory(Path::new("example.dir"), Path::new("example"), b"encryption key").expect("Failed to decrypt directory");
+/// // Now the example.txt is decrypted
+/// ```
+///
+pub fn decrypt_directory(
+    path: &Path,
+    output_path: &Path,
+    encryption_key: &[u8],
+) -> Result<(), DecryptDirectoryError> {
+    trace!("Reading from file");
+    let input = BufReader::new(File::open(path).map_err(DecryptDirectoryError::Fs)?);
+    let data =
+        stream::decrypt_reader(input, encryption_key).map_err(DecryptDirectoryError::Stream)?;
+
+    // Extract next to the target and move into place only once the whole
+    // stream authenticated, so tampered or truncated input leaves nothing
+    fs::create_dir_all(output_path).map_err(DecryptDirectoryError::Fs)?;
+    let temp_path = output_path.join(format!(".{:016x}.tmp", OsRng.next_u64()));
+    fs::create_dir(&temp_path).map_err(DecryptDirectoryError::Fs)?;
+    let result = extract_verified(data, &temp_path)
+        .and_then(|()| move_entries(&temp_path, output_path).map_err(DecryptDirectoryError::Fs));
+    fs::remove_dir_all(&temp_path).ok();
+    result
+}
+
+fn extract_verified(
+    data: Box<dyn Read + '_>,
+    temp_path: &Path,
+) -> Result<(), DecryptDirectoryError> {
+    let mut archive = Archive::new(data);
+    trace!("Extracting archive");
+    archive
+        .unpack(temp_path)
+        .map_err(DecryptDirectoryError::Archive)?;
+    // tar stops at its end marker, the rest holds the authenticated last chunk
+    io::copy(&mut archive.into_inner(), &mut io::sink()).map_err(DecryptDirectoryError::Archive)?;
+    Ok(())
+}
+
+/// Renames the entries of `from` into `to`, refusing to replace any.
+fn move_entries(from: &Path, to: &Path) -> Result<(), io::Error> {
+    let entries = fs::read_dir(from)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(existing) = entries.iter().find(|name| to.join(name).exists()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", to.join(existing).display()),
+        ));
+    }
+    for name in entries {
+        fs::rename(from.join(&name), to.join(&name))?;
+    }
+    Ok(())
+}
+
+/// Packs a directory into a tar archive, under the directory's own name.
+/// This is the plaintext `encrypt_directory` encrypts.
+pub fn archive_directory(path: &Path) -> Result<Vec<u8>, io::Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut archive = Builder::new(Vec::new());
+
+    trace!("Adding folder to archive");
+    archive.append_dir_all(name, path)?;
+    archive.into_inner()
+}
+
+/// Extracts a tar archive made by `archive_directory` into `output_path`.
+pub fn unpack_archive(data: &[u8], output_path: &Path) -> Result<(), io::Error> {
+    let mut archive = Archive::new(data);
+
+    trace!("Extracting archive");
+    archive.unpack(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data() {
+        let encrypted_data = encrypt(b"test", b"test").expect("Failed to encrypt");
+        let data = decrypt(&encrypted_data, b"test").expect("Failed to decrypt");
+        assert_eq!(*data, b"test");
+    }
+
+    #[test]
+    fn ciphers_and_reencryption() {
+        let kdf = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            lanes: 1,
+        };
+        for cipher in Cipher::ALL {
+            let params = EnvelopeParams { cipher, kdf };
+            let encrypted = encrypt_with(b"test", b"test", params).expect("Failed to encrypt");
+            assert_eq!(envelope_params(&encrypted), Some(params));
+            assert_eq!(
+                *decrypt(&encrypted, b"test").expect("Failed to decrypt"),
+                b"test"
+            );
+            assert!(decrypt(&encrypted, b"wrong").is_err());
+
+            // The header is authenticated, another cipher id doesn't decrypt
+            let mut modified = encrypted.clone();
+            modified[5] = Cipher::ALL[(cipher.id() as usize) % 3].id();
+            assert!(decrypt(&modified, b"test").is_err());
+        }
+
+        let legacy = encrypt_legacy(b"test", b"test");
+        assert_eq!(envelope_params(&legacy), Some(EnvelopeParams::legacy()));
+        let current = EnvelopeParams {
+            cipher: Cipher::Aes256Gcm,
+            kdf,
+        };
+        let upgraded = reencrypt(&legacy, b"test", current)
+            .expect("Failed to re-encrypt")
+            .expect("Legacy data wasn't re-encrypted");
+        assert_eq!(envelope_params(&upgraded), Some(current));
+        assert_eq!(
+            *decrypt(&upgraded, b"test").expect("Failed to decrypt"),
+            b"test"
+        );
+        assert!(reencrypt(&upgraded, b"test", current).unwrap().is_none());
+    }
+
+    #[test]
+    fn bound_context() {
+        let params = EnvelopeParams {
+            cipher: Cipher::ChaCha20Poly1305,
+            kdf: KdfParams {
+                memory_kib: 64,
+                iterations: 1,
+                lanes: 1,
+            },
+        };
+        let bound = seal_envelope(b"test", b"test", params, Some(b"entry 1")).unwrap();
+        assert!(is_bound(&bound));
+        assert_eq!(
+            *decrypt_bound(&bound, b"test", b"entry 1").expect("Failed to decrypt"),
+            b"test"
+        );
+        assert!(matches!(
+            decrypt_bound(&bound, b"test", b"entry 2"),
+            Err(DecryptError::Integrity)
+        ));
+        assert!(matches!(
+            decrypt(&bound, b"test"),
+            Err(DecryptError::Integrity)
+        ));
+
+        // Unbound data still decrypts and gets bound by re-encryption
+        let unbound = encrypt_with(b"test", b"test", params).unwrap();
+        assert_eq!(
+            *decrypt_bound(&unbound, b"test", b"entry 1").unwrap(),
+            b"test"
+        );
+        let rebound = reencrypt_bound(&unbound, b"test", params, b"entry 1")
+            .expect("Failed to re-encrypt")
+            .expect("Unbound data wasn't bound");
+        assert!(is_bound(&rebound));
+        assert!(decrypt_bound(&rebound, b"test", b"entry 2").is_err());
+        assert!(
+            reencrypt_bound(&rebound, b"test", params, b"entry 1")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn file() {
+        fs::write("test.txt", "test").expect("Failed to write to file");
+        encrypt_file(Path::new("test.txt"), Path::new("test.txt"), b"test")
+            .expect("Failed to encrypt the file");
+        decrypt_file(Path::new("test.txt"), Path::new("test.txt"), b"test")
+            .expect("Failed to decrypt the file");
+        let data = fs::read("test.txt").expect("Failed to read file");
+        assert_eq!(data, b"test");
+        fs::remove_file("test.txt").expect("Failed to remove the test file");
+    }
+
+    #[test]
+    fn directory() {
+        fs::create_dir("test").expect("Failed to create directory");
+        fs::write("test/test.txt", "test").expect("Failed to write to file");
+        encrypt_directory(Path::new("test"), Path::new("test.dir"), b"test")
+            .expect("Failed to encrypt directory");
+        fs::remove_dir_all("test").expect("Failed to remove test directory");
+        decrypt_directory(Path::new("test.dir"), Path::new("."), b"test")
+            .expect("Failed to decrypt directory");
+        fs::remove_file("test.dir").expect("Failed to remove file");
+        fs::remove_dir_all("test").expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn truncated_directory_leaves_nothing() {
+        fs::create_dir("partial").expect("Failed to create directory");
+        fs::write("partial/test.txt", "test").expect("Failed to write to file");
+        encrypt_directory(Path::new("partial"), Path::new("partial.dir"), b"test")
+            .expect("Failed to encrypt directory");
+        fs::remove_dir_all("partial").expect("Failed to remove test directory");
+        let encrypted = fs::read("partial.dir").expect("Failed to read file");
+        fs::write("partial.dir", &encrypted[..encrypted.len() - 1]).expect("Failed to write");
+
+        let output = Path::new("partial.out");
+        assert!(decrypt_directory(Path::new("partial.dir"), output, b"test").is_err());
+        assert_eq!(fs::read_dir(output).expect("Failed to list").count(), 0);
+        fs::remove_file("partial.dir").expect("Failed to remove file");
+        fs::remove_dir_all(output).expect("Failed to remove test directory");
+    }
+}
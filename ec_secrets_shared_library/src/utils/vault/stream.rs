@@ -0,0 +1,557 @@
+/*---------------------------------------------------------------------------
+    Chunked encryption for data too large to hold in memory, using the
+    STREAM construction: ChaCha20-Poly1305 over fixed size chunks, with a
+    big endian chunk counter and a last-chunk flag in each nonce. Dropping,
+    reordering or appending chunks fails authentication.
+
+    Layout, integers big endian:
+
+        magic "ECSTREAM" | version | algorithm | kdf
+        | argon2 memory KiB (u32) | iterations (u32) | lanes (u32)
+        | salt (32) | nonce prefix (7) | chunk size (u32)
+        | chunks of `chunk size` plaintext bytes plus a 16 byte tag,
+          the last one shorter (possibly empty) and sealed as last
+
+    The header is the associated data of every chunk. Data written by the
+    older whole-buffer `encrypt` (a bincode `PrecryptorFile`) is still
+    accepted by [decrypt_reader] and [decrypt_stream].
+---------------------------------------------------------------------------*/
+use std::io::{self, Cursor, Read, Write};
+
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key,
+    aead::{
+        OsRng, Payload,
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+    },
+};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use super::{DecryptError, KdfParams, decrypt, envelope_params};
+use crate::utils::secret::SecretBytes;
+
+pub const MAGIC: &[u8; 8] = b"ECSTREAM";
+pub const VERSION: u8 = 1;
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+const ALGORITHM_CHACHA20_POLY1305: u8 = 1;
+const KDF_ARGON2ID: u8 = 1;
+const SALT_LEN: usize = 32;
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+pub const HEADER_LEN: usize = MAGIC.len() + 3 + 12 + SALT_LEN + NONCE_PREFIX_LEN + 4;
+
+// Upper bound checked before allocating for an untrusted header
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum StreamError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("unsupported stream version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unsupported stream algorithm {0}")]
+    UnsupportedAlgorithm(u8),
+    #[error("invalid stream header: {0}")]
+    InvalidHeader(&'static str),
+    #[error("failed to generate key from encryption key")]
+    Hashing(argon2::Error),
+    #[error("invalid encryption settings: {0}")]
+    Config(String),
+    #[error(
+        "error decrypting a chunk with chacha20poly1305 (invalid encryption key, or modified or truncated data)"
+    )]
+    Cipher,
+    #[error("error decrypting legacy data: {0}")]
+    Legacy(DecryptError),
+}
+
+/// Marks io errors raised by a failed chunk, see [stream_error].
+#[derive(Error, Debug)]
+#[error("encrypted chunk failed authentication")]
+struct ChunkError;
+
+fn chunk_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, ChunkError)
+}
+
+/// Turns an io error of the adapters back into a [StreamError], so that
+/// authentication failures stay distinguishable from io failures.
+pub fn stream_error(error: io::Error) -> StreamError {
+    if error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<ChunkError>())
+    {
+        StreamError::Cipher
+    } else {
+        StreamError::Io(error)
+    }
+}
+
+fn derive_key(
+    kdf: &KdfParams,
+    encryption_key: &[u8],
+    salt: &[u8],
+) -> Result<SecretBytes, StreamError> {
+    kdf.derive_key(encryption_key, salt)
+        .map_err(StreamError::Hashing)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamHeader {
+    pub kdf: KdfParams,
+    pub salt: [u8; SALT_LEN],
+    pub nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    pub chunk_size: u32,
+}
+
+impl StreamHeader {
+    fn generate(kdf: KdfParams, chunk_size: u32) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        Self {
+            kdf,
+            salt,
+            nonce_prefix,
+            chunk_size,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&[VERSION, ALGORITHM_CHACHA20_POLY1305, KDF_ARGON2ID]);
+        bytes.extend_from_slice(&self.kdf.memory_kib.to_be_bytes());
+        bytes.extend_from_slice(&self.kdf.iterations.to_be_bytes());
+        bytes.extend_from_slice(&self.kdf.lanes.to_be_bytes());
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        bytes.try_into().expect("header has a fixed length")
+    }
+
+    pub fn parse(bytes: &[u8; HEADER_LEN]) -> Result<Self, StreamError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(StreamError::InvalidHeader("missing magic"));
+        }
+        let (version, algorithm, kdf) = (bytes[8], bytes[9], bytes[10]);
+        if version != VERSION {
+            return Err(StreamError::UnsupportedVersion(version));
+        }
+        if algorithm != ALGORITHM_CHACHA20_POLY1305 {
+            return Err(StreamError::UnsupportedAlgorithm(algorithm));
+        }
+        if kdf != KDF_ARGON2ID {
+            return Err(StreamError::InvalidHeader("unsupported key derivation"));
+        }
+        let u32_at = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+        let kdf = KdfParams {
+            memory_kib: u32_at(11),
+            iterations: u32_at(15),
+            lanes: u32_at(19),
+        };
+        kdf.validate().map_err(StreamError::InvalidHeader)?;
+        let salt = bytes[23..23 + SALT_LEN].try_into().unwrap();
+        let nonce_prefix = bytes[55..55 + NONCE_PREFIX_LEN].try_into().unwrap();
+        let chunk_size = u32_at(62);
+        if !(1..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+            return Err(StreamError::InvalidHeader("chunk size out of range"));
+        }
+        Ok(Self {
+            kdf,
+            salt,
+            nonce_prefix,
+            chunk_size,
+        })
+    }
+}
+
+/// Encrypts everything written to it into `inner`. Plaintext is buffered
+/// one chunk at a time; [EncryptWriter::finish] seals the last chunk and
+/// must be called, otherwise the output fails to decrypt as truncated.
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    encryptor: Option<EncryptorBE32<ChaCha20Poly1305>>,
+    header: [u8; HEADER_LEN],
+    buffer: Zeroizing<Vec<u8>>,
+    chunk_size: usize,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(inner: W, encryption_key: &[u8]) -> Result<Self, StreamError> {
+        let kdf = KdfParams::from_env().map_err(StreamError::Config)?;
+        Self::with_params(inner, encryption_key, kdf, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_params(
+        mut inner: W,
+        encryption_key: &[u8],
+        kdf: KdfParams,
+        chunk_size: u32,
+    ) -> Result<Self, StreamError> {
+        kdf.validate().map_err(StreamError::InvalidHeader)?;
+        if !(1..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+            return Err(StreamError::InvalidHeader("chunk size out of range"));
+        }
+        let header = StreamHeader::generate(kdf, chunk_size);
+        let key = derive_key(&kdf, encryption_key, &header.salt)?;
+        let encryptor =
+            EncryptorBE32::new(Key::from_slice(&key), header.nonce_prefix.as_ref().into());
+
+        let header = header.to_bytes();
+        inner.write_all(&header)?;
+        Ok(Self {
+            inner,
+            encryptor: Some(encryptor),
+            header,
+            buffer: Zeroizing::new(Vec::with_capacity(chunk_size as usize)),
+            chunk_size: chunk_size as usize,
+        })
+    }
+
+    fn seal_chunk(&mut self) -> io::Result<()> {
+        let encryptor = self
+            .encryptor
+            .as_mut()
+            .ok_or_else(|| io::Error::other("stream already finished"))?;
+        let chunk = encryptor
+            .encrypt_next(Payload {
+                msg: &self.buffer,
+                aad: &self.header,
+            })
+            .map_err(|_| chunk_error())?;
+        self.inner.write_all(&chunk)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// The writer sealed chunks go to, e.g. to drain a buffer into an async
+    /// sink between writes.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Seals the buffered plaintext as the last chunk and returns `inner`.
+    pub fn finish(mut self) -> io::Result<W> {
+        let encryptor = self
+            .encryptor
+            .take()
+            .ok_or_else(|| io::Error::other("stream already finished"))?;
+        let chunk = encryptor
+            .encrypt_last(Payload {
+                msg: &self.buffer,
+                aad: &self.header,
+            })
+            .map_err(|_| chunk_error())?;
+        self.inner.write_all(&chunk)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full chunk is only sealed once more data arrives, as the last
+        // chunk has to be sealed differently
+        if self.buffer.len() == self.chunk_size && !buf.is_empty() {
+            self.seal_chunk()?;
+        }
+        let len = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    /// Flushes `inner`; plaintext of an incomplete chunk stays buffered.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a stream written by [EncryptWriter]. Every chunk is
+/// authenticated before it is returned; a truncated stream fails on its
+/// last read.
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    decryptor: Option<DecryptorBE32<ChaCha20Poly1305>>,
+    header: [u8; HEADER_LEN],
+    // Read ahead by one byte to tell the last chunk from a full one
+    ciphertext: Vec<u8>,
+    plaintext: Zeroizing<Vec<u8>>,
+    position: usize,
+    sealed_chunk_size: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    pub fn new(mut inner: R, encryption_key: &[u8]) -> Result<Self, StreamError> {
+        let mut header = [0u8; HEADER_LEN];
+        inner.read_exact(&mut header)?;
+        Self::with_header(inner, header, encryption_key)
+    }
+
+    fn with_header(
+        inner: R,
+        header: [u8; HEADER_LEN],
+        encryption_key: &[u8],
+    ) -> Result<Self, StreamError> {
+        let parsed = StreamHeader::parse(&header)?;
+        let key = derive_key(&parsed.kdf, encryption_key, &parsed.salt)?;
+        let sealed_chunk_size = parsed.chunk_size as usize + TAG_LEN;
+        Ok(Self {
+            inner,
+            decryptor: Some(DecryptorBE32::new(
+                Key::from_slice(&key),
+                parsed.nonce_prefix.as_ref().into(),
+            )),
+            header,
+            ciphertext: Vec::with_capacity(sealed_chunk_size + 1),
+            plaintext: Zeroizing::default(),
+            position: 0,
+            sealed_chunk_size,
+        })
+    }
+
+    /// Decrypts the next chunk into `plaintext`, false once the last chunk
+    /// was read.
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        if self.decryptor.is_none() {
+            return Ok(false);
+        }
+        while self.ciphertext.len() <= self.sealed_chunk_size {
+            let start = self.ciphertext.len();
+            self.ciphertext.resize(self.sealed_chunk_size + 1, 0);
+            let read = match self.inner.read(&mut self.ciphertext[start..]) {
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => 0,
+                Err(error) => {
+                    self.ciphertext.truncate(start);
+                    return Err(error);
+                }
+            };
+            self.ciphertext.truncate(start + read);
+            if read == 0 {
+                break;
+            }
+        }
+
+        let plaintext = if self.ciphertext.len() > self.sealed_chunk_size {
+            let rest = self.ciphertext.split_off(self.sealed_chunk_size);
+            let chunk = std::mem::replace(&mut self.ciphertext, rest);
+            self.decryptor
+                .as_mut()
+                .expect("checked above")
+                .decrypt_next(Payload {
+                    msg: &chunk,
+                    aad: &self.header,
+                })
+                .map_err(|_| chunk_error())?
+        } else {
+            let decryptor = self.decryptor.take().expect("checked above");
+            let chunk = std::mem::take(&mut self.ciphertext);
+            decryptor
+                .decrypt_last(Payload {
+                    msg: &chunk,
+                    aad: &self.header,
+                })
+                .map_err(|_| chunk_error())?
+        };
+        self.plaintext = Zeroizing::new(plaintext);
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.plaintext.len() {
+                let len = buf.len().min(self.plaintext.len() - self.position);
+                buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+                self.position += len;
+                return Ok(len);
+            }
+            if buf.is_empty() || !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// Whether `data` starts like a stream written by [EncryptWriter].
+pub fn is_stream(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// A reader over the plaintext of `reader`, which holds either a stream or
+/// data written by the whole-buffer `encrypt`. The latter is decrypted in
+/// memory.
+pub fn decrypt_reader<'a, R: Read + 'a>(
+    reader: R,
+    encryption_key: &[u8],
+) -> Result<Box<dyn Read + 'a>, StreamError> {
+    bounded_decrypt_reader(reader, encryption_key, None)
+}
+
+fn bounded_decrypt_reader<'a, R: Read + 'a>(
+    mut reader: R,
+    encryption_key: &[u8],
+    max_kdf: Option<&KdfParams>,
+) -> Result<Box<dyn Read + 'a>, StreamError> {
+    let check_kdf = |kdf: &KdfParams| match max_kdf {
+        Some(max) if !kdf.within(max) => Err(StreamError::InvalidHeader(
+            "argon2 costs above the accepted maximum",
+        )),
+        _ => Ok(()),
+    };
+    let mut header = [0u8; HEADER_LEN];
+    let mut read = 0;
+    while read < HEADER_LEN {
+        match reader.read(&mut header[read..]) {
+            Ok(0) => break,
+            Ok(len) => read += len,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    if read == HEADER_LEN && is_stream(&header) {
+        check_kdf(&StreamHeader::parse(&header)?.kdf)?;
+        return Ok(Box::new(DecryptReader::with_header(
+            reader,
+            header,
+            encryption_key,
+        )?));
+    }
+    let mut data = header[..read].to_vec();
+    reader.read_to_end(&mut data)?;
+    if let Some(params) = envelope_params(&data) {
+        check_kdf(&params.kdf)?;
+    }
+    let plaintext = decrypt(&data, encryption_key).map_err(StreamError::Legacy)?;
+    Ok(Box::new(Cursor::new(plaintext)))
+}
+
+/// Encrypts everything `reader` yields into `writer`, returning `writer`.
+pub fn encrypt_stream<R: Read, W: Write>(
+    reader: &mut R,
+    writer: W,
+    encryption_key: &[u8],
+) -> Result<W, StreamError> {
+    let mut encrypted = EncryptWriter::new(writer, encryption_key)?;
+    io::copy(reader, &mut encrypted).map_err(stream_error)?;
+    encrypted.finish().map_err(stream_error)
+}
+
+/// Decrypts a stream, or legacy data, from `reader` into `writer` and
+/// returns the number of plaintext bytes.
+pub fn decrypt_stream<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    encryption_key: &[u8],
+) -> Result<u64, StreamError> {
+    let mut plaintext = decrypt_reader(reader, encryption_key)?;
+    io::copy(&mut plaintext, writer).map_err(stream_error)
+}
+
+/// Like [decrypt_stream], but refuses data whose Argon2 costs exceed
+/// `max_kdf` before deriving a key. For servers decrypting uploads, where
+/// the costs come from whoever sent the data.
+pub fn decrypt_stream_bounded<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    encryption_key: &[u8],
+    max_kdf: &KdfParams,
+) -> Result<u64, StreamError> {
+    let mut plaintext = bounded_decrypt_reader(reader, encryption_key, Some(max_kdf))?;
+    io::copy(&mut plaintext, writer).map_err(stream_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::vault::encrypt_legacy;
+
+    // Cheap parameters, the defaults make the tests slow
+    const TEST_KDF: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        lanes: 1,
+    };
+
+    fn encrypt_chunked(data: &[u8], chunk_size: u32) -> Vec<u8> {
+        let mut writer = EncryptWriter::with_params(Vec::new(), b"test", TEST_KDF, chunk_size)
+            .expect("Failed to start stream");
+        // Odd sized writes so chunks don't line up with them
+        for part in data.chunks(7) {
+            writer.write_all(part).expect("Failed to write");
+        }
+        writer.finish().expect("Failed to finish stream")
+    }
+
+    #[test]
+    fn round_trip_across_chunks() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        for size in [0, 1, 16, 999, 1000] {
+            for chunk_size in [1, 16, 100, 4096] {
+                let encrypted = encrypt_chunked(&data[..size], chunk_size);
+                let mut decrypted = Vec::new();
+                decrypt_stream(encrypted.as_slice(), &mut decrypted, b"test")
+                    .expect("Failed to decrypt");
+                assert_eq!(decrypted, &data[..size]);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_modified_streams() {
+        let data = vec![42u8; 100];
+        let encrypted = encrypt_chunked(&data, 16);
+        let sealed = 16 + TAG_LEN;
+
+        // Cut at a chunk boundary: the remaining last chunk wasn't sealed as last
+        let truncated = &encrypted[..HEADER_LEN + 3 * sealed];
+        // Swapped chunks
+        let mut reordered = encrypted.clone();
+        let (first, second) = (HEADER_LEN, HEADER_LEN + sealed);
+        for i in 0..sealed {
+            reordered.swap(first + i, second + i);
+        }
+        // Header tampering
+        let mut modified = encrypted.clone();
+        modified[HEADER_LEN - 1] ^= 1;
+
+        for broken in [truncated, reordered.as_slice(), modified.as_slice()] {
+            let result = decrypt_stream(broken, &mut Vec::new(), b"test");
+            assert!(matches!(result, Err(StreamError::Cipher)), "{result:?}");
+        }
+        let result = decrypt_stream(encrypted.as_slice(), &mut Vec::new(), b"wrong");
+        assert!(matches!(result, Err(StreamError::Cipher)));
+    }
+
+    #[test]
+    fn bounded_decryption_refuses_costly_headers() {
+        let encrypted = encrypt_chunked(b"data", 16);
+        let mut decrypted = Vec::new();
+        decrypt_stream_bounded(encrypted.as_slice(), &mut decrypted, b"test", &TEST_KDF)
+            .expect("Failed to decrypt");
+        assert_eq!(decrypted, b"data");
+
+        let max = KdfParams {
+            memory_kib: 32,
+            ..TEST_KDF
+        };
+        let result = decrypt_stream_bounded(encrypted.as_slice(), &mut Vec::new(), b"test", &max);
+        assert!(matches!(result, Err(StreamError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn decrypts_legacy_format() {
+        let legacy = encrypt_legacy(b"legacy data", b"test");
+        let mut decrypted = Vec::new();
+        decrypt_stream(legacy.as_slice(), &mut decrypted, b"test").expect("Failed to decrypt");
+        assert_eq!(decrypted, b"legacy data");
+    }
+}